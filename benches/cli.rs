@@ -0,0 +1,91 @@
+//! End-to-end benchmarks of the `opz` binary against the fake `op` in `tests/fixtures/fake-op`.
+//!
+//! Run with `cargo bench`. Every invocation gets its own cache directory, so the numbers
+//! are independent of the caller's real `op` session and item list cache.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::{fs, path::PathBuf, process::Command};
+use tempfile::TempDir;
+
+struct Sandbox {
+    cache_home: TempDir,
+    op_bin: PathBuf,
+}
+
+impl Sandbox {
+    fn new() -> Self {
+        Self {
+            cache_home: TempDir::new().unwrap(),
+            op_bin: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fake-op/op"),
+        }
+    }
+
+    fn clear_cache(&self) {
+        let _ = fs::remove_dir_all(self.cache_home.path().join("opz"));
+    }
+
+    fn run(&self, args: &[&str]) {
+        let output = Command::new(env!("CARGO_BIN_EXE_opz"))
+            .args(args)
+            .env("OPZ_OP_BIN", &self.op_bin)
+            .env("XDG_CACHE_HOME", self.cache_home.path())
+            .env_remove("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "opz {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+fn bench_item_list_cache(c: &mut Criterion) {
+    let sandbox = Sandbox::new();
+    let mut group = c.benchmark_group("item_list_cache");
+
+    group.bench_function("cold", |b| {
+        b.iter_batched(
+            || sandbox.clear_cache(),
+            |()| sandbox.run(&["find", "item-1"]),
+            BatchSize::PerIteration,
+        )
+    });
+
+    sandbox.run(&["find", "item-1"]);
+    group.bench_function("warm", |b| b.iter(|| sandbox.run(&["find", "item-1"])));
+    group.finish();
+}
+
+fn bench_matching(c: &mut Criterion) {
+    let sandbox = Sandbox::new();
+    sandbox.run(&["find", "item"]);
+
+    c.bench_function("find/all_items", |b| {
+        b.iter(|| sandbox.run(&["find", "item"]))
+    });
+    c.bench_function("find/no_match", |b| {
+        b.iter(|| sandbox.run(&["find", "missing"]))
+    });
+}
+
+fn bench_env_serialization(c: &mut Criterion) {
+    let sandbox = Sandbox::new();
+    sandbox.run(&["find", "item"]);
+
+    c.bench_function("gen/single_item", |b| {
+        b.iter(|| sandbox.run(&["gen", "item-1"]))
+    });
+    c.bench_function("gen/three_items", |b| {
+        b.iter(|| sandbox.run(&["gen", "item-1", "item-2", "item-3"]))
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_item_list_cache,
+    bench_matching,
+    bench_env_serialization
+);
+criterion_main!(benches);
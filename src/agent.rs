@@ -0,0 +1,399 @@
+//! `opz agent`: keeps item lists and (redacted) item details in memory and answers lookups from
+//! other opz invocations over a Unix socket, so shells and editors that call opz constantly
+//! skip `op` and the cache files. Item lists it has served are refreshed in the background.
+//!
+//! The protocol is one JSON request line and one JSON response line per connection. The agent
+//! never holds secret values: field values are redacted as in the on-disk caches, and secrets
+//! are still read by each invocation itself. A missing, stale or failing agent only means the
+//! invocation looks items up on its own.
+//!
+//! Requests carry the caller's service account scope (see [`service_account::cache_scope`]).
+//! The agent's `op` calls run with its own credentials, so it only answers callers with the
+//! same scope, and keeps what it holds apart per scope like the cache directories do.
+
+use crate::{
+    cli::{
+        item_get_cached, item_list_cache_ttl, redacted_item, refresh_item_list_cache, CachePolicy,
+        ItemGet, ItemListEntry,
+    },
+    service_account,
+};
+use anyhow::Result;
+#[cfg(unix)]
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Overrides the socket path; empty to stop invocations from asking an agent.
+const SOCKET_ENV: &str = "OPZ_AGENT_SOCK";
+
+/// Set in the agent itself, whose lookups must not be sent back to it.
+static SERVING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    ItemList {
+        #[serde(default)]
+        scope: Option<String>,
+        account: Option<String>,
+        vault: Option<String>,
+    },
+    ItemGet {
+        #[serde(default)]
+        scope: Option<String>,
+        account: Option<String>,
+        entry: ItemListEntry,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Ok(serde_json::Value),
+    Error(String),
+}
+
+/// `$OPZ_AGENT_SOCK`, or a per-user socket in the runtime directory; `None` if the variable
+/// is set but empty.
+pub fn socket_path() -> Option<PathBuf> {
+    match std::env::var_os(SOCKET_ENV) {
+        Some(path) if path.is_empty() => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(crate::output::runtime_dir().join(default_socket_name())),
+    }
+}
+
+#[cfg(unix)]
+fn default_socket_name() -> String {
+    // SAFETY: getuid(2) cannot fail and has no preconditions.
+    let uid = unsafe { libc::getuid() };
+    format!("opz-agent-{uid}.sock")
+}
+
+#[cfg(not(unix))]
+fn default_socket_name() -> String {
+    "opz-agent.sock".to_string()
+}
+
+/// The item list for this scope from a running agent.
+pub fn item_list(account: Option<&str>, vault: Option<&str>) -> Option<Vec<ItemListEntry>> {
+    ask(&Request::ItemList {
+        scope: service_account::cache_scope(),
+        account: account.map(String::from),
+        vault: vault.map(String::from),
+    })
+}
+
+/// Redacted details of `entry` from a running agent.
+pub fn item_get(account: Option<&str>, entry: &ItemListEntry) -> Option<ItemGet> {
+    ask(&Request::ItemGet {
+        scope: service_account::cache_scope(),
+        account: account.map(String::from),
+        entry: entry.clone(),
+    })
+}
+
+fn ask<T: serde::de::DeserializeOwned>(request: &Request) -> Option<T> {
+    if SERVING.load(Ordering::Relaxed) {
+        return None;
+    }
+    let path = socket_path()?;
+    match client::request(&path, request) {
+        Ok(value) => {
+            tracing::debug!("served by the agent at {}", path.display());
+            Some(value)
+        }
+        Err(err) => {
+            // No agent running is the common case; anything else is worth a note.
+            if path.exists() {
+                tracing::debug!("agent at {} did not answer: {err:#}", path.display());
+            }
+            None
+        }
+    }
+}
+
+#[cfg(unix)]
+mod client {
+    use super::{Request, Response};
+    use anyhow::{anyhow, Context, Result};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::{fs::MetadataExt, net::UnixStream},
+        path::Path,
+        time::Duration,
+    };
+
+    /// Long enough for the agent to fetch a list it does not hold yet.
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn request<T: serde::de::DeserializeOwned>(path: &Path, request: &Request) -> Result<T> {
+        // A socket someone else created could serve made-up items.
+        // SAFETY: getuid(2) cannot fail and has no preconditions.
+        let uid = unsafe { libc::getuid() };
+        if std::fs::metadata(path)?.uid() != uid {
+            return Err(anyhow!("socket is owned by another user"));
+        }
+        let mut stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        writeln!(stream, "{}", serde_json::to_string(request)?)?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        match serde_json::from_str(&line).context("parse agent response")? {
+            Response::Ok(value) => Ok(serde_json::from_value(value)?),
+            Response::Error(message) => Err(anyhow!(message)),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod client {
+    use super::Request;
+    use anyhow::{anyhow, Result};
+    use std::path::Path;
+
+    pub fn request<T>(_path: &Path, _request: &Request) -> Result<T> {
+        Err(anyhow!("the agent needs Unix sockets"))
+    }
+}
+
+/// Run the agent on `socket` (default [`socket_path`]) until Ctrl-C or SIGTERM.
+#[cfg(unix)]
+pub fn run(socket: Option<PathBuf>) -> Result<()> {
+    use crate::{
+        runtime,
+        supervisor::{Restart, Supervisor},
+    };
+    use std::{os::unix::fs::PermissionsExt, sync::Arc};
+    use tokio::net::UnixListener;
+
+    let path = socket
+        .or_else(socket_path)
+        .ok_or_else(|| anyhow!("{SOCKET_ENV} is empty; pass --socket"))?;
+    if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+        return Err(anyhow!(
+            "an agent is already listening on {}",
+            path.display()
+        ));
+    }
+    // Left behind by an agent that was killed.
+    let _ = std::fs::remove_file(&path);
+    SERVING.store(true, Ordering::Relaxed);
+
+    let state = Arc::new(server::State::default());
+    let result = runtime::block_on(async {
+        let listener =
+            UnixListener::bind(&path).with_context(|| format!("bind {}", path.display()))?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        eprintln!("Serving item lookups on {}", path.display());
+        let listener = Arc::new(listener);
+
+        let mut tasks = Supervisor::new();
+        tasks.cancel_on_signal();
+        let refresh_state = Arc::clone(&state);
+        tasks.spawn("cache refresh", server::RESTART, move |token| {
+            server::refresh_loop(Arc::clone(&refresh_state), token)
+        });
+        tasks.spawn("socket server", Restart::Never, move |token| {
+            server::serve(Arc::clone(&listener), Arc::clone(&state), token)
+        });
+        tasks.wait().await
+    });
+    let _ = std::fs::remove_file(&path);
+    result?
+}
+
+#[cfg(not(unix))]
+pub fn run(_socket: Option<PathBuf>) -> Result<()> {
+    Err(anyhow::anyhow!("opz agent needs Unix sockets"))
+}
+
+#[cfg(unix)]
+mod server {
+    use super::*;
+    use crate::supervisor::Restart;
+    use std::{
+        collections::HashMap,
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+    use tokio::net::UnixListener;
+    use tokio_util::sync::CancellationToken;
+
+    /// Refresh well inside the item list TTL, like `opz daemon`.
+    const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+    pub const RESTART: Restart = Restart::OnFailure {
+        max: 5,
+        backoff: Duration::from_secs(1),
+    };
+
+    /// Service account scope, account and vault.
+    type Scope = (Option<String>, Option<String>, Option<String>);
+    /// Service account scope, account and item id.
+    type ItemKey = (Option<String>, Option<String>, String);
+
+    #[derive(Default)]
+    pub struct State {
+        lists: Mutex<HashMap<Scope, (Instant, Vec<ItemListEntry>)>>,
+        /// With the `updated_at` the details belong to.
+        items: Mutex<HashMap<ItemKey, (Option<String>, ItemGet)>>,
+    }
+
+    fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+        mutex
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    impl State {
+        fn item_list(&self, scope: Scope) -> Result<Vec<ItemListEntry>> {
+            if let Some((fetched, items)) = lock(&self.lists).get(&scope) {
+                if fetched.elapsed() < item_list_cache_ttl() {
+                    return Ok(items.clone());
+                }
+            }
+            self.refresh(scope)
+        }
+
+        fn refresh(&self, scope: Scope) -> Result<Vec<ItemListEntry>> {
+            let items = refresh_item_list_cache(scope.1.as_deref(), scope.2.as_deref())?;
+            lock(&self.lists).insert(scope, (Instant::now(), items.clone()));
+            Ok(items)
+        }
+
+        fn item_get(
+            &self,
+            scope: Option<String>,
+            account: Option<String>,
+            entry: &ItemListEntry,
+        ) -> Result<ItemGet> {
+            let key = (scope, account, entry.id.clone());
+            if let Some((updated_at, item)) = lock(&self.items).get(&key) {
+                if entry.updated_at.is_some() && *updated_at == entry.updated_at {
+                    return Ok(item.clone());
+                }
+            }
+            let policy = CachePolicy {
+                serve_stale: true,
+                offline: false,
+                refresh: false,
+            };
+            let item = redacted_item(&item_get_cached(key.1.as_deref(), entry, policy)?);
+            lock(&self.items).insert(key, (entry.updated_at.clone(), item.clone()));
+            Ok(item)
+        }
+    }
+
+    pub async fn refresh_loop(state: Arc<State>, token: CancellationToken) -> Result<()> {
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => return Ok(()),
+                _ = tokio::time::sleep(REFRESH_INTERVAL) => {}
+            }
+            let scopes: Vec<Scope> = lock(&state.lists).keys().cloned().collect();
+            for scope in scopes {
+                let state = Arc::clone(&state);
+                let result = tokio::task::spawn_blocking(move || state.refresh(scope)).await?;
+                if let Err(err) = result {
+                    tracing::warn!("failed to refresh item list: {err:#}");
+                }
+            }
+        }
+    }
+
+    pub async fn serve(
+        listener: Arc<UnixListener>,
+        state: Arc<State>,
+        token: CancellationToken,
+    ) -> Result<()> {
+        loop {
+            let stream = tokio::select! {
+                _ = token.cancelled() => return Ok(()),
+                accepted = listener.accept() => accepted.context("accept agent connection")?.0,
+            };
+            let stream = stream.into_std()?;
+            stream.set_nonblocking(false)?;
+            let state = Arc::clone(&state);
+            tokio::task::spawn_blocking(move || {
+                if let Err(err) = handle(stream, &state) {
+                    tracing::warn!("agent request failed: {err:#}");
+                }
+            });
+        }
+    }
+
+    fn handle(mut stream: UnixStream, state: &State) -> Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let response = match answer(state, &line) {
+            Ok(value) => Response::Ok(value),
+            Err(err) => Response::Error(format!("{err:#}")),
+        };
+        writeln!(stream, "{}", serde_json::to_string(&response)?)?;
+        Ok(())
+    }
+
+    fn answer(state: &State, line: &str) -> Result<serde_json::Value> {
+        let request: Request = serde_json::from_str(line).context("parse agent request")?;
+        let (Request::ItemList { scope, .. } | Request::ItemGet { scope, .. }) = &request;
+        check_scope(scope.as_deref(), service_account::cache_scope().as_deref())?;
+        Ok(match request {
+            Request::ItemList {
+                scope,
+                account,
+                vault,
+            } => serde_json::to_value(state.item_list((scope, account, vault))?)?,
+            Request::ItemGet {
+                scope,
+                account,
+                entry,
+            } => serde_json::to_value(state.item_get(scope, account, &entry)?)?,
+        })
+    }
+
+    /// The agent's `op` calls see what its own credentials see, which another token (or a
+    /// signed-in user instead of a token) may not.
+    fn check_scope(requested: Option<&str>, own: Option<&str>) -> Result<()> {
+        if requested == own {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "the agent runs with other 1Password credentials ({})",
+            own.unwrap_or("signed-in user")
+        ))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_check_scope() {
+            assert!(check_scope(None, None).is_ok());
+            assert!(check_scope(Some("sa-1"), Some("sa-1")).is_ok());
+            let err = check_scope(Some("sa-1"), None).unwrap_err().to_string();
+            assert!(err.contains("signed-in user"), "{err}");
+            assert!(check_scope(None, Some("sa-1")).is_err());
+            assert!(check_scope(Some("sa-2"), Some("sa-1")).is_err());
+        }
+
+        #[test]
+        fn test_requests_without_scope_still_parse() {
+            let request: Request =
+                serde_json::from_str(r#"{"op":"item_list","account":null,"vault":"Dev"}"#).unwrap();
+            assert!(matches!(
+                request,
+                Request::ItemList { scope: None, vault: Some(vault), .. } if vault == "Dev"
+            ));
+        }
+    }
+}
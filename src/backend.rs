@@ -0,0 +1,485 @@
+//! Ordered lookup backends for item lists and item details (`[backend] chain`): a 1Password
+//! Connect server, the `op` CLI, and opz's own caches. When a backend fails with an error class
+//! listed in `fallback_on`, the next one is tried, so lookups keep working through partial
+//! outages.
+
+pub use crate::config::{Backend, ErrorClass};
+use crate::{config::BackendConfig, parallel, runtime, telemetry_span};
+use anyhow::{anyhow, Context, Result};
+use opentelemetry::KeyValue;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chain {
+    backends: Vec<Backend>,
+    fallback_on: Vec<ErrorClass>,
+}
+
+impl Default for Chain {
+    fn default() -> Self {
+        Self {
+            backends: vec![Backend::Cli],
+            fallback_on: vec![ErrorClass::Unavailable],
+        }
+    }
+}
+
+impl Chain {
+    pub fn from_config(config: &BackendConfig) -> Self {
+        let default = Self::default();
+        Self {
+            backends: config
+                .chain
+                .clone()
+                .filter(|chain| !chain.is_empty())
+                .unwrap_or(default.backends),
+            fallback_on: config.fallback_on.clone().unwrap_or(default.fallback_on),
+        }
+    }
+}
+
+static CHAIN: OnceLock<Chain> = OnceLock::new();
+
+/// Set the process-wide chain from the loaded config; later calls are ignored.
+pub fn configure(chain: Chain) {
+    let _ = CHAIN.set(chain);
+}
+
+fn chain() -> &'static Chain {
+    CHAIN.get_or_init(Chain::default)
+}
+
+impl Backend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Connect => "connect",
+            Backend::Cli => "cli",
+            Backend::Cache => "cache",
+        }
+    }
+}
+
+/// A backend failure whose class is known without looking at the message.
+#[derive(Debug)]
+struct BackendError {
+    class: ErrorClass,
+    message: String,
+}
+
+pub fn error(class: ErrorClass, message: impl Into<String>) -> anyhow::Error {
+    anyhow::Error::new(BackendError {
+        class,
+        message: message.into(),
+    })
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+/// Classify a failure. `op` only reports errors as text, so its messages are matched on
+/// well-known phrases.
+pub fn classify(err: &anyhow::Error) -> ErrorClass {
+    if let Some(err) = err.chain().find_map(|e| e.downcast_ref::<BackendError>()) {
+        return err.class;
+    }
+    let message = format!("{err:#}").to_lowercase();
+    let has = |phrases: &[&str]| phrases.iter().any(|phrase| message.contains(phrase));
+    if has(&[
+        "not currently signed in",
+        "not signed in",
+        "session expired",
+        "invalid session",
+        "authorization",
+        "unauthorized",
+        "401",
+        "403",
+    ]) {
+        ErrorClass::Auth
+    } else if has(&[
+        "isn't an item",
+        "isn't a vault",
+        "not found",
+        "no item",
+        "404",
+    ]) {
+        ErrorClass::NotFound
+    } else if has(&[
+        "failed to run op",
+        "connection refused",
+        "connection reset",
+        "no such host",
+        "dial tcp",
+        "timed out",
+        "timeout",
+        "network",
+        "service unavailable",
+        "bad gateway",
+        "500",
+        "502",
+        "503",
+        "504",
+    ]) {
+        ErrorClass::Unavailable
+    } else {
+        ErrorClass::Other
+    }
+}
+
+/// Whether a failure is likely to go away on its own (network errors, timeouts, 5xx-like
+/// service errors), so the same call is worth repeating. A missing `op` binary is unavailable
+/// too, but not transient.
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    if err
+        .chain()
+        .filter_map(|e| e.downcast_ref::<std::io::Error>())
+        .any(|e| e.kind() == std::io::ErrorKind::NotFound)
+    {
+        return false;
+    }
+    let message = format!("{err:#}").to_lowercase();
+    [
+        "connection refused",
+        "connection reset",
+        "no such host",
+        "dial tcp",
+        "timed out",
+        "timeout",
+        "internal server error",
+        "service unavailable",
+        "bad gateway",
+        "gateway timeout",
+        "too many requests",
+        "(500)",
+        "(502)",
+        "(503)",
+        "(504)",
+        "(429)",
+    ]
+    .iter()
+    .any(|phrase| message.contains(phrase))
+}
+
+/// Run `attempt` against each configured backend in turn and return the first success along
+/// with the backend that produced it, which is also recorded on the current span.
+pub fn with_fallback<T>(
+    operation: &str,
+    attempt: impl FnMut(Backend) -> Result<T>,
+) -> Result<(T, Backend)> {
+    with_fallback_in(chain(), operation, attempt)
+}
+
+fn with_fallback_in<T>(
+    chain: &Chain,
+    operation: &str,
+    mut attempt: impl FnMut(Backend) -> Result<T>,
+) -> Result<(T, Backend)> {
+    let mut backends = chain.backends.iter().copied().peekable();
+    while let Some(backend) = backends.next() {
+        let result = telemetry_span::with_span_result(
+            "load_inputs.backend",
+            vec![
+                KeyValue::new("backend.name", backend.name()),
+                KeyValue::new("backend.operation", operation.to_string()),
+            ],
+            || attempt(backend),
+        );
+        let err = match result {
+            Ok(value) => {
+                tracing::debug!("{operation} served by the {} backend", backend.name());
+                telemetry_span::set_attribute(KeyValue::new("backend.name", backend.name()));
+                return Ok((value, backend));
+            }
+            Err(err) => err,
+        };
+        let Some(next) = backends.peek() else {
+            return Err(err);
+        };
+        if !chain.fallback_on.contains(&classify(&err)) {
+            return Err(err);
+        }
+        tracing::warn!(
+            "{} backend failed ({err:#}); trying {}",
+            backend.name(),
+            next.name()
+        );
+    }
+    Err(anyhow!("No backend configured for {operation}"))
+}
+
+fn connect_credentials() -> Result<(String, String)> {
+    let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    match (var("OP_CONNECT_HOST"), var("OP_CONNECT_TOKEN")) {
+        (Some(host), Some(token)) => Ok((host, token)),
+        _ => Err(error(
+            ErrorClass::Unavailable,
+            "Connect backend needs OP_CONNECT_HOST and OP_CONNECT_TOKEN",
+        )),
+    }
+}
+
+fn connect_get(path: &str) -> Result<Value> {
+    let (host, token) = connect_credentials()?;
+    let url = format!("{}/v1/{path}", host.trim_end_matches('/'));
+    let response = ureq::get(&url)
+        .set("Authorization", &format!("Bearer {token}"))
+        .timeout(CONNECT_TIMEOUT)
+        .call();
+    match response {
+        Ok(response) => response.into_json().context("parse Connect API response"),
+        Err(ureq::Error::Status(code, response)) => {
+            let class = match code {
+                401 | 403 => ErrorClass::Auth,
+                404 => ErrorClass::NotFound,
+                500.. => ErrorClass::Unavailable,
+                _ => ErrorClass::Other,
+            };
+            let message = response
+                .into_json::<Value>()
+                .ok()
+                .and_then(|body| body["message"].as_str().map(str::to_string))
+                .unwrap_or_default();
+            Err(error(
+                class,
+                format!("Connect API error ({code}): {message}"),
+            ))
+        }
+        Err(ureq::Error::Transport(err)) => Err(error(
+            ErrorClass::Unavailable,
+            format!("Connect API unreachable: {err}"),
+        )),
+    }
+}
+
+/// Item list in the shape of `op item list --format json`, across all vaults the Connect
+/// token can read, or only `vault` (matched by name or id).
+pub fn connect_item_list(vault: Option<&str>) -> Result<Value> {
+    let vaults = connect_get("vaults")?;
+    let vaults: Vec<&Value> = vaults
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|v| vault.is_none_or(|wanted| v["name"] == wanted || v["id"] == wanted))
+        .collect();
+    if let (Some(wanted), true) = (vault, vaults.is_empty()) {
+        return Err(error(
+            ErrorClass::NotFound,
+            format!("Vault '{wanted}' is not accessible via Connect"),
+        ));
+    }
+
+    let paths: Vec<String> = vaults
+        .iter()
+        .map(|v| format!("vaults/{}/items", v["id"].as_str().unwrap_or_default()))
+        .collect();
+    let listings = runtime::block_on(connect_get_all(paths))??;
+
+    let mut items = Vec::new();
+    for (v, listed) in vaults.into_iter().zip(listings) {
+        let vault_id = v["id"].as_str().unwrap_or_default();
+        items.extend(listed.as_array().into_iter().flatten().map(|item| {
+            json!({
+                "id": item["id"],
+                "title": item["title"],
+                "vault": { "id": vault_id, "name": v["name"] },
+                "updated_at": item["updatedAt"],
+            })
+        }));
+    }
+    Ok(Value::Array(items))
+}
+
+/// [`connect_get`] for each of `paths` on the shared runtime, [`parallel::MAX_WORKERS`] at a
+/// time; responses follow `paths`.
+async fn connect_get_all(paths: Vec<String>) -> Result<Vec<Value>> {
+    let permits = Arc::new(Semaphore::new(parallel::MAX_WORKERS));
+    let requests: Vec<_> = paths
+        .into_iter()
+        .map(|path| {
+            let permits = Arc::clone(&permits);
+            tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await?;
+                tokio::task::spawn_blocking(move || connect_get(&path)).await?
+            })
+        })
+        .collect();
+    let mut responses = Vec::with_capacity(requests.len());
+    for request in requests {
+        responses.push(request.await??);
+    }
+    Ok(responses)
+}
+
+/// Item details in the shape of `op item get --format json`, minus `vault`.
+pub fn connect_item_get(vault_id: &str, item_id: &str) -> Result<Value> {
+    let item = connect_get(&format!("vaults/{vault_id}/items/{item_id}"))?;
+    Ok(item_from_connect(&item))
+}
+
+fn item_from_connect(item: &Value) -> Value {
+    // Connect fields refer to their section by id only.
+    let sections: HashMap<&str, &Value> = item["sections"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|section| Some((section["id"].as_str()?, &section["label"])))
+        .collect();
+    let fields: Vec<Value> = item["fields"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|field| {
+            let section = field["section"]["id"]
+                .as_str()
+                .and_then(|id| sections.get(id))
+                .map(|label| json!({ "label": label }));
+            json!({
+                "label": field["label"],
+                "type": field["type"],
+                "value": field["value"],
+                "section": section,
+            })
+        })
+        .collect();
+    json!({
+        "fields": fields,
+        "tags": item["tags"].as_array().cloned().unwrap_or_default(),
+        "version": item["version"],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(backends: &[Backend]) -> Chain {
+        Chain {
+            backends: backends.to_vec(),
+            fallback_on: vec![ErrorClass::Unavailable],
+        }
+    }
+
+    #[test]
+    fn test_classify_op_messages_and_typed_errors() {
+        let class = |message: &str| classify(&anyhow!("{message}"));
+        assert_eq!(
+            class("op error (exit status: 1): [ERROR] You are not currently signed in."),
+            ErrorClass::Auth
+        );
+        assert_eq!(
+            class("op error (exit status: 1): \"x\" isn't an item."),
+            ErrorClass::NotFound
+        );
+        assert_eq!(
+            class("op error (exit status: 1): dial tcp: lookup my.1password.com: no such host"),
+            ErrorClass::Unavailable
+        );
+        assert_eq!(class("failed to parse op JSON output"), ErrorClass::Other);
+        assert_eq!(
+            classify(&error(ErrorClass::Auth, "nope").context("item list")),
+            ErrorClass::Auth
+        );
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let transient = |message: &str| is_transient(&anyhow!("{message}"));
+        assert!(transient(
+            "op error (exit status: 1): dial tcp: lookup my.1password.com: no such host"
+        ));
+        assert!(transient(
+            "op error (exit status: 1): [ERROR] (503) Service Unavailable"
+        ));
+        assert!(!transient(
+            "op error (exit status: 1): [ERROR] You are not currently signed in."
+        ));
+        assert!(!transient(
+            "op error (exit status: 1): \"x\" isn't an item."
+        ));
+        let missing = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .context("failed to run op item list");
+        assert!(!is_transient(&missing));
+    }
+
+    #[test]
+    fn test_with_fallback_moves_on_only_for_listed_classes() {
+        let chain = chain(&[Backend::Connect, Backend::Cli, Backend::Cache]);
+        let mut tried = Vec::new();
+        let (value, backend) = with_fallback_in(&chain, "item_list", |backend| {
+            tried.push(backend);
+            match backend {
+                Backend::Connect => Err(error(ErrorClass::Unavailable, "down")),
+                _ => Ok(backend.name()),
+            }
+        })
+        .unwrap();
+        assert_eq!((value, backend), ("cli", Backend::Cli));
+        assert_eq!(tried, vec![Backend::Connect, Backend::Cli]);
+
+        let err = with_fallback_in(&chain, "item_list", |backend| -> Result<()> {
+            assert_eq!(backend, Backend::Connect);
+            Err(error(ErrorClass::Auth, "bad token"))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "bad token");
+    }
+
+    #[test]
+    fn test_with_fallback_returns_last_error_when_chain_is_exhausted() {
+        let chain = chain(&[Backend::Cli, Backend::Cache]);
+        let err = with_fallback_in(&chain, "item_get", |backend| -> Result<()> {
+            Err(error(ErrorClass::Unavailable, backend.name()))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "cache");
+    }
+
+    #[test]
+    fn test_chain_from_config_defaults_to_cli() {
+        assert_eq!(
+            Chain::from_config(&BackendConfig::default()),
+            Chain::default()
+        );
+        let config = BackendConfig {
+            chain: Some(vec![Backend::Cli, Backend::Cache]),
+            fallback_on: None,
+        };
+        assert_eq!(
+            Chain::from_config(&config).backends,
+            vec![Backend::Cli, Backend::Cache]
+        );
+    }
+
+    #[test]
+    fn test_item_from_connect_resolves_section_labels() {
+        let item = json!({
+            "id": "abc",
+            "version": 4,
+            "tags": ["prod"],
+            "sections": [{ "id": "s1", "label": "Database" }],
+            "fields": [
+                { "label": "DB_HOST", "type": "STRING", "value": "db", "section": { "id": "s1" } },
+                { "label": "API_TOKEN", "type": "CONCEALED", "value": "t" }
+            ]
+        });
+        let converted = item_from_connect(&item);
+        assert_eq!(converted["version"], 4);
+        assert_eq!(converted["tags"], json!(["prod"]));
+        assert_eq!(converted["fields"][0]["section"]["label"], "Database");
+        assert_eq!(converted["fields"][1]["type"], "CONCEALED");
+        assert!(converted["fields"][1]["section"].is_null());
+    }
+}
@@ -0,0 +1,164 @@
+//! Timestamped copies of env files taken before opz changes them (`--backup`), so
+//! hand-maintained local overrides can be rolled back with `opz restore`.
+//!
+//! A backup of `.env` is `.env.bak.<UTC time>` next to it, written owner-only like the env
+//! file itself. The names sort by age; only the newest [`KEEP`] are kept.
+
+use crate::output;
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const INFIX: &str = ".bak.";
+
+/// Backups kept per file; older ones are removed when a new one is taken.
+const KEEP: usize = 5;
+
+/// Copy `path` to a new backup if it exists. Returns the backup's path.
+pub fn create(path: &Path) -> Result<Option<PathBuf>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("read {}", path.display())),
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let base = backup_path(path, &timestamp(now));
+    let mut backup = base.clone();
+    // Two backups within a second get a sequence number, which still sorts after the first.
+    let mut seq = 2;
+    while backup.exists() {
+        backup = PathBuf::from(format!("{}-{seq}", base.display()));
+        seq += 1;
+    }
+    output::write_file(&backup, &content)?;
+
+    let backups = list(path)?;
+    for old in &backups[..backups.len().saturating_sub(KEEP)] {
+        let _ = output::remove_file(old);
+    }
+    Ok(Some(backup))
+}
+
+/// Put the newest backup of `path` back in place and remove it, so restoring again goes one
+/// step further back. Returns the backup that was restored.
+pub fn restore(path: &Path) -> Result<PathBuf> {
+    let backup = list(path)?
+        .pop()
+        .ok_or_else(|| anyhow!("No backup of {} to restore", path.display()))?;
+    let content =
+        fs::read_to_string(&backup).with_context(|| format!("read {}", backup.display()))?;
+    output::write_file(path, &content)?;
+    output::remove_file(&backup).with_context(|| format!("remove {}", backup.display()))?;
+    Ok(backup)
+}
+
+/// Backups of `path`, oldest first.
+fn list(path: &Path) -> Result<Vec<PathBuf>> {
+    let prefix = backup_path(path, "");
+    let (Some(dir), Some(name)) = (prefix.parent(), prefix.file_name()) else {
+        return Ok(Vec::new());
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let name = name.to_string_lossy();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("read {}", dir.display())),
+    };
+    let mut backups: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .strip_prefix(name.as_ref())
+                .is_some_and(|stamp| !stamp.is_empty())
+        })
+        .map(|entry| prefix.with_file_name(entry.file_name()))
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
+
+fn backup_path(path: &Path, stamp: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(INFIX);
+    name.push(stamp);
+    PathBuf::from(name)
+}
+
+/// `secs` since the epoch as a compact UTC time, e.g. `20261016T153000Z`.
+fn timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0), "19700101T000000Z");
+        assert_eq!(timestamp(951_782_400), "20000229T000000Z");
+        assert_eq!(timestamp(1_792_164_759), "20261016T153239Z");
+    }
+
+    #[test]
+    fn test_create_and_restore() {
+        let tmp_dir = TempDir::new().unwrap();
+        let env = tmp_dir.path().join(".env");
+        assert_eq!(create(&env).unwrap(), None);
+
+        fs::write(&env, "LOCAL=1\n").unwrap();
+        let first = create(&env).unwrap().unwrap();
+        fs::write(&env, "LOCAL=2\n").unwrap();
+        let second = create(&env).unwrap().unwrap();
+        assert!(first < second);
+        fs::write(&env, "LOCAL=3\n").unwrap();
+
+        assert_eq!(restore(&env).unwrap(), second);
+        assert_eq!(fs::read_to_string(&env).unwrap(), "LOCAL=2\n");
+        assert_eq!(restore(&env).unwrap(), first);
+        assert_eq!(fs::read_to_string(&env).unwrap(), "LOCAL=1\n");
+        assert!(restore(&env).is_err());
+    }
+
+    #[test]
+    fn test_create_keeps_the_newest_backups() {
+        let tmp_dir = TempDir::new().unwrap();
+        let env = tmp_dir.path().join(".env");
+        fs::write(&env, "LOCAL=1\n").unwrap();
+        for _ in 0..KEEP + 2 {
+            create(&env).unwrap();
+        }
+        assert_eq!(list(&env).unwrap().len(), KEEP);
+    }
+}
@@ -0,0 +1,136 @@
+//! Cross-process safety for cache files: advisory locks around refreshes and atomic writes,
+//! so concurrent opz invocations neither duplicate `op` calls nor observe torn files.
+
+use crate::fault::{self, Fault};
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Give up waiting for another process after this long and proceed unlocked; a stuck `op`
+/// (e.g. waiting on an auth prompt) must not wedge every other invocation.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Exclusive advisory lock on `<cache file>.lock`, released on drop.
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    /// Wait (up to [`LOCK_TIMEOUT`]) for the lock guarding `cache_path`. Returns `None` on
+    /// timeout or when locking is unsupported, in which case callers proceed without it.
+    pub fn acquire(cache_path: &Path) -> Result<Option<Lock>> {
+        let started = Instant::now();
+        loop {
+            if let Some(lock) = Self::try_acquire(cache_path)? {
+                return Ok(Some(lock));
+            }
+            if started.elapsed() >= LOCK_TIMEOUT {
+                return Ok(None);
+            }
+            thread::sleep(LOCK_POLL_INTERVAL);
+        }
+    }
+
+    fn try_acquire(cache_path: &Path) -> Result<Option<Lock>> {
+        let path = cache_path.with_extension("lock");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("open {}", path.display()))?;
+
+        if try_lock_exclusive(&file)? {
+            Ok(Some(Lock { _file: file }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn try_lock_exclusive(file: &File) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: flock on a file descriptor we own; the lock is released when `file` is closed.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    if err.kind() == std::io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(err).context("lock cache file")
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock_exclusive(_file: &File) -> Result<bool> {
+    Ok(true)
+}
+
+/// Write a cache file with [`write_atomic`]. Readers treat anything that does not parse as a
+/// miss; `OPZ_FAULT_INJECT=corrupt-cache` writes a torn file to prove it.
+pub fn write_cache(path: &Path, bytes: &[u8]) -> Result<()> {
+    if fault::active(Fault::CorruptCache) {
+        return write_atomic(path, &bytes[..bytes.len() / 2]);
+    }
+    write_atomic(path, bytes)
+}
+
+/// Write via a temp file in the same directory and rename it into place, so readers see
+/// either the old or the new content, never a partial write.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let dir = path
+        .parent()
+        .with_context(|| format!("no parent directory for {}", path.display()))?;
+    fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("create temp file in {}", dir.display()))?;
+    tmp.write_all(bytes)?;
+    tmp.persist(path)
+        .with_context(|| format!("write {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lock_is_exclusive_until_dropped() {
+        let tmp = TempDir::new().unwrap();
+        let cache_path = tmp.path().join("item_list_x.json");
+
+        let held = Lock::try_acquire(&cache_path).unwrap();
+        assert!(held.is_some());
+        assert!(Lock::try_acquire(&cache_path).unwrap().is_none());
+
+        drop(held);
+        assert!(Lock::try_acquire(&cache_path).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_write_atomic_replaces_content_without_leftovers() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("cache").join("item_list_x.json");
+
+        write_atomic(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+}
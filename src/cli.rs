@@ -0,0 +1,10382 @@
+//! The `opz` command line: argument parsing, subcommands and output formatting on top of the
+//! library's lookup, caching and rendering.
+
+use crate::{
+    agent, backend, backup, cache_file,
+    client::{Format, Rendered, SkippedField},
+    config, daemon, error, fault, fifo, generated, gitignore, lockfile, logging, mask, op_version,
+    output, parallel, recent, run_dir, runtime, service_account, shutdown, signin, telemetry,
+    telemetry_span, watch,
+};
+use anyhow::{anyhow, Context, Result};
+use backend::Backend;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use directories::ProjectDirs;
+use opentelemetry::KeyValue;
+use output::{write_stdout, OutputSink};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    ffi::OsString,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+#[command(args_conflicts_with_subcommands = true)]
+#[command(disable_help_subcommand = true)]
+struct Cli {
+    /// Vault name (optional). If omitted, search all items and pick best match.
+    #[arg(long, global = true)]
+    vault: Option<String>,
+
+    /// 1Password account (sign-in address, ID, or user ID) passed to every `op` call.
+    #[arg(long, global = true)]
+    account: Option<String>,
+
+    /// Never answer from an expired item list cache; wait for `op` instead
+    #[arg(long, global = true)]
+    fresh: bool,
+
+    /// Never call `op`; answer only from cached item lists and details
+    #[arg(long, global = true, conflicts_with = "fresh")]
+    offline: bool,
+
+    /// Retry `op` calls that fail with network errors, timeouts or 5xx responses up to N times,
+    /// with exponential backoff
+    #[arg(long, global = true, value_name = "N", default_value_t = 0)]
+    retries: u32,
+
+    /// Kill an `op` call that has not finished after SECS seconds and report which one stalled
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    op_timeout: Option<u64>,
+
+    /// `op` executable to run instead of the one on PATH; defaults to $OPZ_OP_BIN
+    #[arg(long, global = true, value_name = "PATH")]
+    op_bin: Option<PathBuf>,
+
+    /// Hand `op run` its env file through a named pipe instead of a file in the run
+    /// directory, so nothing is written to disk (falls back to a file without FIFO support)
+    #[arg(long, global = true)]
+    pipe_env_file: bool,
+
+    /// Config profile to apply (e.g. dev, staging, prod); defaults to $OPZ_PROFILE
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Fail instead of warning when an item has moved past its pinned `item_version`
+    #[arg(long, global = true)]
+    strict_version: bool,
+
+    /// Resolve items only through the ids pinned in .opz.lock, and fail if an item is not
+    /// pinned or changed since `opz lock` (also never answers from an expired item list)
+    #[arg(long, global = true)]
+    frozen: bool,
+
+    /// Follow field values that are themselves `op://` references (shared sub-secrets) to
+    /// the secret they end at, instead of exporting the reference string
+    #[arg(long, global = true)]
+    resolve_nested: bool,
+
+    /// Remove env files left behind by interrupted runs instead of only warning about them
+    #[arg(long, global = true)]
+    auto_gc: bool,
+
+    /// Add the env file to the repository's .gitignore if git would not ignore it yet
+    #[arg(long, global = true)]
+    ensure_gitignore: bool,
+
+    /// Ignore cached item lists and details for this run (the caches are still updated)
+    #[arg(
+        long,
+        global = true,
+        visible_alias = "no-cache",
+        conflicts_with = "offline"
+    )]
+    refresh: bool,
+
+    /// Print JSON on stdout for informational commands (find, vaults, show, fields, diff, check,
+    /// verify-env, recent, whoami, cache status, help), and report errors as one JSON object
+    /// on stderr
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Log more on stderr: -v for progress, -vv for `op` calls, cache hits/misses and timings
+    /// ($OPZ_LOG takes a tracing filter instead)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Print only errors on stderr, no warnings
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Write trace spans as JSON lines to PATH (stderr without one) instead of sending them to
+    /// the OTLP endpoint
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        num_args = 0..=1,
+        require_equals = true
+    )]
+    trace_debug: Option<Option<PathBuf>>,
+
+    /// Export no traces, metrics or logs, whatever the OTEL_* variables and `[telemetry]`
+    /// say; also $OPZ_NO_TELEMETRY=1 or $OTEL_SDK_DISABLED=true
+    #[arg(long, global = true)]
+    no_telemetry: bool,
+
+    /// Output env file path, optionally with a format (`secrets.json:json`); repeat for
+    /// several files (none generated if omitted)
+    #[arg(
+        long,
+        value_name = "ENV[:FORMAT]",
+        value_parser = EnvFileArg::parse,
+        conflicts_with = "temp_env"
+    )]
+    env_file: Vec<EnvFileArg>,
+
+    #[command(flatten)]
+    run_opts: RunOptions,
+
+    /// Run with the items of the previous run (see `opz recent`)
+    #[arg(long, conflicts_with = "items")]
+    last: bool,
+
+    /// Also use the item whose website is on this URL's host, for items whose titles are
+    /// inconsistent (e.g. `--url https://api.stripe.com`); repeatable
+    #[arg(long, value_name = "URL", conflicts_with = "last")]
+    url: Vec<String>,
+
+    /// Time cold vs warm item list lookups (development aid).
+    #[arg(long, hide = true)]
+    bench_cache: bool,
+
+    #[command(subcommand)]
+    cmd: Option<Cmd>,
+
+    /// Item titles (when not using subcommand)
+    #[arg(value_name = "ITEM")]
+    items: Vec<String>,
+
+    /// Command to run (after --)
+    #[arg(last = true)]
+    command: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Find items by keyword (title contains)
+    Find {
+        /// Search every signed-in account and add an account column
+        #[arg(long, conflicts_with = "account")]
+        all_accounts: bool,
+
+        /// Find items with a website on this URL's host instead of by title
+        #[arg(long, value_name = "URL", conflicts_with = "query")]
+        url: Option<String>,
+
+        #[arg(required_unless_present = "url")]
+        query: Option<String>,
+    },
+
+    /// List vaults (cached for an hour)
+    Vaults,
+
+    /// Show valid env labels from 1Password items
+    Show {
+        /// Show item title header for each section
+        #[arg(long)]
+        with_item: bool,
+
+        /// Print a metadata card per item (vault, category, tags, sections, field types and
+        /// value lengths, updated time, version) instead of labels; values are never shown
+        #[arg(long, conflicts_with = "with_item")]
+        summary: bool,
+
+        /// Item titles
+        #[arg(value_name = "ITEM", num_args = 1..)]
+        items: Vec<String>,
+    },
+
+    /// List an item's fields with their type, section and the env var each would be exported
+    /// as (or why it is skipped); values are never shown
+    Fields {
+        /// Item title
+        item: String,
+    },
+
+    /// Generate env file only (do not run command). Keys already in an existing file are
+    /// replaced in place; new keys are appended.
+    Gen {
+        /// Output env file path, optionally with its own format (`secrets.json:json`); repeat
+        /// for several files (prints to stdout if omitted)
+        #[arg(long, value_name = "ENV[:FORMAT]", value_parser = EnvFileArg::parse)]
+        env_file: Vec<EnvFileArg>,
+
+        /// Append the generated lines to an existing env file instead of replacing the keys it
+        /// already has in place
+        #[arg(long, conflicts_with = "fd")]
+        append: bool,
+
+        /// Copy an existing env file to a timestamped backup before changing it (undo with
+        /// `opz restore`)
+        #[arg(long, conflicts_with = "fd")]
+        backup: bool,
+
+        /// Write to inherited file descriptor FD instead (e.g. `--fd 3 3>out.env`)
+        #[arg(
+            long,
+            value_name = "FD",
+            conflicts_with = "env_file",
+            value_parser = clap::value_parser!(i32).range(3..)
+        )]
+        fd: Option<i32>,
+
+        /// Output format (of stdout, --fd, and env files without a `:FORMAT`)
+        #[arg(long, value_enum, default_value_t = Format::Dotenv)]
+        format: Format,
+
+        /// Print dotenv lines with the secret values read, instead of references, to stdout
+        /// (e.g. to pipe into an importer); `file-only` fields stay references
+        #[arg(
+            long,
+            conflicts_with_all = ["env_file", "fd", "format", "append", "backup"]
+        )]
+        stdout: bool,
+
+        /// Item titles
+        #[arg(value_name = "ITEM", num_args = 1..)]
+        items: Vec<String>,
+    },
+
+    /// Write a dotenv file with its `op://` references replaced by the secret values, read in
+    /// one batch; other lines (comments included) are copied as they are
+    Resolve {
+        /// Dotenv file containing `op://` references
+        #[arg(long = "in", value_name = "ENV")]
+        input: PathBuf,
+
+        /// Output env file path (prints to stdout if omitted)
+        #[arg(long, value_name = "ENV")]
+        out: Option<PathBuf>,
+
+        /// Copy an existing output file to a timestamped backup before replacing it (undo with
+        /// `opz restore`)
+        #[arg(long, requires = "out")]
+        backup: bool,
+    },
+
+    /// Put back the newest backup `--backup` took of an env file (and remove that backup, so
+    /// restoring again goes one step further back)
+    Restore {
+        /// Env file to restore
+        #[arg(value_name = "ENV", default_value = ".env")]
+        env_file: PathBuf,
+    },
+
+    #[command(about = "Create a 1Password item from .env or private config file")]
+    Create {
+        #[arg(value_name = "ITEM", help = "Item title used when ENV is exactly .env")]
+        item: String,
+
+        #[arg(
+            value_name = "ENV",
+            help = "Source file path (defaults to .env). Non-.env creates Secure Note(s) named from git remotes."
+        )]
+        source_file: Option<PathBuf>,
+    },
+
+    /// Run command with secrets from 1Password item
+    Run {
+        /// Output env file path, optionally with a format (`secrets.json:json`); repeat for
+        /// several files (none generated if omitted)
+        #[arg(
+            long,
+            value_name = "ENV[:FORMAT]",
+            value_parser = EnvFileArg::parse,
+            conflicts_with = "temp_env"
+        )]
+        env_file: Vec<EnvFileArg>,
+
+        #[command(flatten)]
+        run_opts: RunOptions,
+
+        /// Item titles
+        #[arg(value_name = "ITEM", num_args = 1..)]
+        items: Vec<String>,
+
+        /// Command to run (after --)
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+
+    /// Run a command with secrets and restart it with freshly resolved ones when an item is
+    /// updated (e.g. a rotated password)
+    Watch {
+        /// Seconds between checks for item updates
+        #[arg(
+            long,
+            value_name = "SECS",
+            default_value_t = 60,
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        interval: u64,
+
+        /// Allow concealed fields to be substituted into command arguments (visible via `ps`)
+        #[arg(long)]
+        allow_argv_secrets: bool,
+
+        /// Item titles (defaults to the configured items)
+        #[arg(value_name = "ITEM")]
+        items: Vec<String>,
+
+        /// Command to run (after --)
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+
+    /// Explain where an env var comes from: item, field, section, `[labels]` mapping and
+    /// `[policies]`, and which candidates are overridden or skipped
+    ExplainEnv {
+        /// Env var name
+        var: String,
+
+        /// Item titles (defaults to the configured items)
+        #[arg(value_name = "ITEM")]
+        items: Vec<String>,
+    },
+
+    /// Check that the current credentials can see the items and read (or, with --write,
+    /// write) their vaults, without fetching any secret values
+    Preflight {
+        /// Also require write access to the items' vaults
+        #[arg(long)]
+        write: bool,
+
+        /// Item titles (defaults to the configured items)
+        #[arg(value_name = "ITEM")]
+        items: Vec<String>,
+    },
+
+    /// Compare an item with a local env file: keys only in one of them, and keys whose values
+    /// differ (redacted unless --show-values). Exits non-zero when they differ
+    Diff {
+        /// Print the differing values instead of redacting them
+        #[arg(long)]
+        show_values: bool,
+
+        /// Item title
+        item: String,
+
+        /// Env file to compare with
+        #[arg(value_name = "ENV", default_value = ".env")]
+        env_file: PathBuf,
+    },
+
+    /// Check that an item has every key a schema file (e.g. `.env.example`) lists, with a
+    /// non-empty value; exits non-zero if any is missing or empty, for CI
+    Check {
+        /// Dotenv file whose keys are required (values are ignored)
+        #[arg(long, value_name = "ENV", default_value = ".env.example")]
+        schema: PathBuf,
+
+        /// Also fail when the item has keys the schema does not list
+        #[arg(long)]
+        strict: bool,
+
+        /// Item title
+        item: String,
+    },
+
+    /// Confirm that this process's environment holds every env var of an item with the item's
+    /// value (compared by hash, never printed), e.g. inside a container or CI job; exits
+    /// non-zero on a missing or different variable
+    VerifyEnv {
+        /// Item title
+        item: String,
+    },
+
+    /// Pin items to their current id, vault and version in .opz.lock, so later runs resolve
+    /// exactly these items (checked with `--frozen`)
+    Lock {
+        /// Item titles (default: the items configured in .opz.toml)
+        items: Vec<String>,
+    },
+
+    /// List the items recent runs used, most recent first (reuse the last run's with
+    /// `opz --last -- <COMMAND>`)
+    Recent,
+
+    /// Show the signed-in 1Password account and how `op` is authenticated
+    Whoami,
+
+    /// Run a command in every workspace member with its own items
+    Workspace {
+        #[command(subcommand)]
+        action: WorkspaceCmd,
+    },
+
+    /// Share project configuration as a bundle file (no secrets)
+    Config {
+        #[command(subcommand)]
+        action: ConfigCmd,
+    },
+
+    /// Remove env files left behind by interrupted `--keep=none` runs, and run directories of
+    /// runs that died
+    Gc {
+        /// Only report what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Print a shell completion script (item titles complete from the cache)
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// Print cached item titles (or vault names) for shell completion; never calls `op`
+    #[command(name = "__complete-items", hide = true)]
+    CompleteItems {
+        #[arg(long)]
+        vaults: bool,
+    },
+
+    /// Print the opz(1) man page as roff
+    #[command(hide = true)]
+    Man {
+        /// Write opz.1 and one page per subcommand (opz-run.1, ...) into this directory
+        #[arg(long, value_name = "DIR")]
+        out_dir: Option<PathBuf>,
+    },
+
+    /// Serve item lookups from memory to other opz invocations over a Unix socket
+    Agent {
+        /// Socket to listen on; defaults to $OPZ_AGENT_SOCK or opz-agent-<uid>.sock in
+        /// $XDG_RUNTIME_DIR
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+    },
+
+    /// Keep the item list cache warm in the foreground
+    Daemon {
+        /// Serve health, cache freshness, and version as JSON on this loopback address
+        #[arg(long, value_name = "ADDR")]
+        http: Option<std::net::SocketAddr>,
+    },
+
+    /// Manage the item list cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheCmd,
+    },
+
+    /// Print help for opz or a subcommand
+    Help {
+        /// Subcommand path to describe (e.g. `run`)
+        #[arg(value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+}
+
+/// Options shared by `opz run` and the top-level shorthand.
+#[derive(Args, Debug, Default)]
+struct RunOptions {
+    /// Allow concealed fields to be substituted into command arguments (visible via `ps`)
+    #[arg(long)]
+    allow_argv_secrets: bool,
+
+    /// What to leave in --env-file once the command exits (no effect without --env-file)
+    #[arg(long, value_enum, default_value_t = KeepEnvFile::File)]
+    keep: KeepEnvFile,
+
+    /// Restore or remove --env-file as soon as the command has started (optionally MS
+    /// milliseconds later) instead of when it exits; implies --keep none
+    #[arg(
+        long,
+        value_name = "MS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0",
+        conflicts_with = "keep"
+    )]
+    eager_clear: Option<u64>,
+
+    /// Write the env file into a private per-run directory (under $XDG_RUNTIME_DIR) instead of
+    /// the working tree, pass its path as $OPZ_ENV_FILE, and delete it afterwards
+    #[arg(long, conflicts_with = "keep")]
+    temp_env: bool,
+
+    /// Append the generated lines to an existing env file instead of replacing the keys it
+    /// already has in place
+    #[arg(long, conflicts_with = "temp_env")]
+    append: bool,
+
+    /// Copy an existing env file to a timestamped backup before changing it (undo with
+    /// `opz restore`)
+    #[arg(long, conflicts_with = "temp_env")]
+    backup: bool,
+
+    /// Replace secret values (every resolved reference and --prompt value) in the command's
+    /// stdout/stderr with *** (the command then writes to pipes instead of the terminal)
+    #[arg(long)]
+    mask_output: bool,
+
+    /// Ask for FIELD on the terminal (hidden input) and pass it to the command's environment
+    /// only; repeat for several fields
+    #[arg(long, value_name = "FIELD")]
+    prompt: Vec<String>,
+
+    /// Also set KEY=VALUE (not a secret) in the command's environment, over an item field of
+    /// the same name; repeat for several variables
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_assignment)]
+    extra_env: Vec<(String, String)>,
+
+    /// Start the command with only PATH, HOME and TERM from opz's environment (plus --pass
+    /// variables) besides the secrets, for reproducible runs
+    #[arg(long)]
+    isolate: bool,
+
+    /// With --isolate, also pass VAR from opz's environment; repeat for several variables
+    #[arg(long, value_name = "VAR", requires = "isolate")]
+    pass: Vec<String>,
+}
+
+impl RunOptions {
+    fn env_merge(&self) -> EnvMerge {
+        EnvMerge::from_append(self.append)
+    }
+
+    /// The parent variables the command may see, or `None` to inherit all of them.
+    fn inherited_env(&self) -> Option<Vec<&str>> {
+        self.isolate.then(|| {
+            ISOLATED_ENV
+                .iter()
+                .copied()
+                .chain(self.pass.iter().map(String::as_str))
+                .collect()
+        })
+    }
+
+    fn keep(&self) -> KeepEnvFile {
+        if self.eager_clear.is_some() {
+            KeepEnvFile::None
+        } else {
+            self.keep
+        }
+    }
+}
+
+/// A `--env KEY=VALUE` argument.
+fn parse_env_assignment(value: &str) -> Result<(String, String), String> {
+    let Some((key, value)) = value.split_once('=') else {
+        return Err("expected KEY=VALUE".to_string());
+    };
+    let mut chars = key.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("'{key}' is not a valid environment variable name"));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// How generated lines go into an env file that already exists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EnvMerge {
+    /// Replace the lines of keys the file already has where they are, keeping comments and
+    /// other keys, and append only new keys.
+    Replace,
+    /// Append every generated line after the existing content (`--append`).
+    Append,
+}
+
+impl EnvMerge {
+    fn from_append(append: bool) -> Self {
+        if append {
+            EnvMerge::Append
+        } else {
+            EnvMerge::Replace
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum KeepEnvFile {
+    /// Keep the generated file as-is. It only holds `op://` references, so `refs` is accepted
+    /// as another name for it
+    #[default]
+    #[value(alias = "refs")]
+    File,
+    /// Restore the previous file (or remove it if opz created it)
+    None,
+}
+
+/// An `--env-file` value: a path with an optional `:FORMAT` suffix (`secrets.json:json`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct EnvFileArg {
+    path: PathBuf,
+    /// `None` for a bare path: dotenv for `run`, `--format` for `gen`.
+    format: Option<Format>,
+}
+
+impl EnvFileArg {
+    fn parse(value: &str) -> Result<Self, String> {
+        // Only a format name counts as a suffix, so paths that contain `:` keep working.
+        let (path, format) = match value.rsplit_once(':') {
+            Some((path, suffix)) => match <Format as ValueEnum>::from_str(suffix, true) {
+                Ok(format) => (path, Some(format)),
+                Err(_) => (value, None),
+            },
+            None => (value, None),
+        };
+        if path.is_empty() {
+            return Err(format!("no path before ':' in '{value}'"));
+        }
+        Ok(Self {
+            path: PathBuf::from(path),
+            format,
+        })
+    }
+
+    fn dotenv(path: PathBuf) -> Self {
+        Self { path, format: None }
+    }
+}
+
+/// The paths of `env_files` for telemetry, or `-` if there are none.
+fn env_files_label(env_files: &[EnvFileArg]) -> String {
+    if env_files.is_empty() {
+        return "-".to_string();
+    }
+    env_files
+        .iter()
+        .map(|file| file.path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Subcommand, Debug)]
+enum WorkspaceCmd {
+    /// Run the command in each `[[workspace.members]]` directory from `.opz.toml`
+    Run {
+        #[command(flatten)]
+        run_opts: RunOptions,
+
+        /// Command to run (after --)
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCmd {
+    /// Package the current project's opz config into a bundle
+    Export {
+        /// Bundle file to write
+        #[arg(long, value_name = "FILE")]
+        bundle: PathBuf,
+    },
+
+    /// Unpack a bundle into the current directory
+    Import {
+        /// Bundle file to read
+        #[arg(long, value_name = "FILE")]
+        bundle: PathBuf,
+
+        /// Overwrite existing files
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCmd {
+    /// Remove all cached item lists and item details
+    Clear,
+
+    /// Show per-vault cache age and size
+    Status,
+
+    /// Pre-populate caches (all vaults, or the listed ones)
+    Warm {
+        /// Only fetch caches that are not fresh (used for background refreshes)
+        #[arg(long, hide = true)]
+        if_stale: bool,
+
+        /// Vault names
+        #[arg(value_name = "VAULT")]
+        vaults: Vec<String>,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct ItemListEntry {
+    pub(crate) id: String,
+    pub(crate) title: String,
+    #[serde(default)]
+    pub(crate) vault: Option<ItemVault>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) updated_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    urls: Vec<ItemUrl>,
+}
+
+impl ItemListEntry {
+    /// Whether one of the item's websites is on `host` (see [`url_host`]).
+    fn has_host(&self, host: &str) -> bool {
+        self.urls
+            .iter()
+            .filter_map(|url| url_host(&url.href))
+            .any(|item_host| item_host.eq_ignore_ascii_case(host))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct ItemVault {
+    id: String,
+    pub(crate) name: String,
+}
+
+/// On-disk item list cache. The scope is stored so `opz cache status` can describe the file.
+#[derive(Deserialize, Serialize, Debug)]
+struct ItemListCache {
+    #[serde(default)]
+    account: Option<String>,
+    #[serde(default)]
+    vault: Option<String>,
+    items: Vec<ItemListEntry>,
+}
+
+const DEFAULT_ITEM_LIST_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Overrides `defaults.term_grace_secs`, for CI runners that set their own kill timeout.
+const TERM_GRACE_ENV: &str = "OPZ_TERM_GRACE_SECS";
+
+/// Cleanup grace period after SIGTERM, from `$OPZ_TERM_GRACE_SECS` or the config.
+fn term_grace_secs(config: &config::Config) -> Result<Option<u64>> {
+    match std::env::var(TERM_GRACE_ENV) {
+        Ok(value) if !value.trim().is_empty() => value
+            .trim()
+            .parse()
+            .map(Some)
+            .with_context(|| format!("{TERM_GRACE_ENV} must be a number of seconds: {value}")),
+        _ => Ok(config.defaults.term_grace_secs),
+    }
+}
+
+/// Item list TTL, overridable with `defaults.cache_ttl_secs`; fixed once the config is loaded.
+static ITEM_LIST_CACHE_TTL: OnceLock<Duration> = OnceLock::new();
+
+pub(crate) fn item_list_cache_ttl() -> Duration {
+    *ITEM_LIST_CACHE_TTL.get_or_init(|| DEFAULT_ITEM_LIST_CACHE_TTL)
+}
+
+/// Expired item lists younger than this are served while a background refresh runs.
+const ITEM_LIST_CACHE_MAX_STALE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How the item list cache may be used for one invocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CachePolicy {
+    pub(crate) serve_stale: bool,
+    /// Use caches of any age and fail instead of calling `op`.
+    pub(crate) offline: bool,
+    /// Skip reading caches, but still write what was fetched.
+    pub(crate) refresh: bool,
+}
+
+impl CachePolicy {
+    fn from_cli(cli: &Cli) -> Self {
+        Self {
+            serve_stale: !cli.fresh && !cli.frozen,
+            offline: cli.offline,
+            refresh: cli.refresh,
+        }
+    }
+}
+
+/// Process-wide settings for every `op` invocation, fixed once the CLI is parsed. Library
+/// callers get the defaults, which still honor `OPZ_OP_BIN`.
+#[derive(Debug)]
+struct OpSettings {
+    offline: bool,
+    /// How often a transiently failing `op` call is repeated.
+    retries: u32,
+    /// How long a non-interactive `op` call may run before it is killed.
+    timeout: Option<Duration>,
+    /// `--op-bin` / `OPZ_OP_BIN`; `None` runs `op` from PATH.
+    bin: Option<PathBuf>,
+    /// `--pipe-env-file`: feed `op run --env-file` through a FIFO.
+    pipe_env_file: bool,
+}
+
+impl Default for OpSettings {
+    fn default() -> Self {
+        OpSettings {
+            offline: false,
+            retries: 0,
+            timeout: None,
+            bin: op_bin_from_env(),
+            pipe_env_file: false,
+        }
+    }
+}
+
+fn op_bin_from_env() -> Option<PathBuf> {
+    std::env::var_os("OPZ_OP_BIN")
+        .map(PathBuf::from)
+        .filter(|path| !path.as_os_str().is_empty())
+}
+
+static OP_SETTINGS: OnceLock<OpSettings> = OnceLock::new();
+
+fn op_settings() -> &'static OpSettings {
+    OP_SETTINGS.get_or_init(OpSettings::default)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum CacheUse {
+    Fresh,
+    /// Serve the cached list and refresh it in the background.
+    Stale,
+    Refetch,
+}
+
+fn cache_use(age: Duration, policy: CachePolicy) -> CacheUse {
+    if policy.refresh {
+        CacheUse::Refetch
+    } else if age < item_list_cache_ttl() || policy.offline {
+        CacheUse::Fresh
+    } else if policy.serve_stale && age < ITEM_LIST_CACHE_MAX_STALE {
+        CacheUse::Stale
+    } else {
+        CacheUse::Refetch
+    }
+}
+
+/// On-disk `op item get` cache, valid while the item list still reports `updated_at`.
+/// Field values are redacted before writing; only labels, types, and tags are kept.
+#[derive(Deserialize, Serialize, Debug)]
+struct ItemGetCache {
+    updated_at: String,
+    item: ItemGet,
+}
+
+const ITEM_GET_CACHE_TTL: Duration = Duration::from_secs(300);
+/// Part of the item details cache file name, changed when cached details would read
+/// differently: `v2` keeps `op://` values, which older files redacted.
+const ITEM_GET_CACHE_FORMAT: &str = "v2_";
+const REDACTED_FIELD_VALUE: &str = "[redacted]";
+/// Tells a command started with `--temp-env` where its env file is.
+const TEMP_ENV_VAR: &str = "OPZ_ENV_FILE";
+
+#[derive(Deserialize, Serialize, Debug)]
+struct AccountListEntry {
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    account_uuid: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct WhoAmI {
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    user_type: String,
+    #[serde(default)]
+    account_uuid: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct ItemGet {
+    #[serde(default)]
+    pub(crate) fields: Vec<ItemField>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    vault: Option<ItemVault>,
+    #[serde(default)]
+    version: Option<u64>,
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default)]
+    updated_at: Option<String>,
+    #[serde(default)]
+    urls: Vec<ItemUrl>,
+}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ItemUrl {
+    #[serde(default)]
+    primary: bool,
+    #[serde(default)]
+    href: String,
+}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct ItemField {
+    #[serde(default)]
+    pub(crate) label: Option<String>,
+    #[serde(default, rename = "type")]
+    field_type: Option<String>,
+    #[serde(default)]
+    value: Option<serde_json::Value>,
+    #[serde(default)]
+    section: Option<ItemFieldSection>,
+}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ItemFieldSection {
+    #[serde(default)]
+    label: Option<String>,
+}
+
+/// Run the CLI with the process arguments; what the `opz` binary does.
+pub fn main() -> Result<()> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    // Before anything opens a file that could take the number.
+    if let Some(fd) = requested_fd(&args) {
+        output::claim_fd(fd);
+    }
+    let telemetry_config = startup_telemetry_config();
+    let telemetry_off = no_telemetry_requested(&args) || telemetry::disabled_by_env();
+    // The OTLP gRPC exporter needs a runtime from the start; otherwise it starts on first use.
+    let _runtime = if !telemetry_off && telemetry::exports_over_grpc(&telemetry_config) {
+        Some(
+            runtime::get()
+                .context("failed to start Tokio runtime for OTLP gRPC exporter")?
+                .enter(),
+        )
+    } else {
+        None
+    };
+    run_main(args, &telemetry_config, telemetry_off)
+}
+
+/// Telemetry starts before the CLI is parsed, so its config section is read up front.
+/// A broken config file is reported later by `run_cli`.
+fn startup_telemetry_config() -> config::TelemetryConfig {
+    std::env::current_dir()
+        .ok()
+        .and_then(|cwd| config::load(&cwd).ok())
+        .map(|config| config.telemetry)
+        .unwrap_or_default()
+}
+
+fn run_main(
+    args: Vec<OsString>,
+    telemetry_config: &config::TelemetryConfig,
+    telemetry_off: bool,
+) -> Result<()> {
+    let command_hint = detect_command_hint(&args).to_string();
+    telemetry_span::configure_redaction(telemetry_config);
+    // Off means no exporter, resource detection or `git` call at all.
+    let telemetry = if telemetry_off {
+        telemetry::TelemetryHandle::disabled()
+    } else {
+        telemetry::init(
+            &command_hint,
+            env!("CARGO_PKG_VERSION"),
+            telemetry_config,
+            span_dump_requested(&args),
+        )
+    };
+    let traces_enabled = telemetry.traces_enabled();
+    let _flush_telemetry = shutdown::register(shutdown::Stage::FlushTelemetry, move || {
+        telemetry.shutdown_best_effort()
+    });
+    install_panic_hook();
+    error::set_json_format(json_errors_requested(&args));
+
+    // Printed with the error, so a failure can be looked up in the tracing backend.
+    let mut failed_trace_id = None;
+    // Unwind out of the spans first so they end and get flushed by the shutdown sequence.
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _parent = traces_enabled.then(|| telemetry_span::parent_context_from_env().attach());
+        telemetry_span::with_span(
+            &format!("cli.{command_hint}"),
+            telemetry_span::build_cli_trace_attrs(&command_hint, &args),
+            || {
+                let started = Instant::now();
+                let result = run_cli(&args);
+                telemetry_span::record_run_summary();
+                telemetry::record_run(&command_hint, result.is_ok());
+                tracing::debug!(
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    ok = result.is_ok(),
+                    "{command_hint} finished"
+                );
+                if let Err(err) = &result {
+                    if !is_clap_display_error(err) {
+                        failed_trace_id = telemetry_span::current_trace_id();
+                        telemetry_span::record_error_message(&err.to_string());
+                        // Printed to stderr by the caller, not logged, so export it here.
+                        telemetry::emit_log(tracing::Level::ERROR, "opz", &format!("{err:#}"));
+                    }
+                }
+                result
+            },
+        )
+    }));
+
+    shutdown::run();
+    let Ok(result) = result else {
+        if BROKEN_PIPE_PANIC.load(std::sync::atomic::Ordering::SeqCst) {
+            std::process::exit(EXIT_BROKEN_PIPE);
+        }
+        std::process::exit(EXIT_PANIC);
+    };
+    match result {
+        Ok(()) => Ok(()),
+        // The reader went away (`opz find | head`); nobody is left to read an error.
+        Err(err) if is_broken_pipe(&err) => std::process::exit(EXIT_BROKEN_PIPE),
+        Err(err) if err.is::<ReportedInJson>() => std::process::exit(1),
+        Err(err) => {
+            let clap_err = err.downcast_ref::<clap::Error>();
+            let code = exit_code(&err);
+            if error::json_format() && !is_clap_display_error(&err) {
+                let mut json = error_json(&err);
+                if let Some(trace_id) = failed_trace_id {
+                    json["error"]["trace_id"] = trace_id.into();
+                }
+                // One line, so wrappers can take the last line of stderr after any warnings.
+                eprintln!("{json}");
+                std::process::exit(code);
+            }
+            if let Some(clap_err) = clap_err {
+                let _ = clap_err.print();
+                std::process::exit(code);
+            }
+            if let Some(typed) = err.downcast_ref::<error::Error>() {
+                if !typed.candidates().is_empty() {
+                    eprintln!("Ambiguous item title. Candidates:");
+                    for candidate in typed.candidates() {
+                        let vault = candidate.vault.as_deref().unwrap_or("-");
+                        eprintln!("  {}  [{}]  {}", candidate.id, vault, candidate.title);
+                    }
+                }
+            }
+            // What returning the error from `main` would print, with the class's status.
+            eprintln!("Error: {err:?}");
+            if let Some(trace_id) = failed_trace_id {
+                eprintln!("trace_id={trace_id}");
+            }
+            std::process::exit(code);
+        }
+    }
+}
+
+/// The command already described the failure in its JSON output.
+#[derive(Debug)]
+struct ReportedInJson;
+
+impl std::fmt::Display for ReportedInJson {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("failure reported in JSON output")
+    }
+}
+
+impl std::error::Error for ReportedInJson {}
+
+/// `--json` as seen before parsing, so argument errors can be reported as JSON too.
+fn json_requested(args: &[OsString]) -> bool {
+    args.iter()
+        .skip(1)
+        .take_while(|arg| *arg != "--")
+        .any(|arg| arg == "--json")
+}
+
+fn error_json(err: &anyhow::Error) -> serde_json::Value {
+    let message = match err.downcast_ref::<clap::Error>() {
+        // Drop clap's "error: " prefix, tips, and usage footer.
+        Some(clap_err) => clap_err
+            .to_string()
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join(" ")
+            .trim_start_matches("error: ")
+            .to_string(),
+        None => err.to_string(),
+    };
+    let causes: Vec<String> = err.chain().skip(1).map(ToString::to_string).collect();
+    let mut fields = error::json_fields(err);
+    fields.insert("message".into(), message.into());
+    fields.insert("causes".into(), causes.into());
+    fields.insert("exit_code".into(), exit_code(err).into());
+    serde_json::json!({ "error": fields })
+}
+
+fn exit_code(err: &anyhow::Error) -> i32 {
+    err.downcast_ref::<clap::Error>()
+        .map_or_else(|| error::exit_code(err), clap::Error::exit_code)
+}
+
+/// `--trace-debug[=PATH]` as seen before parsing, since telemetry starts first.
+fn span_dump_requested(args: &[OsString]) -> Option<telemetry::SpanDump> {
+    args.iter()
+        .skip(1)
+        .take_while(|arg| *arg != "--")
+        .find_map(|arg| {
+            let arg = arg.to_string_lossy();
+            if arg == "--trace-debug" {
+                Some(telemetry::SpanDump::Stderr)
+            } else {
+                arg.strip_prefix("--trace-debug=")
+                    .map(|path| telemetry::SpanDump::File(PathBuf::from(path)))
+            }
+        })
+}
+
+/// The `--fd N` / `--fd=N` value before `--`, if it parses.
+fn requested_fd(args: &[OsString]) -> Option<i32> {
+    let mut args = args.iter().skip(1).take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        let Some(arg) = arg.to_str() else {
+            continue;
+        };
+        if let Some(value) = arg.strip_prefix("--fd=") {
+            return value.parse().ok();
+        }
+        if arg == "--fd" {
+            return args.next()?.to_str()?.parse().ok();
+        }
+    }
+    None
+}
+
+/// `--no-telemetry` as seen before parsing, since telemetry starts first.
+fn no_telemetry_requested(args: &[OsString]) -> bool {
+    args.iter()
+        .skip(1)
+        .take_while(|arg| *arg != "--")
+        .any(|arg| arg == "--no-telemetry")
+}
+
+/// `--json` anywhere before `--`, or `OPZ_ERROR_FORMAT=json`.
+fn json_errors_requested(args: &[OsString]) -> bool {
+    json_requested(args) || std::env::var("OPZ_ERROR_FORMAT").is_ok_and(|format| format == "json")
+}
+
+/// Exit status for internal errors (EX_SOFTWARE), distinct from command failures.
+const EXIT_PANIC: i32 = 70;
+
+/// Exit status when stdout/stderr is a closed pipe: what a shell reports for a process
+/// killed by SIGPIPE (128 + 13).
+const EXIT_BROKEN_PIPE: i32 = 141;
+
+/// Set by the panic hook when `print!`-style output hit a closed pipe.
+static BROKEN_PIPE_PANIC: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|err| err.kind() == std::io::ErrorKind::BrokenPipe)
+    })
+}
+
+/// `print!`/`eprint!` panic with "failed printing to stdout: Broken pipe (os error 32)".
+fn is_broken_pipe_panic(message: &str) -> bool {
+    message.starts_with("failed printing to std") && message.contains("Broken pipe")
+}
+
+/// Replaces the default panic report, which could echo secret values, with a sanitized one.
+/// The env file is restored right away; telemetry is flushed once the panic has unwound.
+fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic payload".to_string());
+        if is_broken_pipe_panic(&message) {
+            // Not a bug, and reporting it could hit the same closed pipe.
+            BROKEN_PIPE_PANIC.store(true, std::sync::atomic::Ordering::SeqCst);
+            shutdown::run_through(shutdown::Stage::RestoreEnvFile);
+            return;
+        }
+        let location = info
+            .location()
+            .map(|location| format!("{}:{}", location.file(), location.line()));
+
+        telemetry_span::record_panic(&message, location.as_deref());
+        shutdown::run_through(shutdown::Stage::RestoreEnvFile);
+        eprint!(
+            "{}",
+            panic_report(
+                &message,
+                location.as_deref(),
+                telemetry_span::current_trace_id().as_deref()
+            )
+        );
+    }));
+}
+
+fn panic_report(message: &str, location: Option<&str>, trace_id: Option<&str>) -> String {
+    let mut out = format!(
+        "opz: internal error: {}",
+        telemetry_span::sanitize_for_trace(message)
+    );
+    if let Some(location) = location {
+        out.push_str(&format!(" ({location})"));
+    }
+    out.push('\n');
+    if let Some(trace_id) = trace_id {
+        out.push_str(&format!("trace_id={trace_id}\n"));
+    }
+    out.push_str("This is a bug in opz; please report it.\n");
+    out
+}
+
+fn run_cli(args: &[OsString]) -> Result<()> {
+    let mut cli = telemetry_span::with_span("parse_args", vec![], || {
+        let parse_result = Cli::try_parse_from(args);
+        if let Err(err) = &parse_result {
+            if err.exit_code() != 0 {
+                telemetry_span::record_error_message(&err.to_string());
+            }
+        }
+        parse_result
+    })?;
+    logging::init(cli.verbose, cli.quiet)?;
+    // The daemon shuts down through its supervisor instead.
+    if !matches!(cli.cmd, Some(Cmd::Daemon { .. } | Cmd::Agent { .. })) {
+        shutdown::install_signal_handlers();
+    }
+    let _ = OP_SETTINGS.set(OpSettings {
+        offline: cli.offline,
+        retries: cli.retries,
+        timeout: cli.op_timeout.map(Duration::from_secs),
+        bin: cli
+            .op_bin
+            .clone()
+            .filter(|path| !path.as_os_str().is_empty())
+            .or_else(op_bin_from_env),
+        pipe_env_file: cli.pipe_env_file,
+    });
+    let config = telemetry_span::with_span_result("load_config", vec![], || {
+        let _ = std::env::var_os("OPZ_TRACE_CAPTURE_ARGS");
+        let cwd = std::env::current_dir().context("resolve current directory")?;
+        let mut config = config::load(&cwd)?;
+        let profile = cli
+            .profile
+            .clone()
+            .or_else(|| std::env::var("OPZ_PROFILE").ok())
+            .filter(|name| !name.is_empty());
+        if let Some(profile) = profile {
+            config.activate_profile(&profile)?;
+        }
+        Ok(config)
+    })?;
+    apply_op_env(&mut cli, |name| std::env::var(name).ok());
+    if cli.last {
+        if cli.cmd.is_some() {
+            return Err(anyhow!(
+                "--last only applies to running a command. Usage: opz --last -- <COMMAND>..."
+            ));
+        }
+        cli.items = recent::last()?;
+        if cli.items.is_empty() {
+            return Err(anyhow!(
+                "No previous run to reuse; run `opz <ITEM>... -- <COMMAND>...` first"
+            ));
+        }
+    }
+    if !cli.url.is_empty() {
+        if cli.cmd.is_some() {
+            return Err(anyhow!(
+                "--url only applies to running a command. Usage: opz --url <URL> -- <COMMAND>..."
+            ));
+        }
+        for url in &cli.url {
+            url_host(url).ok_or_else(|| anyhow!("--url {url}: no host in the URL"))?;
+        }
+    }
+    apply_config_defaults(&mut cli, &config);
+    if let Some(secs) = config.defaults.cache_ttl_secs {
+        let _ = ITEM_LIST_CACHE_TTL.set(Duration::from_secs(secs));
+    }
+    if let Some(secs) = term_grace_secs(&config)? {
+        shutdown::set_grace_period(Duration::from_secs(secs));
+    }
+    backend::configure(backend::Chain::from_config(&config.backend));
+
+    if cli.bench_cache {
+        return bench_cache(&cli);
+    }
+    if !matches!(
+        cli.cmd,
+        Some(
+            Cmd::Gc { .. } | Cmd::Completions { .. } | Cmd::CompleteItems { .. } | Cmd::Man { .. }
+        )
+    ) {
+        check_leftover_env_files(cli.auto_gc);
+    }
+
+    match &cli.cmd {
+        Some(Cmd::Find {
+            all_accounts,
+            url,
+            query,
+        }) => {
+            let host = match url {
+                Some(url) => {
+                    Some(url_host(url).ok_or_else(|| anyhow!("--url {url}: no host in the URL"))?)
+                }
+                None => None,
+            };
+            // (account passed to `op`, label shown in the account column)
+            let accounts: Vec<(Option<String>, Option<String>)> = if *all_accounts {
+                telemetry_span::with_span_result("load_inputs.account_list", vec![], || {
+                    Ok(account_list(CachePolicy::from_cli(&cli))?
+                        .into_iter()
+                        .map(|account| {
+                            let label = if account.url.is_empty() {
+                                account.account_uuid.clone()
+                            } else {
+                                account.url
+                            };
+                            (Some(account.account_uuid), Some(label))
+                        })
+                        .collect())
+                })?
+            } else {
+                vec![(cli.account.clone(), None)]
+            };
+            let listings = telemetry_span::with_span_result(
+                "load_inputs",
+                vec![KeyValue::new("account.count", accounts.len() as i64)],
+                || {
+                    parallel::map(&accounts, |(account, label)| {
+                        let items = item_list_cached(
+                            account.as_deref(),
+                            cli.vault.as_deref(),
+                            CachePolicy::from_cli(&cli),
+                        )?;
+                        Ok((label.as_deref(), items))
+                    })
+                    .into_iter()
+                    .collect::<Result<Vec<_>>>()
+                },
+            )?;
+            let q = query.as_deref().unwrap_or_default().to_lowercase();
+            let matches = telemetry_span::with_span("main_operation", vec![], || {
+                listings
+                    .iter()
+                    .flat_map(|(label, items)| {
+                        items
+                            .iter()
+                            .filter(|x| match host {
+                                Some(host) => x.has_host(host),
+                                None => x.title.to_lowercase().contains(&q),
+                            })
+                            .map(|it| (it, *label))
+                    })
+                    .collect::<Vec<_>>()
+            });
+
+            telemetry_span::with_span_result("write_outputs", vec![], || {
+                if cli.json {
+                    print_json(&find_json(&matches))?;
+                    return Ok(());
+                }
+                let mut out = std::io::stdout().lock();
+                for (item, label) in &matches {
+                    writeln!(out, "{}", format_find_row(item, *label))?;
+                }
+                Ok(())
+            })
+        }
+        Some(Cmd::Show {
+            summary: true,
+            items,
+            ..
+        }) => show_item_summaries(&cli, items),
+        Some(Cmd::Show {
+            with_item, items, ..
+        }) => show_item_labels(&cli, &config, items, *with_item),
+        Some(Cmd::Fields { item }) => list_item_fields(&cli, &config, item),
+        Some(Cmd::ExplainEnv { var, items }) => explain_env(&cli, &config, var, items),
+        Some(Cmd::Preflight { write, items }) => preflight(&cli, items, *write),
+        Some(Cmd::Resolve { input, out, backup }) => {
+            resolve_env_file(&cli, input, &OutputSink::new(out.as_deref(), None), *backup)
+        }
+        Some(Cmd::Restore { env_file }) => {
+            let restored = telemetry_span::with_span_result("main_operation", vec![], || {
+                backup::restore(env_file)
+            })?;
+            eprintln!(
+                "Restored {} from {}",
+                env_file.display(),
+                restored.display()
+            );
+            Ok(())
+        }
+        Some(Cmd::Check {
+            schema,
+            strict,
+            item,
+        }) => check_item_schema(&cli, &config, item, schema, *strict),
+        Some(Cmd::VerifyEnv { item }) => verify_item_env(&cli, &config, item),
+        Some(Cmd::Lock { items }) => lock_items(&cli, items),
+        Some(Cmd::Diff {
+            show_values,
+            item,
+            env_file,
+        }) => diff_item_env(&cli, &config, item, env_file, *show_values),
+        Some(Cmd::Gen {
+            items,
+            env_file,
+            append,
+            backup,
+            fd,
+            format,
+            stdout,
+        }) => generate_env_output(
+            &cli,
+            &config,
+            items,
+            GenOutput {
+                env_files: env_file,
+                fd: *fd,
+                format: *format,
+                merge: EnvMerge::from_append(*append),
+                backup: *backup,
+                values: *stdout,
+            },
+        ),
+        Some(Cmd::Help { command }) => print_help(command, cli.json),
+        Some(Cmd::Vaults) => list_vaults(&cli),
+        Some(Cmd::Whoami) => show_whoami(&cli),
+        Some(Cmd::Cache { action }) => run_cache_command(&cli, action),
+        Some(Cmd::Config { action }) => run_config_command(action),
+        Some(Cmd::Gc { dry_run }) => {
+            let (results, run_dirs) =
+                telemetry_span::with_span_result("main_operation", vec![], || {
+                    Ok((generated::collect(*dry_run)?, run_dir::collect(*dry_run)?))
+                })?;
+            eprint!("{}", gc_report_string(&results, *dry_run));
+            eprint!("{}", gc_run_dirs_string(&run_dirs, *dry_run));
+            Ok(())
+        }
+        Some(Cmd::Completions { shell }) => write_stdout(&completion_script(*shell)),
+        Some(Cmd::CompleteItems { vaults }) => {
+            let mut out = std::io::stdout().lock();
+            for candidate in cached_completion_candidates(*vaults)? {
+                writeln!(out, "{candidate}")?;
+            }
+            Ok(())
+        }
+        Some(Cmd::Man { out_dir }) => match out_dir {
+            Some(dir) => {
+                fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+                clap_mangen::generate_to(Cli::command(), dir)
+                    .with_context(|| format!("write man pages to {}", dir.display()))?;
+                eprintln!("Wrote man pages to {}", dir.display());
+                Ok(())
+            }
+            None => {
+                let mut page = Vec::new();
+                clap_mangen::Man::new(Cli::command()).render(&mut page)?;
+                std::io::stdout().write_all(&page)?;
+                Ok(())
+            }
+        },
+        Some(Cmd::Recent) => list_recent(&cli),
+        Some(Cmd::Agent { socket }) => agent::run(socket.clone()),
+        Some(Cmd::Daemon { http }) => {
+            daemon::run(cli.account.as_deref(), cli.vault.as_deref(), *http)
+        }
+        Some(Cmd::Workspace {
+            action: WorkspaceCmd::Run { run_opts, command },
+        }) => run_workspace(&cli, &config, command, run_opts),
+        Some(Cmd::Create { item, source_file }) => {
+            let env_path = source_file.as_deref().unwrap_or_else(|| Path::new(".env"));
+            create_item_from_env(&cli, item, env_path)
+        }
+        Some(Cmd::Watch {
+            interval,
+            allow_argv_secrets,
+            items,
+            command,
+        }) => {
+            if items.is_empty() || command.is_empty() {
+                return Err(anyhow!(
+                    "Items and a command after '--' are required. Usage: opz watch [OPTIONS] <ITEM>... -- <COMMAND>..."
+                ));
+            }
+            watch_items(
+                &cli,
+                &config,
+                items,
+                command,
+                Duration::from_secs(*interval),
+                *allow_argv_secrets,
+            )
+        }
+        Some(Cmd::Run {
+            items,
+            env_file,
+            run_opts,
+            command,
+        }) => {
+            if command.is_empty() {
+                return Err(anyhow!(
+                    "Command required after '--'. Usage: opz run [OPTIONS] [--env-file <ENV>] <ITEM>... -- <COMMAND>..."
+                ));
+            }
+            run_with_items(&cli, &config, items, env_file, command, run_opts, None)
+        }
+        None => {
+            if cli.items.is_empty() && cli.url.is_empty() {
+                return Err(anyhow!(
+                    "At least one item title is required (or set `item` or a --profile in .opz.toml). Usage: opz [OPTIONS] [--env-file <ENV>] <ITEM>... -- <COMMAND>..."
+                ));
+            }
+
+            if cli.command.is_empty() {
+                return Err(anyhow!(
+                    "Command required after '--'. Usage: opz [OPTIONS] [--env-file <ENV>] <ITEM>... -- <COMMAND>..."
+                ));
+            }
+            run_with_items(
+                &cli,
+                &config,
+                &cli.items,
+                &cli.env_file,
+                &cli.command,
+                &cli.run_opts,
+                None,
+            )
+        }
+    }
+}
+
+/// Fill `--account`/`--vault` from the 1Password CLI's own `OP_ACCOUNT`/`OP_VAULT` when the
+/// flags are absent, ahead of config defaults.
+fn apply_op_env(cli: &mut Cli, var: impl Fn(&str) -> Option<String>) {
+    let var = |name| var(name).filter(|value| !value.is_empty());
+    if cli.account.is_none() {
+        cli.account = var("OP_ACCOUNT");
+    }
+    if cli.vault.is_none() {
+        cli.vault = var("OP_VAULT");
+    }
+}
+
+/// Fill what the command line left out from the config files: the project's `item`/`vault`
+/// mapping and `[defaults]`.
+fn apply_config_defaults(cli: &mut Cli, config: &config::Config) {
+    if cli.vault.is_none() {
+        cli.vault = config.vault.clone().or(config.defaults.vault.clone());
+    }
+    // `--url` picks the items of a run as well.
+    let urls = !cli.url.is_empty();
+    let (items, env_file) = match &mut cli.cmd {
+        Some(Cmd::Run {
+            items, env_file, ..
+        })
+        | Some(Cmd::Gen {
+            items, env_file, ..
+        }) => (items, Some(env_file)),
+        Some(Cmd::Show { items, .. })
+        | Some(Cmd::ExplainEnv { items, .. })
+        | Some(Cmd::Preflight { items, .. })
+        | Some(Cmd::Lock { items })
+        | Some(Cmd::Watch { items, .. }) => (items, None),
+        None => (&mut cli.items, Some(&mut cli.env_file)),
+        Some(_) => return,
+    };
+    if items.is_empty() && !urls {
+        items.extend(config.default_items());
+    }
+    if let (Some(env_file), Some(default_env_file)) = (env_file, &config.defaults.env_file) {
+        if env_file.is_empty() {
+            env_file.push(EnvFileArg::dotenv(default_env_file.clone()));
+        }
+    }
+}
+
+/// How `op` authenticates in this environment.
+#[derive(Debug, PartialEq, Eq)]
+enum AuthSource {
+    ServiceAccount,
+    SessionToken,
+    DesktopApp,
+    None,
+}
+
+impl AuthSource {
+    fn detect(signed_in: bool, service_account_token: bool, session_token: bool) -> Self {
+        if service_account_token {
+            AuthSource::ServiceAccount
+        } else if session_token {
+            AuthSource::SessionToken
+        } else if signed_in {
+            // Without a token in the environment, `op` can only be signed in through the app.
+            AuthSource::DesktopApp
+        } else {
+            AuthSource::None
+        }
+    }
+
+    /// Stable identifier for `--json` output.
+    fn key(&self) -> &'static str {
+        match self {
+            AuthSource::ServiceAccount => "service_account",
+            AuthSource::SessionToken => "session_token",
+            AuthSource::DesktopApp => "desktop_app",
+            AuthSource::None => "none",
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            AuthSource::ServiceAccount => "service account token (OP_SERVICE_ACCOUNT_TOKEN)",
+            AuthSource::SessionToken => "session token (OP_SESSION_*)",
+            AuthSource::DesktopApp => "1Password desktop app integration",
+            AuthSource::None => "none",
+        }
+    }
+}
+
+fn show_whoami(cli: &Cli) -> Result<()> {
+    let (whoami, accounts) = telemetry_span::with_span("load_inputs", vec![], || {
+        let whoami = op_json(cli.account.as_deref(), &["whoami", "--format", "json"])
+            .and_then(|v| Ok(serde_json::from_value::<WhoAmI>(v)?));
+        let accounts = account_list(CachePolicy::from_cli(cli));
+        (whoami, accounts)
+    });
+
+    let service_account_token = service_account::active();
+    let session_token =
+        std::env::vars_os().any(|(key, _)| key.to_string_lossy().starts_with("OP_SESSION_"));
+    let auth = AuthSource::detect(whoami.is_ok(), service_account_token, session_token);
+
+    if cli.json {
+        let report = whoami_json(
+            whoami.as_ref().ok(),
+            accounts.as_deref().unwrap_or_default(),
+            service_account_token,
+            &auth,
+        );
+        telemetry_span::with_span_result("write_outputs", vec![], || print_json(&report))?;
+        // The report already says `signed_in: false`; only the exit status is left.
+        return whoami
+            .map(|_| ())
+            .map_err(|_| anyhow::Error::new(ReportedInJson));
+    }
+
+    let report = telemetry_span::with_span("main_operation", vec![], || {
+        whoami_report(
+            whoami.as_ref().ok(),
+            accounts.as_deref().unwrap_or_default(),
+            service_account_token,
+            &auth,
+        )
+    });
+    telemetry_span::with_span_result("write_outputs", vec![], || write_stdout(&report))?;
+
+    whoami
+        .map(|_| ())
+        .map_err(|err| anyhow!("Not signed in to 1Password: {err}"))
+}
+
+/// Vault permissions `opz preflight` requires of the current credentials.
+const PREFLIGHT_READ: &str = "read_items";
+const PREFLIGHT_WRITE: &str = "write_items";
+
+/// One `opz preflight` check and its outcome.
+#[derive(Serialize, Debug)]
+struct PreflightCheck {
+    name: String,
+    ok: bool,
+    detail: String,
+}
+
+#[derive(Serialize, Debug)]
+struct PreflightReport {
+    item: String,
+    checks: Vec<PreflightCheck>,
+}
+
+impl PreflightReport {
+    fn ok(&self) -> bool {
+        self.checks.iter().all(|check| check.ok)
+    }
+}
+
+/// Verify access with `op item list` and `op vault list --permission`, which return metadata
+/// only; `op item get` is never called, so no secret value leaves 1Password. The lists are
+/// fetched live, bypassing opz's caches and backend chain, since the point is to test the
+/// credentials a later step will use.
+fn preflight(cli: &Cli, items: &[String], write: bool) -> Result<()> {
+    if items.is_empty() {
+        return Err(anyhow!(
+            "At least one item title is required (or set `item` or a --profile in .opz.toml). Usage: opz preflight [--write] [ITEM]..."
+        ));
+    }
+    let account = cli.account.as_deref();
+    let permissions: &[&str] = if write {
+        &[PREFLIGHT_READ, PREFLIGHT_WRITE]
+    } else {
+        &[PREFLIGHT_READ]
+    };
+    let reports = telemetry_span::with_span(
+        "load_inputs",
+        vec![KeyValue::new("item.count", items.len() as i64)],
+        || {
+            let listed = op_item_list(account, cli.vault.as_deref());
+            let granted: Vec<(&str, Result<Vec<ItemVault>>)> = permissions
+                .iter()
+                .map(|permission| {
+                    let vaults = op_json(
+                        account,
+                        &[
+                            "vault",
+                            "list",
+                            "--permission",
+                            permission,
+                            "--format",
+                            "json",
+                        ],
+                    )
+                    .and_then(|v| Ok(serde_json::from_value(v)?));
+                    (*permission, vaults)
+                })
+                .collect();
+            items
+                .iter()
+                .map(|item| preflight_report(item, &listed, &granted))
+                .collect::<Vec<_>>()
+        },
+    );
+    let failed: Vec<&str> = reports
+        .iter()
+        .filter(|report| !report.ok())
+        .map(|report| report.item.as_str())
+        .collect();
+
+    if cli.json {
+        let report = serde_json::json!({ "ok": failed.is_empty(), "items": reports });
+        telemetry_span::with_span_result("write_outputs", vec![], || print_json(&report))?;
+        if !failed.is_empty() {
+            return Err(anyhow::Error::new(ReportedInJson));
+        }
+        return Ok(());
+    }
+    telemetry_span::with_span_result("write_outputs", vec![], || {
+        write_stdout(&preflight_output_string(&reports))
+    })?;
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Preflight failed for: {}", failed.join(", ")))
+    }
+}
+
+fn preflight_report(
+    item_title: &str,
+    listed: &Result<Vec<ItemListEntry>>,
+    granted: &[(&str, Result<Vec<ItemVault>>)],
+) -> PreflightReport {
+    let failure = |err: &anyhow::Error| format!("{err:#}").trim_end().to_string();
+    let mut checks = Vec::new();
+    let entry = match listed
+        .as_ref()
+        .map_err(failure)
+        .and_then(|items| match_item_entry(items, item_title).map_err(|err| failure(&err)))
+    {
+        Ok(entry) => entry,
+        Err(detail) => {
+            checks.push(PreflightCheck {
+                name: "item".to_string(),
+                ok: false,
+                detail,
+            });
+            return PreflightReport {
+                item: item_title.to_string(),
+                checks,
+            };
+        }
+    };
+    let vault = entry.vault.as_ref();
+    let vault_name = vault.map_or("-", |v| v.name.as_str());
+    checks.push(PreflightCheck {
+        name: "item".to_string(),
+        ok: true,
+        detail: format!("'{}' ({}) in vault {vault_name}", entry.title, entry.id),
+    });
+    for (permission, vaults) in granted {
+        let (ok, detail) = match vaults {
+            Err(err) => (
+                false,
+                format!("could not list vaults with {permission}: {}", failure(err)),
+            ),
+            Ok(vaults) if vault.is_some_and(|v| vaults.iter().any(|g| g.id == v.id)) => {
+                (true, format!("vault {vault_name} grants {permission}"))
+            }
+            Ok(_) => (
+                false,
+                format!("vault {vault_name} does not grant {permission} to these credentials"),
+            ),
+        };
+        checks.push(PreflightCheck {
+            name: permission.to_string(),
+            ok,
+            detail,
+        });
+    }
+    PreflightReport {
+        item: entry.title.clone(),
+        checks,
+    }
+}
+
+fn preflight_output_string(reports: &[PreflightReport]) -> String {
+    let width = reports
+        .iter()
+        .flat_map(|report| &report.checks)
+        .map(|check| check.name.len())
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    for report in reports {
+        out.push_str(&report.item);
+        out.push('\n');
+        for check in &report.checks {
+            let status = if check.ok { "ok" } else { "FAIL" };
+            out.push_str(&format!(
+                "  {status:<4}  {:<width$}  {}\n",
+                check.name, check.detail
+            ));
+        }
+    }
+    out
+}
+
+fn whoami_json(
+    whoami: Option<&WhoAmI>,
+    accounts: &[AccountListEntry],
+    service_account_token: bool,
+    auth: &AuthSource,
+) -> serde_json::Value {
+    let accounts: Vec<serde_json::Value> = accounts
+        .iter()
+        .map(|account| {
+            serde_json::json!({
+                "uuid": account.account_uuid,
+                "url": account.url,
+                "email": account.email,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "signed_in": whoami.is_some(),
+        "account": whoami.map(|me| serde_json::json!({ "uuid": me.account_uuid, "url": me.url })),
+        "user": whoami.map(|me| serde_json::json!({ "email": me.email, "type": me.user_type })),
+        "service_account_token": service_account_token,
+        "desktop_app": *auth == AuthSource::DesktopApp,
+        "auth": auth.key(),
+        "accounts": accounts,
+    })
+}
+
+fn whoami_report(
+    whoami: Option<&WhoAmI>,
+    accounts: &[AccountListEntry],
+    service_account_token: bool,
+    auth: &AuthSource,
+) -> String {
+    let mut out = String::new();
+    match whoami {
+        Some(me) => {
+            out.push_str("Signed in:             yes\n");
+            out.push_str(&format!(
+                "Account:               {} ({})\n",
+                me.url, me.account_uuid
+            ));
+            out.push_str(&format!(
+                "User:                  {} [{}]\n",
+                me.email, me.user_type
+            ));
+        }
+        None => out.push_str("Signed in:             no\n"),
+    }
+    out.push_str(&format!(
+        "Service account token: {}\n",
+        if service_account_token {
+            "set"
+        } else {
+            "not set"
+        }
+    ));
+    out.push_str(&format!(
+        "Desktop app:           {}\n",
+        if *auth == AuthSource::DesktopApp {
+            "integration in use"
+        } else {
+            "not in use"
+        }
+    ));
+    out.push_str(&format!("Authenticated via:     {}\n", auth.describe()));
+    out.push_str(&format!("Configured accounts:   {}\n", accounts.len()));
+    for account in accounts {
+        out.push_str(&format!(
+            "  {}  {}  {}\n",
+            account.account_uuid, account.url, account.email
+        ));
+    }
+    out
+}
+
+fn print_help(path: &[String], json: bool) -> Result<()> {
+    let mut root = Cli::command();
+    root.build();
+    let mut cmd = &root;
+    for name in path {
+        cmd = cmd
+            .find_subcommand(name)
+            .ok_or_else(|| anyhow!("unknown subcommand: {name}"))?;
+    }
+
+    if json {
+        print_json(&command_tree_json(cmd))?;
+    } else {
+        write_stdout(&cmd.clone().render_help().to_string())?;
+    }
+    Ok(())
+}
+
+/// Self-describing command/flag tree for external tools (wrappers, docs generators, IDEs).
+fn command_tree_json(cmd: &clap::Command) -> serde_json::Value {
+    let args: Vec<serde_json::Value> = cmd
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .map(|arg| {
+            serde_json::json!({
+                "id": arg.get_id().as_str(),
+                "long": arg.get_long(),
+                "short": arg.get_short().map(String::from),
+                "positional": arg.is_positional(),
+                "help": arg.get_help().map(ToString::to_string),
+                "required": arg.is_required_set(),
+                "global": arg.is_global_set(),
+                "takes_value": arg.get_action().takes_values(),
+                "multiple": arg
+                    .get_num_args()
+                    .is_some_and(|range| range.max_values() > 1),
+                "value_names": arg
+                    .get_value_names()
+                    .map(|names| names.iter().map(ToString::to_string).collect::<Vec<_>>())
+                    .unwrap_or_default(),
+                "default_values": arg
+                    .get_default_values()
+                    .iter()
+                    .map(|value| value.to_string_lossy().to_string())
+                    .collect::<Vec<_>>(),
+                "possible_values": arg
+                    .get_possible_values()
+                    .iter()
+                    .map(|value| value.get_name().to_string())
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    let subcommands: Vec<serde_json::Value> = cmd
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .map(command_tree_json)
+        .collect();
+
+    serde_json::json!({
+        "name": cmd.get_name(),
+        "version": cmd.get_version(),
+        "about": cmd.get_about().map(ToString::to_string),
+        "args": args,
+        "subcommands": subcommands,
+    })
+}
+
+fn find_json(matches: &[(&ItemListEntry, Option<&str>)]) -> serde_json::Value {
+    let items: Vec<serde_json::Value> = matches
+        .iter()
+        .map(|(item, account)| {
+            serde_json::json!({
+                "id": item.id,
+                "title": item.title,
+                "vault": item.vault,
+                "account": account,
+            })
+        })
+        .collect();
+    serde_json::json!({ "items": items })
+}
+
+fn list_recent(cli: &Cli) -> Result<()> {
+    let entries = telemetry_span::with_span_result("load_inputs", vec![], recent::entries)?;
+    telemetry_span::with_span_result("write_outputs", vec![], || {
+        if cli.json {
+            return print_json(&serde_json::json!({ "items": entries }));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut out = std::io::stdout().lock();
+        for entry in &entries {
+            writeln!(out, "{}", format_recent_row(entry, now))?;
+        }
+        Ok(())
+    })
+}
+
+fn format_recent_row(entry: &recent::Entry, now: u64) -> String {
+    let age = format_age(Duration::from_secs(now.saturating_sub(entry.used_at_unix)));
+    format!("{}\t{}\t{age} ago", entry.id, entry.title)
+}
+
+fn list_vaults(cli: &Cli) -> Result<()> {
+    let account = cli.account.as_deref();
+    let vaults = telemetry_span::with_span_result("load_inputs", vec![], || {
+        vault_list(account, CachePolicy::from_cli(cli))
+    })?;
+    telemetry_span::with_span_result("write_outputs", vec![], || {
+        if cli.json {
+            return print_json(&serde_json::json!({ "vaults": vaults }));
+        }
+        let mut out = std::io::stdout().lock();
+        for vault in &vaults {
+            writeln!(out, "{}\t{}", vault.id, vault.name)?;
+        }
+        Ok(())
+    })
+}
+
+fn print_json(value: &serde_json::Value) -> Result<()> {
+    write_stdout(&format!("{}\n", serde_json::to_string_pretty(value)?))
+}
+
+fn format_find_row(item: &ItemListEntry, account: Option<&str>) -> String {
+    let vault = item.vault.as_ref().map(|v| v.name.as_str()).unwrap_or("-");
+    match account {
+        Some(account) => format!("{}\t{}\t{}\t{}", item.id, account, vault, item.title),
+        None => format!("{}\t{}\t{}", item.id, vault, item.title),
+    }
+}
+
+fn is_clap_display_error(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<clap::Error>()
+        .is_some_and(|clap_err| clap_err.exit_code() == 0)
+}
+
+fn detect_command_hint(args: &[OsString]) -> &'static str {
+    let mut idx = 1;
+    while idx < args.len() {
+        let arg = args[idx].to_string_lossy();
+
+        if arg == "--" {
+            return "run";
+        }
+        if arg == "--help" || arg == "-h" {
+            return "help";
+        }
+        if arg == "--version" || arg == "-V" {
+            return "version";
+        }
+
+        if arg == "--vault" || arg == "--account" || arg == "--env-file" {
+            idx += 2;
+            continue;
+        }
+        if arg.starts_with("--vault=")
+            || arg.starts_with("--account=")
+            || arg.starts_with("--env-file=")
+        {
+            idx += 1;
+            continue;
+        }
+        if arg.starts_with("--") {
+            idx += 1;
+            continue;
+        }
+
+        return match arg.as_ref() {
+            "find" => "find",
+            "vaults" => "vaults",
+            "show" => "show",
+            "fields" => "fields",
+            "gen" => "gen",
+            "create" => "create",
+            "run" => "run",
+            "help" => "help",
+            "whoami" => "whoami",
+            "preflight" => "preflight",
+            "diff" => "diff",
+            "check" => "check",
+            "verify-env" => "verify_env",
+            "lock" => "lock",
+            "recent" => "recent",
+            "resolve" => "resolve",
+            "restore" => "restore",
+            "watch" => "watch",
+            "cache" => "cache",
+            "workspace" => "workspace",
+            "config" => "config",
+            "daemon" => "daemon",
+            "agent" => "agent",
+            "gc" => "gc",
+            "completions" => "completions",
+            "man" => "man",
+            "__complete-items" => "complete_items",
+            _ => "run",
+        };
+    }
+
+    "run"
+}
+
+/// Resolved item title and its env lines.
+type EnvSection = (String, Vec<String>);
+
+pub(crate) struct CollectedEnv {
+    pub(crate) sections: Vec<EnvSection>,
+    /// Env keys whose winning field is concealed.
+    concealed_keys: HashSet<String>,
+    /// Resolved item title and its tags.
+    item_tags: Vec<(String, Vec<String>)>,
+    /// Item id of each section.
+    item_ids: Vec<String>,
+    /// Where each env key's winning field came from, for `[output]` grouping.
+    pub(crate) key_origins: HashMap<String, KeyOrigin>,
+    /// `[policies]` of each env key's winning field.
+    key_policies: HashMap<String, BTreeSet<config::FieldPolicy>>,
+    /// Labelled fields that produced no env line.
+    pub(crate) skipped: Vec<SkippedField>,
+    /// Digest of each concealed value the item details carried, keyed by env key.
+    value_digests: HashMap<String, [u8; 32]>,
+    /// Plain values for the command's environment (see `item_plain_env`).
+    plain_env: Vec<(String, String)>,
+}
+
+pub(crate) struct KeyOrigin {
+    /// Index into `sections`.
+    item: usize,
+    /// Label of the 1Password section holding the field.
+    section: Option<String>,
+}
+
+fn collect_item_env_sections(
+    cli: &Cli,
+    config: &config::Config,
+    items: &[String],
+) -> Result<CollectedEnv> {
+    let mut found = find_items_locked(cli, items)?;
+    found.extend(find_items_by_url(cli)?);
+    for (item_title, (_, _, resolved_title, item)) in items.iter().zip(&found) {
+        if config.item.as_deref() == Some(item_title.as_str()) {
+            check_item_version(
+                config.item_version,
+                resolved_title,
+                item,
+                cli.strict_version,
+            )?;
+        }
+    }
+    let nested = if cli.resolve_nested {
+        nested_references(&found)
+    } else {
+        HashMap::new()
+    };
+    let mut collected = collect_env(config, found)?;
+    if !nested.is_empty() {
+        follow_nested_references(cli, &mut collected.sections, &nested)?;
+    }
+    confirm_production_fields(&config.guard, &collected)?;
+    Ok(collected)
+}
+
+/// How many references `--resolve-nested` follows from one field before giving up.
+const MAX_NESTED_REFERENCES: usize = 8;
+
+/// Field references whose value is itself an `op://` reference, mapped to that value.
+fn nested_references(found: &[(String, String, String, ItemGet)]) -> HashMap<String, String> {
+    found
+        .iter()
+        .flat_map(|(item_id, vault_id, _, item)| {
+            item.fields.iter().filter_map(move |field| {
+                let label = field.label.as_deref()?;
+                let value = field.value.as_ref()?.as_str()?.trim();
+                is_op_reference(value).then(|| {
+                    (
+                        format!("op://{vault_id}/{item_id}/{label}"),
+                        value.to_string(),
+                    )
+                })
+            })
+        })
+        .collect()
+}
+
+/// Point env lines whose field holds a reference at the reference the chain ends at, so `op`
+/// reads the shared secret rather than the reference string.
+fn follow_nested_references(
+    cli: &Cli,
+    sections: &mut [EnvSection],
+    nested: &HashMap<String, String>,
+) -> Result<()> {
+    let account = cli.account.as_deref();
+    let policy = CachePolicy::from_cli(cli);
+    for (_, lines) in sections.iter_mut() {
+        for line in lines.iter_mut() {
+            let Some((key, reference)) = line.split_once('=') else {
+                continue;
+            };
+            let Some(target) = nested.get(reference) else {
+                continue;
+            };
+            let end = follow_reference_chain(reference, target, |r| {
+                referenced_reference(account, policy, r)
+            })
+            .with_context(|| format!("--resolve-nested: {key}"))?;
+            *line = format!("{key}={end}");
+        }
+    }
+    Ok(())
+}
+
+/// The `op://` reference the field at `reference` holds, or `None` if it holds anything else.
+/// The field is looked up in the item details like any item's (so from the redacted cache
+/// where possible), not read with `op read`: the secret at the end of the chain is never read.
+fn referenced_reference(
+    account: Option<&str>,
+    policy: CachePolicy,
+    reference: &str,
+) -> Result<Option<String>> {
+    let path = reference
+        .strip_prefix("op://")
+        .ok_or_else(|| anyhow!("not an op:// reference: {reference}"))?;
+    let parts: Vec<&str> = path.split('/').collect();
+    let (vault, item, section, field) = match parts[..] {
+        [vault, item, field] => (vault, item, None, field),
+        [vault, item, section, field] => (vault, item, Some(section), field),
+        _ => return Err(anyhow!("unsupported reference: {reference}")),
+    };
+    let entries = item_list_cached(account, Some(vault), policy)?;
+    let entry = entries
+        .iter()
+        .find(|entry| entry.id == item)
+        .map_or_else(|| match_item_entry(&entries, item), Ok)?;
+    let details = item_get_cached(account, entry, policy)?;
+    let found = details
+        .fields
+        .iter()
+        .find(|f| {
+            f.label.as_deref() == Some(field)
+                && section.is_none_or(|section| {
+                    f.section.as_ref().and_then(|s| s.label.as_deref()) == Some(section)
+                })
+        })
+        .ok_or_else(|| anyhow!("no field {field} in {reference}"))?;
+    Ok(found
+        .value
+        .as_ref()
+        .and_then(|value| value.as_str())
+        .map(str::trim)
+        .filter(|value| is_op_reference(value))
+        .map(String::from))
+}
+
+/// The last reference of the chain `start` -> `next` -> ..., asking `next_of` for the reference
+/// each one's field holds, if any.
+fn follow_reference_chain(
+    start: &str,
+    next: &str,
+    next_of: impl Fn(&str) -> Result<Option<String>>,
+) -> Result<String> {
+    let mut seen = vec![start.to_string()];
+    let mut current = next.to_string();
+    loop {
+        if seen.contains(&current) {
+            seen.push(current);
+            return Err(anyhow!("reference cycle: {}", seen.join(" -> ")));
+        }
+        if seen.len() > MAX_NESTED_REFERENCES {
+            return Err(anyhow!(
+                "more than {MAX_NESTED_REFERENCES} nested references from {start}"
+            ));
+        }
+        let Some(value) = next_of(&current)? else {
+            return Ok(current);
+        };
+        seen.push(std::mem::replace(&mut current, value));
+    }
+}
+
+/// Env lines and per-key metadata of items already looked up, in order (later items win).
+pub(crate) fn collect_env(
+    config: &config::Config,
+    found: Vec<(String, String, String, ItemGet)>,
+) -> Result<CollectedEnv> {
+    let mut collected = CollectedEnv {
+        sections: Vec::with_capacity(found.len()),
+        concealed_keys: HashSet::new(),
+        item_tags: Vec::with_capacity(found.len()),
+        item_ids: Vec::with_capacity(found.len()),
+        key_origins: HashMap::new(),
+        key_policies: HashMap::new(),
+        skipped: Vec::new(),
+        value_digests: HashMap::new(),
+        plain_env: Vec::new(),
+    };
+
+    for (item_id, vault_id, resolved_title, item) in found {
+        let env_lines = item_to_env_lines(&item, &vault_id, &item_id, config)?;
+        // Later items win on duplicate keys, so their concealment wins too.
+        for key in env_lines.iter().filter_map(|line| parse_env_key(line)) {
+            collected.concealed_keys.remove(key);
+            collected.value_digests.remove(key);
+            collected.plain_env.retain(|(plain, _)| plain != key);
+        }
+        for (key, value) in item_plain_env(&item, config) {
+            collected.plain_env.retain(|(plain, _)| *plain != key);
+            collected.plain_env.push((key, value));
+        }
+        collected
+            .concealed_keys
+            .extend(item_concealed_keys(&item, config)?);
+        collected
+            .value_digests
+            .extend(concealed_value_digests(&item, config)?);
+        for field in &item.fields {
+            let Some(label) = field.label.as_deref() else {
+                continue;
+            };
+            let Some(key) = field_env_key(config, &item, field) else {
+                continue;
+            };
+            collected
+                .key_policies
+                .insert(key.clone(), config.field_policies(label));
+            let section = field.section.as_ref().and_then(|s| s.label.clone());
+            collected.key_origins.insert(
+                key,
+                KeyOrigin {
+                    item: collected.sections.len(),
+                    section,
+                },
+            );
+        }
+        collected
+            .skipped
+            .extend(
+                item_skipped_fields(&item, config)
+                    .into_iter()
+                    .map(|(label, reason)| SkippedField {
+                        item: resolved_title.clone(),
+                        label,
+                        reason,
+                    }),
+            );
+        collected
+            .item_tags
+            .push((resolved_title.clone(), item.tags.clone()));
+        collected.item_ids.push(item_id);
+        collected.sections.push((resolved_title, env_lines));
+    }
+
+    Ok(collected)
+}
+
+/// Keys whose winning field carries `policy`, sorted.
+fn keys_with_policy(collected: &CollectedEnv, policy: config::FieldPolicy) -> Vec<&str> {
+    let mut keys: Vec<&str> = collected
+        .key_policies
+        .iter()
+        .filter(|(_, policies)| policies.contains(&policy))
+        .map(|(key, _)| key.as_str())
+        .collect();
+    keys.sort_unstable();
+    keys
+}
+
+/// `prod-confirm` fields taken from production-tagged items need a yes on the terminal.
+fn confirm_production_fields(guard: &config::GuardConfig, collected: &CollectedEnv) -> Result<()> {
+    let keys: Vec<&str> = keys_with_policy(collected, config::FieldPolicy::ProdConfirm)
+        .into_iter()
+        .filter(|key| {
+            collected.key_origins.get(*key).is_some_and(|origin| {
+                collected.item_tags[origin.item]
+                    .1
+                    .iter()
+                    .any(|tag| guard.is_production_tag(tag))
+            })
+        })
+        .collect();
+    if keys.is_empty() {
+        return Ok(());
+    }
+    let names = keys.join(", ");
+    service_account::ensure_interactive(&format!(
+        "Production field(s) {names} (prod-confirm policy)"
+    ))?;
+    let question = format!("Use production field(s) {names} (prod-confirm policy)?");
+    if confirm_on_tty(&question).with_context(|| {
+        format!(
+            "Production field(s) {names} need confirmation (prod-confirm policy), but there is no terminal to ask on"
+        )
+    })? {
+        Ok(())
+    } else {
+        Err(anyhow!("Aborted: production field(s) {names} not confirmed"))
+    }
+}
+
+/// Ask a yes/no question on the controlling terminal; anything but `y`/`yes` is a no.
+fn confirm_on_tty(question: &str) -> Result<bool> {
+    let mut tty = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")?;
+    write!(tty, "{question} [y/N] ")?;
+    let mut answer = String::new();
+    std::io::BufRead::read_line(&mut std::io::BufReader::new(&tty), &mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Digest of each exported concealed value, keyed by env key. Cached item details carry only a
+/// redaction marker and are skipped, so this only sees values fresh from `op`; `run` checks
+/// the values it resolved instead.
+fn concealed_value_digests(
+    item: &ItemGet,
+    config: &config::Config,
+) -> Result<Vec<(String, [u8; 32])>> {
+    let concealed: HashSet<String> = item_concealed_keys(item, config)?.into_iter().collect();
+    Ok(item
+        .fields
+        .iter()
+        .filter_map(|f| {
+            let key = field_env_key(config, item, f)?;
+            let value = f.value.as_ref()?.as_str()?;
+            if !concealed.contains(&key) || value.is_empty() || value == REDACTED_FIELD_VALUE {
+                return None;
+            }
+            Some((key, Sha256::digest(value.as_bytes()).into()))
+        })
+        .collect())
+}
+
+/// Digest of each resolved concealed value, keyed by env key.
+fn resolved_value_digests(
+    env_vars: &HashMap<String, String>,
+    concealed_keys: &HashSet<String>,
+) -> HashMap<String, [u8; 32]> {
+    env_vars
+        .iter()
+        .filter(|(key, value)| concealed_keys.contains(*key) && !value.is_empty())
+        .map(|(key, value)| (key.clone(), Sha256::digest(value.as_bytes()).into()))
+        .collect()
+}
+
+fn warn_duplicate_values(digests: &HashMap<String, [u8; 32]>) {
+    for keys in duplicate_value_keys(digests) {
+        tracing::warn!(
+            "{} have the same secret value; check the item for a mis-filled field.",
+            keys.join(", ")
+        );
+    }
+}
+
+/// Groups of env keys (sorted) that share one value.
+fn duplicate_value_keys(digests: &HashMap<String, [u8; 32]>) -> Vec<Vec<String>> {
+    let mut by_digest: HashMap<&[u8; 32], Vec<String>> = HashMap::new();
+    for (key, digest) in digests {
+        by_digest.entry(digest).or_default().push(key.clone());
+    }
+    let mut groups: Vec<Vec<String>> = by_digest
+        .into_values()
+        .filter(|keys| keys.len() > 1)
+        .map(|mut keys| {
+            keys.sort();
+            keys
+        })
+        .collect();
+    groups.sort();
+    groups
+}
+
+/// Compare the project's mapped item against its pinned `item_version`, so secret changes get
+/// reviewed like any other config change.
+fn check_item_version(
+    pinned: Option<u64>,
+    title: &str,
+    item: &ItemGet,
+    strict: bool,
+) -> Result<()> {
+    let (Some(pinned), Some(version)) = (pinned, item.version) else {
+        return Ok(());
+    };
+    if version <= pinned {
+        return Ok(());
+    }
+    let message = format!(
+        "Item '{title}' is at version {version}, past item_version = {pinned} pinned in {}. Review the changes in 1Password, then bump the pin.",
+        config::PROJECT_CONFIG_FILE
+    );
+    if strict {
+        return Err(anyhow!("{message}"));
+    }
+    tracing::warn!("{message}");
+    Ok(())
+}
+
+fn collect_item_label_sections(
+    cli: &Cli,
+    config: &config::Config,
+    items: &[String],
+) -> Result<Vec<(String, Vec<String>)>> {
+    let mut sections = Vec::with_capacity(items.len());
+    let found = find_items(
+        cli.account.as_deref(),
+        cli.vault.as_deref(),
+        CachePolicy::from_cli(cli),
+        items,
+    )?;
+
+    for (_, _, resolved_title, item) in found {
+        let labels = item_to_valid_labels(&item, config)?;
+        sections.push((resolved_title, labels));
+    }
+
+    Ok(sections)
+}
+
+pub(crate) fn merge_env_lines(sections: &[(String, Vec<String>)]) -> Vec<String> {
+    let mut merged_lines: Vec<String> = Vec::new();
+    let mut key_positions: HashMap<String, usize> = HashMap::new();
+
+    for (_, lines) in sections {
+        for line in lines {
+            if let Some(key) = parse_env_key(line) {
+                if let Some(&idx) = key_positions.get(key) {
+                    merged_lines[idx] = line.clone();
+                } else {
+                    key_positions.insert(key.to_string(), merged_lines.len());
+                    merged_lines.push(line.clone());
+                }
+            }
+        }
+    }
+
+    merged_lines
+}
+
+/// Arrange merged env lines per `[output]`: listed keys first, the rest by item, section,
+/// or name, with optional blank-line or comment separators between groups.
+pub(crate) fn layout_env_lines(
+    merged_lines: &[String],
+    sections: &[EnvSection],
+    key_origins: &HashMap<String, KeyOrigin>,
+    output: &config::OutputConfig,
+) -> Vec<String> {
+    let keyed: Vec<(&str, &String)> = merged_lines
+        .iter()
+        .filter_map(|line| parse_env_key(line).map(|key| (key, line)))
+        .collect();
+
+    // (group header, line); consecutive lines with the same header form a group.
+    let mut grouped: Vec<(Option<String>, &String)> = output
+        .keys
+        .iter()
+        .filter_map(|wanted| keyed.iter().find(|(key, _)| key == wanted))
+        .map(|(_, line)| (None, *line))
+        .collect();
+    let mut rest: Vec<(&str, &String)> = keyed
+        .iter()
+        .filter(|(key, _)| !output.keys.iter().any(|wanted| wanted == key))
+        .copied()
+        .collect();
+
+    let origin = |key: &str| key_origins.get(key);
+    match output.order.unwrap_or_default() {
+        config::OutputOrder::Item => {
+            rest.sort_by_key(|(key, _)| origin(key).map_or(0, |o| o.item));
+            grouped.extend(rest.iter().map(|(key, line)| {
+                let title = origin(key).and_then(|o| sections.get(o.item));
+                (title.map(|(title, _)| format!("item: {title}")), *line)
+            }));
+        }
+        config::OutputOrder::Section => {
+            let section_of = |key: &str| origin(key).and_then(|o| o.section.clone());
+            let mut order: Vec<Option<String>> = Vec::new();
+            for (key, _) in &rest {
+                let section = section_of(key);
+                if !order.contains(&section) {
+                    order.push(section);
+                }
+            }
+            rest.sort_by_key(|(key, _)| {
+                let section = section_of(key);
+                order.iter().position(|s| *s == section)
+            });
+            grouped.extend(rest.iter().map(|(key, line)| {
+                let section = section_of(key).unwrap_or_else(|| "(none)".to_string());
+                (Some(format!("section: {section}")), *line)
+            }));
+        }
+        config::OutputOrder::Alphabetical => {
+            rest.sort_by_key(|(key, _)| *key);
+            grouped.extend(rest.iter().map(|(key, line)| {
+                let prefix = key.split('_').next().unwrap_or(key);
+                (Some(prefix.to_string()), *line)
+            }));
+        }
+    }
+
+    let separators = output.separators.unwrap_or_default();
+    let mut out = Vec::with_capacity(grouped.len());
+    let mut current: Option<&Option<String>> = None;
+    for (header, line) in &grouped {
+        if current != Some(header) {
+            if current.is_some() && separators != config::Separators::None {
+                out.push(String::new());
+            }
+            if let (config::Separators::Comment, Some(header)) = (separators, header) {
+                out.push(format!("# --- {header} ---"));
+            }
+            current = Some(header);
+        }
+        out.push((*line).clone());
+    }
+    out
+}
+
+fn resolve_env_vars(
+    account: Option<&str>,
+    env_lines: &[String],
+    run_dir: &mut run_dir::RunDir,
+) -> Result<HashMap<String, String>> {
+    let references: Vec<(String, String)> = env_lines
+        .iter()
+        .filter_map(|line| {
+            parse_env_line_kv(line).map(|(key, reference)| (key.to_string(), reference.to_string()))
+        })
+        .collect();
+    if references.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    if let Ok(env_vars) = resolve_env_vars_batch(account, &references, run_dir) {
+        return Ok(env_vars);
+    }
+
+    // Fallback path for environments where batch resolution is unavailable.
+    let mut env_vars: HashMap<String, String> = HashMap::with_capacity(references.len());
+    for line in env_lines {
+        if let Some((key, reference)) = parse_env_line_kv(line) {
+            let value = op_read(account, reference)?;
+            env_vars.insert(key.to_string(), value);
+        }
+    }
+
+    Ok(env_vars)
+}
+
+fn resolve_env_vars_batch(
+    account: Option<&str>,
+    references: &[(String, String)],
+    run_dir: &mut run_dir::RunDir,
+) -> Result<HashMap<String, String>> {
+    telemetry_span::with_span_result(
+        "load_inputs.op_run_batch_resolve",
+        vec![KeyValue::new(
+            "env.reference_count",
+            references.len() as i64,
+        )],
+        || {
+            let batch_file = run_dir.file("op-run.env")?;
+            let lines: Vec<String> = references
+                .iter()
+                .map(|(key, reference)| format!("{key}={reference}"))
+                .collect();
+            let content = env_lines_string(&lines);
+
+            // Built first: a pipe nobody will read is left to `Feed::finish` to unblock.
+            let mut cmd = op_command(account)?;
+            let feed = if op_settings().pipe_env_file {
+                fifo::feed(&batch_file, content.clone())
+                    .inspect_err(|err| {
+                        tracing::debug!("writing the op run env file instead: {err:#}")
+                    })
+                    .ok()
+            } else {
+                None
+            };
+            if feed.is_none() {
+                output::write_file(&batch_file, &content)?;
+            }
+            cmd.arg("run")
+                .arg("--no-masking")
+                .arg("--env-file")
+                .arg(&batch_file)
+                .arg("--")
+                .arg("sh")
+                .arg("-c")
+                .arg("env -0");
+            let op_args = logging::sanitize_op_args(cmd.get_args());
+            let out = run_op(cmd);
+            if let Some(feed) = feed {
+                feed.finish();
+            }
+            let _ = output::remove_file(&batch_file);
+            let out = out.context(
+                error::Error::new(
+                    error::Kind::OpFailed,
+                    "failed to run `op run` for batch secret resolution",
+                )
+                .with_op_args(op_args.clone()),
+            )?;
+
+            if !out.status.success() {
+                let stderr = String::from_utf8_lossy(&out.stderr);
+                return Err(error::Error::new(
+                    op_error_kind(&stderr),
+                    format!("op run failed: {stderr}"),
+                )
+                .with_op_args(op_args)
+                .into());
+            }
+
+            let wanted_keys: std::collections::HashSet<&str> =
+                references.iter().map(|(key, _)| key.as_str()).collect();
+            let mut env_vars = HashMap::with_capacity(references.len());
+            for record in out.stdout.split(|b| *b == b'\0') {
+                if record.is_empty() {
+                    continue;
+                }
+                let kv = String::from_utf8_lossy(record);
+                let Some((key, value)) = kv.split_once('=') else {
+                    continue;
+                };
+                if wanted_keys.contains(key) {
+                    env_vars.insert(key.to_string(), value.to_string());
+                }
+            }
+
+            if env_vars.len() != references.len() {
+                return Err(anyhow!(
+                    "batch resolution was incomplete ({}/{})",
+                    env_vars.len(),
+                    references.len()
+                ));
+            }
+
+            Ok(env_vars)
+        },
+    )
+}
+
+fn sectioned_env_output_string(sections: &[(String, Vec<String>)]) -> String {
+    let mut out = String::new();
+    for (idx, (title, lines)) in sections.iter().enumerate() {
+        if idx > 0 {
+            out.push('\n');
+        }
+        out.push_str(&format!("# --- item: {} ---\n", title));
+        for line in lines {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn show_item_labels(
+    cli: &Cli,
+    config: &config::Config,
+    items: &[String],
+    with_item: bool,
+) -> Result<()> {
+    let sections = telemetry_span::with_span_result(
+        "load_inputs",
+        vec![KeyValue::new("item.count", items.len() as i64)],
+        || collect_item_label_sections(cli, config, items),
+    )?;
+    if cli.json {
+        let items: Vec<serde_json::Value> = sections
+            .iter()
+            .map(|(title, labels)| serde_json::json!({ "title": title, "labels": labels }))
+            .collect();
+        return telemetry_span::with_span_result("write_outputs", vec![], || {
+            print_json(&serde_json::json!({ "items": items }))
+        });
+    }
+    let rendered = telemetry_span::with_span("main_operation", vec![], || {
+        show_output_string(&sections, with_item)
+    });
+    telemetry_span::with_span_result("write_outputs", vec![], || write_stdout(&rendered))
+}
+
+/// Value-free overview of an item for `opz show --summary`.
+#[derive(Serialize, Debug)]
+struct ItemSummary {
+    title: String,
+    id: String,
+    vault: Option<String>,
+    category: Option<String>,
+    tags: Vec<String>,
+    sections: Vec<String>,
+    fields: Vec<FieldSummary>,
+    updated_at: Option<String>,
+    version: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+struct FieldSummary {
+    label: Option<String>,
+    #[serde(rename = "type")]
+    field_type: Option<String>,
+    section: Option<String>,
+    /// Characters in the value; `None` when only the redacted cache copy was available.
+    value_length: Option<usize>,
+}
+
+fn show_item_summaries(cli: &Cli, items: &[String]) -> Result<()> {
+    let summaries = telemetry_span::with_span_result(
+        "load_inputs",
+        vec![KeyValue::new("item.count", items.len() as i64)],
+        || {
+            let account = cli.account.as_deref();
+            let policy = CachePolicy::from_cli(cli);
+            items
+                .iter()
+                .map(|item_title| {
+                    let entry = find_item_entry(account, cli.vault.as_deref(), policy, item_title)?;
+                    // The details cache is redacted, so value lengths need a live read.
+                    let (item, values_known) = if policy.offline {
+                        (item_get_cached(account, &entry, policy)?, false)
+                    } else {
+                        let (item, source) = item_get(account, &entry)?;
+                        (item, source != Backend::Cache)
+                    };
+                    Ok(item_summary(&entry, &item, values_known))
+                })
+                .collect::<Result<Vec<_>>>()
+        },
+    )?;
+    if cli.json {
+        return telemetry_span::with_span_result("write_outputs", vec![], || {
+            print_json(&serde_json::json!({ "items": summaries }))
+        });
+    }
+    telemetry_span::with_span_result("write_outputs", vec![], || {
+        write_stdout(&summary_output_string(&summaries))
+    })
+}
+
+fn item_summary(entry: &ItemListEntry, item: &ItemGet, values_known: bool) -> ItemSummary {
+    let mut sections: Vec<String> = Vec::new();
+    let fields = item
+        .fields
+        .iter()
+        .map(|field| {
+            let section = field
+                .section
+                .as_ref()
+                .and_then(|s| s.label.clone())
+                .filter(|label| !label.is_empty());
+            if let Some(section) = &section {
+                if !sections.contains(section) {
+                    sections.push(section.clone());
+                }
+            }
+            let value_length = match &field.value {
+                None | Some(serde_json::Value::Null) => Some(0),
+                Some(_) if !values_known => None,
+                Some(serde_json::Value::String(value)) => Some(value.chars().count()),
+                Some(value) => Some(value.to_string().chars().count()),
+            };
+            FieldSummary {
+                label: field.label.clone(),
+                field_type: field.field_type.clone(),
+                section,
+                value_length,
+            }
+        })
+        .collect();
+    ItemSummary {
+        title: entry.title.clone(),
+        id: entry.id.clone(),
+        vault: entry
+            .vault
+            .as_ref()
+            .or(item.vault.as_ref())
+            .map(|v| v.name.clone()),
+        category: item.category.clone(),
+        tags: item.tags.clone(),
+        sections,
+        fields,
+        updated_at: item.updated_at.clone().or_else(|| entry.updated_at.clone()),
+        version: item.version,
+    }
+}
+
+fn summary_output_string(summaries: &[ItemSummary]) -> String {
+    fn or_dash(value: Option<&str>) -> &str {
+        value.filter(|v| !v.is_empty()).unwrap_or("-")
+    }
+    fn list(values: &[String]) -> String {
+        if values.is_empty() {
+            "-".to_string()
+        } else {
+            values.join(", ")
+        }
+    }
+
+    let mut out = String::new();
+    for (idx, summary) in summaries.iter().enumerate() {
+        if idx > 0 {
+            out.push('\n');
+        }
+        out.push_str(&format!("{} ({})\n", summary.title, summary.id));
+        out.push_str(&format!(
+            "  vault:     {}\n",
+            or_dash(summary.vault.as_deref())
+        ));
+        out.push_str(&format!(
+            "  category:  {}\n",
+            or_dash(summary.category.as_deref())
+        ));
+        out.push_str(&format!("  tags:      {}\n", list(&summary.tags)));
+        out.push_str(&format!("  sections:  {}\n", list(&summary.sections)));
+        out.push_str(&format!(
+            "  updated:   {}\n",
+            or_dash(summary.updated_at.as_deref())
+        ));
+        out.push_str(&format!(
+            "  version:   {}\n",
+            summary.version.map_or("-".to_string(), |v| v.to_string())
+        ));
+        if summary.fields.is_empty() {
+            out.push_str("  fields:    -\n");
+            continue;
+        }
+        out.push_str("  fields:\n");
+        let rows: Vec<(&str, &str, String, Option<&str>)> = summary
+            .fields
+            .iter()
+            .map(|field| {
+                let length = match field.value_length {
+                    Some(0) => "empty".to_string(),
+                    Some(1) => "1 char".to_string(),
+                    Some(n) => format!("{n} chars"),
+                    None => "length unknown (cached)".to_string(),
+                };
+                (
+                    field.label.as_deref().unwrap_or("(no label)"),
+                    or_dash(field.field_type.as_deref()),
+                    length,
+                    field.section.as_deref(),
+                )
+            })
+            .collect();
+        let label_width = rows.iter().map(|r| r.0.chars().count()).max().unwrap_or(0);
+        let type_width = rows.iter().map(|r| r.1.chars().count()).max().unwrap_or(0);
+        let length_width = rows.iter().map(|r| r.2.len()).max().unwrap_or(0);
+        for (label, field_type, length, section) in rows {
+            let line = match section {
+                Some(section) => format!(
+                    "    {label:<label_width$}  {field_type:<type_width$}  {length:<length_width$}  [{section}]"
+                ),
+                None => format!("    {label:<label_width$}  {field_type:<type_width$}  {length}"),
+            };
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// One row of `opz fields`.
+#[derive(Serialize, Debug, PartialEq)]
+struct FieldListing {
+    label: Option<String>,
+    #[serde(rename = "type")]
+    field_type: Option<String>,
+    section: Option<String>,
+    /// Env var the field is exported as.
+    env: Option<String>,
+    /// Why the field is not exported.
+    skipped: Option<String>,
+    /// `[policies]` matching the field's label.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    policies: Vec<&'static str>,
+}
+
+fn list_item_fields(cli: &Cli, config: &config::Config, item_title: &str) -> Result<()> {
+    let (id, _, title, item) = telemetry_span::with_span_result(
+        "load_inputs",
+        vec![KeyValue::new("item.count", 1)],
+        || {
+            find_item(
+                cli.account.as_deref(),
+                cli.vault.as_deref(),
+                CachePolicy::from_cli(cli),
+                item_title,
+            )
+        },
+    )?;
+    let fields =
+        telemetry_span::with_span("main_operation", vec![], || field_listings(&item, config));
+    telemetry_span::with_span_result("write_outputs", vec![], || {
+        if cli.json {
+            return print_json(&serde_json::json!({
+                "item": title,
+                "id": id,
+                "fields": fields,
+            }));
+        }
+        write_stdout(&field_listing_output_string(&fields))
+    })
+}
+
+fn field_listings(item: &ItemGet, config: &config::Config) -> Vec<FieldListing> {
+    let re = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").expect("valid regex");
+    item.fields
+        .iter()
+        .map(|field| {
+            let policies = field
+                .label
+                .as_deref()
+                .map(|label| config.field_policies(label))
+                .unwrap_or_default();
+            let (env, skipped) = match field_export(&re, config, item, field) {
+                Some(Ok(_)) if policies.contains(&config::FieldPolicy::NeverExport) => {
+                    (None, Some("[policies] never-export".to_string()))
+                }
+                Some(Ok(key)) => (Some(key), None),
+                Some(Err(reason)) => (None, Some(reason)),
+                None => (None, Some("no label".to_string())),
+            };
+            FieldListing {
+                label: field.label.clone(),
+                field_type: field.field_type.as_deref().map(field_kind),
+                section: field
+                    .section
+                    .as_ref()
+                    .and_then(|s| s.label.clone())
+                    .filter(|label| !label.is_empty()),
+                env,
+                skipped,
+                policies: policies
+                    .into_iter()
+                    .map(config::FieldPolicy::name)
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// `op` field type in the words of the 1Password app: `concealed`, `text`, `otp`, ...
+fn field_kind(field_type: &str) -> String {
+    match field_type.to_ascii_uppercase().as_str() {
+        "STRING" => "text".to_string(),
+        other => other.to_ascii_lowercase(),
+    }
+}
+
+fn field_listing_output_string(fields: &[FieldListing]) -> String {
+    let rows: Vec<[String; 4]> = fields
+        .iter()
+        .map(|field| {
+            [
+                field
+                    .label
+                    .clone()
+                    .unwrap_or_else(|| "(no label)".to_string()),
+                field.field_type.clone().unwrap_or_else(|| "-".to_string()),
+                field.section.clone().unwrap_or_else(|| "-".to_string()),
+                match (&field.env, &field.skipped) {
+                    (Some(env), _) if field.policies.contains(&"file-only") => {
+                        format!("{env} (file-only: --env-file reference only)")
+                    }
+                    (Some(env), _) => env.clone(),
+                    (None, Some(reason)) => format!("(skipped: {reason})"),
+                    (None, None) => "-".to_string(),
+                },
+            ]
+        })
+        .collect();
+    let header = ["LABEL", "TYPE", "SECTION", "ENV"].map(String::from);
+    let widths: Vec<usize> = (0..3)
+        .map(|col| {
+            std::iter::once(&header)
+                .chain(&rows)
+                .map(|row| row[col].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut out = String::new();
+    for [label, kind, section, env] in std::iter::once(&header).chain(&rows) {
+        out.push_str(&format!(
+            "{label:<w0$}  {kind:<w1$}  {section:<w2$}  {env}\n",
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        ));
+    }
+    out
+}
+
+/// A field of the given items whose label maps to the explained env var.
+#[derive(Debug)]
+struct EnvCandidate {
+    item: String,
+    label: String,
+    section: Option<String>,
+    field_type: Option<String>,
+    reference: String,
+    mapping: config::LabelMapping,
+    policies: BTreeSet<config::FieldPolicy>,
+    /// Why the field is not exported, if it is not.
+    skipped: Option<String>,
+    /// Candidate that replaces this exported one (later fields and items win).
+    overridden_by: Option<String>,
+}
+
+impl EnvCandidate {
+    fn name(&self) -> String {
+        format!("{} > {}", self.item, self.label)
+    }
+
+    fn status(&self) -> String {
+        match (&self.skipped, &self.overridden_by) {
+            (Some(reason), _) => format!("skipped: {reason}"),
+            (None, Some(winner)) => format!("overridden by {winner}"),
+            (None, None) => "used".to_string(),
+        }
+    }
+}
+
+fn explain_env(cli: &Cli, config: &config::Config, var: &str, items: &[String]) -> Result<()> {
+    if items.is_empty() {
+        return Err(anyhow!(
+            "At least one item title is required (or set `item` or a --profile in .opz.toml). Usage: opz explain-env <VAR> [ITEM]..."
+        ));
+    }
+    let mut candidates = telemetry_span::with_span_result(
+        "load_inputs",
+        vec![KeyValue::new("item.count", items.len() as i64)],
+        || -> Result<Vec<EnvCandidate>> {
+            let mut candidates = Vec::new();
+            let found = find_items(
+                cli.account.as_deref(),
+                cli.vault.as_deref(),
+                CachePolicy::from_cli(cli),
+                items,
+            )?;
+            for (item_id, vault_id, resolved_title, item) in found {
+                candidates.extend(env_candidates(
+                    var,
+                    &resolved_title,
+                    &item,
+                    &vault_id,
+                    &item_id,
+                    config,
+                )?);
+            }
+            Ok(candidates)
+        },
+    )?;
+    if candidates.is_empty() {
+        return Err(anyhow!(
+            "No field of {} maps to {var} (see `opz show`)",
+            items.join(", ")
+        ));
+    }
+    mark_overridden(&mut candidates);
+    let in_shell = std::env::var_os(var).is_some();
+
+    if cli.json {
+        let json: Vec<serde_json::Value> = candidates
+            .iter()
+            .map(|c| {
+                serde_json::json!({
+                    "item": c.item,
+                    "label": c.label,
+                    "section": c.section,
+                    "type": c.field_type,
+                    "reference": c.reference,
+                    "transforms": c.mapping.transforms,
+                    "policies": c.policies.iter().map(|p| p.name()).collect::<Vec<_>>(),
+                    "used": c.skipped.is_none() && c.overridden_by.is_none(),
+                    "status": c.status(),
+                })
+            })
+            .collect();
+        return telemetry_span::with_span_result("write_outputs", vec![], || {
+            print_json(&serde_json::json!({
+                "var": var,
+                "candidates": json,
+                "in_shell_environment": in_shell,
+            }))
+        });
+    }
+    let report = telemetry_span::with_span("main_operation", vec![], || {
+        explain_env_report(var, &candidates, in_shell)
+    });
+    telemetry_span::with_span_result("write_outputs", vec![], || write_stdout(&report))
+}
+
+/// How an item's env vars and an env file disagree. Differing values are `(key, item value,
+/// file value)`; a file value that is an `op://` reference is compared with the item's
+/// reference instead of its secret.
+#[derive(Debug, Default, PartialEq)]
+struct EnvDiff {
+    only_in_item: Vec<String>,
+    only_in_file: Vec<String>,
+    different: Vec<(String, String, String)>,
+}
+
+impl EnvDiff {
+    fn is_empty(&self) -> bool {
+        self.only_in_item.is_empty() && self.only_in_file.is_empty() && self.different.is_empty()
+    }
+}
+
+/// `opz diff`: compare the env vars of `item` with `env_file`.
+fn diff_item_env(
+    cli: &Cli,
+    config: &config::Config,
+    item: &str,
+    env_file: &Path,
+    show_values: bool,
+) -> Result<()> {
+    let items = [item.to_string()];
+    let (file_pairs, references, values) = telemetry_span::with_span_result(
+        "load_inputs",
+        vec![KeyValue::new("item.count", 1)],
+        || -> Result<_> {
+            let file_pairs = read_env_pairs(env_file, |_, _| true)?;
+            let collected = collect_item_env_sections(cli, config, &items)?;
+            let references: Vec<(String, String)> = merge_env_lines(&collected.sections)
+                .iter()
+                .filter_map(|line| parse_env_line_kv(line))
+                .map(|(key, reference)| (key.to_string(), reference.to_string()))
+                .collect();
+            // Only secrets the file has a plain value for are read.
+            let to_read: Vec<String> = references
+                .iter()
+                .filter(|(key, _)| {
+                    file_pairs
+                        .iter()
+                        .any(|(file_key, value)| file_key == key && !is_op_reference(value))
+                })
+                .map(|(key, reference)| format!("{key}={reference}"))
+                .collect();
+            let values = if to_read.is_empty() {
+                HashMap::new()
+            } else {
+                let mut run_dir = run_dir::RunDir::create(&items)?;
+                let values = resolve_env_vars(cli.account.as_deref(), &to_read, &mut run_dir);
+                let _ = run_dir::remove(run_dir.path());
+                values?
+            };
+            Ok((file_pairs, references, values))
+        },
+    )?;
+    let diff = telemetry_span::with_span("main_operation", vec![], || {
+        env_diff(&references, &values, &file_pairs)
+    });
+    let file_label = env_file.display().to_string();
+
+    if cli.json {
+        let different: Vec<serde_json::Value> = diff
+            .different
+            .iter()
+            .map(|(key, item_value, file_value)| {
+                let mut entry = serde_json::json!({ "key": key });
+                if show_values {
+                    entry["item_value"] = item_value.as_str().into();
+                    entry["file_value"] = file_value.as_str().into();
+                }
+                entry
+            })
+            .collect();
+        let report = serde_json::json!({
+            "item": item,
+            "env_file": file_label,
+            "in_sync": diff.is_empty(),
+            "only_in_item": diff.only_in_item,
+            "only_in_file": diff.only_in_file,
+            "different": different,
+        });
+        telemetry_span::with_span_result("write_outputs", vec![], || print_json(&report))?;
+        if !diff.is_empty() {
+            return Err(anyhow::Error::new(ReportedInJson));
+        }
+        return Ok(());
+    }
+    telemetry_span::with_span_result("write_outputs", vec![], || {
+        write_stdout(&env_diff_output_string(
+            &diff,
+            item,
+            &file_label,
+            show_values,
+        ))
+    })?;
+    if diff.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("{item} and {file_label} differ"))
+    }
+}
+
+/// `opz check`: how an item measures up to a schema file's keys.
+#[derive(Debug, Default, PartialEq)]
+struct SchemaCheck {
+    /// Schema keys the item has no field for.
+    missing: Vec<String>,
+    /// Schema keys whose field is empty.
+    empty: Vec<String>,
+    /// Item keys the schema does not list.
+    extra: Vec<String>,
+}
+
+impl SchemaCheck {
+    fn passed(&self, strict: bool) -> bool {
+        self.missing.is_empty() && self.empty.is_empty() && (!strict || self.extra.is_empty())
+    }
+}
+
+/// `opz check`: verify that `item` has a non-empty value for every key in `schema`.
+fn check_item_schema(
+    cli: &Cli,
+    config: &config::Config,
+    item: &str,
+    schema: &Path,
+    strict: bool,
+) -> Result<()> {
+    let items = [item.to_string()];
+    let (schema_keys, references, values) = telemetry_span::with_span_result(
+        "load_inputs",
+        vec![KeyValue::new("item.count", 1)],
+        || -> Result<_> {
+            let schema_keys: Vec<String> = read_env_pairs(schema, |_, _| true)?
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect();
+            let collected = collect_item_env_sections(cli, config, &items)?;
+            let references: Vec<(String, String)> = merge_env_lines(&collected.sections)
+                .iter()
+                .filter_map(|line| parse_env_line_kv(line))
+                .map(|(key, reference)| (key.to_string(), reference.to_string()))
+                .collect();
+            // Only the required secrets are read, to tell empty fields apart.
+            let to_read: Vec<String> = references
+                .iter()
+                .filter(|(key, _)| schema_keys.contains(key))
+                .map(|(key, reference)| format!("{key}={reference}"))
+                .collect();
+            let values = if to_read.is_empty() {
+                HashMap::new()
+            } else {
+                let mut run_dir = run_dir::RunDir::create(&items)?;
+                let values = resolve_env_vars(cli.account.as_deref(), &to_read, &mut run_dir);
+                let _ = run_dir::remove(run_dir.path());
+                values?
+            };
+            Ok((schema_keys, references, values))
+        },
+    )?;
+    let check = telemetry_span::with_span("main_operation", vec![], || {
+        schema_check(&schema_keys, &references, &values)
+    });
+    let schema_label = schema.display().to_string();
+    let passed = check.passed(strict);
+
+    if cli.json {
+        let report = serde_json::json!({
+            "item": item,
+            "schema": schema_label,
+            "passed": passed,
+            "missing": check.missing,
+            "empty": check.empty,
+            "extra": check.extra,
+        });
+        telemetry_span::with_span_result("write_outputs", vec![], || print_json(&report))?;
+        if !passed {
+            return Err(anyhow::Error::new(ReportedInJson));
+        }
+        return Ok(());
+    }
+    telemetry_span::with_span_result("write_outputs", vec![], || {
+        write_stdout(&schema_check_output_string(&check, item, &schema_label))
+    })?;
+    if passed {
+        Ok(())
+    } else {
+        Err(anyhow!("{item} does not satisfy {schema_label}"))
+    }
+}
+
+/// `opz verify-env`: env vars of the item the environment lacks or holds another value for.
+#[derive(Debug, Default, PartialEq)]
+struct EnvVerification {
+    missing: Vec<String>,
+    different: Vec<String>,
+    matching: usize,
+}
+
+impl EnvVerification {
+    fn passed(&self) -> bool {
+        self.missing.is_empty() && self.different.is_empty()
+    }
+}
+
+/// `opz verify-env`: check the current environment against the secrets of `item`.
+fn verify_item_env(cli: &Cli, config: &config::Config, item: &str) -> Result<()> {
+    let items = [item.to_string()];
+    let (keys, values) = telemetry_span::with_span_result(
+        "load_inputs",
+        vec![KeyValue::new("item.count", 1)],
+        || -> Result<_> {
+            let collected = collect_item_env_sections(cli, config, &items)?;
+            let lines = merge_env_lines(&collected.sections);
+            let mut run_dir = run_dir::RunDir::create(&items)?;
+            // `file-only` fields never reach a command's environment, so they are not expected.
+            let values = resolve_command_env(
+                cli.account.as_deref(),
+                &lines,
+                &collected.key_policies,
+                &mut run_dir,
+            );
+            let _ = run_dir::remove(run_dir.path());
+            let keys: Vec<String> = lines
+                .iter()
+                .filter_map(|line| parse_env_key(line))
+                .map(str::to_string)
+                .collect();
+            Ok((keys, values?))
+        },
+    )?;
+    let verification = telemetry_span::with_span("main_operation", vec![], || {
+        verify_env(&keys, &values, |key| std::env::var_os(key))
+    });
+    let passed = verification.passed();
+
+    if cli.json {
+        let report = serde_json::json!({
+            "item": item,
+            "passed": passed,
+            "matching": verification.matching,
+            "missing": verification.missing,
+            "different": verification.different,
+        });
+        telemetry_span::with_span_result("write_outputs", vec![], || print_json(&report))?;
+        if !passed {
+            return Err(anyhow::Error::new(ReportedInJson));
+        }
+        return Ok(());
+    }
+    telemetry_span::with_span_result("write_outputs", vec![], || {
+        write_stdout(&env_verification_output_string(&verification, item))
+    })?;
+    if passed {
+        Ok(())
+    } else {
+        Err(anyhow!("The environment does not match {item}"))
+    }
+}
+
+/// Compare the item's secrets in `values` (for `keys`, in that order) with what `env` holds,
+/// by SHA-256 digest. Only key names are reported, never values.
+fn verify_env(
+    keys: &[String],
+    values: &HashMap<String, String>,
+    env: impl Fn(&str) -> Option<OsString>,
+) -> EnvVerification {
+    let mut verification = EnvVerification::default();
+    for key in keys {
+        let Some(expected) = values.get(key) else {
+            continue;
+        };
+        match env(key) {
+            None => verification.missing.push(key.clone()),
+            Some(actual) => {
+                let expected: [u8; 32] = Sha256::digest(expected.as_bytes()).into();
+                let actual: [u8; 32] = Sha256::digest(actual.as_encoded_bytes()).into();
+                if expected == actual {
+                    verification.matching += 1;
+                } else {
+                    verification.different.push(key.clone());
+                }
+            }
+        }
+    }
+    verification
+}
+
+fn env_verification_output_string(verification: &EnvVerification, item: &str) -> String {
+    let mut out = String::new();
+    for key in &verification.missing {
+        out.push_str(&format!("- {key}  (not set)\n"));
+    }
+    for key in &verification.different {
+        out.push_str(&format!("~ {key}  (differs from {item})\n"));
+    }
+    if verification.passed() {
+        out.push_str(&format!(
+            "All {} variables of {item} are set and match\n",
+            verification.matching
+        ));
+    }
+    out
+}
+
+/// Compare the keys of `schema_keys` with the item's `(key, op:// reference)` pairs and the
+/// secrets read for them in `values`.
+fn schema_check(
+    schema_keys: &[String],
+    references: &[(String, String)],
+    values: &HashMap<String, String>,
+) -> SchemaCheck {
+    let mut check = SchemaCheck::default();
+    for key in schema_keys {
+        if !references.iter().any(|(item_key, _)| item_key == key) {
+            check.missing.push(key.clone());
+        } else if values.get(key).is_none_or(|value| value.is_empty()) {
+            check.empty.push(key.clone());
+        }
+    }
+    check.extra = references
+        .iter()
+        .filter(|(key, _)| !schema_keys.contains(key))
+        .map(|(key, _)| key.clone())
+        .collect();
+    check
+}
+
+fn schema_check_output_string(check: &SchemaCheck, item: &str, schema: &str) -> String {
+    let mut out = String::new();
+    for key in &check.missing {
+        out.push_str(&format!("- {key}  (missing from {item})\n"));
+    }
+    for key in &check.empty {
+        out.push_str(&format!("! {key}  (empty in {item})\n"));
+    }
+    for key in &check.extra {
+        out.push_str(&format!("+ {key}  (not in {schema})\n"));
+    }
+    if check.missing.is_empty() && check.empty.is_empty() {
+        out.push_str(&format!("{item} has every key in {schema}\n"));
+    }
+    out
+}
+
+/// Compare the item's `(key, op:// reference)` pairs, with the secrets read in `values`, to
+/// the env file's pairs. Keys keep the item's and then the file's order.
+fn env_diff(
+    references: &[(String, String)],
+    values: &HashMap<String, String>,
+    file_pairs: &[(String, String)],
+) -> EnvDiff {
+    let mut diff = EnvDiff::default();
+    for (key, reference) in references {
+        let Some((_, file_value)) = file_pairs.iter().find(|(file_key, _)| file_key == key) else {
+            diff.only_in_item.push(key.clone());
+            continue;
+        };
+        let item_value = if is_op_reference(file_value) {
+            reference
+        } else {
+            values.get(key).unwrap_or(reference)
+        };
+        if item_value != file_value {
+            diff.different
+                .push((key.clone(), item_value.clone(), file_value.clone()));
+        }
+    }
+    diff.only_in_file = file_pairs
+        .iter()
+        .filter(|(key, _)| !references.iter().any(|(item_key, _)| item_key == key))
+        .map(|(key, _)| key.clone())
+        .collect();
+    diff
+}
+
+fn env_diff_output_string(diff: &EnvDiff, item: &str, file: &str, show_values: bool) -> String {
+    if diff.is_empty() {
+        return format!("{item} and {file} are in sync\n");
+    }
+    let mut out = String::new();
+    for key in &diff.only_in_item {
+        out.push_str(&format!("- {key}  (only in {item})\n"));
+    }
+    for key in &diff.only_in_file {
+        out.push_str(&format!("+ {key}  (only in {file})\n"));
+    }
+    for (key, item_value, file_value) in &diff.different {
+        out.push_str(&format!("~ {key}  (values differ)\n"));
+        if show_values {
+            out.push_str(&format!("    {item}: {item_value}\n"));
+            out.push_str(&format!("    {file}: {file_value}\n"));
+        }
+    }
+    out
+}
+
+/// Fields of `item` whose label maps to `var`, exported or not, in field order.
+fn env_candidates(
+    var: &str,
+    title: &str,
+    item: &ItemGet,
+    vault_id: &str,
+    item_id: &str,
+    config: &config::Config,
+) -> Result<Vec<EnvCandidate>> {
+    let re = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$")?;
+    Ok(item
+        .fields
+        .iter()
+        .filter_map(|field| {
+            let label = field.label.as_deref()?;
+            let mapping = field_mapping(config, item, field)?;
+            if mapping.key != var {
+                return None;
+            }
+            let skipped = mapping.dropped_by.clone().or_else(|| {
+                if !re.is_match(var) {
+                    Some("not a valid env var name".to_string())
+                } else if field.value.is_none() {
+                    Some("field has no value".to_string())
+                } else {
+                    None
+                }
+            });
+            Some(EnvCandidate {
+                item: title.to_string(),
+                label: label.to_string(),
+                section: field.section.as_ref().and_then(|s| s.label.clone()),
+                field_type: field.field_type.clone(),
+                reference: format!("op://{vault_id}/{item_id}/{label}"),
+                policies: config.field_policies(label),
+                mapping,
+                skipped,
+                overridden_by: None,
+            })
+        })
+        .collect())
+}
+
+/// Every exported candidate but the last is replaced by the last one.
+fn mark_overridden(candidates: &mut [EnvCandidate]) {
+    let Some(winner) = candidates.iter().rposition(|c| c.skipped.is_none()) else {
+        return;
+    };
+    let winner_name = candidates[winner].name();
+    for candidate in &mut candidates[..winner] {
+        if candidate.skipped.is_none() {
+            candidate.overridden_by = Some(winner_name.clone());
+        }
+    }
+}
+
+fn explain_env_report(var: &str, candidates: &[EnvCandidate], in_shell: bool) -> String {
+    let mut out = format!("{var}\n");
+    for candidate in candidates {
+        out.push_str(&format!(
+            "  {}  ({})\n",
+            candidate.name(),
+            candidate.status()
+        ));
+        out.push_str(&format!("    reference:  {}\n", candidate.reference));
+        out.push_str(&format!(
+            "    section:    {}\n",
+            candidate.section.as_deref().unwrap_or("-")
+        ));
+        out.push_str(&format!(
+            "    type:       {}\n",
+            candidate.field_type.as_deref().unwrap_or("-")
+        ));
+        let mapping = if candidate.mapping.transforms.is_empty() {
+            "label as-is".to_string()
+        } else {
+            candidate.mapping.transforms.join(", ")
+        };
+        out.push_str(&format!("    mapping:    {mapping}\n"));
+        let policies: Vec<&str> = candidate.policies.iter().map(|p| p.name()).collect();
+        out.push_str(&format!(
+            "    policies:   {}\n",
+            if policies.is_empty() {
+                "-".to_string()
+            } else {
+                policies.join(", ")
+            }
+        ));
+    }
+
+    let winner = candidates
+        .iter()
+        .find(|c| c.skipped.is_none() && c.overridden_by.is_none());
+    let file_only = winner.is_some_and(|c| c.policies.contains(&config::FieldPolicy::FileOnly));
+    if file_only {
+        out.push_str(&format!(
+            "{var} is file-only: commands see it only as a reference in --env-file.\n"
+        ));
+    }
+    if in_shell {
+        if winner.is_some() && !file_only {
+            out.push_str(&format!(
+                "{var} is also set in this shell; opz replaces it for the command.\n"
+            ));
+        } else {
+            out.push_str(&format!(
+                "{var} is also set in this shell, and the command inherits that value.\n"
+            ));
+        }
+    }
+    out
+}
+
+fn show_output_string(sections: &[(String, Vec<String>)], with_item: bool) -> String {
+    let mut out = String::new();
+
+    if with_item {
+        for (idx, (title, labels)) in sections.iter().enumerate() {
+            if idx > 0 {
+                out.push('\n');
+            }
+            out.push_str(&format!("# --- item: {} ---\n", title));
+            for label in labels {
+                out.push_str(label);
+                out.push('\n');
+            }
+        }
+        return out;
+    }
+
+    for (_, labels) in sections {
+        for label in labels {
+            out.push_str(label);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn create_item_from_env(cli: &Cli, item_title: &str, env_file: &Path) -> Result<()> {
+    if !is_exact_dotenv(env_file) {
+        return telemetry_span::with_span_result(
+            "main_operation",
+            vec![
+                KeyValue::new("cli.input_path", env_file.display().to_string()),
+                KeyValue::new("item.title", item_title.to_string()),
+            ],
+            || create_secure_notes_from_file(cli, env_file),
+        );
+    }
+
+    telemetry_span::with_span_result(
+        "main_operation",
+        vec![
+            KeyValue::new("cli.input_path", env_file.display().to_string()),
+            KeyValue::new("item.title", item_title.to_string()),
+        ],
+        || create_api_credential_item_from_env(cli, item_title, env_file),
+    )
+}
+
+fn is_exact_dotenv(path: &Path) -> bool {
+    path.file_name().and_then(|name| name.to_str()) == Some(".env")
+}
+
+fn create_api_credential_item_from_env(cli: &Cli, item_title: &str, env_file: &Path) -> Result<()> {
+    let env_pairs = telemetry_span::with_span_result(
+        "load_inputs",
+        vec![KeyValue::new(
+            "cli.input_path",
+            env_file.display().to_string(),
+        )],
+        || parse_env_file(env_file),
+    )?;
+    if env_pairs.is_empty() {
+        return Err(anyhow!(
+            "No valid env entries found in {}",
+            env_file.display()
+        ));
+    }
+
+    let args = telemetry_span::with_span("main_operation", vec![], || {
+        build_create_item_args(cli.vault.as_deref(), item_title, &env_pairs)
+    });
+    telemetry_span::with_span_result("write_outputs", vec![], || {
+        run_op_item_create(cli.account.as_deref(), &args)?;
+        invalidate_item_list_cache_best_effort();
+        Ok(())
+    })
+}
+
+fn build_create_item_args(
+    vault: Option<&str>,
+    item_title: &str,
+    env_pairs: &[(String, String)],
+) -> Vec<String> {
+    let mut args = vec![
+        "item".to_string(),
+        "create".to_string(),
+        "--category".to_string(),
+        "API Credential".to_string(),
+        "--title".to_string(),
+        item_title.to_string(),
+    ];
+
+    if let Some(v) = vault {
+        args.push("--vault".to_string());
+        args.push(v.to_string());
+    }
+
+    // key[text]=value creates a custom text field where the field label is the key.
+    for (key, value) in env_pairs {
+        args.push(format!("{}[text]={}", key, value));
+    }
+
+    args
+}
+
+fn create_secure_notes_from_file(cli: &Cli, file_path: &Path) -> Result<()> {
+    let (file_name, content, remote_repo_names) = telemetry_span::with_span_result(
+        "load_inputs",
+        vec![KeyValue::new(
+            "cli.input_path",
+            file_path.display().to_string(),
+        )],
+        || {
+            let content = fs::read_to_string(file_path)
+                .with_context(|| format!("read {}", file_path.display()))?;
+            let file_name = file_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .ok_or_else(|| anyhow!("invalid file path: {}", file_path.display()))?;
+            let remote_repo_names = list_remote_repo_names()?;
+            Ok((file_name, content, remote_repo_names))
+        },
+    )?;
+    let (body, item_titles) = telemetry_span::with_span("main_operation", vec![], || {
+        let body = build_secure_note_body(&file_name, &content);
+        let item_titles = dedupe_titles_with_sequence(&remote_repo_names);
+        (body, item_titles)
+    });
+
+    telemetry_span::with_span_result("write_outputs", vec![], || {
+        for item_title in item_titles {
+            let args = build_create_secure_note_args(cli.vault.as_deref(), &item_title, &body);
+            run_op_item_create(cli.account.as_deref(), &args)?;
+        }
+        invalidate_item_list_cache_best_effort();
+        Ok(())
+    })
+}
+
+fn build_secure_note_body(file_name: &str, content: &str) -> String {
+    let mut body = format!("```{}\n", file_name);
+    body.push_str(content);
+    if !content.ends_with('\n') {
+        body.push('\n');
+    }
+    body.push_str("```");
+    body
+}
+
+fn build_create_secure_note_args(vault: Option<&str>, item_title: &str, body: &str) -> Vec<String> {
+    let mut args = vec![
+        "item".to_string(),
+        "create".to_string(),
+        "--category".to_string(),
+        "Secure Note".to_string(),
+        "--title".to_string(),
+        item_title.to_string(),
+    ];
+
+    if let Some(v) = vault {
+        args.push("--vault".to_string());
+        args.push(v.to_string());
+    }
+
+    args.push(format!("notesPlain={}", body));
+    args
+}
+
+fn run_op_item_create(account: Option<&str>, args: &[String]) -> Result<()> {
+    telemetry_span::with_span_result(
+        "write_outputs.op_item_create",
+        vec![KeyValue::new("op.arg_count", args.len() as i64)],
+        || {
+            let mut cmd = op_command(account)?;
+            cmd.args(args)
+                .stdin(Stdio::inherit())
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit());
+
+            let status = run_op_interactive(cmd)
+                .context(error::Error::new(
+                    error::Kind::OpFailed,
+                    "failed to run `op item create`",
+                ))?
+                .status;
+
+            if !status.success() {
+                return Err(error::Error::new(
+                    error::Kind::OpFailed,
+                    format!("op item create failed with status: {status}"),
+                )
+                .into());
+            }
+
+            Ok(())
+        },
+    )
+}
+
+fn list_remote_repo_names() -> Result<Vec<String>> {
+    let out = Command::new("git")
+        .args(["config", "--get-regexp", r"^remote\..*\.url$"])
+        .output()
+        .context("failed to run `git config --get-regexp '^remote\\..*\\.url$'`")?;
+
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
+        return Err(anyhow!(
+            "failed to read git remotes: {}",
+            if stderr.is_empty() {
+                "no remote configured"
+            } else {
+                &stderr
+            }
+        ));
+    }
+
+    let stdout = String::from_utf8(out.stdout).context("git output was not valid UTF-8")?;
+    let mut repo_names = Vec::new();
+    for line in stdout.lines() {
+        let mut parts = line.split_whitespace();
+        let _key = parts.next();
+        let Some(url) = parts.next() else {
+            continue;
+        };
+        if let Some(repo_name) = extract_org_repo_from_remote_url(url) {
+            repo_names.push(repo_name);
+        }
+    }
+
+    if repo_names.is_empty() {
+        return Err(anyhow!(
+            "no parseable git remotes found; non-.env create requires at least one remote URL like https://host/org/repo.git"
+        ));
+    }
+
+    Ok(repo_names)
+}
+
+fn extract_org_repo_from_remote_url(url: &str) -> Option<String> {
+    let stripped = url.split(['?', '#']).next()?;
+    let path = if let Some((_, rest)) = stripped.split_once("://") {
+        let (host_part, path_part) = rest.split_once('/')?;
+        if host_part.is_empty() {
+            return None;
+        }
+        path_part
+    } else if stripped.contains('@') && stripped.contains(':') {
+        let (_, path_part) = stripped.split_once(':')?;
+        path_part
+    } else {
+        return None;
+    };
+
+    let normalized = path.trim_matches('/').trim_end_matches(".git");
+    let segments: Vec<&str> = normalized
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    if segments.len() < 2 {
+        return None;
+    }
+
+    let org = segments[segments.len() - 2];
+    let repo = segments[segments.len() - 1];
+    Some(format!("{org}/{repo}"))
+}
+
+fn dedupe_titles_with_sequence(base_titles: &[String]) -> Vec<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut titles = Vec::with_capacity(base_titles.len());
+
+    for base in base_titles {
+        let count = counts.entry(base.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            titles.push(base.clone());
+        } else {
+            titles.push(format!("{}-{}", base, count));
+        }
+    }
+
+    titles
+}
+
+fn parse_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    read_env_pairs(path, |key, value| {
+        if is_op_reference(value) {
+            eprintln!("Skipped already imported op:// value for key: {key}");
+            return false;
+        }
+        true
+    })
+}
+
+/// Key/value pairs of a dotenv file, for the lines `keep` accepts; the last occurrence of a
+/// key wins.
+fn read_env_pairs(
+    path: &Path,
+    mut keep: impl FnMut(&str, &str) -> bool,
+) -> Result<Vec<(String, String)>> {
+    let content = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    let label_re = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$")?;
+    let mut pairs = Vec::new();
+
+    for raw_line in content.lines() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let normalized = match line.strip_prefix("export") {
+            Some(rest) if rest.chars().next().is_some_and(char::is_whitespace) => rest.trim_start(),
+            _ => line,
+        };
+        let Some((raw_key, raw_value)) = normalized.split_once('=') else {
+            continue;
+        };
+        let key = raw_key.trim();
+        if !label_re.is_match(key) {
+            eprintln!("Skipped invalid key in env file: {key}");
+            continue;
+        }
+
+        let value = normalize_env_value(raw_value);
+        if !keep(key, &value) {
+            continue;
+        }
+
+        // Last occurrence wins for duplicate keys.
+        if let Some(pos) = pairs
+            .iter()
+            .position(|(existing_key, _)| existing_key == key)
+        {
+            pairs.remove(pos);
+        }
+
+        pairs.push((key.to_string(), value));
+    }
+
+    Ok(pairs)
+}
+
+fn normalize_env_value(raw_value: &str) -> String {
+    let mut value = strip_inline_comment(raw_value).trim().to_string();
+    if value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"'))
+            || (value.starts_with('\'') && value.ends_with('\'')))
+    {
+        value = value[1..value.len() - 1].to_string();
+    }
+    value
+}
+
+fn strip_inline_comment(value: &str) -> &str {
+    let mut in_single_quote = false;
+    let mut in_double_quote = false;
+    let mut escaped_in_double = false;
+
+    for (idx, ch) in value.char_indices() {
+        if in_double_quote {
+            if escaped_in_double {
+                escaped_in_double = false;
+                continue;
+            }
+            if ch == '\\' {
+                escaped_in_double = true;
+                continue;
+            }
+            if ch == '"' {
+                in_double_quote = false;
+            }
+            continue;
+        }
+
+        if in_single_quote {
+            if ch == '\'' {
+                in_single_quote = false;
+            }
+            continue;
+        }
+
+        match ch {
+            '"' => in_double_quote = true,
+            '\'' => in_single_quote = true,
+            '#' if idx == 0 || value[..idx].chars().last().is_some_and(char::is_whitespace) => {
+                return value[..idx].trim_end();
+            }
+            _ => {}
+        }
+    }
+
+    value
+}
+
+fn is_op_reference(value: &str) -> bool {
+    value.starts_with("op://")
+}
+
+/// [`find_item`] for each title, looked up in parallel; results follow `titles`.
+pub(crate) fn find_items(
+    account: Option<&str>,
+    vault: Option<&str>,
+    policy: CachePolicy,
+    titles: &[String],
+) -> Result<Vec<(String, String, String, ItemGet)>> {
+    parallel::map(titles, |title| find_item(account, vault, policy, title))
+        .into_iter()
+        .collect()
+}
+
+/// [`find_items`], except that titles pinned in `.opz.lock` resolve to the pinned item. With
+/// `--frozen`, every title must be pinned and unchanged.
+fn find_items_locked(
+    cli: &Cli,
+    titles: &[String],
+) -> Result<Vec<(String, String, String, ItemGet)>> {
+    let (account, vault) = (cli.account.as_deref(), cli.vault.as_deref());
+    let policy = CachePolicy::from_cli(cli);
+    let lock_path = lockfile::path(&std::env::current_dir()?);
+    let lock = lockfile::load(&lock_path)?;
+    if cli.frozen && lock.is_none() {
+        return Err(anyhow!(
+            "--frozen needs {}, which does not exist. Run `opz lock` first.",
+            lock_path.display()
+        ));
+    }
+    parallel::map(titles, |title| {
+        match lock.as_ref().and_then(|lock| lock.get(title)) {
+            Some(locked) => find_locked_item(account, vault, policy, locked, cli.frozen),
+            None if cli.frozen => Err(anyhow!(
+                "Item '{title}' is not pinned in {} (--frozen). Run `opz lock {title}`.",
+                lock_path.display()
+            )),
+            None => find_item(account, vault, policy, title),
+        }
+    })
+    .into_iter()
+    .collect()
+}
+
+/// The item a lockfile entry pins, looked up by id in the item list so caches and `--offline`
+/// still apply and a renamed item is still found.
+fn find_locked_item(
+    account: Option<&str>,
+    vault: Option<&str>,
+    policy: CachePolicy,
+    locked: &lockfile::LockedItem,
+    frozen: bool,
+) -> Result<(String, String, String, ItemGet)> {
+    let entry = item_list_cached(account, vault, policy)?
+        .into_iter()
+        .find(|entry| entry.id == locked.id)
+        .ok_or_else(|| {
+            anyhow!(
+                "Item '{}' pinned in {} (id {}) no longer exists. Run `opz lock` to pin it again.",
+                locked.title,
+                lockfile::LOCK_FILE,
+                locked.id
+            )
+        })?;
+    let item = item_get_cached(account, &entry, policy)?;
+    if let Some(change) = locked.change(item.version, item.updated_at.as_deref()) {
+        let message = format!(
+            "Item '{}' changed since it was pinned in {} ({change}). Review the changes in 1Password, then run `opz lock` again.",
+            locked.title,
+            lockfile::LOCK_FILE
+        );
+        if frozen {
+            return Err(anyhow!("{message}"));
+        }
+        tracing::warn!("{message}");
+    }
+    Ok((entry.id, locked.vault_id.clone(), entry.title, item))
+}
+
+/// `opz lock`: pin `items` as they resolve by title right now.
+fn lock_items(cli: &Cli, items: &[String]) -> Result<()> {
+    if items.is_empty() {
+        return Err(anyhow!(
+            "No items to lock. Pass item titles or set `item` in {}.",
+            config::PROJECT_CONFIG_FILE
+        ));
+    }
+    let path = lockfile::path(&std::env::current_dir()?);
+    let (mut lock, found) =
+        telemetry_span::with_span_result("load_inputs", vec![], || -> Result<_> {
+            let lock = lockfile::load(&path)?.unwrap_or_default();
+            let policy = CachePolicy {
+                serve_stale: false,
+                ..CachePolicy::from_cli(cli)
+            };
+            let found = find_items(cli.account.as_deref(), cli.vault.as_deref(), policy, items)?;
+            Ok((lock, found))
+        })?;
+    telemetry_span::with_span("main_operation", vec![], || {
+        for (title, (id, vault_id, _, item)) in items.iter().zip(found) {
+            eprintln!(
+                "Pinned {title} to {id}{}",
+                item.version
+                    .map(|version| format!(" (version {version})"))
+                    .unwrap_or_default()
+            );
+            lock.pin(lockfile::LockedItem {
+                title: title.clone(),
+                id,
+                vault_id,
+                version: item.version,
+                updated_at: item.updated_at,
+            });
+        }
+    });
+    telemetry_span::with_span_result("write_outputs", vec![], || lockfile::write(&path, &lock))?;
+    eprintln!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// Find and match item by title, returns (item_id, vault_id, item_title, item_details)
+fn find_item(
+    account: Option<&str>,
+    vault: Option<&str>,
+    policy: CachePolicy,
+    item_title: &str,
+) -> Result<(String, String, String, ItemGet)> {
+    if let Some(direct) = direct_item(item_title) {
+        match find_item_direct(account, policy, &direct) {
+            // A title can happen to look like an id; it is then matched as a title below.
+            Err(err)
+                if direct.vault.is_none()
+                    && error::kind(&err) == Some(error::Kind::ItemNotFound) => {}
+            found => return found,
+        }
+    }
+    let entry = find_item_entry(account, vault, policy, item_title)?;
+    entry_item(account, policy, entry)
+}
+
+/// The item for each top-level `--url`, matched by website host only; results follow the
+/// order of the flags.
+fn find_items_by_url(cli: &Cli) -> Result<Vec<(String, String, String, ItemGet)>> {
+    let (account, vault) = (cli.account.as_deref(), cli.vault.as_deref());
+    let policy = CachePolicy::from_cli(cli);
+    parallel::map(&cli.url, |url| {
+        let items = item_list_cached(account, vault, policy)?;
+        let entry = match_url_entry(&items, url)?.clone();
+        entry_item(account, policy, entry)
+    })
+    .into_iter()
+    .collect()
+}
+
+/// (item_id, vault_id, item_title, item_details) for a matched item list entry.
+fn entry_item(
+    account: Option<&str>,
+    policy: CachePolicy,
+    entry: ItemListEntry,
+) -> Result<(String, String, String, ItemGet)> {
+    let item = item_get_cached(account, &entry, policy)?;
+    let vault_id = resolve_vault_id(entry.vault.as_ref(), item.vault.as_ref())
+        .ok_or_else(|| anyhow!("Vault ID is required. Try specifying --vault."))?;
+
+    Ok((entry.id, vault_id, entry.title, item))
+}
+
+/// An ITEM argument that names one item without a title match: a 1Password item id, or an
+/// `op://<vault>/<item>[/...]` reference.
+#[derive(Debug, PartialEq)]
+struct DirectItem<'a> {
+    vault: Option<&'a str>,
+    item: &'a str,
+}
+
+fn direct_item(arg: &str) -> Option<DirectItem<'_>> {
+    if let Some(path) = arg.strip_prefix("op://") {
+        let mut segments = path.split('/');
+        let vault = segments.next().filter(|s| !s.is_empty())?;
+        let item = segments.next().filter(|s| !s.is_empty())?;
+        return Some(DirectItem {
+            vault: Some(vault),
+            item,
+        });
+    }
+    is_item_id(arg).then_some(DirectItem {
+        vault: None,
+        item: arg,
+    })
+}
+
+/// 1Password item ids are 26 lowercase letters and digits.
+fn is_item_id(s: &str) -> bool {
+    s.len() == 26
+        && s.bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+}
+
+/// `op item get` on the id or reference directly, skipping the item list and title match.
+fn find_item_direct(
+    account: Option<&str>,
+    policy: CachePolicy,
+    direct: &DirectItem,
+) -> Result<(String, String, String, ItemGet)> {
+    if policy.offline {
+        return Err(anyhow!(
+            "Item '{}' is fetched directly with `op item get`, which --offline does not allow",
+            direct.item
+        ));
+    }
+    let mut args = vec!["item", "get", direct.item];
+    if let Some(vault) = direct.vault {
+        args.extend(["--vault", vault]);
+    }
+    args.extend(["--format", "json"]);
+    let value = telemetry_span::with_span_result("load_inputs.item_get_direct", vec![], || {
+        op_json(account, &args)
+    })?;
+    let id = value["id"].as_str().unwrap_or(direct.item).to_string();
+    let title = value["title"].as_str().unwrap_or(direct.item).to_string();
+    let item: ItemGet = serde_json::from_value(value)?;
+    let vault_id = resolve_vault_id(None, item.vault.as_ref())
+        .ok_or_else(|| anyhow!("Vault ID is required. Try specifying --vault."))?;
+    tracing::info!(
+        "'{}' fetched item '{title}' ({id}) in vault {} directly",
+        direct.item,
+        item.vault.as_ref().map_or("-", |v| v.name.as_str())
+    );
+    Ok((id, vault_id, title, item))
+}
+
+/// The item list entry `item_title` refers to: an exact title match, or else the only item
+/// whose title contains it.
+fn find_item_entry(
+    account: Option<&str>,
+    vault: Option<&str>,
+    policy: CachePolicy,
+    item_title: &str,
+) -> Result<ItemListEntry> {
+    let items = item_list_cached(account, vault, policy)?;
+    match_item_entry(&items, item_title).cloned()
+}
+
+fn match_item_entry<'a>(items: &'a [ItemListEntry], item_title: &str) -> Result<&'a ItemListEntry> {
+    let (scope, title) = vault_qualified(items, item_title);
+    let mut matches: Vec<&ItemListEntry> =
+        scope.iter().copied().filter(|x| x.title == title).collect();
+    let mut match_kind = "exact title";
+
+    // If exact match not found, fallback to contains (simple fuzzy)
+    if matches.is_empty() {
+        match_kind = "substring title";
+        let q = title.to_lowercase();
+        matches = scope
+            .into_iter()
+            .filter(|x| x.title.to_lowercase().contains(&q))
+            .collect();
+    }
+    telemetry_span::note_title_match(match_kind == "exact title", matches.len());
+    unique_item_entry(matches, item_title, match_kind)
+}
+
+/// The one item with a website on `url`'s host (see [`ItemListEntry::has_host`]).
+fn match_url_entry<'a>(items: &'a [ItemListEntry], url: &str) -> Result<&'a ItemListEntry> {
+    let host = url_host(url).ok_or_else(|| anyhow!("--url {url}: no host in the URL"))?;
+    let matches: Vec<&ItemListEntry> = items.iter().filter(|x| x.has_host(host)).collect();
+    if matches.is_empty() {
+        return Err(error::Error::new(
+            error::Kind::ItemNotFound,
+            format!("No item has a website on {host} (--url {url})"),
+        )
+        .into());
+    }
+    unique_item_entry(matches, url, "website")
+}
+
+/// The one entry in `matches`, or an item-not-found or ambiguous-item error.
+fn unique_item_entry<'a>(
+    matches: Vec<&'a ItemListEntry>,
+    item_title: &str,
+    match_kind: &str,
+) -> Result<&'a ItemListEntry> {
+    if matches.is_empty() {
+        let message = if service_account::active() {
+            format!(
+                "No item matched title: {item_title} (only vaults granted to the {} token are searched)",
+                service_account::TOKEN_ENV
+            )
+        } else {
+            format!("No item matched title: {item_title}")
+        };
+        return Err(error::Error::new(error::Kind::ItemNotFound, message).into());
+    }
+    if matches.len() > 1 {
+        let candidates: Vec<error::Candidate> = matches
+            .iter()
+            .take(20)
+            .map(|it| error::Candidate {
+                id: it.id.clone(),
+                title: it.title.clone(),
+                vault: it.vault.as_ref().map(|v| v.name.clone()),
+            })
+            .collect();
+        return Err(error::Error::new(
+            error::Kind::AmbiguousItem,
+            "Please be more specific or use `opz find <query>` and pass exact title.",
+        )
+        .with_candidates(candidates)
+        .into());
+    }
+
+    let entry = matches[0];
+    tracing::info!(
+        "'{item_title}' matched item '{}' ({}) in vault {} ({match_kind} match)",
+        entry.title,
+        entry.id,
+        entry.vault.as_ref().map_or("-", |v| v.name.as_str())
+    );
+    Ok(entry)
+}
+
+/// Host of a website URL, without port, credentials or a leading `www.`; the scheme is
+/// optional since 1Password keeps websites as typed (`stripe.com`).
+fn url_host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_port = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = host_port.split(':').next()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    (!host.is_empty()).then_some(host)
+}
+
+/// `Vault/Title` split on the first `/` into the vault's items and the title, when the part
+/// before it names a vault some item is in and no item is titled `item_title` as a whole
+/// (titles such as `org/repo` stay titles). Otherwise all items and `item_title` itself.
+fn vault_qualified<'a, 'b>(
+    items: &'a [ItemListEntry],
+    item_title: &'b str,
+) -> (Vec<&'a ItemListEntry>, &'b str) {
+    let all = || items.iter().collect();
+    if items.iter().any(|x| x.title == item_title) {
+        return (all(), item_title);
+    }
+    let Some((vault, title)) = item_title.split_once('/') else {
+        return (all(), item_title);
+    };
+    let in_vault: Vec<&ItemListEntry> = items
+        .iter()
+        .filter(|x| x.vault.as_ref().is_some_and(|v| v.name == vault))
+        .collect();
+    if in_vault.is_empty() {
+        return (all(), item_title);
+    }
+    (in_vault, title)
+}
+
+fn resolve_vault_id(
+    list_vault: Option<&ItemVault>,
+    item_vault: Option<&ItemVault>,
+) -> Option<String> {
+    list_vault.or(item_vault).map(|v| v.id.clone())
+}
+
+/// Env lines of `collected` in `[output]` order, and the output text in `format`. Printed
+/// dotenv output keeps one block per item unless `[output]` asks for a layout.
+pub(crate) fn render_env(
+    collected: &CollectedEnv,
+    output: &config::OutputConfig,
+    format: Format,
+) -> Rendered {
+    let lines = layout_env_lines(
+        &merge_env_lines(&collected.sections),
+        &collected.sections,
+        &collected.key_origins,
+        output,
+    );
+    let custom_layout = output.order.is_some() || !output.keys.is_empty();
+    let text = match format {
+        Format::Dotenv if !custom_layout => sectioned_env_output_string(&collected.sections),
+        _ => env_file_text(&lines, format),
+    };
+    Rendered {
+        lines,
+        skipped: collected.skipped.clone(),
+        text,
+    }
+}
+
+/// Where and how `opz gen` writes its output.
+struct GenOutput<'a> {
+    env_files: &'a [EnvFileArg],
+    fd: Option<i32>,
+    format: Format,
+    merge: EnvMerge,
+    backup: bool,
+    /// Print resolved values to stdout (`--stdout`) instead of any of the above.
+    values: bool,
+}
+
+fn generate_env_output(
+    cli: &Cli,
+    config: &config::Config,
+    items: &[String],
+    output: GenOutput,
+) -> Result<()> {
+    let GenOutput {
+        env_files,
+        fd,
+        format,
+        merge,
+        backup,
+        values,
+    } = output;
+    let collected = telemetry_span::with_span_result(
+        "load_inputs",
+        vec![KeyValue::new("item.count", items.len() as i64)],
+        || collect_item_env_sections(cli, config, items),
+    )?;
+    let rendered = telemetry_span::with_span("main_operation", vec![], || {
+        render_env(&collected, &config.output, format)
+    });
+    telemetry::record_fields_exported("gen", rendered.lines.len());
+    warn_duplicate_values(&collected.value_digests);
+    for skipped in &rendered.skipped {
+        tracing::debug!(
+            "skipped field '{}' of '{}': {}",
+            skipped.label,
+            skipped.item,
+            skipped.reason
+        );
+    }
+
+    if values {
+        return print_resolved_env(cli, items, &rendered.lines, &collected.key_policies);
+    }
+    // --fd wins over env files, which may come from config defaults.
+    if fd.is_some() || env_files.is_empty() {
+        let sink = OutputSink::new(None, fd);
+        return telemetry_span::with_span_result(
+            "write_outputs",
+            vec![
+                KeyValue::new("cli.output_mode", sink.mode()),
+                KeyValue::new("cli.output_path", sink.describe()),
+            ],
+            || sink.write(&rendered.text),
+        );
+    }
+    telemetry_span::with_span_result(
+        "write_outputs",
+        vec![
+            KeyValue::new("cli.output_mode", "file"),
+            KeyValue::new("cli.output_path", env_files_label(env_files)),
+        ],
+        || {
+            for file in env_files {
+                let format = file.format.unwrap_or(format);
+                if backup {
+                    back_up_env_file(&file.path)?;
+                }
+                if format != Format::Nix {
+                    guard_gitignore(&file.path, cli.ensure_gitignore)?;
+                }
+                write_env_output(&file.path, &rendered.lines, format, merge)?;
+                eprintln!("Generated: {}", file.path.display());
+            }
+            Ok(())
+        },
+    )
+}
+
+/// `opz gen --stdout`: `lines` with their references read (one `op run` for all of them),
+/// printed as dotenv. `file-only` fields stay references, as they do for `run`.
+fn print_resolved_env(
+    cli: &Cli,
+    items: &[String],
+    lines: &[String],
+    key_policies: &HashMap<String, BTreeSet<config::FieldPolicy>>,
+) -> Result<()> {
+    let mut run_dir = run_dir::RunDir::create(items)?;
+    let run_dir_path = run_dir.path().to_path_buf();
+    let remove_run_dir = shutdown::register(shutdown::Stage::RestoreEnvFile, move || {
+        let _ = run_dir::remove(&run_dir_path);
+    });
+    let values = resolve_command_env(cli.account.as_deref(), lines, key_policies, &mut run_dir);
+    remove_run_dir.run_now();
+    let values = values?;
+    let resolved: Vec<String> = lines
+        .iter()
+        .map(|line| match parse_env_line_kv(line) {
+            Some((key, reference)) => {
+                let value = values.get(key).map_or(reference, String::as_str);
+                format!("{key}={}", dotenv_value(value))
+            }
+            None => line.clone(),
+        })
+        .collect();
+    telemetry_span::with_span_result(
+        "write_outputs",
+        vec![
+            KeyValue::new("cli.output_mode", "stdout"),
+            KeyValue::new("cli.output_path", "-"),
+        ],
+        || write_stdout(&env_lines_string(&resolved)),
+    )
+}
+
+/// `opz resolve`: the key/value pairs of `input`, with every `op://` reference read through
+/// [`resolve_env_vars`] (one `op run` for all of them), written to `sink` as dotenv.
+fn resolve_env_file(cli: &Cli, input: &Path, sink: &OutputSink, backup: bool) -> Result<()> {
+    let (content, values) = telemetry_span::with_span_result("load_inputs", vec![], || {
+        let content =
+            fs::read_to_string(input).with_context(|| format!("read {}", input.display()))?;
+        let mut references: Vec<&str> = content
+            .lines()
+            .filter_map(env_reference_line)
+            .map(|(_, reference)| reference)
+            .collect();
+        references.sort_unstable();
+        references.dedup();
+        // Read under made-up keys, so a key that appears twice can hold two references.
+        let lines: Vec<String> = references
+            .iter()
+            .enumerate()
+            .map(|(index, reference)| format!("OPZ_REF_{index}={reference}"))
+            .collect();
+        let mut run_dir = run_dir::RunDir::create(&[input.display().to_string()])?;
+        let run_dir_path = run_dir.path().to_path_buf();
+        let remove_run_dir = shutdown::register(shutdown::Stage::RestoreEnvFile, move || {
+            let _ = run_dir::remove(&run_dir_path);
+        });
+        let values = resolve_env_vars(cli.account.as_deref(), &lines, &mut run_dir);
+        remove_run_dir.run_now();
+        let mut values = values?;
+        let values: HashMap<String, String> = references
+            .iter()
+            .enumerate()
+            .filter_map(|(index, reference)| {
+                let value = values.remove(&format!("OPZ_REF_{index}"))?;
+                Some((reference.to_string(), value))
+            })
+            .collect();
+        Ok((content, values))
+    })?;
+    let text = telemetry_span::with_span("main_operation", vec![], || {
+        resolved_env_text(&content, &values)
+    });
+    telemetry::record_fields_exported("resolve", values.len());
+
+    telemetry_span::with_span_result(
+        "write_outputs",
+        vec![
+            KeyValue::new("cli.output_mode", sink.mode()),
+            KeyValue::new("cli.output_path", sink.describe()),
+        ],
+        || {
+            if let OutputSink::File(path) = sink {
+                if backup {
+                    back_up_env_file(path)?;
+                }
+                guard_gitignore(path, cli.ensure_gitignore)?;
+            }
+            sink.write(&text)?;
+            if let OutputSink::File(path) = sink {
+                eprintln!("Generated: {} ({} resolved)", path.display(), values.len());
+            }
+            Ok(())
+        },
+    )
+}
+
+/// `[export ]KEY` and the reference of a dotenv line whose value is an `op://` reference.
+fn env_reference_line(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim();
+    if trimmed.starts_with('#') {
+        return None;
+    }
+    let (raw_key, raw_value) = trimmed.split_once('=')?;
+    let value = strip_inline_comment(raw_value).trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .unwrap_or(value);
+    is_op_reference(value).then_some((raw_key.trim_end(), value))
+}
+
+/// `content` with each reference line rewritten to its value from `values` (keyed by
+/// reference); every other line, comments and blank lines included, is kept verbatim.
+fn resolved_env_text(content: &str, values: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(content.len());
+    for line in content.lines() {
+        match env_reference_line(line).and_then(|(prefix, reference)| {
+            values
+                .get(reference)
+                .map(|value| format!("{prefix}={}", dotenv_value(value)))
+        }) {
+            Some(resolved) => out.push_str(&resolved),
+            None => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// `--backup`: copy `path` aside before it is changed, warning if git could commit the copy.
+fn back_up_env_file(path: &Path) -> Result<()> {
+    let Some(backup) = backup::create(path)? else {
+        return Ok(());
+    };
+    eprintln!("Backed up {} to {}", path.display(), backup.display());
+    if gitignore::status(&backup) == gitignore::Status::NotIgnored {
+        tracing::warn!(
+            "{} is not ignored by git; add a pattern such as `.env.bak.*` to .gitignore.",
+            backup.display()
+        );
+    }
+    Ok(())
+}
+
+/// `value` as a dotenv value: bare when that is unambiguous, single-quoted (literal in every
+/// dotenv dialect) when possible, and double-quoted with `\\`, `\"` and `\n` escapes otherwise.
+fn dotenv_value(value: &str) -> String {
+    let bare = |c: char| c.is_ascii_alphanumeric() || "_-.,/:@%+=".contains(c);
+    if value.chars().all(bare) {
+        return value.to_string();
+    }
+    if !value.contains(['\'', '\n']) {
+        return format!("'{value}'");
+    }
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "\\$")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
+
+/// Warn before writing an env file that git would pick up, or with `ensure` add it to
+/// `.gitignore`. Nix output is left alone: flakes only see tracked files.
+fn guard_gitignore(path: &Path, ensure: bool) -> Result<()> {
+    if gitignore::status(path) != gitignore::Status::NotIgnored {
+        return Ok(());
+    }
+    if ensure {
+        let (pattern, gitignore) = gitignore::add(path)?;
+        eprintln!("Added {pattern} to {}", gitignore.display());
+    } else {
+        tracing::warn!(
+            "{} is not ignored by git, so it can be committed by accident. Add it to .gitignore or rerun with --ensure-gitignore.",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn env_lines_string(lines: &[String]) -> String {
+    lines.iter().map(|line| format!("{line}\n")).collect()
+}
+
+/// `lines` as the whole content of a file in `format`.
+fn env_file_text(lines: &[String], format: Format) -> String {
+    match format {
+        Format::Dotenv => env_lines_string(lines),
+        Format::Nix => nix_attrset_string(lines),
+        Format::Json => json_object_string(lines),
+    }
+}
+
+/// Render env lines as a JSON object of references, in line order.
+fn json_object_string(lines: &[String]) -> String {
+    let members: Vec<String> = lines
+        .iter()
+        .filter_map(|line| parse_env_line_kv(line))
+        .map(|(key, value)| {
+            format!(
+                "  {}: {}",
+                serde_json::Value::from(key),
+                serde_json::Value::from(value)
+            )
+        })
+        .collect();
+    if members.is_empty() {
+        return "{}\n".to_string();
+    }
+    format!("{{\n{}\n}}\n", members.join(",\n"))
+}
+
+/// Render env lines as a Nix attrset. Only `op://` references are emitted, never values,
+/// since anything evaluated by Nix may end up in the world-readable store.
+fn nix_attrset_string(lines: &[String]) -> String {
+    let mut out = String::from("{\n");
+    for (key, value) in lines.iter().filter_map(|line| parse_env_line_kv(line)) {
+        out.push_str(&format!(
+            "  {} = {};\n",
+            nix_attr_name(key),
+            nix_string(value)
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn nix_attr_name(key: &str) -> String {
+    let mut chars = key.chars();
+    let plain = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\''));
+    if plain {
+        key.to_string()
+    } else {
+        nix_string(key)
+    }
+}
+
+fn nix_string(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
+
+/// Expand $VAR and ${VAR} references in a string using provided environment variables.
+/// Only expands variables that exist in the provided map; others are left as-is
+/// (e.g., $HOME, $PATH).
+fn expand_vars(s: &str, env_vars: &HashMap<String, String>) -> String {
+    expand_vars_tracked(s, env_vars, &mut Vec::new())
+}
+
+/// Names of the variables [`expand_vars`] would substitute in `s`.
+fn expanded_var_names(s: &str, env_vars: &HashMap<String, String>) -> Vec<String> {
+    let mut used = Vec::new();
+    expand_vars_tracked(s, env_vars, &mut used);
+    used
+}
+
+fn expand_vars_tracked(
+    s: &str,
+    env_vars: &HashMap<String, String>,
+    used: &mut Vec<String>,
+) -> String {
+    let mut result = String::with_capacity(s.len() * 2);
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '$' {
+            // Try to parse ${VAR} or $VAR
+            let mut var_name = String::new();
+            let mut is_braced = false;
+
+            if chars.peek() == Some(&'{') {
+                is_braced = true;
+                chars.next(); // consume '{'
+            }
+
+            // Collect variable name (ASCII alphanumeric + underscore only)
+            // This matches shell variable naming rules
+            while let Some(&next) = chars.peek() {
+                match next {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => {
+                        var_name.push(chars.next().unwrap());
+                    }
+                    _ => break,
+                }
+            }
+
+            if is_braced {
+                if chars.peek() == Some(&'}') {
+                    chars.next(); // consume '}'
+                } else {
+                    // Invalid ${ syntax, treat as literal
+                    result.push_str("$\\{");
+                    result.push_str(&var_name);
+                    continue;
+                }
+            }
+
+            // Look up the variable and replace, or keep original literal form
+            if let Some(value) = env_vars.get(&var_name) {
+                result.push_str(value);
+                used.push(var_name);
+            } else {
+                // Variable not found in our env, keep $VAR as-is
+                result.push('$');
+                result.push_str(&var_name);
+            }
+        } else {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+fn run_with_items(
+    cli: &Cli,
+    config: &config::Config,
+    items: &[String],
+    env_files: &[EnvFileArg],
+    command: &[String],
+    run_opts: &RunOptions,
+    dir: Option<&Path>,
+) -> Result<()> {
+    // Everything the run writes besides --env-file goes into its own directory, which is
+    // removed on every exit route, signals included.
+    let mut run_dir = run_dir::RunDir::create(items)?;
+    tracing::debug!("run {} uses {}", run_dir.id(), run_dir.path().display());
+    let run_dir_path = run_dir.path().to_path_buf();
+    let remove_run_dir = shutdown::register(shutdown::Stage::RestoreEnvFile, move || {
+        let _ = run_dir::remove(&run_dir_path);
+    });
+    let temp_env_file;
+    let env_files = if run_opts.temp_env {
+        temp_env_file = [EnvFileArg::dotenv(run_dir.file(".env")?)];
+        &temp_env_file[..]
+    } else {
+        env_files
+    };
+    let CollectedEnv {
+        sections,
+        mut concealed_keys,
+        item_tags,
+        item_ids,
+        key_origins,
+        key_policies,
+        plain_env,
+        ..
+    } = telemetry_span::with_span_result(
+        "load_inputs",
+        vec![KeyValue::new("item.count", items.len() as i64)],
+        || collect_item_env_sections(cli, config, items),
+    )?;
+    check_command_guard(&config.guard, command, &item_tags)?;
+    // Workspace members run their own items, which `opz --last` should not pick up.
+    if dir.is_none() {
+        let used: Vec<(String, String)> = sections
+            .iter()
+            .map(|(title, _)| title.clone())
+            .zip(item_ids)
+            .collect();
+        if let Err(err) = recent::record(&used) {
+            tracing::warn!("failed to record recently used items: {err:#}");
+        }
+    }
+    let prompted = prompt_values(&run_opts.prompt)?;
+    let merged_env_lines = telemetry_span::with_span("main_operation", vec![], || {
+        layout_env_lines(
+            &merge_env_lines(&sections),
+            &sections,
+            &key_origins,
+            &config.output,
+        )
+    });
+    telemetry::record_fields_exported("run", merged_env_lines.len());
+
+    let restore_env_files = telemetry_span::with_span_result(
+        "write_outputs",
+        vec![
+            KeyValue::new("cli.output_path", env_files_label(env_files)),
+            KeyValue::new("cli.command_arg_count", command.len() as i64),
+        ],
+        || {
+            let mut restores = Vec::with_capacity(env_files.len());
+            for file in env_files {
+                let path = &file.path;
+                let format = file.format.unwrap_or(Format::Dotenv);
+                if !run_opts.temp_env && format != Format::Nix {
+                    guard_gitignore(path, cli.ensure_gitignore)?;
+                }
+                let created = !path.exists();
+                if run_opts.backup {
+                    back_up_env_file(path)?;
+                }
+                restores.push(write_env_output_guarded(
+                    path,
+                    &merged_env_lines,
+                    format,
+                    run_opts.env_merge(),
+                )?);
+                eprintln!("Generated: {}", path.display());
+                if run_opts.keep() == KeepEnvFile::None {
+                    // Lets `opz gc` find the file if this run never gets to clean up.
+                    let recorded = fs::read(path)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|content| generated::record(path, &content, created));
+                    if let Err(err) = recorded {
+                        tracing::warn!("failed to record generated env file: {err:#}");
+                    }
+                }
+            }
+            Ok(restores)
+        },
+    )?;
+    // With `--keep=none` the restores stay armed, so they also happen if opz dies mid-run.
+    let mut forget_records = Vec::new();
+    let mut restore_env_files = match run_opts.keep() {
+        KeepEnvFile::None => {
+            // Registered after the restores, so the `opz gc` records go right after the files
+            // on every exit route, signals included.
+            forget_records = env_files
+                .iter()
+                .map(|file| {
+                    let path = file.path.clone();
+                    shutdown::register(shutdown::Stage::RestoreEnvFile, move || {
+                        let _ = generated::forget(&path);
+                    })
+                })
+                .collect();
+            restore_env_files
+        }
+        KeepEnvFile::File => {
+            for restore in restore_env_files {
+                restore.disarm();
+            }
+            Vec::new()
+        }
+    };
+
+    // First pass: collect all environment variable values
+    let mut env_vars = resolve_command_env(
+        cli.account.as_deref(),
+        &merged_env_lines,
+        &key_policies,
+        &mut run_dir,
+    )?;
+    warn_duplicate_values(&resolved_value_digests(&env_vars, &concealed_keys));
+    // Like `op run`, mask every value read from a reference, whatever its field type.
+    let mut masked_values: Vec<String> = if run_opts.mask_output {
+        env_vars.values().cloned().collect()
+    } else {
+        Vec::new()
+    };
+    add_plain_env(&mut env_vars, &mut concealed_keys, plain_env);
+    for (key, value) in &run_opts.extra_env {
+        // A plain value now, whatever the item field of that name was.
+        concealed_keys.remove(key);
+        env_vars.insert(key.clone(), value.clone());
+    }
+    for (key, value) in prompted {
+        if run_opts.mask_output {
+            masked_values.push(value.clone());
+        }
+        concealed_keys.insert(key.clone());
+        env_vars.insert(key, value);
+    }
+    if run_opts.temp_env {
+        if let Some(file) = env_files.first() {
+            env_vars.insert(TEMP_ENV_VAR.to_string(), file.path.display().to_string());
+        }
+    }
+
+    // Second pass: expand $VAR references in command arguments
+    let expanded_args = expand_command(
+        command,
+        &env_vars,
+        &key_policies,
+        &concealed_keys,
+        run_opts.allow_argv_secrets,
+    )?;
+
+    let result = telemetry_span::with_span_result("write_outputs.command_exec", vec![], || {
+        let mut cmd = command_with_env(&expanded_args, &env_vars, run_opts.inherited_env());
+        if let Some(dir) = dir {
+            cmd.current_dir(dir);
+        }
+
+        let masker = run_opts
+            .mask_output
+            .then(|| Arc::new(mask::Masker::new(masked_values.iter().map(String::as_str))));
+        let output = || match masker {
+            Some(_) => Stdio::piped(),
+            None => Stdio::inherit(),
+        };
+        if telemetry_span::capture_args() {
+            telemetry_span::set_run_attribute(KeyValue::new(
+                "child.args",
+                telemetry_span::sanitize_for_trace(&command.join(" ")),
+            ));
+        }
+        let started = Instant::now();
+        let mut child = cmd
+            .stdin(Stdio::inherit())
+            .stdout(output())
+            .stderr(output())
+            .spawn()
+            .context("failed to run command")?;
+        let mut pumps = Vec::new();
+        if let Some(masker) = &masker {
+            if let Some(stdout) = child.stdout.take() {
+                let masker = Arc::clone(masker);
+                pumps.push(std::thread::spawn(move || {
+                    mask::pump(stdout, std::io::stdout(), &masker)
+                }));
+            }
+            if let Some(stderr) = child.stderr.take() {
+                let masker = Arc::clone(masker);
+                pumps.push(std::thread::spawn(move || {
+                    mask::pump(stderr, std::io::stderr(), &masker)
+                }));
+            }
+        }
+        let pid = child.id();
+        let stop_child = shutdown::register(shutdown::Stage::StopChild, move || {
+            shutdown::stop_child(pid)
+        });
+        if let Some(delay_ms) = run_opts.eager_clear {
+            std::thread::sleep(Duration::from_millis(delay_ms));
+            for restore in restore_env_files.drain(..) {
+                restore.run_now();
+            }
+            for forget in forget_records.drain(..) {
+                forget.run_now();
+            }
+        }
+        let status = child.wait().context("failed to wait for command")?;
+        stop_child.disarm();
+        record_child_outcome(status, started.elapsed());
+        // A closed stdout only means the command's output has nowhere to go; its status still
+        // decides the result.
+        for pump in pumps {
+            let _ = pump.join();
+        }
+
+        if !status.success() {
+            return Err(anyhow!("command failed with status: {}", status));
+        }
+        Ok(())
+    });
+
+    match run_opts.keep() {
+        KeepEnvFile::None => {
+            for restore in restore_env_files {
+                restore.run_now();
+            }
+            for forget in forget_records {
+                forget.run_now();
+            }
+        }
+        KeepEnvFile::File => {}
+    }
+    remove_run_dir.run_now();
+    result
+}
+
+/// Plain item values (see `item_plain_env`) for the command's environment. They come from
+/// later items than any reference they replace, so they win like a later field would.
+fn add_plain_env(
+    env_vars: &mut HashMap<String, String>,
+    concealed_keys: &mut HashSet<String>,
+    plain_env: Vec<(String, String)>,
+) {
+    for (key, value) in plain_env {
+        concealed_keys.remove(&key);
+        env_vars.insert(key, value);
+    }
+}
+
+/// Secret values of `env_lines` for the command's environment. `file-only` fields are left
+/// out: they reach the command only as references in --env-file.
+fn resolve_command_env(
+    account: Option<&str>,
+    env_lines: &[String],
+    key_policies: &HashMap<String, BTreeSet<config::FieldPolicy>>,
+    run_dir: &mut run_dir::RunDir,
+) -> Result<HashMap<String, String>> {
+    let mut env_vars = telemetry_span::with_span_result("load_inputs", vec![], || {
+        resolve_env_vars(account, env_lines, run_dir)
+    })?;
+    env_vars.retain(|key, _| {
+        !key_policies
+            .get(key)
+            .is_some_and(|policies| policies.contains(&config::FieldPolicy::FileOnly))
+    });
+    Ok(env_vars)
+}
+
+/// `command` with `$VAR` references expanded, unless that would put a secret in argv that
+/// the field policies or `--allow-argv-secrets` do not allow there.
+fn expand_command(
+    command: &[String],
+    env_vars: &HashMap<String, String>,
+    key_policies: &HashMap<String, BTreeSet<config::FieldPolicy>>,
+    concealed_keys: &HashSet<String>,
+    allow_argv_secrets: bool,
+) -> Result<Vec<String>> {
+    let expanded_args: Vec<String> = telemetry_span::with_span("main_operation", vec![], || {
+        command
+            .iter()
+            .map(|arg| expand_vars(arg, env_vars))
+            .collect()
+    });
+    let argv_vars: Vec<String> = command
+        .iter()
+        .flat_map(|arg| expanded_var_names(arg, env_vars))
+        .collect();
+    check_argv_policy(&argv_vars, key_policies)?;
+    check_argv_secrets(&argv_vars, concealed_keys, allow_argv_secrets)?;
+    Ok(expanded_args)
+}
+
+/// `--isolate`: what the command still gets from opz's environment.
+const ISOLATED_ENV: [&str; 3] = ["PATH", "HOME", "TERM"];
+
+/// `sh -c 'exec "$@"'` running `args`, with the secrets in its environment. With `inherit`,
+/// only those variables of opz's own environment are passed on (`--isolate`).
+fn command_with_env(
+    args: &[String],
+    env_vars: &HashMap<String, String>,
+    inherit: Option<Vec<&str>>,
+) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c");
+    cmd.arg("exec \"$@\"");
+    cmd.arg("sh");
+    cmd.args(args);
+    if let Some(names) = inherit {
+        cmd.env_clear();
+        for name in names {
+            if let Some(value) = std::env::var_os(name) {
+                cmd.env(name, value);
+            }
+        }
+    }
+    // Lets the command join this trace; fields of the items still win on a name clash.
+    cmd.envs(telemetry_span::trace_context_env());
+    cmd.envs(env_vars);
+    cmd
+}
+
+/// `opz watch`: the command runs as with `opz run` (without an env file), and [`watch::run`]
+/// restarts it with secrets resolved afresh whenever one of the items is updated.
+fn watch_items(
+    cli: &Cli,
+    config: &config::Config,
+    items: &[String],
+    command: &[String],
+    interval: Duration,
+    allow_argv_secrets: bool,
+) -> Result<()> {
+    if cli.offline {
+        return Err(anyhow!(
+            "opz watch polls `op` for item changes and cannot run with --offline"
+        ));
+    }
+    let mut run_dir = run_dir::RunDir::create(items)?;
+    let run_dir_path = run_dir.path().to_path_buf();
+    let remove_run_dir = shutdown::register(shutdown::Stage::RestoreEnvFile, move || {
+        let _ = run_dir::remove(&run_dir_path);
+    });
+    let account = cli.account.as_deref();
+    let vault = cli.vault.as_deref();
+    // Watched by id, so the items are followed even if they are renamed.
+    let watched = items
+        .iter()
+        .map(|title| {
+            find_item_entry(account, vault, CachePolicy::from_cli(cli), title)
+                .map(|entry| (entry.title, entry.id))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let versions = || {
+        let fresh = CachePolicy {
+            serve_stale: false,
+            offline: false,
+            refresh: true,
+        };
+        let listed = item_list_cached(account, vault, fresh)?;
+        Ok(watched
+            .iter()
+            .map(|(title, id)| {
+                let updated_at = listed
+                    .iter()
+                    .find(|entry| &entry.id == id)
+                    .and_then(|entry| entry.updated_at.clone());
+                (title.clone(), updated_at)
+            })
+            .collect())
+    };
+    // The item list was just refreshed, so the item details are fetched again if they moved.
+    let prepare = || {
+        let CollectedEnv {
+            sections,
+            mut concealed_keys,
+            item_tags,
+            key_origins,
+            key_policies,
+            plain_env,
+            ..
+        } = telemetry_span::with_span_result(
+            "load_inputs",
+            vec![KeyValue::new("item.count", items.len() as i64)],
+            || collect_item_env_sections(cli, config, items),
+        )?;
+        check_command_guard(&config.guard, command, &item_tags)?;
+        let env_lines = layout_env_lines(
+            &merge_env_lines(&sections),
+            &sections,
+            &key_origins,
+            &config.output,
+        );
+        let mut env_vars = resolve_command_env(account, &env_lines, &key_policies, &mut run_dir)?;
+        add_plain_env(&mut env_vars, &mut concealed_keys, plain_env);
+        let args = expand_command(
+            command,
+            &env_vars,
+            &key_policies,
+            &concealed_keys,
+            allow_argv_secrets,
+        )?;
+        Ok(command_with_env(&args, &env_vars, None))
+    };
+    let spawn = |mut cmd: Command| {
+        cmd.stdin(Stdio::inherit())
+            .spawn()
+            .context("failed to run command")
+    };
+    let started = Instant::now();
+    let status = telemetry_span::with_span_result("write_outputs.command_exec", vec![], || {
+        watch::run(interval, shutdown::grace_period(), prepare, spawn, versions)
+    });
+    remove_run_dir.run_now();
+    let status = status?;
+    record_child_outcome(status, started.elapsed());
+    if !status.success() {
+        return Err(anyhow!("command failed with status: {}", status));
+    }
+    Ok(())
+}
+
+/// `child.*` attributes for the run span, so a trace tells a failed command from a failed `op`.
+fn record_child_outcome(status: std::process::ExitStatus, elapsed: Duration) {
+    if let Some(code) = status.code() {
+        telemetry_span::set_run_attribute(KeyValue::new("child.exit_code", i64::from(code)));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            telemetry_span::set_run_attribute(KeyValue::new("child.signal", i64::from(signal)));
+        }
+    }
+    telemetry_span::set_run_attribute(KeyValue::new(
+        "child.duration_ms",
+        elapsed.as_millis() as i64,
+    ));
+}
+
+/// Run `command` in every workspace member, continuing past failures, then print a summary.
+fn run_workspace(
+    cli: &Cli,
+    config: &config::Config,
+    command: &[String],
+    run_opts: &RunOptions,
+) -> Result<()> {
+    let workspace = &config.workspace;
+    if workspace.members.is_empty() {
+        return Err(anyhow!(
+            "No workspace members configured. Add [[workspace.members]] entries to {}",
+            config::PROJECT_CONFIG_FILE
+        ));
+    }
+
+    let mut results = Vec::with_capacity(workspace.members.len());
+    for member in &workspace.members {
+        let name = member.path.display().to_string();
+        eprintln!("==> {name}");
+        let result = telemetry_span::with_span_result(
+            "workspace.member",
+            vec![KeyValue::new("workspace.member", name.clone())],
+            || {
+                run_with_items(
+                    cli,
+                    config,
+                    &member.items,
+                    &[],
+                    command,
+                    run_opts,
+                    Some(&workspace.member_dir(member)),
+                )
+            },
+        );
+        if let Err(err) = &result {
+            eprintln!("{name}: {err:#}");
+        }
+        results.push((name, result.is_ok()));
+    }
+
+    eprint!("{}", workspace_summary_string(&results));
+    let failed = results.iter().filter(|(_, ok)| !ok).count();
+    if failed > 0 {
+        return Err(anyhow!(
+            "{failed} of {} workspace members failed",
+            results.len()
+        ));
+    }
+    Ok(())
+}
+
+fn workspace_summary_string(results: &[(String, bool)]) -> String {
+    let mut out = String::from("MEMBER\tSTATUS\n");
+    for (name, ok) in results {
+        out.push_str(&format!("{name}\t{}\n", if *ok { "ok" } else { "failed" }));
+    }
+    out
+}
+
+/// clap's static script plus a hook that completes item titles and `--vault` values from
+/// `opz __complete-items`, which only reads the cache.
+fn completion_script(shell: clap_complete::Shell) -> String {
+    let mut command = Cli::command();
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, "opz", &mut script);
+    let mut out = String::from_utf8_lossy(&script).into_owned();
+    let (item_commands, other_commands) = completion_subcommands(&command);
+    let hook = match shell {
+        clap_complete::Shell::Bash => BASH_ITEM_COMPLETION,
+        clap_complete::Shell::Zsh => ZSH_ITEM_COMPLETION,
+        clap_complete::Shell::Fish => FISH_ITEM_COMPLETION,
+        _ => return out,
+    };
+    out.push_str(
+        &hook
+            .replace("@ITEM_COMMANDS@", &item_commands.join(" "))
+            .replace("@OTHER_COMMANDS@", &other_commands.join("|"))
+            .replace(
+                "@ALL_COMMANDS@",
+                &[item_commands, other_commands].concat().join(" "),
+            ),
+    );
+    out
+}
+
+/// Visible subcommand names split into those taking existing item titles as positionals
+/// (`item`/`items`) and the rest, so the hooks follow the CLI as it grows. `create` names a
+/// new item, so it counts as the rest.
+fn completion_subcommands(command: &clap::Command) -> (Vec<String>, Vec<String>) {
+    let mut item_commands = Vec::new();
+    let mut other_commands = Vec::new();
+    for sub in command.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        let name = sub.get_name().to_string();
+        let takes_items = name != "create"
+            && sub.get_positionals().any(|arg| {
+                let id = arg.get_id().as_str();
+                id == "item" || id == "items"
+            });
+        if takes_items {
+            item_commands.push(name);
+        } else {
+            other_commands.push(name);
+        }
+    }
+    // clap only adds its `help` subcommand once the command is built.
+    if !other_commands.iter().any(|name| name == "help") {
+        other_commands.push("help".to_string());
+    }
+    (item_commands, other_commands)
+}
+
+const BASH_ITEM_COMPLETION: &str = r#"
+_opz_cached() {
+    local IFS=$'\n' candidate
+    for candidate in $(opz __complete-items $2 2>/dev/null); do
+        [[ "$candidate" == "$1"* ]] && COMPREPLY+=("$(printf '%q' "$candidate")")
+    done
+}
+_opz_with_items() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local before=" ${COMP_WORDS[*]:1:COMP_CWORD-1} "
+    if [[ "$before" == *" -- "* ]]; then
+        COMPREPLY=()
+    elif [[ "$prev" == "--vault" ]]; then
+        COMPREPLY=()
+        _opz_cached "$cur" --vaults
+    else
+        _opz "$@"
+        [[ "$cur" == -* ]] && return
+        COMPREPLY=($(printf '%s\n' "${COMPREPLY[@]}" | grep -v '^-'))
+        [[ "$before" =~ \ (@OTHER_COMMANDS@)\  ]] ||
+            _opz_cached "$cur"
+    fi
+}
+complete -F _opz_with_items -o bashdefault -o default opz
+"#;
+
+const ZSH_ITEM_COMPLETION: &str = r#"
+_opz_with_items() {
+    if (( ${words[(I)--]} )); then
+        _opz "$@"
+    elif [[ "${words[CURRENT-1]}" == "--vault" ]]; then
+        local -a vaults; vaults=("${(@f)$(opz __complete-items --vaults 2>/dev/null)}")
+        compadd -a vaults
+    else
+        if [[ "${words[CURRENT]}" != -* ]] &&
+            ! (( ${words[(I)(@OTHER_COMMANDS@)]} )); then
+            local -a items; items=("${(@f)$(opz __complete-items 2>/dev/null)}")
+            compadd -a items
+        fi
+        _opz "$@"
+    fi
+}
+compdef _opz_with_items opz
+"#;
+
+const FISH_ITEM_COMPLETION: &str = r#"
+complete -c opz -n '__fish_seen_subcommand_from @ITEM_COMMANDS@; or not __fish_seen_subcommand_from @ALL_COMMANDS@' -f -a '(opz __complete-items 2>/dev/null)' -d 'Item'
+complete -c opz -l vault -x -a '(opz __complete-items --vaults 2>/dev/null)'
+"#;
+
+/// Item titles (or vault names) from every item list cache, regardless of age.
+fn cached_completion_candidates(vaults: bool) -> Result<Vec<String>> {
+    let cache_dir = item_list_cache_dir()?;
+    let Ok(dir) = fs::read_dir(&cache_dir) else {
+        return Ok(Vec::new());
+    };
+
+    let mut candidates = BTreeSet::new();
+    for entry in dir {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if !(name.starts_with("item_list_") && name.ends_with(".json")) {
+            continue;
+        }
+        let Some(cache) = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<ItemListCache>(&bytes).ok())
+        else {
+            continue;
+        };
+        for item in cache.items {
+            if vaults {
+                candidates.extend(item.vault.map(|vault| vault.name));
+            } else {
+                candidates.insert(item.title);
+            }
+        }
+    }
+    Ok(candidates.into_iter().collect())
+}
+
+/// Best-effort startup check for env files abandoned by crashed `--keep=none` runs.
+fn check_leftover_env_files(auto_gc: bool) {
+    if auto_gc {
+        if let Ok(results) = generated::collect(false) {
+            eprint!("{}", gc_report_string(&results, false));
+        }
+        if let Ok(run_dirs) = run_dir::collect(false) {
+            eprint!("{}", gc_run_dirs_string(&run_dirs, false));
+        }
+        return;
+    }
+    if let Ok(stale) = generated::stale() {
+        if !stale.is_empty() {
+            tracing::warn!(
+                "{} env file(s) left behind by interrupted opz runs. Run `opz gc` to clean up (or pass --auto-gc).",
+                stale.len()
+            );
+        }
+    }
+}
+
+fn gc_report_string(results: &[(generated::Entry, generated::Outcome)], dry_run: bool) -> String {
+    let mut out = String::new();
+    for (entry, outcome) in results {
+        let path = entry.path.display();
+        match outcome {
+            generated::Outcome::Removed if dry_run => {
+                out.push_str(&format!("Would remove: {path}\n"))
+            }
+            generated::Outcome::Removed => out.push_str(&format!("Removed: {path}\n")),
+            generated::Outcome::Missing => {}
+            generated::Outcome::Modified => {
+                out.push_str(&format!("Kept (modified since opz wrote it): {path}\n"))
+            }
+            generated::Outcome::Preexisting => out.push_str(&format!(
+                "Kept (opz merged into an existing file; review it manually): {path}\n"
+            )),
+        }
+    }
+    out
+}
+
+fn gc_run_dirs_string(run_dirs: &[PathBuf], dry_run: bool) -> String {
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    run_dirs
+        .iter()
+        .map(|path| format!("{verb} run directory: {}\n", path.display()))
+        .collect()
+}
+
+/// Refuse to wrap deny-listed commands (e.g. `env`, `printenv`, `curl`) when any item is
+/// production-tagged, since they would dump injected secrets to the terminal or network.
+fn check_command_guard(
+    guard: &config::GuardConfig,
+    command: &[String],
+    item_tags: &[(String, Vec<String>)],
+) -> Result<()> {
+    let Some(program) = command
+        .first()
+        .and_then(|arg| Path::new(arg).file_name())
+        .and_then(|name| name.to_str())
+    else {
+        return Ok(());
+    };
+    if !guard.denies(program) {
+        return Ok(());
+    }
+
+    let production_items: Vec<&str> = item_tags
+        .iter()
+        .filter(|(_, tags)| tags.iter().any(|tag| guard.is_production_tag(tag)))
+        .map(|(title, _)| title.as_str())
+        .collect();
+    if production_items.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "Refusing to run `{program}` with production-tagged item(s): {}.\n\
+         `{program}` is listed in `guard.deny_commands` of the opz config because it could print \
+         injected secrets to the terminal or send them over the network.\n\
+         Use a non-production item, or remove `{program}` from the deny-list in {} or {}.",
+        production_items.join(", "),
+        config::PROJECT_CONFIG_FILE,
+        config::global_config_path()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "the global config".to_string()),
+    ))
+}
+
+/// Read each `--prompt` field from the terminal with echo off. The values are never written
+/// to the env file or any cache.
+fn prompt_values(fields: &[String]) -> Result<Vec<(String, String)>> {
+    let re = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$")?;
+    fields
+        .iter()
+        .map(|field| {
+            if !re.is_match(field) {
+                return Err(anyhow!(
+                    "--prompt {field}: not a valid environment variable name"
+                ));
+            }
+            service_account::ensure_interactive(&format!("--prompt {field}"))?;
+            let value = rpassword::prompt_password(format!("{field}: ")).with_context(|| {
+                format!("failed to read {field} from the terminal (--prompt needs a TTY)")
+            })?;
+            Ok((field.clone(), value))
+        })
+        .collect()
+}
+
+/// `argv-forbidden` fields never reach argv, whatever the flags say.
+fn check_argv_policy(
+    argv_vars: &[String],
+    key_policies: &HashMap<String, BTreeSet<config::FieldPolicy>>,
+) -> Result<()> {
+    let mut forbidden: Vec<&str> = argv_vars
+        .iter()
+        .map(String::as_str)
+        .filter(|name| {
+            key_policies
+                .get(*name)
+                .is_some_and(|policies| policies.contains(&config::FieldPolicy::ArgvForbidden))
+        })
+        .collect();
+    forbidden.sort_unstable();
+    forbidden.dedup();
+    if forbidden.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "Refusing to substitute {} into command arguments: the argv-forbidden policy in the opz config applies.\n\
+         Read the value from the environment inside the command instead.",
+        forbidden.join(", ")
+    ))
+}
+
+/// argv is world-readable (`ps`, `/proc/<pid>/cmdline`), so concealed values must not land there
+/// unless the user explicitly opts in.
+fn check_argv_secrets(
+    argv_vars: &[String],
+    concealed_keys: &HashSet<String>,
+    allow: bool,
+) -> Result<()> {
+    let mut leaked: Vec<&str> = argv_vars
+        .iter()
+        .map(String::as_str)
+        .filter(|name| concealed_keys.contains(*name))
+        .collect();
+    leaked.sort_unstable();
+    leaked.dedup();
+    if leaked.is_empty() {
+        return Ok(());
+    }
+
+    let names = leaked.join(", ");
+    if allow {
+        tracing::warn!(
+            "concealed field(s) {names} substituted into command arguments; argv is visible to other users via `ps`."
+        );
+        return Ok(());
+    }
+
+    let example = leaked[0];
+    Err(anyhow!(
+        "Refusing to substitute concealed field(s) {names} into command arguments: argv is visible to other users via `ps`.\n\
+         Read the value from the environment inside the command instead (e.g. `-- sh -c 'tool --token \"${example}\"'`), \
+         or write it to a file with --env-file.\n\
+         Pass --allow-argv-secrets to substitute anyway."
+    ))
+}
+
+/// Env lines for an item's fields. Keys come from the label (after `[fields]`/`[labels]`
+/// config); the `op://` reference always uses the original label.
+fn item_to_env_lines(
+    item: &ItemGet,
+    vault_id: &str,
+    item_id: &str,
+    config: &config::Config,
+) -> Result<Vec<String>> {
+    let re = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$")?;
+    let mut out = Vec::new();
+
+    for f in &item.fields {
+        let Some(label) = f.label.as_ref() else {
+            continue;
+        };
+        let Some(key) = field_env_key(config, item, f) else {
+            continue;
+        };
+        if !re.is_match(&key) {
+            // env var invalid -> skip
+            continue;
+        }
+        // Skip fields without value
+        if f.value.is_none() {
+            continue;
+        }
+
+        let reference = format!("op://{}/{}/{}", vault_id, item_id, label);
+        out.push(format!("{k}={v}", k = key, v = reference));
+    }
+
+    Ok(out)
+}
+
+/// Env key a Login item's primary website is exported as.
+const LOGIN_WEBSITE_KEY: &str = "WEBSITE";
+
+/// Plain (non-reference) env values of an item: the primary website of a Login item. `op`
+/// keeps URLs outside the item's fields, so there is no reference to export; they stay out of
+/// the env lines, which hold references only, and reach the command's environment like
+/// `--env` values. A field exported under the same key wins.
+fn item_plain_env(item: &ItemGet, config: &config::Config) -> Vec<(String, String)> {
+    let taken = |key: &str| {
+        item.fields
+            .iter()
+            .any(|field| field_env_key(config, item, field).as_deref() == Some(key))
+    };
+    login_website(item, config)
+        .filter(|_| !taken(LOGIN_WEBSITE_KEY))
+        .map(|website| (LOGIN_WEBSITE_KEY.to_string(), website.to_string()))
+        .into_iter()
+        .collect()
+}
+
+/// Primary website of a Login item with `[labels] smart = true`.
+fn login_website<'a>(item: &'a ItemGet, config: &config::Config) -> Option<&'a str> {
+    if !config.smart_labels()
+        || !item
+            .category
+            .as_deref()
+            .is_some_and(|category| category.eq_ignore_ascii_case("LOGIN"))
+    {
+        return None;
+    }
+    item.urls
+        .iter()
+        .find(|url| url.primary)
+        .or(item.urls.first())
+        .map(|url| url.href.as_str())
+        .filter(|href| !href.is_empty())
+}
+
+/// [`config::Config::map_field`] for a field of `item`. Built-in fields sit outside any
+/// section, so only those get the item category's conventional names.
+fn field_mapping(
+    config: &config::Config,
+    item: &ItemGet,
+    field: &ItemField,
+) -> Option<config::LabelMapping> {
+    let label = field.label.as_deref()?;
+    let builtin = field
+        .section
+        .as_ref()
+        .and_then(|s| s.label.as_ref())
+        .is_none();
+    let category = item.category.as_deref().filter(|_| builtin);
+    Some(config.map_field(category, label))
+}
+
+/// [`config::Config::env_key`] for a field of `item`; see [`field_mapping`].
+fn field_env_key(config: &config::Config, item: &ItemGet, field: &ItemField) -> Option<String> {
+    let mapping = field_mapping(config, item, field)?;
+    mapping.dropped_by.is_none().then_some(mapping.key)
+}
+
+/// Labelled fields [`item_to_env_lines`] leaves out, with the reason.
+fn item_skipped_fields(item: &ItemGet, config: &config::Config) -> Vec<(String, String)> {
+    let re = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").expect("valid regex");
+    item.fields
+        .iter()
+        .filter_map(|f| {
+            let reason = field_export(&re, config, item, f)?.err()?;
+            Some((f.label.clone()?, reason))
+        })
+        .collect()
+}
+
+/// The env key a labelled field of `item` is exported as, or why it is left out; `None` for a
+/// field without a label. `re` matches valid env var names.
+fn field_export(
+    re: &Regex,
+    config: &config::Config,
+    item: &ItemGet,
+    f: &ItemField,
+) -> Option<std::result::Result<String, String>> {
+    let mapping = field_mapping(config, item, f)?;
+    Some(if let Some(rule) = mapping.dropped_by {
+        Err(rule)
+    } else if !re.is_match(&mapping.key) {
+        Err(format!("\"{}\" is not a valid env var name", mapping.key))
+    } else if f.value.is_none() {
+        Err("no value".to_string())
+    } else {
+        Ok(mapping.key)
+    })
+}
+
+/// Env keys of exported fields typed `CONCEALED` (passwords, tokens).
+fn item_concealed_keys(item: &ItemGet, config: &config::Config) -> Result<Vec<String>> {
+    let re = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$")?;
+    Ok(item
+        .fields
+        .iter()
+        .filter(|f| f.value.is_some())
+        .filter(|f| {
+            f.field_type
+                .as_deref()
+                .is_some_and(|t| t.eq_ignore_ascii_case("CONCEALED"))
+        })
+        .filter_map(|f| field_env_key(config, item, f))
+        .filter(|key| re.is_match(key))
+        .collect())
+}
+
+fn item_to_valid_labels(item: &ItemGet, config: &config::Config) -> Result<Vec<String>> {
+    let re = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$")?;
+    let mut out = Vec::new();
+
+    for f in &item.fields {
+        let Some(key) = field_env_key(config, item, f) else {
+            continue;
+        };
+        if !re.is_match(&key) {
+            continue;
+        }
+        out.push(key);
+    }
+    if login_website(item, config).is_some() && !out.iter().any(|key| key == LOGIN_WEBSITE_KEY) {
+        out.push(LOGIN_WEBSITE_KEY.to_string());
+    }
+
+    Ok(out)
+}
+
+/// Parse env line to extract key name (e.g., "KEY=value" -> "KEY")
+fn parse_env_key(line: &str) -> Option<&str> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
+    }
+    let trimmed = match trimmed.strip_prefix("export") {
+        Some(rest) if rest.starts_with(char::is_whitespace) => rest.trim_start(),
+        _ => trimmed,
+    };
+    trimmed.split('=').next().map(str::trim_end)
+}
+
+/// Parse env line to extract key and value (e.g., "KEY=value" -> ("KEY", "value"))
+fn parse_env_line_kv(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
+    }
+    trimmed.split_once('=')
+}
+
+/// Read a secret from 1Password using op read
+fn op_read(account: Option<&str>, reference: &str) -> Result<String> {
+    telemetry_span::with_span_result("load_inputs.op_read", vec![], || {
+        let mut cmd = op_command(account)?;
+        cmd.arg("read").arg(reference);
+        let op_args = logging::sanitize_op_args(cmd.get_args());
+        let out = run_op(cmd).context(
+            error::Error::new(error::Kind::OpFailed, "failed to run `op read`")
+                .with_op_args(op_args.clone()),
+        )?;
+
+        if !out.status.success() {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            return Err(error::Error::new(
+                op_error_kind(&stderr),
+                format!("op read failed: {stderr}"),
+            )
+            .with_op_args(op_args)
+            .into());
+        }
+
+        Ok(String::from_utf8(out.stdout)?.trim().to_string())
+    })
+}
+
+fn write_env_file(path: &Path, new_lines: &[String], merge: EnvMerge) -> Result<()> {
+    write_env_file_guarded(path, new_lines, merge)?.disarm();
+    Ok(())
+}
+
+/// Merge `new_lines` into the env file and return the still-armed action that puts the
+/// previous content back (or removes the file if opz created it).
+fn write_env_file_guarded(
+    path: &Path,
+    new_lines: &[String],
+    merge: EnvMerge,
+) -> Result<shutdown::Registration> {
+    telemetry_span::with_span_result(
+        "write_outputs.write_env_file",
+        vec![
+            KeyValue::new("cli.output_path", path.display().to_string()),
+            KeyValue::new("env.line_count", new_lines.len() as i64),
+        ],
+        || {
+            use std::collections::HashMap;
+
+            // Build a map of new keys for quick lookup
+            let new_keys: HashMap<String, &str> = new_lines
+                .iter()
+                .filter_map(|line| parse_env_key(line).map(|key| (key.to_string(), line.as_str())))
+                .collect();
+
+            let mut result_lines: Vec<String> = Vec::new();
+            let mut written_keys: std::collections::HashSet<String> =
+                std::collections::HashSet::new();
+
+            // Read existing file and merge
+            let mut original: Option<String> = None;
+            if path.exists() {
+                let content =
+                    fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+                original = Some(content.clone());
+
+                for line in content.lines() {
+                    if merge == EnvMerge::Append {
+                        result_lines.push(line.to_string());
+                    } else if let Some(key) = parse_env_key(line) {
+                        if let Some(&new_line) = new_keys.get(key) {
+                            // Overwrite with new value, keeping an `export` prefix
+                            if line.trim_start().starts_with("export") {
+                                result_lines.push(format!("export {new_line}"));
+                            } else {
+                                result_lines.push(new_line.to_string());
+                            }
+                            written_keys.insert(key.to_string());
+                        } else {
+                            // Keep existing line
+                            result_lines.push(line.to_string());
+                        }
+                    } else {
+                        // Comment or empty line - keep as is
+                        result_lines.push(line.to_string());
+                    }
+                }
+            }
+
+            // Append new keys that weren't already in the file. Separator lines (blank lines
+            // and comments) are only written along with a new key that follows them.
+            let mut pending: Vec<&String> = Vec::new();
+            let mut after_key = false;
+            for line in new_lines {
+                match parse_env_key(line) {
+                    Some(key) => {
+                        after_key = true;
+                        if !written_keys.contains(key) {
+                            for separator in pending.drain(..) {
+                                if !(result_lines.is_empty() && separator.is_empty()) {
+                                    result_lines.push(separator.clone());
+                                }
+                            }
+                            result_lines.push(line.clone());
+                        }
+                    }
+                    None => {
+                        if after_key {
+                            pending.clear();
+                            after_key = false;
+                        }
+                        pending.push(line);
+                    }
+                }
+            }
+
+            replace_file_guarded(path, original, &env_lines_string(&result_lines))
+        },
+    )
+}
+
+/// Write `lines` to the env file at `path` in `format`: dotenv files are merged into, others
+/// replaced.
+fn write_env_output(path: &Path, lines: &[String], format: Format, merge: EnvMerge) -> Result<()> {
+    match format {
+        Format::Dotenv => write_env_file(path, lines, merge),
+        _ => output::write_file(path, &env_file_text(lines, format)),
+    }
+}
+
+/// Like [`write_env_output`], but return the still-armed action that puts the previous
+/// content back.
+fn write_env_output_guarded(
+    path: &Path,
+    lines: &[String],
+    format: Format,
+    merge: EnvMerge,
+) -> Result<shutdown::Registration> {
+    if format == Format::Dotenv {
+        return write_env_file_guarded(path, lines, merge);
+    }
+    let original = match fs::read_to_string(path) {
+        Ok(content) => Some(content),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err).with_context(|| format!("read {}", path.display())),
+    };
+    replace_file_guarded(path, original, &env_file_text(lines, format))
+}
+
+/// Replace `path` with `text`; the returned action puts `original` back (or removes the file
+/// if there was none), also if opz dies mid-write.
+fn replace_file_guarded(
+    path: &Path,
+    original: Option<String>,
+    text: &str,
+) -> Result<shutdown::Registration> {
+    let restore_path = path.to_path_buf();
+    // The generated file is owner-only; the restored one gets the original mode back.
+    let permissions = fs::metadata(path).ok().map(|meta| meta.permissions());
+    let restore = shutdown::register(shutdown::Stage::RestoreEnvFile, move || {
+        match &original {
+            // The generated file holds references only, so there is nothing to wipe first.
+            Some(content) => {
+                let _ = output::restore_file(&restore_path, content, permissions);
+            }
+            None => {
+                let _ = output::remove_file(&restore_path);
+            }
+        }
+    });
+    output::write_file(path, text)?;
+    Ok(restore)
+}
+
+/// Build an `op` invocation scoped to `account` (when given).
+pub(crate) fn op_command(account: Option<&str>) -> Result<Command> {
+    if op_settings().offline {
+        return Err(anyhow!(
+            "This operation needs the 1Password CLI (`op`), which --offline does not allow"
+        ));
+    }
+    if let Some(bin) = &op_settings().bin {
+        if op_path().is_none() {
+            return Err(error::Error::new(
+                error::Kind::OpFailed,
+                format!(
+                    "1Password CLI not found at {} (set by --op-bin or OPZ_OP_BIN)",
+                    bin.display()
+                ),
+            )
+            .into());
+        }
+    }
+    if let Some(version) = op_cli_version() {
+        op_version::check(version)?;
+    }
+    let mut cmd = Command::new(op_program());
+    cmd.envs(signin::session_env())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(account) = account {
+        cmd.arg("--account").arg(account);
+    }
+    Ok(cmd)
+}
+
+/// Run a prepared `op` command to completion on the shared runtime, logging its sanitized
+/// arguments and duration. Past `--op-timeout` it is killed and a `TimedOut` error names the
+/// call.
+fn run_op(cmd: Command) -> std::io::Result<Output> {
+    run_op_within(cmd, op_settings().timeout)
+}
+
+/// [`run_op`] for calls that wait on the user (sign-in prompts, editors), which
+/// `--op-timeout` must not cut short.
+pub(crate) fn run_op_interactive(cmd: Command) -> std::io::Result<Output> {
+    run_op_within(cmd, None)
+}
+
+/// Each call gets its own span, named after the `op` subcommand, with its exit status, stderr
+/// size and duration, and the reason a cache was bypassed when that is why `op` runs.
+fn run_op_within(cmd: Command, timeout: Option<Duration>) -> std::io::Result<Output> {
+    let args = logging::sanitize_op_args(cmd.get_args());
+    let subcommand = op_subcommand(cmd.get_args());
+    let mut attrs = vec![KeyValue::new("op.command", subcommand.clone())];
+    if telemetry_span::capture_args() {
+        attrs.push(KeyValue::new("op.args", args.clone()));
+    }
+    if let Some(reason) = telemetry_span::cache_bypass_reason() {
+        attrs.push(KeyValue::new("cache.bypass_reason", reason));
+    }
+    if let Some(timeout) = timeout {
+        attrs.push(KeyValue::new("op.timeout_secs", timeout.as_secs() as i64));
+    }
+    telemetry_span::with_span(&format!("op {subcommand}"), attrs, || {
+        let result = run_op_traced(cmd, timeout, &args, &subcommand);
+        match &result {
+            Ok(out) => {
+                telemetry_span::set_attribute(KeyValue::new(
+                    "op.exit_status",
+                    out.status.code().map_or(-1, i64::from),
+                ));
+                telemetry_span::set_attribute(KeyValue::new(
+                    "op.stderr_bytes",
+                    out.stderr.len() as i64,
+                ));
+                if !out.status.success() {
+                    telemetry_span::record_error_message(&format!(
+                        "op {subcommand} exited with {}",
+                        out.status
+                    ));
+                }
+            }
+            Err(err) => {
+                telemetry_span::set_attribute(KeyValue::new(
+                    "op.timed_out",
+                    err.kind() == std::io::ErrorKind::TimedOut,
+                ));
+                telemetry_span::record_error_message(&err.to_string());
+            }
+        }
+        result
+    })
+}
+
+fn run_op_traced(
+    cmd: Command,
+    timeout: Option<Duration>,
+    args: &str,
+    subcommand: &str,
+) -> std::io::Result<Output> {
+    tracing::debug!("running op {args}");
+    if fault::active(fault::Fault::OpTimeout) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "op timed out (injected fault)",
+        ));
+    }
+    let started = Instant::now();
+    let result = match runtime::block_on(output_within(cmd.into(), timeout)) {
+        Ok(result) => result,
+        Err(err) => Err(std::io::Error::other(format!("{err:#}"))),
+    };
+    let result = result.and_then(|out| {
+        out.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!(
+                    "op {args} timed out after {}s (--op-timeout) and was killed",
+                    started.elapsed().as_secs()
+                ),
+            )
+        })
+    });
+    tracing::debug!(
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "op {args} finished"
+    );
+    telemetry_span::set_attribute(KeyValue::new(
+        "op.duration_ms",
+        started.elapsed().as_millis() as i64,
+    ));
+    telemetry::record_op_call(
+        subcommand,
+        started.elapsed(),
+        result.as_ref().is_ok_and(|out| out.status.success()),
+    );
+    result
+}
+
+/// The `op` subcommand of an invocation without its arguments (`item get`, `read`), skipping
+/// global flags such as `--account <name>`. A bare flag (`--version`) stands for itself.
+fn op_subcommand<'a>(args: impl IntoIterator<Item = &'a std::ffi::OsStr>) -> String {
+    const GROUPS: &[&str] = &[
+        "account", "connect", "document", "group", "item", "plugin", "user", "vault",
+    ];
+    let mut args = args.into_iter().map(|arg| arg.to_string_lossy());
+    let mut words: Vec<String> = Vec::new();
+    let mut first_flag = None;
+    while let Some(arg) = args.next() {
+        if arg.starts_with("--") {
+            if !arg.contains('=') && words.is_empty() {
+                args.next();
+            }
+            first_flag.get_or_insert_with(|| arg.into_owned());
+            continue;
+        }
+        words.push(arg.into_owned());
+        if words.len() == 2 || !GROUPS.contains(&words[0].as_str()) {
+            break;
+        }
+    }
+    match first_flag {
+        Some(flag) if words.is_empty() => flag,
+        _ => words.join(" "),
+    }
+}
+
+/// Like [`Command::output`], except that stdio is left as configured on `cmd`, and once
+/// `timeout` has passed the child is killed and `None` returned.
+async fn output_within(
+    mut cmd: tokio::process::Command,
+    timeout: Option<Duration>,
+) -> std::io::Result<Option<Output>> {
+    // Dropping the wait below on timeout kills the child; the runtime reaps it.
+    let output = cmd.kill_on_drop(true).spawn()?.wait_with_output();
+    match timeout {
+        None => output.await.map(Some),
+        Some(timeout) => match tokio::time::timeout(timeout, output).await {
+            Ok(output) => output.map(Some),
+            Err(_) => Ok(None),
+        },
+    }
+}
+
+/// `op item list`, limited to `vault` (a name or id) by `op` itself.
+fn op_item_list(account: Option<&str>, vault: Option<&str>) -> Result<Vec<ItemListEntry>> {
+    let mut args = vec!["item", "list", "--format", "json"];
+    if let Some(vault) = vault {
+        args.extend(["--vault", vault]);
+    }
+    Ok(serde_json::from_value(op_json(account, &args)?)?)
+}
+
+/// The `op` executable to run: `--op-bin` / `OPZ_OP_BIN`, or `op` from PATH.
+fn op_program() -> &'static std::ffi::OsStr {
+    op_settings()
+        .bin
+        .as_deref()
+        .map_or("op".as_ref(), Path::as_os_str)
+}
+
+/// Where [`op_program`] resolves to, searching PATH for a bare name as `Command` would.
+fn op_path() -> Option<PathBuf> {
+    let program = Path::new(op_program());
+    if program.components().count() > 1 {
+        return program.is_file().then(|| program.to_path_buf());
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+/// The installed `op` CLI's version, asked once per process and cached on disk per binary
+/// (path and modification time, so an upgrade is noticed at once). `None` when `op` is not
+/// on `PATH` or its answer is not a version.
+fn op_cli_version() -> Option<op_version::Version> {
+    static VERSION: OnceLock<Option<op_version::Version>> = OnceLock::new();
+    *VERSION.get_or_init(|| {
+        let path = op_path()?;
+        let modified = fs::metadata(&path)
+            .and_then(|meta| meta.modified())
+            .ok()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let key = format!("op version {} {modified}", path.display());
+        let policy = CachePolicy {
+            serve_stale: true,
+            offline: false,
+            refresh: false,
+        };
+        let output: String = metadata_cached(&key, policy, || {
+            let mut cmd = Command::new(&path);
+            cmd.arg("--version")
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            let out = run_op(cmd)?;
+            if !out.status.success() {
+                return Err(anyhow!("op --version failed ({})", out.status));
+            }
+            Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+        })
+        .inspect_err(|err| tracing::debug!("could not determine the op version: {err:#}"))
+        .ok()?;
+        let version = op_version::Version::parse(&output);
+        tracing::debug!("op version: {}", version.map_or(output, |v| v.to_string()));
+        version
+    })
+}
+
+fn op_json(account: Option<&str>, args: &[&str]) -> Result<serde_json::Value> {
+    let operation = args.iter().take(2).copied().collect::<Vec<_>>().join(" ");
+    telemetry_span::with_span_result(
+        "load_inputs.op_json",
+        vec![KeyValue::new("op.operation", operation)],
+        // `whoami` reports a missing session rather than fixing it.
+        || match op_json_retrying(account, args) {
+            Err(err)
+                if args.first() != Some(&"whoami")
+                    && signin::is_signed_out(&format!("{err:#}")) =>
+            {
+                signin::recover(account, err)?;
+                op_json_retrying(account, args)
+            }
+            result => result,
+        },
+    )
+}
+
+const OP_RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const OP_RETRY_MAX_DELAY: Duration = Duration::from_secs(8);
+
+/// Delay before retry number `attempt` (from 0): doubling from the base, capped.
+fn op_retry_delay(attempt: u32) -> Duration {
+    OP_RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(OP_RETRY_MAX_DELAY)
+}
+
+/// [`op_json_once`], repeated up to `--retries` times while the failure looks transient.
+fn op_json_retrying(account: Option<&str>, args: &[&str]) -> Result<serde_json::Value> {
+    let retries = op_settings().retries;
+    let mut attempt = 0;
+    loop {
+        match op_json_once(account, args) {
+            Err(err) if attempt < retries && backend::is_transient(&err) => {
+                let delay = op_retry_delay(attempt);
+                attempt += 1;
+                tracing::warn!(
+                    "op {} failed ({}); retrying in {:.1}s ({attempt}/{retries})",
+                    args.iter().take(2).copied().collect::<Vec<_>>().join(" "),
+                    format!("{err:#}").lines().next().unwrap_or_default(),
+                    delay.as_secs_f32()
+                );
+                std::thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Failure class of an `op` call that exited unsuccessfully, from its stderr.
+fn op_error_kind(stderr: &str) -> error::Kind {
+    if signin::is_signed_out(stderr) {
+        error::Kind::NotSignedIn
+    } else if stderr.contains("isn't an item") {
+        error::Kind::ItemNotFound
+    } else {
+        error::Kind::OpFailed
+    }
+}
+
+fn op_json_once(account: Option<&str>, args: &[&str]) -> Result<serde_json::Value> {
+    if fault::active(fault::Fault::SignedOut) {
+        return Err(anyhow!(
+            "op error (exit status: 1): [ERROR] You are not currently signed in. Please run `op signin --help` for instructions (injected fault)"
+        ));
+    }
+    let mut cmd = op_command(account)?;
+    cmd.args(args);
+    let op_args = logging::sanitize_op_args(cmd.get_args());
+    let out = run_op(cmd).with_context(|| {
+        error::Error::new(
+            error::Kind::OpFailed,
+            format!("failed to run op {}", args.join(" ")),
+        )
+        .with_op_args(op_args.clone())
+    })?;
+
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let mut message = format!("op error ({}): {stderr}", out.status);
+        if let Some(hint) = service_account::access_hint(&stderr) {
+            message = format!("{}\n{hint}", message.trim_end());
+        }
+        return Err(error::Error::new(op_error_kind(&stderr), message)
+            .with_op_args(op_args)
+            .into());
+    }
+
+    let mut stdout = out.stdout;
+    if fault::active(fault::Fault::MalformedJson) {
+        stdout.truncate(stdout.len() / 2);
+    }
+    let v: serde_json::Value =
+        serde_json::from_slice(&stdout).context("failed to parse op JSON output")?;
+    Ok(v)
+}
+
+/// Cache `op item list --format json` to speed up repeated runs.
+pub(crate) fn item_list_cached(
+    account: Option<&str>,
+    vault: Option<&str>,
+    policy: CachePolicy,
+) -> Result<Vec<ItemListEntry>> {
+    telemetry_span::with_span_result(
+        "load_inputs.item_list_cached",
+        vec![
+            KeyValue::new("vault.specified", vault.is_some()),
+            KeyValue::new("account.specified", account.is_some()),
+        ],
+        || {
+            if !policy.refresh && !policy.offline {
+                if let Some(items) = agent::item_list(account, vault) {
+                    return Ok(items);
+                }
+            }
+            let cache_path = cache_file_path(account, vault)?;
+
+            let age = fs::metadata(&cache_path)
+                .and_then(|meta| meta.modified())
+                .map(|mtime| SystemTime::now().duration_since(mtime).unwrap_or_default());
+            let mut bypass = "missing";
+            if let Ok(age) = age {
+                let cache_use = cache_use(age, policy);
+                bypass = match cache_use {
+                    CacheUse::Refetch if policy.refresh => "refresh",
+                    CacheUse::Refetch => "expired",
+                    _ => "unreadable",
+                };
+                if cache_use != CacheUse::Refetch {
+                    let cached = telemetry_span::with_span_result(
+                        "load_inputs.item_list_cache_read",
+                        vec![
+                            KeyValue::new("cache.path", cache_path.display().to_string()),
+                            KeyValue::new("cache.stale", cache_use == CacheUse::Stale),
+                        ],
+                        || {
+                            let bytes = fs::read(&cache_path)?;
+                            let cache: ItemListCache = serde_json::from_slice(&bytes)?;
+                            Ok(cache.items)
+                        },
+                    );
+                    // Unreadable (or pre-envelope) cache files are simply refetched.
+                    if let Ok(items) = cached {
+                        telemetry::record_cache_lookup("item_list", true);
+                        telemetry_span::note_item_list_lookup(Some((
+                            age,
+                            cache_use == CacheUse::Stale,
+                        )));
+                        tracing::debug!(
+                            age_secs = age.as_secs(),
+                            stale = cache_use == CacheUse::Stale,
+                            "item list cache hit ({})",
+                            cache_path.display()
+                        );
+                        if cache_use == CacheUse::Stale {
+                            spawn_background_refresh(account, vault);
+                        }
+                        return Ok(items);
+                    }
+                }
+            }
+
+            telemetry::record_cache_lookup("item_list", false);
+            telemetry_span::note_item_list_lookup(None);
+            tracing::debug!(
+                refresh = policy.refresh,
+                "item list cache miss ({bypass}, {})",
+                cache_path.display()
+            );
+            if policy.offline {
+                return Err(anyhow!(
+                    "No cached item list for {} (--offline). Run `opz cache warm` while online.",
+                    vault.map_or("all vaults".to_string(), |v| format!("vault '{v}'"))
+                ));
+            }
+            telemetry_span::with_cache_bypass(bypass, || {
+                if policy.refresh {
+                    refresh_item_list_cache(account, vault)
+                } else {
+                    refresh_item_list_cache_once(account, vault)
+                }
+            })
+        },
+    )
+}
+
+/// Refresh under the cache lock. Whoever gets the lock first calls `op`; processes that were
+/// waiting on it reuse the list it wrote instead of fetching again.
+fn refresh_item_list_cache_once(
+    account: Option<&str>,
+    vault: Option<&str>,
+) -> Result<Vec<ItemListEntry>> {
+    let cache_path = cache_file_path(account, vault)?;
+    let _lock =
+        telemetry_span::with_span_result("load_inputs.item_list_cache_lock", vec![], || {
+            cache_file::Lock::acquire(&cache_path)
+        })?;
+
+    let fresh = fs::metadata(&cache_path)
+        .and_then(|meta| meta.modified())
+        .map(|mtime| SystemTime::now().duration_since(mtime).unwrap_or_default())
+        .is_ok_and(|age| age < item_list_cache_ttl());
+    if fresh {
+        let cached = fs::read(&cache_path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<ItemListCache>(&bytes).ok());
+        if let Some(cache) = cached {
+            return Ok(cache.items);
+        }
+    }
+
+    refresh_item_list_cache(account, vault)
+}
+
+/// Re-run `opz cache warm` for this scope as a detached child so the current invocation can
+/// answer from the stale list right away. Failures only mean the next run refetches.
+fn spawn_background_refresh(account: Option<&str>, vault: Option<&str>) {
+    let Ok(exe) = std::env::current_exe() else {
+        return;
+    };
+    let mut cmd = Command::new(exe);
+    // Global flags go after the subcommand; before it they would select the run shorthand.
+    cmd.args(["cache", "warm", "--if-stale"]);
+    if let Some(account) = account {
+        cmd.args(["--account", account]);
+    }
+    if let Some(vault) = vault {
+        cmd.args(["--vault", vault]);
+    }
+    if let Some(bin) = &op_settings().bin {
+        cmd.arg("--op-bin").arg(bin);
+    }
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    if let Ok(mut child) = cmd.spawn() {
+        // Reap it if we are still around (e.g. `opz run` of a long-lived command).
+        std::thread::spawn(move || child.wait());
+    }
+}
+
+/// Cached item list regardless of its age, for the `cache` backend.
+fn read_item_list_cache(cache_path: &Path, vault: Option<&str>) -> Result<Vec<ItemListEntry>> {
+    fs::read(cache_path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<ItemListCache>(&bytes).ok())
+        .map(|cache| cache.items)
+        .ok_or_else(|| {
+            backend::error(
+                backend::ErrorClass::NotFound,
+                format!(
+                    "No cached item list for {}",
+                    vault.map_or("all vaults".to_string(), |v| format!("vault '{v}'"))
+                ),
+            )
+        })
+}
+
+/// Fetch the item list through the backend chain and rewrite the cache file.
+pub(crate) fn refresh_item_list_cache(
+    account: Option<&str>,
+    vault: Option<&str>,
+) -> Result<Vec<ItemListEntry>> {
+    let cache_path = cache_file_path(account, vault)?;
+    let (items, source) =
+        telemetry_span::with_span_result("load_inputs.item_list_fetch", vec![], || {
+            backend::with_fallback("item_list", |source| match source {
+                Backend::Connect => Ok(serde_json::from_value(backend::connect_item_list(vault)?)?),
+                Backend::Cli => op_item_list(account, vault),
+                Backend::Cache => read_item_list_cache(&cache_path, vault),
+            })
+        })?;
+    if source == Backend::Cache {
+        // Rewriting would make an old list look fresh.
+        return Ok(items);
+    }
+    let cache = ItemListCache {
+        account: account.map(String::from),
+        vault: vault.map(String::from),
+        items,
+    };
+    telemetry_span::with_span_result(
+        "load_inputs.item_list_cache_write",
+        vec![KeyValue::new(
+            "cache.path",
+            cache_path.display().to_string(),
+        )],
+        || cache_file::write_cache(&cache_path, &serde_json::to_vec(&cache)?),
+    )?;
+    Ok(cache.items)
+}
+
+const BENCH_CACHE_ROUNDS: usize = 5;
+
+/// Alternate cold (cache file removed) and warm lookups of the item list and report timings.
+fn bench_cache(cli: &Cli) -> Result<()> {
+    let account = cli.account.as_deref();
+    let vault = cli.vault.as_deref();
+    let cache_path = cache_file_path(account, vault)?;
+
+    let timed = || -> Result<Duration> {
+        let started = Instant::now();
+        item_list_cached(account, vault, CachePolicy::from_cli(cli))?;
+        Ok(started.elapsed())
+    };
+
+    let mut cold = Vec::with_capacity(BENCH_CACHE_ROUNDS);
+    let mut warm = Vec::with_capacity(BENCH_CACHE_ROUNDS);
+    for _ in 0..BENCH_CACHE_ROUNDS {
+        match fs::remove_file(&cache_path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(err).with_context(|| format!("remove {}", cache_path.display()))
+            }
+        }
+        cold.push(timed()?);
+        warm.push(timed()?);
+    }
+
+    write_stdout(&bench_cache_report(&mut cold, &mut warm))
+}
+
+fn bench_cache_report(cold: &mut [Duration], warm: &mut [Duration]) -> String {
+    let mut out = String::from("PATH\tMIN\tMEDIAN\tMAX\n");
+    for (name, samples) in [("cold", &mut *cold), ("warm", &mut *warm)] {
+        samples.sort();
+        let (Some(min), Some(max)) = (samples.first(), samples.last()) else {
+            continue;
+        };
+        let median = samples[samples.len() / 2];
+        out.push_str(&format!(
+            "{name}\t{:.2}ms\t{:.2}ms\t{:.2}ms\n",
+            min.as_secs_f64() * 1000.0,
+            median.as_secs_f64() * 1000.0,
+            max.as_secs_f64() * 1000.0
+        ));
+    }
+    out
+}
+
+/// Cache directory for `op` data. Under a service account token it is a per-token
+/// subdirectory, so CI jobs never mix their results with a signed-in user's or each other's.
+fn item_list_cache_dir() -> Result<PathBuf> {
+    let proj = ProjectDirs::from("dev", "opz", "opz").ok_or_else(|| anyhow!("no cache dir"))?;
+    let base = proj.cache_dir().to_path_buf();
+    Ok(match service_account::cache_scope() {
+        Some(scope) => base.join(scope),
+        None => base,
+    })
+}
+
+fn cache_file_path(account: Option<&str>, vault: Option<&str>) -> Result<PathBuf> {
+    let base = item_list_cache_dir()?;
+    let vault_key = vault.unwrap_or("_all_");
+    // Keep the account-less key unchanged so existing caches stay valid.
+    let key = match account {
+        Some(account) => format!("{account}\n{vault_key}"),
+        None => vault_key.to_string(),
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(key.as_bytes());
+    let name = format!("item_list_{}.json", hex::encode(hasher.finalize()));
+    Ok(base.join(name))
+}
+
+fn invalidate_item_list_cache() -> Result<()> {
+    remove_cache_files("item_list_")
+}
+
+fn invalidate_item_detail_cache() -> Result<()> {
+    remove_cache_files("item_get_")
+}
+
+/// Account list, vault list, and `op` version change far less often than items, so they are
+/// cached separately with a longer TTL.
+const METADATA_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Read-through cache for `op` metadata, keyed by a short description such as "account list".
+fn metadata_cached<T: Serialize + serde::de::DeserializeOwned>(
+    key: &str,
+    policy: CachePolicy,
+    fetch: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    telemetry_span::with_span_result(
+        "load_inputs.metadata_cached",
+        vec![KeyValue::new("cache.key", key.to_string())],
+        || {
+            let mut hasher = Sha256::new();
+            hasher.update(key.as_bytes());
+            let path = item_list_cache_dir()?
+                .join(format!("meta_{}.json", hex::encode(hasher.finalize())));
+            metadata_cached_at(&path, key, policy, fetch)
+        },
+    )
+}
+
+fn metadata_cached_at<T: Serialize + serde::de::DeserializeOwned>(
+    path: &Path,
+    key: &str,
+    policy: CachePolicy,
+    fetch: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let age = fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .map(|mtime| SystemTime::now().duration_since(mtime).unwrap_or_default());
+    let fresh = age
+        .as_ref()
+        .is_ok_and(|age| *age < METADATA_CACHE_TTL || policy.offline);
+    let bypass = match age {
+        Err(_) => "missing",
+        Ok(_) if policy.refresh => "refresh",
+        Ok(_) if !fresh => "expired",
+        Ok(_) => "unreadable",
+    };
+    if !policy.refresh {
+        let cached = fresh
+            .then(|| fs::read(path).ok())
+            .flatten()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+        if let Some(cached) = cached {
+            telemetry::record_cache_lookup("metadata", true);
+            tracing::debug!("{key} cache hit");
+            return Ok(cached);
+        }
+    }
+    if policy.offline {
+        return Err(anyhow!(
+            "No cached {key} (--offline). Run the command once while online."
+        ));
+    }
+    telemetry::record_cache_lookup("metadata", false);
+    tracing::debug!(refresh = policy.refresh, "{key} cache miss ({bypass})");
+
+    let value = telemetry_span::with_cache_bypass(bypass, fetch)?;
+    if let Ok(bytes) = serde_json::to_vec(&value) {
+        if let Err(err) = cache_file::write_cache(path, &bytes) {
+            tracing::warn!("failed to write {key} cache: {err:#}");
+        }
+    }
+    Ok(value)
+}
+
+fn invalidate_metadata_cache() -> Result<()> {
+    remove_cache_files("meta_")
+}
+
+fn remove_cache_files(prefix: &str) -> Result<()> {
+    let cache_dir = item_list_cache_dir()?;
+    if !cache_dir.exists() {
+        return Ok(());
+    }
+
+    for entry in
+        fs::read_dir(&cache_dir).with_context(|| format!("read {}", cache_dir.display()))?
+    {
+        let entry = entry?;
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if name.starts_with(prefix) && name.ends_with(".json") {
+            fs::remove_file(&path).with_context(|| format!("remove {}", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn run_config_command(action: &ConfigCmd) -> Result<()> {
+    let cwd = std::env::current_dir().context("resolve current directory")?;
+    match action {
+        ConfigCmd::Export { bundle } => {
+            let exported = telemetry_span::with_span_result("load_inputs", vec![], || {
+                config::export_bundle(&cwd)
+            })?;
+            telemetry_span::with_span_result("write_outputs", vec![], || {
+                let json = serde_json::to_string_pretty(&exported)?;
+                fs::write(bundle, json + "\n")
+                    .with_context(|| format!("write {}", bundle.display()))
+            })?;
+            eprintln!("Exported: {}", bundle.display());
+            Ok(())
+        }
+        ConfigCmd::Import { bundle, force } => {
+            let imported: config::Bundle =
+                telemetry_span::with_span_result("load_inputs", vec![], || {
+                    let bytes =
+                        fs::read(bundle).with_context(|| format!("read {}", bundle.display()))?;
+                    serde_json::from_slice(&bytes)
+                        .with_context(|| format!("parse {}", bundle.display()))
+                })?;
+            let written = telemetry_span::with_span_result("write_outputs", vec![], || {
+                config::import_bundle(&imported, &cwd, *force)
+            })?;
+            for path in written {
+                eprintln!("Imported: {}", path.display());
+            }
+            Ok(())
+        }
+    }
+}
+
+fn run_cache_command(cli: &Cli, action: &CacheCmd) -> Result<()> {
+    match action {
+        CacheCmd::Clear => telemetry_span::with_span_result("write_outputs", vec![], || {
+            invalidate_item_list_cache()?;
+            invalidate_item_detail_cache()?;
+            invalidate_metadata_cache()?;
+            eprintln!("Cleared: {}", item_list_cache_dir()?.display());
+            Ok(())
+        }),
+        CacheCmd::Status => {
+            let entries =
+                telemetry_span::with_span_result("load_inputs", vec![], cache_status_entries)?;
+            if cli.json {
+                let caches = cache_status_json(&entries, SystemTime::now());
+                return telemetry_span::with_span_result("write_outputs", vec![], || {
+                    print_json(&serde_json::json!({ "caches": caches }))
+                });
+            }
+            let rendered = telemetry_span::with_span("main_operation", vec![], || {
+                cache_status_output_string(&entries, SystemTime::now())
+            });
+            telemetry_span::with_span_result("write_outputs", vec![], || write_stdout(&rendered))
+        }
+        CacheCmd::Warm { if_stale, vaults } => {
+            let targets: Vec<Option<&str>> = if vaults.is_empty() {
+                vec![cli.vault.as_deref()]
+            } else {
+                vaults.iter().map(|vault| Some(vault.as_str())).collect()
+            };
+            telemetry_span::with_span_result(
+                "main_operation",
+                vec![KeyValue::new("vault.count", targets.len() as i64)],
+                || {
+                    let warmed = parallel::map(&targets, |vault| {
+                        if *if_stale {
+                            refresh_item_list_cache_once(cli.account.as_deref(), *vault)
+                        } else {
+                            refresh_item_list_cache(cli.account.as_deref(), *vault)
+                        }
+                    });
+                    for (vault, items) in targets.iter().zip(warmed) {
+                        let items = items?;
+                        eprintln!(
+                            "Warmed: {} ({} items)",
+                            vault.unwrap_or("(all vaults)"),
+                            items.len()
+                        );
+                    }
+                    Ok(())
+                },
+            )
+        }
+    }
+}
+
+pub(crate) struct CacheStatusEntry {
+    pub(crate) account: Option<String>,
+    pub(crate) vault: Option<String>,
+    pub(crate) item_count: Option<usize>,
+    pub(crate) size: u64,
+    pub(crate) modified: SystemTime,
+}
+
+pub(crate) fn cache_status_entries() -> Result<Vec<CacheStatusEntry>> {
+    let cache_dir = item_list_cache_dir()?;
+    if !cache_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = Vec::new();
+    for entry in
+        fs::read_dir(&cache_dir).with_context(|| format!("read {}", cache_dir.display()))?
+    {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if !(name.starts_with("item_list_") && name.ends_with(".json")) {
+            continue;
+        }
+
+        let meta = fs::metadata(&path).with_context(|| format!("stat {}", path.display()))?;
+        let cache = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<ItemListCache>(&bytes).ok());
+        entries.push(CacheStatusEntry {
+            account: cache.as_ref().and_then(|c| c.account.clone()),
+            vault: cache.as_ref().and_then(|c| c.vault.clone()),
+            item_count: cache.as_ref().map(|c| c.items.len()),
+            size: meta.len(),
+            modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        });
+    }
+    entries.sort_by(|a, b| (&a.account, &a.vault).cmp(&(&b.account, &b.vault)));
+    Ok(entries)
+}
+
+fn cache_status_output_string(entries: &[CacheStatusEntry], now: SystemTime) -> String {
+    let mut out = String::from("VAULT\tACCOUNT\tITEMS\tSIZE\tAGE\tSTATE\n");
+    for entry in entries {
+        let age = now.duration_since(entry.modified).unwrap_or_default();
+        let state = cache_state(entry, age);
+        out.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            entry.vault.as_deref().unwrap_or("(all)"),
+            entry.account.as_deref().unwrap_or("-"),
+            entry
+                .item_count
+                .map(|count| count.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            format_bytes(entry.size),
+            format_age(age),
+            state
+        ));
+    }
+    out
+}
+
+/// Per-scope cache freshness, shared by `cache status --json` and the daemon's `/status`.
+pub(crate) fn cache_status_json(
+    entries: &[CacheStatusEntry],
+    now: SystemTime,
+) -> Vec<serde_json::Value> {
+    entries
+        .iter()
+        .map(|entry| {
+            let age = now.duration_since(entry.modified).unwrap_or_default();
+            serde_json::json!({
+                "vault": entry.vault,
+                "account": entry.account,
+                "items": entry.item_count,
+                "size_bytes": entry.size,
+                "age_secs": age.as_secs(),
+                "state": cache_state(entry, age),
+            })
+        })
+        .collect()
+}
+
+fn cache_state(entry: &CacheStatusEntry, age: Duration) -> &'static str {
+    match entry.item_count {
+        None => "unreadable",
+        Some(_) if age < item_list_cache_ttl() => "fresh",
+        Some(_) => "stale",
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let kib = bytes as f64 / 1024.0;
+    if kib < 1024.0 {
+        return format!("{kib:.1} KiB");
+    }
+    format!("{:.1} MiB", kib / 1024.0)
+}
+
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+fn invalidate_item_list_cache_best_effort() {
+    if let Err(err) = invalidate_item_list_cache() {
+        tracing::warn!("failed to invalidate item list cache: {err}");
+    }
+}
+
+/// `op item get` through a short-TTL cache keyed by item id, invalidated as soon as the item
+/// list reports a different `updated_at`. Items without `updated_at` are never cached.
+pub(crate) fn item_get_cached(
+    account: Option<&str>,
+    entry: &ItemListEntry,
+    policy: CachePolicy,
+) -> Result<ItemGet> {
+    let offline_miss = || {
+        anyhow!(
+            "Details for item '{}' are not cached (--offline). Use it once while online first.",
+            entry.title
+        )
+    };
+    let Some(updated_at) = entry.updated_at.as_deref() else {
+        if policy.offline {
+            return Err(offline_miss());
+        }
+        tracing::debug!(
+            "item details for '{}' are not cacheable (no updated_at)",
+            entry.title
+        );
+        return telemetry_span::with_cache_bypass("uncacheable", || item_get(account, entry))
+            .map(|(item, _)| item);
+    };
+    if !policy.refresh && !policy.offline {
+        if let Some(item) = agent::item_get(account, entry) {
+            return Ok(item);
+        }
+    }
+
+    telemetry_span::with_span_result(
+        "load_inputs.item_get_cached",
+        vec![KeyValue::new("account.specified", account.is_some())],
+        || {
+            let cache_path = item_cache_file_path(account, &entry.id)?;
+
+            let age = fs::metadata(&cache_path)
+                .and_then(|meta| meta.modified())
+                .map(|mtime| SystemTime::now().duration_since(mtime).unwrap_or_default());
+            let fresh = age.as_ref().is_ok_and(|age| *age < ITEM_GET_CACHE_TTL);
+            let mut bypass = match age {
+                Err(_) => "missing",
+                Ok(_) if policy.refresh => "refresh",
+                Ok(_) if !fresh => "expired",
+                Ok(_) => "unreadable",
+            };
+            if (fresh && !policy.refresh) || policy.offline {
+                let cached = fs::read(&cache_path)
+                    .ok()
+                    .and_then(|bytes| serde_json::from_slice::<ItemGetCache>(&bytes).ok());
+                match cached {
+                    Some(cached) if cached.updated_at == updated_at => {
+                        telemetry::record_cache_lookup("item", true);
+                        tracing::debug!("item cache hit for '{}'", entry.title);
+                        return Ok(cached.item);
+                    }
+                    Some(_) => bypass = "changed",
+                    None => {}
+                }
+            }
+            if policy.offline {
+                return Err(offline_miss());
+            }
+            telemetry::record_cache_lookup("item", false);
+            tracing::debug!(
+                refresh = policy.refresh,
+                "item cache miss for '{}' ({bypass})",
+                entry.title
+            );
+
+            let (item, source) =
+                telemetry_span::with_cache_bypass(bypass, || item_get(account, entry))?;
+            if source == Backend::Cache {
+                return Ok(item);
+            }
+            let cache = ItemGetCache {
+                updated_at: updated_at.to_string(),
+                item: redacted_item(&item),
+            };
+            // A failed cache write only costs the next run a refetch.
+            let _ = serde_json::to_vec(&cache)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| cache_file::write_cache(&cache_path, &bytes));
+            Ok(item)
+        },
+    )
+}
+
+/// Copy of `item` safe to persist: every present field value is replaced by a marker, except
+/// `op://` references, which name a secret without holding it (for `--resolve-nested`).
+pub(crate) fn redacted_item(item: &ItemGet) -> ItemGet {
+    ItemGet {
+        fields: item
+            .fields
+            .iter()
+            .map(|field| ItemField {
+                label: field.label.clone(),
+                field_type: field.field_type.clone(),
+                value: field.value.as_ref().map(|value| match value.as_str() {
+                    Some(reference) if is_op_reference(reference) => value.clone(),
+                    _ => serde_json::Value::from(REDACTED_FIELD_VALUE),
+                }),
+                section: field.section.clone(),
+            })
+            .collect(),
+        tags: item.tags.clone(),
+        vault: item.vault.clone(),
+        version: item.version,
+        category: item.category.clone(),
+        updated_at: item.updated_at.clone(),
+        urls: item.urls.clone(),
+    }
+}
+
+fn item_cache_file_path(account: Option<&str>, item_id: &str) -> Result<PathBuf> {
+    let base = item_list_cache_dir()?;
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}\n{item_id}", account.unwrap_or_default()).as_bytes());
+    let name = format!(
+        "item_get_{ITEM_GET_CACHE_FORMAT}{}.json",
+        hex::encode(hasher.finalize())
+    );
+    Ok(base.join(name))
+}
+
+fn item_get(account: Option<&str>, entry: &ItemListEntry) -> Result<(ItemGet, Backend)> {
+    telemetry_span::with_span_result("load_inputs.item_get", vec![], || {
+        backend::with_fallback("item_get", |source| match source {
+            Backend::Connect => {
+                let vault = entry
+                    .vault
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Item '{}' has no vault id", entry.title))?;
+                let mut item: ItemGet =
+                    serde_json::from_value(backend::connect_item_get(&vault.id, &entry.id)?)?;
+                item.vault = Some(vault.clone());
+                Ok(item)
+            }
+            Backend::Cli => Ok(serde_json::from_value(op_json(
+                account,
+                &["item", "get", &entry.id, "--format", "json"],
+            )?)?),
+            Backend::Cache => read_item_cache(account, entry),
+        })
+    })
+}
+
+/// Cached (redacted) item details regardless of age, for the `cache` backend.
+fn read_item_cache(account: Option<&str>, entry: &ItemListEntry) -> Result<ItemGet> {
+    let cache_path = item_cache_file_path(account, &entry.id)?;
+    fs::read(&cache_path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<ItemGetCache>(&bytes).ok())
+        .map(|cache| cache.item)
+        .ok_or_else(|| {
+            backend::error(
+                backend::ErrorClass::NotFound,
+                format!("Details for item '{}' are not cached", entry.title),
+            )
+        })
+}
+
+fn vault_list(account: Option<&str>, policy: CachePolicy) -> Result<Vec<ItemVault>> {
+    let key = format!("vault list {}", account.unwrap_or_default());
+    metadata_cached(key.trim_end(), policy, || {
+        let v = op_json(account, &["vault", "list", "--format", "json"])?;
+        Ok(serde_json::from_value(v)?)
+    })
+}
+
+fn account_list(policy: CachePolicy) -> Result<Vec<AccountListEntry>> {
+    let accounts: Vec<AccountListEntry> = metadata_cached("account list", policy, || {
+        let v = op_json(None, &["account", "list", "--format", "json"])?;
+        Ok(serde_json::from_value(v)?)
+    })?;
+    if accounts.is_empty() {
+        return Err(anyhow!(
+            "No 1Password accounts found. Sign in with `op signin` first."
+        ));
+    }
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    // ============================================
+    // Tests for item_to_env_lines()
+    // ============================================
+
+    fn make_field(label: Option<&str>, has_value: bool) -> ItemField {
+        ItemField {
+            section: None,
+            label: label.map(String::from),
+            field_type: None,
+            value: if has_value {
+                Some(serde_json::Value::String("test".to_string()))
+            } else {
+                None
+            },
+        }
+    }
+
+    fn concealed_field(label: &str, value: &str) -> ItemField {
+        ItemField {
+            section: None,
+            label: Some(label.to_string()),
+            field_type: Some("CONCEALED".to_string()),
+            value: Some(serde_json::Value::from(value)),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_concealed_values_are_grouped_by_key() {
+        let mut plain = make_field(Some("REGION"), true);
+        plain.value = Some(serde_json::Value::from("same"));
+        let item = make_item(vec![
+            concealed_field("API_TOKEN", "same"),
+            concealed_field("ADMIN_TOKEN", "same"),
+            concealed_field("DB_PASSWORD", "other"),
+            concealed_field("CACHED_A", REDACTED_FIELD_VALUE),
+            concealed_field("CACHED_B", REDACTED_FIELD_VALUE),
+            plain,
+        ]);
+        let digests: HashMap<String, [u8; 32]> =
+            concealed_value_digests(&item, &config::Config::default())
+                .unwrap()
+                .into_iter()
+                .collect();
+        assert_eq!(digests.len(), 3);
+        assert_eq!(
+            duplicate_value_keys(&digests),
+            vec![vec!["ADMIN_TOKEN".to_string(), "API_TOKEN".to_string()]]
+        );
+    }
+
+    fn make_item(fields: Vec<ItemField>) -> ItemGet {
+        ItemGet {
+            version: None,
+            fields,
+            tags: Vec::new(),
+            vault: None,
+            category: None,
+            updated_at: None,
+            urls: Vec::new(),
+        }
+    }
+
+    fn env_lines(item: &ItemGet) -> Vec<String> {
+        item_to_env_lines(item, "vault-id", "abc123", &config::Config::default()).unwrap()
+    }
+
+    fn valid_labels(item: &ItemGet) -> Vec<String> {
+        item_to_valid_labels(item, &config::Config::default()).unwrap()
+    }
+
+    #[test]
+    fn test_item_to_env_lines_basic() {
+        let item = make_item(vec![
+            make_field(Some("API_KEY"), true),
+            make_field(Some("DB_HOST"), true),
+        ]);
+        let lines = env_lines(&item);
+        assert_eq!(lines.len(), 2);
+        assert!(lines.contains(&"API_KEY=op://vault-id/abc123/API_KEY".to_string()));
+        assert!(lines.contains(&"DB_HOST=op://vault-id/abc123/DB_HOST".to_string()));
+    }
+
+    #[test]
+    fn test_item_to_env_lines_smart_category_names() {
+        let mut config = config::Config::default();
+        config.labels.smart = Some(true);
+        let mut custom = make_field(Some("server"), true);
+        custom.section = Some(ItemFieldSection {
+            label: Some("replica".to_string()),
+        });
+        let mut item = make_item(vec![
+            make_field(Some("server"), true),
+            make_field(Some("port"), true),
+            make_field(Some("database"), true),
+            make_field(Some("username"), true),
+            make_field(Some("password"), true),
+            custom,
+        ]);
+        item.category = Some("DATABASE".to_string());
+        assert_eq!(
+            item_to_env_lines(&item, "vault-id", "abc123", &config).unwrap(),
+            vec![
+                "DB_HOST=op://vault-id/abc123/server",
+                "DB_PORT=op://vault-id/abc123/port",
+                "DB_NAME=op://vault-id/abc123/database",
+                "DB_USER=op://vault-id/abc123/username",
+                "DB_PASSWORD=op://vault-id/abc123/password",
+                "server=op://vault-id/abc123/server",
+            ]
+        );
+
+        let mut item = make_item(vec![
+            make_field(Some("username"), true),
+            make_field(Some("password"), true),
+        ]);
+        item.category = Some("LOGIN".to_string());
+        item.urls = vec![ItemUrl {
+            primary: true,
+            href: "https://example.com/login?next=/".to_string(),
+        }];
+        assert_eq!(
+            item_to_env_lines(&item, "vault-id", "abc123", &config).unwrap(),
+            vec![
+                "USERNAME=op://vault-id/abc123/username",
+                "PASSWORD=op://vault-id/abc123/password",
+            ]
+        );
+        // The website is a plain value, kept out of the reference lines.
+        assert_eq!(
+            item_plain_env(&item, &config),
+            vec![(
+                "WEBSITE".to_string(),
+                "https://example.com/login?next=/".to_string()
+            )]
+        );
+        assert_eq!(
+            item_to_valid_labels(&item, &config).unwrap(),
+            vec!["USERNAME", "PASSWORD", "WEBSITE"]
+        );
+        item.fields.push(make_field(Some("WEBSITE"), true));
+        assert!(item_plain_env(&item, &config).is_empty());
+
+        // Off by default: labels stay as they are and there is no website value.
+        let default = config::Config::default();
+        assert_eq!(
+            env_lines(&item)[0],
+            "username=op://vault-id/abc123/username"
+        );
+        assert!(item_plain_env(&item, &default).is_empty());
+    }
+
+    #[test]
+    fn test_item_to_env_lines_skips_invalid_labels() {
+        let item = make_item(vec![
+            make_field(Some("VALID_KEY"), true),
+            make_field(Some("invalid-key"), true), // dash not allowed
+            make_field(Some("123_START"), true),   // can't start with number
+            make_field(Some("has space"), true),   // space not allowed
+        ]);
+        let lines = env_lines(&item);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0], "VALID_KEY=op://vault-id/abc123/VALID_KEY");
+    }
+
+    #[test]
+    fn test_item_to_env_lines_valid_label_patterns() {
+        let item = make_item(vec![
+            make_field(Some("_UNDERSCORE_START"), true),
+            make_field(Some("lowercase"), true),
+            make_field(Some("MixedCase123"), true),
+            make_field(Some("WITH_123_NUMBERS"), true),
+        ]);
+        let lines = env_lines(&item);
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn test_item_to_env_lines_skips_no_label() {
+        let item = make_item(vec![
+            make_field(None, true),
+            make_field(Some("VALID"), true),
+        ]);
+        let lines = env_lines(&item);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0], "VALID=op://vault-id/abc123/VALID");
+    }
+
+    #[test]
+    fn test_item_to_env_lines_empty_fields() {
+        let item = make_item(vec![]);
+        let lines = env_lines(&item);
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn test_item_to_env_lines_skips_no_value() {
+        let item = make_item(vec![
+            make_field(Some("NO_VALUE"), false),
+            make_field(Some("HAS_VALUE"), true),
+        ]);
+        let lines = env_lines(&item);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0], "HAS_VALUE=op://vault-id/abc123/HAS_VALUE");
+    }
+
+    #[test]
+    fn test_item_to_valid_labels_skips_invalid_and_missing() {
+        let item = make_item(vec![
+            make_field(Some("VALID_KEY"), false),
+            make_field(Some("invalid-key"), true),
+            make_field(None, true),
+        ]);
+        let labels = valid_labels(&item);
+        assert_eq!(labels, vec!["VALID_KEY".to_string()]);
+    }
+
+    #[test]
+    fn test_item_concealed_keys_only_concealed_exportable_fields() {
+        let mut password = make_field(Some("DB_PASSWORD"), true);
+        password.field_type = Some("CONCEALED".to_string());
+        let mut invalid = make_field(Some("not-valid"), true);
+        invalid.field_type = Some("CONCEALED".to_string());
+        let mut host = make_field(Some("DB_HOST"), true);
+        host.field_type = Some("STRING".to_string());
+
+        let item = make_item(vec![password, invalid, host]);
+        assert_eq!(
+            item_concealed_keys(&item, &config::Config::default()).unwrap(),
+            vec!["DB_PASSWORD".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_item_to_env_lines_applies_label_config() {
+        let config: config::Config = toml::from_str(
+            "[fields]\nexclude = [\"debug\"]\n[labels]\nuppercase = true\nprefix = \"APP_\"\nrename = { \"api-key\" = \"API_KEY\" }\n",
+        )
+        .unwrap();
+        let mut password = make_field(Some("password"), true);
+        password.field_type = Some("CONCEALED".to_string());
+        let item = make_item(vec![
+            make_field(Some("host"), true),
+            make_field(Some("api-key"), true),
+            make_field(Some("debug"), true),
+            password,
+        ]);
+
+        assert_eq!(
+            item_to_env_lines(&item, "v", "i", &config).unwrap(),
+            vec![
+                "APP_HOST=op://v/i/host",
+                "API_KEY=op://v/i/api-key",
+                "APP_PASSWORD=op://v/i/password"
+            ]
+        );
+        assert_eq!(
+            item_concealed_keys(&item, &config).unwrap(),
+            vec!["APP_PASSWORD"]
+        );
+    }
+
+    #[test]
+    fn test_item_skipped_fields_give_reasons() {
+        let config: config::Config = toml::from_str("[fields]\nexclude = [\"debug\"]\n").unwrap();
+        let item = make_item(vec![
+            make_field(Some("host"), true),
+            make_field(Some("debug"), true),
+            make_field(Some("my-key"), true),
+            make_field(Some("empty"), false),
+            make_field(None, true),
+        ]);
+
+        assert_eq!(
+            item_skipped_fields(&item, &config),
+            vec![
+                (
+                    "debug".to_string(),
+                    "[fields] exclude \"debug\"".to_string()
+                ),
+                (
+                    "my-key".to_string(),
+                    "\"my-key\" is not a valid env var name".to_string()
+                ),
+                ("empty".to_string(), "no value".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_env_candidates_explain_overrides_and_skips() {
+        let config: config::Config = toml::from_str(
+            "[fields]\nexclude = [\"old_token\"]\n[labels]\nuppercase = true\nrename = { \"admin\" = \"TOKEN\" }\n",
+        )
+        .unwrap();
+        let base = make_item(vec![
+            make_field(Some("token"), true),
+            make_field(Some("old_token"), true),
+            make_field(Some("host"), true),
+        ]);
+        let overlay = make_item(vec![make_field(Some("admin"), true)]);
+
+        let mut candidates = env_candidates("TOKEN", "base", &base, "v", "b", &config).unwrap();
+        candidates.extend(env_candidates("TOKEN", "overlay", &overlay, "v", "o", &config).unwrap());
+        mark_overridden(&mut candidates);
+
+        let statuses: Vec<(String, String)> =
+            candidates.iter().map(|c| (c.name(), c.status())).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (
+                    "base > token".to_string(),
+                    "overridden by overlay > admin".to_string()
+                ),
+                ("overlay > admin".to_string(), "used".to_string()),
+            ]
+        );
+        let report = explain_env_report("TOKEN", &candidates, false);
+        assert!(report.contains("    reference:  op://v/o/admin\n"));
+        assert!(report.contains("    mapping:    [labels.rename] \"admin\" = \"TOKEN\"\n"));
+
+        let skipped = env_candidates("OLD_TOKEN", "base", &base, "v", "b", &config).unwrap();
+        assert_eq!(
+            skipped[0].status(),
+            "skipped: [fields] exclude \"old_token\""
+        );
+    }
+
+    #[test]
+    fn test_apply_config_defaults_cli_wins() {
+        let defaults = config::Config {
+            defaults: config::Defaults {
+                vault: Some("Team".to_string()),
+                env_file: Some(PathBuf::from(".env.local")),
+                cache_ttl_secs: None,
+                term_grace_secs: None,
+            },
+            ..Default::default()
+        };
+
+        let mut cli = Cli::try_parse_from(["opz", "run", "item", "--", "true"]).unwrap();
+        apply_config_defaults(&mut cli, &defaults);
+        assert_eq!(cli.vault.as_deref(), Some("Team"));
+        match &cli.cmd {
+            Some(Cmd::Run { env_file, .. }) => {
+                assert_eq!(*env_file, [EnvFileArg::dotenv(PathBuf::from(".env.local"))])
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let mut cli = Cli::try_parse_from([
+            "opz",
+            "gen",
+            "--vault",
+            "Personal",
+            "--env-file",
+            ".env",
+            "item",
+        ])
+        .unwrap();
+        apply_config_defaults(&mut cli, &defaults);
+        assert_eq!(cli.vault.as_deref(), Some("Personal"));
+        match &cli.cmd {
+            Some(Cmd::Gen { env_file, .. }) => {
+                assert_eq!(*env_file, [EnvFileArg::dotenv(PathBuf::from(".env"))])
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_apply_op_env_fills_missing_flags() {
+        let env = |name: &str| match name {
+            "OP_ACCOUNT" => Some("team.1password.com".to_string()),
+            "OP_VAULT" => Some("Team".to_string()),
+            _ => None,
+        };
+
+        let mut cli = Cli::try_parse_from(["opz", "find", "db"]).unwrap();
+        apply_op_env(&mut cli, env);
+        apply_config_defaults(
+            &mut cli,
+            &config::Config {
+                vault: Some("Personal".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(cli.account.as_deref(), Some("team.1password.com"));
+        assert_eq!(cli.vault.as_deref(), Some("Team"));
+
+        let mut cli = Cli::try_parse_from(["opz", "find", "--vault", "Personal", "db"]).unwrap();
+        apply_op_env(&mut cli, env);
+        assert_eq!(cli.vault.as_deref(), Some("Personal"));
+
+        let mut cli = Cli::try_parse_from(["opz", "find", "db"]).unwrap();
+        apply_op_env(&mut cli, |_| Some(String::new()));
+        assert_eq!(cli.account, None);
+        assert_eq!(cli.vault, None);
+    }
+
+    #[test]
+    fn test_apply_config_defaults_uses_project_item_mapping() {
+        let config = config::Config {
+            item: Some("my-service prod".to_string()),
+            vault: Some("Team".to_string()),
+            defaults: config::Defaults {
+                vault: Some("Personal".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut cli = Cli::try_parse_from(["opz", "--", "npm", "start"]).unwrap();
+        apply_config_defaults(&mut cli, &config);
+        assert_eq!(cli.items, vec!["my-service prod"]);
+        assert_eq!(cli.command, vec!["npm", "start"]);
+        assert_eq!(cli.vault.as_deref(), Some("Team"));
+
+        let mut cli = Cli::try_parse_from(["opz", "show", "other"]).unwrap();
+        apply_config_defaults(&mut cli, &config);
+        match &cli.cmd {
+            Some(Cmd::Show { items, .. }) => assert_eq!(items, &vec!["other".to_string()]),
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_metadata_cached_at_reads_through_and_honors_policy() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("meta_x.json");
+        let policy = CachePolicy {
+            serve_stale: true,
+            offline: false,
+            refresh: false,
+        };
+
+        let offline = CachePolicy {
+            offline: true,
+            ..policy
+        };
+        assert!(
+            metadata_cached_at::<Vec<String>>(&path, "vault list", offline, || {
+                panic!("offline must not fetch")
+            })
+            .is_err()
+        );
+
+        let fetched =
+            metadata_cached_at(&path, "vault list", policy, || Ok(vec!["Team".to_string()]))
+                .unwrap();
+        assert_eq!(fetched, vec!["Team"]);
+        let cached: Vec<String> = metadata_cached_at(&path, "vault list", policy, || {
+            panic!("fresh cache must not fetch")
+        })
+        .unwrap();
+        assert_eq!(cached, vec!["Team"]);
+
+        let refresh = CachePolicy {
+            refresh: true,
+            ..policy
+        };
+        let refetched = metadata_cached_at(&path, "vault list", refresh, || {
+            Ok(vec!["Team".to_string(), "Ops".to_string()])
+        })
+        .unwrap();
+        assert_eq!(refetched, vec!["Team", "Ops"]);
+    }
+
+    #[test]
+    fn test_completion_script_hooks_cached_items() {
+        for shell in [
+            clap_complete::Shell::Bash,
+            clap_complete::Shell::Zsh,
+            clap_complete::Shell::Fish,
+        ] {
+            let script = completion_script(shell);
+            assert!(script.contains("opz __complete-items"), "{shell}");
+            assert!(script.contains("--vaults"), "{shell}");
+            assert!(!script.contains("_COMMANDS@"), "{shell}");
+        }
+
+        let (item_commands, other_commands) = completion_subcommands(&Cli::command());
+        for name in ["run", "show", "gen", "fields", "watch", "lock"] {
+            assert!(item_commands.iter().any(|c| c == name), "{name}");
+        }
+        for name in ["create", "whoami", "config", "help", "recent"] {
+            assert!(other_commands.iter().any(|c| c == name), "{name}");
+        }
+        assert!(!other_commands.iter().any(|c| c == "__complete-items"));
+        let bash = completion_script(clap_complete::Shell::Bash);
+        assert!(bash.contains("|recent|"), "{bash}");
+        let powershell = completion_script(clap_complete::Shell::PowerShell);
+        assert!(powershell.contains("Register-ArgumentCompleter"));
+
+        let cli = Cli::try_parse_from(["opz", "__complete-items", "--vaults"]).unwrap();
+        assert!(matches!(cli.cmd, Some(Cmd::CompleteItems { vaults: true })));
+    }
+
+    #[test]
+    fn test_man_pages_cover_subcommands() {
+        let tmp = TempDir::new().unwrap();
+        clap_mangen::generate_to(Cli::command(), tmp.path()).unwrap();
+
+        let page = fs::read_to_string(tmp.path().join("opz.1")).unwrap();
+        assert!(page.starts_with(".ie"), "{page}");
+        assert!(page.contains(".TH opz 1"));
+        assert!(page.contains("1Password CLI wrapper"));
+        let run = fs::read_to_string(tmp.path().join("opz-run.1")).unwrap();
+        assert!(run.contains("\\-\\-env\\-file"));
+        assert!(tmp.path().join("opz-cache-warm.1").exists());
+        assert!(!tmp.path().join("opz-man.1").exists());
+        assert!(!tmp.path().join("opz-__complete-items.1").exists());
+    }
+
+    #[test]
+    fn test_check_item_version() {
+        let mut item = make_item(vec![]);
+        item.version = Some(4);
+
+        assert!(check_item_version(Some(4), "svc", &item, true).is_ok());
+        assert!(check_item_version(Some(5), "svc", &item, true).is_ok());
+        assert!(check_item_version(None, "svc", &item, true).is_ok());
+        assert!(check_item_version(Some(3), "svc", &item, false).is_ok());
+        let err = check_item_version(Some(3), "svc", &item, true).unwrap_err();
+        assert!(err.to_string().contains("version 4, past item_version = 3"));
+
+        item.version = None;
+        assert!(check_item_version(Some(3), "svc", &item, true).is_ok());
+    }
+
+    #[test]
+    fn test_item_field_type_deserialization() {
+        let json = r#"{"label": "password", "type": "CONCEALED", "value": "x"}"#;
+        let field: ItemField = serde_json::from_str(json).unwrap();
+        assert_eq!(field.field_type.as_deref(), Some("CONCEALED"));
+    }
+
+    #[test]
+    fn test_resolve_vault_id_prefers_id_even_with_unicode_name() {
+        let list_vault = ItemVault {
+            id: "vault-123".to_string(),
+            name: "情報管理共有".to_string(),
+        };
+        let item_vault = ItemVault {
+            id: "vault-fallback".to_string(),
+            name: "別名".to_string(),
+        };
+
+        let resolved = resolve_vault_id(Some(&list_vault), Some(&item_vault));
+        assert_eq!(resolved.as_deref(), Some("vault-123"));
+    }
+
+    // ============================================
+    // Tests for parse_env_key()
+    // ============================================
+
+    #[test]
+    fn test_parse_env_key_basic() {
+        assert_eq!(parse_env_key("KEY=value"), Some("KEY"));
+        assert_eq!(parse_env_key("FOO_BAR=baz"), Some("FOO_BAR"));
+    }
+
+    #[test]
+    fn test_parse_env_key_with_quotes() {
+        assert_eq!(parse_env_key(r#"KEY="value""#), Some("KEY"));
+    }
+
+    #[test]
+    fn test_parse_env_key_with_export_and_spaces() {
+        assert_eq!(parse_env_key("export KEY=value"), Some("KEY"));
+        assert_eq!(parse_env_key("KEY = value"), Some("KEY"));
+        assert_eq!(parse_env_key("exported=1"), Some("exported"));
+    }
+
+    #[test]
+    fn test_parse_env_key_comments_and_empty() {
+        assert_eq!(parse_env_key("# comment"), None);
+        assert_eq!(parse_env_key(""), None);
+        assert_eq!(parse_env_key("   "), None);
+        assert_eq!(parse_env_key("  # indented comment"), None);
+    }
+
+    // ============================================
+    // Tests for write_env_file()
+    // ============================================
+
+    #[test]
+    fn test_write_env_file_creates_file() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+
+        let lines = vec![
+            r#"KEY1="value1""#.to_string(),
+            r#"KEY2="value2""#.to_string(),
+        ];
+
+        write_env_file(&file_path, &lines, EnvMerge::Replace).unwrap();
+
+        assert!(file_path.exists());
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert!(content.contains(r#"KEY1="value1""#));
+        assert!(content.contains(r#"KEY2="value2""#));
+    }
+
+    #[test]
+    fn test_write_env_file_with_newlines() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+
+        let lines = vec![r#"MULTI="line1\nline2""#.to_string()];
+
+        write_env_file(&file_path, &lines, EnvMerge::Replace).unwrap();
+
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert!(content.contains(r#"MULTI="line1\nline2""#));
+    }
+
+    #[test]
+    fn test_write_env_file_empty_lines() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+
+        let lines: Vec<String> = vec![];
+        write_env_file(&file_path, &lines, EnvMerge::Replace).unwrap();
+
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert!(content.is_empty());
+    }
+
+    #[test]
+    fn test_write_env_file_appends_new_keys() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+
+        // Write initial content
+        fs::write(&file_path, "OLD_KEY=old_value\n").unwrap();
+
+        // Append with new content
+        let lines = vec![r#"NEW_KEY="new_value""#.to_string()];
+        write_env_file(&file_path, &lines, EnvMerge::Replace).unwrap();
+
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert!(content.contains("OLD_KEY=old_value"));
+        assert!(content.contains(r#"NEW_KEY="new_value""#));
+    }
+
+    #[test]
+    fn test_write_env_file_appends_separators_only_with_new_keys() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+        fs::write(&file_path, "A=old\n").unwrap();
+
+        let lines: Vec<String> = [
+            "# --- item: foo ---",
+            "A=new",
+            "",
+            "# --- item: bar ---",
+            "B=new",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        write_env_file(&file_path, &lines, EnvMerge::Replace).unwrap();
+        assert_eq!(
+            fs::read_to_string(&file_path).unwrap(),
+            "A=new\n\n# --- item: bar ---\nB=new\n"
+        );
+
+        let new_path = tmp_dir.path().join(".env.new");
+        write_env_file(&new_path, &lines, EnvMerge::Replace).unwrap();
+        assert_eq!(
+            fs::read_to_string(&new_path).unwrap(),
+            lines.join("\n") + "\n"
+        );
+    }
+
+    #[test]
+    fn test_write_env_file_overwrites_duplicates() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+
+        // Write initial content with a key we'll overwrite
+        fs::write(&file_path, "API_KEY=old_secret\nOTHER_KEY=keep_me\n").unwrap();
+
+        // Overwrite API_KEY
+        let lines = vec![r#"API_KEY="new_secret""#.to_string()];
+        write_env_file(&file_path, &lines, EnvMerge::Replace).unwrap();
+
+        let content = fs::read_to_string(&file_path).unwrap();
+        // Should have new value, not old
+        assert!(content.contains(r#"API_KEY="new_secret""#));
+        assert!(!content.contains("API_KEY=old_secret"));
+        // Other key should be preserved
+        assert!(content.contains("OTHER_KEY=keep_me"));
+    }
+
+    #[test]
+    fn test_write_env_file_replaces_export_and_spaced_keys_in_place() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+        fs::write(
+            &file_path,
+            "# local\nexport API_KEY=old\nDB_URL = old\nLOCAL=1\n",
+        )
+        .unwrap();
+
+        let lines = vec![
+            "API_KEY=op://v/i/API_KEY".to_string(),
+            "DB_URL=op://v/i/DB_URL".to_string(),
+            "NEW=op://v/i/NEW".to_string(),
+        ];
+        // Repeated runs must not pile up duplicates.
+        write_env_file(&file_path, &lines, EnvMerge::Replace).unwrap();
+        write_env_file(&file_path, &lines, EnvMerge::Replace).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&file_path).unwrap(),
+            "# local\nexport API_KEY=op://v/i/API_KEY\nDB_URL=op://v/i/DB_URL\nLOCAL=1\nNEW=op://v/i/NEW\n"
+        );
+    }
+
+    #[test]
+    fn test_write_env_file_append_keeps_existing_lines() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+        fs::write(&file_path, "API_KEY=old\n").unwrap();
+
+        let lines = vec!["API_KEY=op://v/i/API_KEY".to_string()];
+        write_env_file(&file_path, &lines, EnvMerge::Append).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&file_path).unwrap(),
+            "API_KEY=old\nAPI_KEY=op://v/i/API_KEY\n"
+        );
+    }
+
+    #[test]
+    fn test_write_env_file_preserves_comments() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+
+        // Write initial content with comments
+        fs::write(
+            &file_path,
+            "# This is a comment\nKEY1=value1\n\n# Another comment\n",
+        )
+        .unwrap();
+
+        // Add new key
+        let lines = vec![r#"KEY2="value2""#.to_string()];
+        write_env_file(&file_path, &lines, EnvMerge::Replace).unwrap();
+
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert!(content.contains("# This is a comment"));
+        assert!(content.contains("# Another comment"));
+        assert!(content.contains("KEY1=value1"));
+        assert!(content.contains(r#"KEY2="value2""#));
+    }
+
+    #[test]
+    fn test_write_env_file_mixed_overwrite_and_append() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+
+        // Initial content
+        fs::write(&file_path, "KEY1=original1\nKEY2=original2\n").unwrap();
+
+        // Overwrite KEY1 and add KEY3
+        let lines = vec![
+            r#"KEY1="updated1""#.to_string(),
+            r#"KEY3="new3""#.to_string(),
+        ];
+        write_env_file(&file_path, &lines, EnvMerge::Replace).unwrap();
+
+        let content = fs::read_to_string(&file_path).unwrap();
+        let content_lines: Vec<&str> = content.lines().collect();
+
+        // KEY1 should be updated (in its original position)
+        assert!(content_lines[0].contains(r#"KEY1="updated1""#));
+        // KEY2 should be preserved
+        assert!(content_lines[1].contains("KEY2=original2"));
+        // KEY3 should be appended
+        assert!(content_lines[2].contains(r#"KEY3="new3""#));
+    }
+
+    // ============================================
+    // Tests for cache_file_path()
+    // ============================================
+
+    #[test]
+    fn test_cache_file_path_with_vault() {
+        let path1 = cache_file_path(None, Some("my-vault")).unwrap();
+        let path2 = cache_file_path(None, Some("other-vault")).unwrap();
+
+        // Different vaults should produce different paths
+        assert_ne!(path1, path2);
+
+        // Path should end with .json
+        assert!(path1.extension().unwrap() == "json");
+        assert!(path2.extension().unwrap() == "json");
+
+        // Filename should start with item_list_
+        let name1 = path1.file_name().unwrap().to_str().unwrap();
+        assert!(name1.starts_with("item_list_"));
+    }
+
+    #[test]
+    fn test_cache_file_path_without_vault() {
+        let path = cache_file_path(None, None).unwrap();
+
+        // Should produce a valid path
+        assert!(path.extension().unwrap() == "json");
+
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("item_list_"));
+    }
+
+    #[test]
+    fn test_cache_file_path_deterministic() {
+        // Same input should produce same output
+        let path1 = cache_file_path(None, Some("test-vault")).unwrap();
+        let path2 = cache_file_path(None, Some("test-vault")).unwrap();
+        assert_eq!(path1, path2);
+
+        let path3 = cache_file_path(None, None).unwrap();
+        let path4 = cache_file_path(None, None).unwrap();
+        assert_eq!(path3, path4);
+    }
+
+    #[test]
+    fn test_cache_file_path_scoped_by_account() {
+        let unscoped = cache_file_path(None, Some("test-vault")).unwrap();
+        let account_a = cache_file_path(Some("a.1password.com"), Some("test-vault")).unwrap();
+        let account_b = cache_file_path(Some("b.1password.com"), Some("test-vault")).unwrap();
+
+        assert_ne!(unscoped, account_a);
+        assert_ne!(account_a, account_b);
+        assert_eq!(
+            account_a,
+            cache_file_path(Some("a.1password.com"), Some("test-vault")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_item_list_cache_rejects_legacy_bare_array() {
+        let legacy = r#"[{"id": "abc", "title": "x"}]"#;
+        assert!(serde_json::from_str::<ItemListCache>(legacy).is_err());
+
+        let current = r#"{"vault": "Team", "items": [{"id": "abc", "title": "x"}]}"#;
+        let cache: ItemListCache = serde_json::from_str(current).unwrap();
+        assert_eq!(cache.vault.as_deref(), Some("Team"));
+        assert!(cache.account.is_none());
+        assert_eq!(cache.items.len(), 1);
+    }
+
+    #[test]
+    fn test_format_bytes_and_age() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(2048), "2.0 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+        assert_eq!(format_age(Duration::from_secs(42)), "42s");
+        assert_eq!(format_age(Duration::from_secs(300)), "5m");
+        assert_eq!(format_age(Duration::from_secs(7200)), "2h");
+        assert_eq!(format_age(Duration::from_secs(3 * 86400)), "3d");
+    }
+
+    #[test]
+    fn test_cache_status_output_string() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(10_000);
+        let entries = vec![
+            CacheStatusEntry {
+                account: None,
+                vault: None,
+                item_count: Some(3),
+                size: 100,
+                modified: now - Duration::from_secs(10),
+            },
+            CacheStatusEntry {
+                account: Some("acme".to_string()),
+                vault: Some("Team".to_string()),
+                item_count: Some(1),
+                size: 2048,
+                modified: now - Duration::from_secs(600),
+            },
+            CacheStatusEntry {
+                account: None,
+                vault: None,
+                item_count: None,
+                size: 5,
+                modified: now,
+            },
+        ];
+
+        assert_eq!(
+            cache_status_output_string(&entries, now),
+            "VAULT\tACCOUNT\tITEMS\tSIZE\tAGE\tSTATE\n\
+             (all)\t-\t3\t100 B\t10s\tfresh\n\
+             Team\tacme\t1\t2.0 KiB\t10m\tstale\n\
+             (all)\t-\t-\t5 B\t0s\tunreadable\n"
+        );
+    }
+
+    #[test]
+    fn test_cli_parse_cache_warm_vaults() {
+        let cli = Cli::try_parse_from(["opz", "cache", "warm", "Team", "Private"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Cache {
+                action: CacheCmd::Warm { vaults, .. },
+            }) => assert_eq!(vaults, vec!["Team".to_string(), "Private".to_string()]),
+            _ => panic!("expected cache warm command"),
+        }
+    }
+
+    // ============================================
+    // Tests for ItemListEntry and ItemGet deserialization
+    // ============================================
+
+    #[test]
+    fn test_item_list_entry_deserialization() {
+        let json =
+            r#"{"id": "abc123", "title": "My Item", "vault": {"id": "v1", "name": "Personal"}}"#;
+        let item: ItemListEntry = serde_json::from_str(json).unwrap();
+        assert_eq!(item.id, "abc123");
+        assert_eq!(item.title, "My Item");
+        assert!(item.vault.is_some());
+        assert_eq!(item.vault.as_ref().unwrap().name, "Personal");
+    }
+
+    #[test]
+    fn test_item_list_entry_without_vault() {
+        let json = r#"{"id": "abc123", "title": "My Item"}"#;
+        let item: ItemListEntry = serde_json::from_str(json).unwrap();
+        assert_eq!(item.id, "abc123");
+        assert_eq!(item.title, "My Item");
+        assert!(item.vault.is_none());
+    }
+
+    #[test]
+    fn test_item_get_deserialization() {
+        let json = r#"{
+            "fields": [
+                {"label": "username", "value": "user@example.com"},
+                {"label": "password", "value": "secret"}
+            ]
+        }"#;
+        let item: ItemGet = serde_json::from_str(json).unwrap();
+        assert_eq!(item.fields.len(), 2);
+        assert_eq!(item.fields[0].label, Some("username".to_string()));
+    }
+
+    #[test]
+    fn test_item_get_empty_fields() {
+        let json = r#"{}"#;
+        let item: ItemGet = serde_json::from_str(json).unwrap();
+        assert!(item.fields.is_empty());
+    }
+
+    #[test]
+    fn test_item_field_with_null_value() {
+        // Unknown fields (like "value") are ignored during deserialization
+        let json = r#"{"label": "empty_field", "value": null}"#;
+        let field: ItemField = serde_json::from_str(json).unwrap();
+        assert_eq!(field.label, Some("empty_field".to_string()));
+    }
+
+    #[test]
+    fn test_item_field_missing_value() {
+        let json = r#"{"label": "no_value_field"}"#;
+        let field: ItemField = serde_json::from_str(json).unwrap();
+        assert_eq!(field.label, Some("no_value_field".to_string()));
+    }
+
+    // ============================================
+    // Tests for parse_env_file()
+    // ============================================
+
+    #[test]
+    fn test_parse_env_file_basic() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+        fs::write(&file_path, "API_KEY=secret\nDB_HOST=localhost\n").unwrap();
+
+        let pairs = parse_env_file(&file_path).unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0], ("API_KEY".to_string(), "secret".to_string()));
+        assert_eq!(pairs[1], ("DB_HOST".to_string(), "localhost".to_string()));
+    }
+
+    #[test]
+    fn test_parse_env_file_handles_comments_export_and_quotes() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+        fs::write(
+            &file_path,
+            r#"# comment
+export TOKEN=abc
+QUOTED="hello"
+SINGLE='world'
+"#,
+        )
+        .unwrap();
+
+        let pairs = parse_env_file(&file_path).unwrap();
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(pairs[0], ("TOKEN".to_string(), "abc".to_string()));
+        assert_eq!(pairs[1], ("QUOTED".to_string(), "hello".to_string()));
+        assert_eq!(pairs[2], ("SINGLE".to_string(), "world".to_string()));
+    }
+
+    #[test]
+    fn test_parse_env_file_skips_invalid_keys() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+        fs::write(
+            &file_path,
+            "VALID=value\nINVALID-KEY=value\n1INVALID=value\n",
+        )
+        .unwrap();
+
+        let pairs = parse_env_file(&file_path).unwrap();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0], ("VALID".to_string(), "value".to_string()));
+    }
+
+    #[test]
+    fn test_parse_env_file_supports_inline_comments_and_hash_in_quotes() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+        fs::write(
+            &file_path,
+            r#"PLAIN=value # comment
+NO_COMMENT=value#hash
+DOUBLE="value # kept"
+SINGLE='value # kept'
+"#,
+        )
+        .unwrap();
+
+        let pairs = parse_env_file(&file_path).unwrap();
+        assert_eq!(pairs.len(), 4);
+        assert_eq!(pairs[0], ("PLAIN".to_string(), "value".to_string()));
+        assert_eq!(
+            pairs[1],
+            ("NO_COMMENT".to_string(), "value#hash".to_string())
+        );
+        assert_eq!(pairs[2], ("DOUBLE".to_string(), "value # kept".to_string()));
+        assert_eq!(pairs[3], ("SINGLE".to_string(), "value # kept".to_string()));
+    }
+
+    #[test]
+    fn test_parse_env_file_allows_export_with_multiple_spaces() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+        fs::write(&file_path, "export   TOKEN=abc\n").unwrap();
+
+        let pairs = parse_env_file(&file_path).unwrap();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0], ("TOKEN".to_string(), "abc".to_string()));
+    }
+
+    #[test]
+    fn test_parse_env_file_duplicate_keys_last_wins() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+        fs::write(&file_path, "A=first\nB=keep\nA=last\n").unwrap();
+
+        let pairs = parse_env_file(&file_path).unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0], ("B".to_string(), "keep".to_string()));
+        assert_eq!(pairs[1], ("A".to_string(), "last".to_string()));
+    }
+
+    #[test]
+    fn test_parse_env_file_skips_existing_op_references() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join(".env");
+        fs::write(
+            &file_path,
+            "NEW_SECRET=plain\nEXISTING=op://vault/item/EXISTING\n",
+        )
+        .unwrap();
+
+        let pairs = parse_env_file(&file_path).unwrap();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0], ("NEW_SECRET".to_string(), "plain".to_string()));
+    }
+
+    #[test]
+    fn test_is_op_reference() {
+        assert!(is_op_reference("op://vault/item/key"));
+        assert!(!is_op_reference("value"));
+    }
+
+    #[test]
+    fn test_build_create_item_args_uses_api_credential_category_and_text_fields() {
+        let env_pairs = vec![
+            ("API_KEY".to_string(), "secret".to_string()),
+            ("DB_HOST".to_string(), "localhost".to_string()),
+        ];
+
+        let args = build_create_item_args(Some("Private"), "my-item", &env_pairs);
+
+        assert_eq!(args[0], "item");
+        assert_eq!(args[1], "create");
+        assert!(args.contains(&"--category".to_string()));
+        assert!(args.contains(&"API Credential".to_string()));
+        assert!(args.contains(&"--title".to_string()));
+        assert!(args.contains(&"my-item".to_string()));
+        assert!(args.contains(&"--vault".to_string()));
+        assert!(args.contains(&"Private".to_string()));
+        assert!(args.contains(&"API_KEY[text]=secret".to_string()));
+        assert!(args.contains(&"DB_HOST[text]=localhost".to_string()));
+    }
+
+    #[test]
+    fn test_is_exact_dotenv() {
+        assert!(is_exact_dotenv(Path::new(".env")));
+        assert!(!is_exact_dotenv(Path::new(".env.local")));
+        assert!(!is_exact_dotenv(Path::new("config/.env.production")));
+        assert!(!is_exact_dotenv(Path::new("secrets.toml")));
+    }
+
+    #[test]
+    fn test_extract_org_repo_from_remote_url() {
+        assert_eq!(
+            extract_org_repo_from_remote_url("https://github.com/f4ah6o/opz.git"),
+            Some("f4ah6o/opz".to_string())
+        );
+        assert_eq!(
+            extract_org_repo_from_remote_url("git@github.com:f4ah6o/opz.git"),
+            Some("f4ah6o/opz".to_string())
+        );
+        assert_eq!(
+            extract_org_repo_from_remote_url("ssh://git@github.com/f4ah6o/opz.git"),
+            Some("f4ah6o/opz".to_string())
+        );
+        assert_eq!(extract_org_repo_from_remote_url("file:///tmp/opz"), None);
+    }
+
+    #[test]
+    fn test_dedupe_titles_with_sequence() {
+        let base = vec![
+            "a/b".to_string(),
+            "a/b".to_string(),
+            "c/d".to_string(),
+            "a/b".to_string(),
+        ];
+        let deduped = dedupe_titles_with_sequence(&base);
+        assert_eq!(
+            deduped,
+            vec![
+                "a/b".to_string(),
+                "a/b-2".to_string(),
+                "c/d".to_string(),
+                "a/b-3".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_secure_note_body() {
+        let body = build_secure_note_body("app.conf", "line1\nline2");
+        assert_eq!(body, "```app.conf\nline1\nline2\n```");
+    }
+
+    #[test]
+    fn test_build_create_secure_note_args() {
+        let args = build_create_secure_note_args(Some("Private"), "f4ah6o/opz", "```a\nb\n```");
+
+        assert_eq!(args[0], "item");
+        assert_eq!(args[1], "create");
+        assert!(args.contains(&"--category".to_string()));
+        assert!(args.contains(&"Secure Note".to_string()));
+        assert!(args.contains(&"--title".to_string()));
+        assert!(args.contains(&"f4ah6o/opz".to_string()));
+        assert!(args.contains(&"--vault".to_string()));
+        assert!(args.contains(&"Private".to_string()));
+        assert!(args.contains(&"notesPlain=```a\nb\n```".to_string()));
+    }
+
+    // ============================================
+    // Tests for expand_vars()
+    // ============================================
+
+    #[test]
+    fn test_expand_vars_simple() {
+        let mut env = HashMap::new();
+        env.insert("API_TOKEN".to_string(), "secret123".to_string());
+        assert_eq!(expand_vars("Bearer $API_TOKEN", &env), "Bearer secret123");
+    }
+
+    #[test]
+    fn test_expand_vars_braced() {
+        let mut env = HashMap::new();
+        env.insert("HOST".to_string(), "example.com".to_string());
+        assert_eq!(
+            expand_vars("https://${HOST}/api", &env),
+            "https://example.com/api"
+        );
+    }
+
+    #[test]
+    fn test_expand_vars_multiple() {
+        let mut env = HashMap::new();
+        env.insert("USER".to_string(), "alice".to_string());
+        env.insert("HOST".to_string(), "server.com".to_string());
+        assert_eq!(expand_vars("$USER@$HOST", &env), "alice@server.com");
+    }
+
+    #[test]
+    fn test_expand_vars_unknown_var() {
+        let env = HashMap::new();
+        // Unknown vars should be preserved as-is
+        assert_eq!(expand_vars("$HOME/dir", &env), "$HOME/dir");
+        assert_eq!(expand_vars("$PATH", &env), "$PATH");
+    }
+
+    #[test]
+    fn test_expand_vars_mixed_known_unknown() {
+        let mut env = HashMap::new();
+        env.insert("API_TOKEN".to_string(), "secret".to_string());
+        assert_eq!(
+            expand_vars("Authorization: $API_TOKEN for $HOME", &env),
+            "Authorization: secret for $HOME"
+        );
+    }
+
+    #[test]
+    fn test_expand_vars_with_special_chars() {
+        let mut env = HashMap::new();
+        env.insert("TOKEN".to_string(), "a$b\"c`d".to_string());
+        let result = expand_vars("$TOKEN", &env);
+        assert_eq!(result, r#"a$b"c`d"#);
+    }
+
+    #[test]
+    fn test_expand_vars_empty_value() {
+        let mut env = HashMap::new();
+        env.insert("EMPTY".to_string(), "".to_string());
+        // $EMPTYsuffix looks for "EMPTYsuffix" variable, not "EMPTY"
+        // Since EMPTYsuffix doesn't exist, it remains as-is for shell expansion
+        assert_eq!(
+            expand_vars("prefix$EMPTYsuffix", &env),
+            "prefix$EMPTYsuffix"
+        );
+        // Use ${EMPTY} to explicitly mark variable boundaries
+        assert_eq!(expand_vars("prefix${EMPTY}suffix", &env), "prefixsuffix");
+        // Direct usage should expand to empty string
+        assert_eq!(expand_vars("$EMPTY", &env), "");
+    }
+
+    #[test]
+    fn test_expand_vars_partial_name() {
+        let mut env = HashMap::new();
+        env.insert("API".to_string(), "test".to_string());
+        // $API_TOKEN looks for "API_TOKEN" variable, not "API"
+        // Since API_TOKEN doesn't exist, it remains as-is
+        assert_eq!(expand_vars("$API_TOKEN", &env), "$API_TOKEN");
+    }
+
+    #[test]
+    fn test_expand_vars_no_vars() {
+        let env = HashMap::new();
+        assert_eq!(expand_vars("hello world", &env), "hello world");
+    }
+
+    #[test]
+    fn test_expand_vars_consecutive_dollars() {
+        let mut env = HashMap::new();
+        env.insert("A".to_string(), "1".to_string());
+        env.insert("B".to_string(), "2".to_string());
+        assert_eq!(expand_vars("$A$B", &env), "12");
+    }
+
+    #[test]
+    fn test_expand_vars_underscore_in_name() {
+        let mut env = HashMap::new();
+        env.insert("API_TOKEN".to_string(), "secret".to_string());
+        assert_eq!(expand_vars("$API_TOKEN", &env), "secret");
+        assert_eq!(expand_vars("${API_TOKEN}", &env), "secret");
+    }
+
+    #[test]
+    fn test_check_command_guard_blocks_denied_command_with_production_item() {
+        let guard = config::GuardConfig {
+            deny_commands: vec!["printenv".to_string()],
+            production_tags: None,
+        };
+        let item_tags = vec![
+            ("dev".to_string(), vec![]),
+            ("api prod".to_string(), vec!["Production".to_string()]),
+        ];
+
+        let err = check_command_guard(&guard, &["/usr/bin/printenv".to_string()], &item_tags)
+            .unwrap_err();
+        assert!(err.to_string().contains("api prod"));
+        assert!(err.to_string().contains("`printenv`"));
+    }
+
+    #[test]
+    fn test_check_command_guard_allows_other_commands_and_items() {
+        let guard = config::GuardConfig {
+            deny_commands: vec!["env".to_string()],
+            production_tags: None,
+        };
+        let prod = vec![("api".to_string(), vec!["prod".to_string()])];
+        let dev = vec![("api".to_string(), vec!["dev".to_string()])];
+
+        assert!(check_command_guard(&guard, &["npm".to_string()], &prod).is_ok());
+        assert!(check_command_guard(&guard, &["env".to_string()], &dev).is_ok());
+        assert!(check_command_guard(&guard, &[], &prod).is_ok());
+    }
+
+    #[test]
+    fn test_expanded_var_names_only_known_vars() {
+        let mut env = HashMap::new();
+        env.insert("TOKEN".to_string(), "secret".to_string());
+        env.insert("HOST".to_string(), "example.com".to_string());
+        assert_eq!(
+            expanded_var_names("https://${HOST}/?t=$TOKEN&home=$HOME", &env),
+            vec!["HOST".to_string(), "TOKEN".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_check_argv_secrets_refuses_concealed_by_default() {
+        let concealed: HashSet<String> = ["TOKEN".to_string()].into_iter().collect();
+        let err = check_argv_secrets(&["TOKEN".to_string()], &concealed, false).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("TOKEN"));
+        assert!(message.contains("--allow-argv-secrets"));
+        assert!(message.contains("--env-file"));
+    }
+
+    #[test]
+    fn test_check_argv_secrets_allows_with_flag_or_non_concealed() {
+        let concealed: HashSet<String> = ["TOKEN".to_string()].into_iter().collect();
+        assert!(check_argv_secrets(&["TOKEN".to_string()], &concealed, true).is_ok());
+        assert!(check_argv_secrets(&["HOST".to_string()], &concealed, false).is_ok());
+    }
+
+    #[test]
+    fn test_check_argv_policy_refuses_forbidden_fields() {
+        let key_policies = HashMap::from([
+            (
+                "TLS_KEY".to_string(),
+                BTreeSet::from([config::FieldPolicy::ArgvForbidden]),
+            ),
+            ("HOST".to_string(), BTreeSet::new()),
+        ]);
+        let err = check_argv_policy(&["TLS_KEY".to_string()], &key_policies).unwrap_err();
+        assert!(err.to_string().contains("TLS_KEY"));
+        assert!(check_argv_policy(&["HOST".to_string()], &key_policies).is_ok());
+    }
+
+    #[test]
+    fn test_cli_parse_allow_argv_secrets() {
+        let cli = Cli::try_parse_from(["opz", "run", "--allow-argv-secrets", "foo", "--", "echo"])
+            .unwrap();
+        match cli.cmd {
+            Some(Cmd::Run { run_opts, .. }) => assert!(run_opts.allow_argv_secrets),
+            _ => panic!("expected run command"),
+        }
+
+        let cli =
+            Cli::try_parse_from(["opz", "--allow-argv-secrets", "foo", "--", "echo"]).unwrap();
+        assert!(cli.run_opts.allow_argv_secrets);
+    }
+
+    #[test]
+    fn test_merge_env_lines_last_item_wins() {
+        let sections = vec![
+            (
+                "foo".to_string(),
+                vec![
+                    "A=op://vault1/item1/A".to_string(),
+                    "B=op://vault1/item1/B".to_string(),
+                ],
+            ),
+            (
+                "bar".to_string(),
+                vec![
+                    "A=op://vault2/item2/A".to_string(),
+                    "C=op://vault2/item2/C".to_string(),
+                ],
+            ),
+        ];
+
+        let merged = merge_env_lines(&sections);
+        assert_eq!(
+            merged,
+            vec![
+                "A=op://vault2/item2/A".to_string(),
+                "B=op://vault1/item1/B".to_string(),
+                "C=op://vault2/item2/C".to_string(),
+            ]
+        );
+    }
+
+    fn layout_fixture() -> (Vec<EnvSection>, HashMap<String, KeyOrigin>) {
+        let sections = vec![
+            (
+                "foo".to_string(),
+                vec![
+                    "DB_HOST=op://v/1/DB_HOST".to_string(),
+                    "A=op://v/1/A".to_string(),
+                ],
+            ),
+            (
+                "bar".to_string(),
+                vec!["DB_PORT=op://v/2/DB_PORT".to_string()],
+            ),
+        ];
+        let origin = |item, section: Option<&str>| KeyOrigin {
+            item,
+            section: section.map(String::from),
+        };
+        let key_origins = HashMap::from([
+            ("DB_HOST".to_string(), origin(0, Some("Database"))),
+            ("A".to_string(), origin(0, None)),
+            ("DB_PORT".to_string(), origin(1, Some("Database"))),
+        ]);
+        (sections, key_origins)
+    }
+
+    #[test]
+    fn test_layout_env_lines_default_keeps_merge_order() {
+        let (sections, key_origins) = layout_fixture();
+        let merged = merge_env_lines(&sections);
+        let output = config::OutputConfig::default();
+        assert_eq!(
+            layout_env_lines(&merged, &sections, &key_origins, &output),
+            merged
+        );
+    }
+
+    #[test]
+    fn test_layout_env_lines_by_section_with_comments() {
+        let (sections, key_origins) = layout_fixture();
+        let output = config::OutputConfig {
+            order: Some(config::OutputOrder::Section),
+            separators: Some(config::Separators::Comment),
+            ..Default::default()
+        };
+        assert_eq!(
+            layout_env_lines(
+                &merge_env_lines(&sections),
+                &sections,
+                &key_origins,
+                &output
+            ),
+            vec![
+                "# --- section: Database ---",
+                "DB_HOST=op://v/1/DB_HOST",
+                "DB_PORT=op://v/2/DB_PORT",
+                "",
+                "# --- section: (none) ---",
+                "A=op://v/1/A",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layout_env_lines_explicit_keys_then_alphabetical() {
+        let (sections, key_origins) = layout_fixture();
+        let output = config::OutputConfig {
+            keys: vec!["DB_PORT".to_string(), "MISSING".to_string()],
+            order: Some(config::OutputOrder::Alphabetical),
+            separators: Some(config::Separators::Blank),
+        };
+        assert_eq!(
+            layout_env_lines(
+                &merge_env_lines(&sections),
+                &sections,
+                &key_origins,
+                &output
+            ),
+            vec![
+                "DB_PORT=op://v/2/DB_PORT",
+                "",
+                "A=op://v/1/A",
+                "",
+                "DB_HOST=op://v/1/DB_HOST",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sectioned_env_output_string() {
+        let sections = vec![
+            (
+                "foo".to_string(),
+                vec!["A=op://v1/i1/A".to_string(), "B=op://v1/i1/B".to_string()],
+            ),
+            ("bar".to_string(), vec!["C=op://v2/i2/C".to_string()]),
+        ];
+
+        let rendered = sectioned_env_output_string(&sections);
+        assert_eq!(
+            rendered,
+            "# --- item: foo ---\nA=op://v1/i1/A\nB=op://v1/i1/B\n\n# --- item: bar ---\nC=op://v2/i2/C\n"
+        );
+    }
+
+    #[test]
+    fn test_show_output_string_plain() {
+        let sections = vec![
+            ("foo".to_string(), vec!["A".to_string(), "B".to_string()]),
+            ("bar".to_string(), vec!["C".to_string()]),
+        ];
+
+        let rendered = show_output_string(&sections, false);
+        assert_eq!(rendered, "A\nB\nC\n");
+    }
+
+    #[test]
+    fn test_show_output_string_with_item() {
+        let sections = vec![
+            ("foo".to_string(), vec!["A".to_string(), "B".to_string()]),
+            ("bar".to_string(), vec!["C".to_string()]),
+        ];
+
+        let rendered = show_output_string(&sections, true);
+        assert_eq!(
+            rendered,
+            "# --- item: foo ---\nA\nB\n\n# --- item: bar ---\nC\n"
+        );
+    }
+
+    #[test]
+    fn test_cli_parse_show_multiple_items() {
+        let cli = Cli::try_parse_from(["opz", "show", "foo", "bar"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Show {
+                with_item, items, ..
+            }) => {
+                assert!(!with_item);
+                assert_eq!(items, vec!["foo".to_string(), "bar".to_string()]);
+            }
+            _ => panic!("expected show command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parse_show_with_item_flag() {
+        let cli = Cli::try_parse_from(["opz", "show", "--with-item", "foo"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Show {
+                with_item, items, ..
+            }) => {
+                assert!(with_item);
+                assert_eq!(items, vec!["foo".to_string()]);
+            }
+            _ => panic!("expected show command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parse_run_multiple_items() {
+        let cli = Cli::try_parse_from(["opz", "run", "foo", "bar", "--", "echo", "ok"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Run {
+                items,
+                command,
+                env_file,
+                ..
+            }) => {
+                assert_eq!(items, vec!["foo".to_string(), "bar".to_string()]);
+                assert_eq!(command, vec!["echo".to_string(), "ok".to_string()]);
+                assert!(env_file.is_empty());
+            }
+            _ => panic!("expected run command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parse_run_with_env_file_option() {
+        let cli = Cli::try_parse_from([
+            "opz",
+            "run",
+            "--env-file",
+            ".env",
+            "foo",
+            "bar",
+            "--",
+            "env",
+        ])
+        .unwrap();
+        match cli.cmd {
+            Some(Cmd::Run {
+                items, env_file, ..
+            }) => {
+                assert_eq!(items, vec!["foo".to_string(), "bar".to_string()]);
+                assert_eq!(env_file, [EnvFileArg::dotenv(PathBuf::from(".env"))]);
+            }
+            _ => panic!("expected run command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parse_gen_multiple_items() {
+        let cli = Cli::try_parse_from(["opz", "gen", "foo", "bar"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Gen {
+                items, env_file, ..
+            }) => {
+                assert_eq!(items, vec!["foo".to_string(), "bar".to_string()]);
+                assert!(env_file.is_empty());
+            }
+            _ => panic!("expected gen command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parse_top_level_multiple_items() {
+        let cli = Cli::try_parse_from([
+            "opz",
+            "--env-file",
+            ".env.local",
+            "foo",
+            "bar",
+            "--",
+            "printenv",
+        ])
+        .unwrap();
+        assert!(cli.cmd.is_none());
+        assert_eq!(cli.items, vec!["foo".to_string(), "bar".to_string()]);
+        assert_eq!(cli.command, vec!["printenv".to_string()]);
+        assert_eq!(
+            cli.env_file,
+            [EnvFileArg::dotenv(PathBuf::from(".env.local"))]
+        );
+    }
+
+    #[test]
+    fn test_cli_parse_find_all_accounts() {
+        let cli = Cli::try_parse_from(["opz", "find", "--all-accounts", "db"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Find {
+                all_accounts,
+                url,
+                query,
+            }) => {
+                assert!(all_accounts);
+                assert_eq!(url, None);
+                assert_eq!(query.as_deref(), Some("db"));
+            }
+            _ => panic!("expected find command"),
+        }
+        let cli = Cli::try_parse_from(["opz", "find", "--url", "https://api.stripe.com"]).unwrap();
+        assert!(matches!(
+            cli.cmd,
+            Some(Cmd::Find {
+                url: Some(_),
+                query: None,
+                ..
+            })
+        ));
+        assert!(Cli::try_parse_from(["opz", "find"]).is_err());
+        let cli =
+            Cli::try_parse_from(["opz", "--url", "https://api.stripe.com", "--", "x"]).unwrap();
+        assert_eq!(cli.url, vec!["https://api.stripe.com"]);
+        let cli = Cli::try_parse_from(["opz", "--url", "api.stripe.com", "--", "x"]).unwrap();
+        assert_eq!(cli.url, vec!["api.stripe.com"]);
+        assert!(cli.items.is_empty());
+    }
+
+    #[test]
+    fn test_cli_parse_find_all_accounts_conflicts_with_account() {
+        let result =
+            Cli::try_parse_from(["opz", "--account", "my", "find", "--all-accounts", "db"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_cli_parse_global_account() {
+        let cli = Cli::try_parse_from(["opz", "--account", "my.1password.com", "foo", "--", "env"])
+            .unwrap();
+        assert_eq!(cli.account.as_deref(), Some("my.1password.com"));
+        assert_eq!(cli.items, vec!["foo".to_string()]);
+    }
+
+    #[test]
+    fn test_op_subcommand_drops_arguments_and_global_flags() {
+        let subcommand = |args: &[&str]| op_subcommand(args.iter().map(std::ffi::OsStr::new));
+        assert_eq!(
+            subcommand(&[
+                "--account",
+                "work",
+                "item",
+                "get",
+                "id1",
+                "--format",
+                "json"
+            ]),
+            "item get"
+        );
+        assert_eq!(subcommand(&["read", "op://v/i/f"]), "read");
+        assert_eq!(subcommand(&["whoami", "--format=json"]), "whoami");
+        assert_eq!(subcommand(&["--version"]), "--version");
+    }
+
+    #[test]
+    fn test_detect_command_hint_skips_account_value() {
+        let args: Vec<OsString> = ["opz", "--account", "find", "show", "x"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert_eq!(detect_command_hint(&args), "show");
+    }
+
+    #[test]
+    fn test_format_find_row_with_and_without_account() {
+        let item = ItemListEntry {
+            id: "abc".to_string(),
+            title: "db prod".to_string(),
+            vault: Some(ItemVault {
+                id: "v1".to_string(),
+                name: "Team".to_string(),
+            }),
+            updated_at: None,
+            urls: Vec::new(),
+        };
+        assert_eq!(format_find_row(&item, None), "abc\tTeam\tdb prod");
+        assert_eq!(
+            format_find_row(&item, Some("my.1password.com")),
+            "abc\tmy.1password.com\tTeam\tdb prod"
+        );
+    }
+
+    #[test]
+    fn test_match_item_entry_accepts_vault_qualified_titles() {
+        let entry = |id: &str, title: &str, vault: &str| ItemListEntry {
+            id: id.to_string(),
+            title: title.to_string(),
+            vault: Some(ItemVault {
+                id: format!("{vault}-id"),
+                name: vault.to_string(),
+            }),
+            updated_at: None,
+            urls: Vec::new(),
+        };
+        let items = vec![
+            entry("a", "Service Prod", "Team Vault"),
+            entry("b", "Service Prod", "Private"),
+            entry("c", "org/repo", "Private"),
+        ];
+        assert!(match_item_entry(&items, "Service Prod").is_err());
+        assert_eq!(
+            match_item_entry(&items, "Team Vault/Service Prod")
+                .unwrap()
+                .id,
+            "a"
+        );
+        assert_eq!(match_item_entry(&items, "Private/Service").unwrap().id, "b");
+        // Titles containing `/` still match as titles.
+        assert_eq!(match_item_entry(&items, "org/repo").unwrap().id, "c");
+        assert!(match_item_entry(&items, "Nowhere/Service Prod").is_err());
+    }
+
+    #[test]
+    fn test_match_item_entry_by_website_host() {
+        let entry = |id: &str, title: &str, urls: &[&str]| ItemListEntry {
+            id: id.to_string(),
+            title: title.to_string(),
+            vault: None,
+            updated_at: None,
+            urls: urls
+                .iter()
+                .map(|href| ItemUrl {
+                    primary: false,
+                    href: href.to_string(),
+                })
+                .collect(),
+        };
+        let items = vec![
+            entry("a", "Stripe (live)", &["https://api.stripe.com/v1"]),
+            entry("b", "stripe dashboard", &["dashboard.stripe.com"]),
+            entry(
+                "c",
+                "GitHub",
+                &["https://www.github.com", "https://gist.github.com"],
+            ),
+            entry("d", "GitHub bot", &["github.com:443/login"]),
+        ];
+        let by_url = |url: &str| match_url_entry(&items, url).map(|e| e.id.as_str());
+        assert_eq!(by_url("https://api.stripe.com").unwrap(), "a");
+        assert_eq!(by_url("api.stripe.com").unwrap(), "a");
+        assert_eq!(by_url("https://dashboard.stripe.com/test").unwrap(), "b");
+        assert_eq!(by_url("https://gist.github.com").unwrap(), "c");
+        assert!(by_url("https://github.com").is_err());
+        let err = by_url("stripe.com").unwrap_err();
+        assert_eq!(
+            error::kind(&err),
+            Some(error::Kind::ItemNotFound),
+            "a host without a website match must not fall back to titles"
+        );
+        assert!(by_url("https:///path").is_err());
+        // Titles are never matched by host.
+        assert!(match_item_entry(&items, "https://api.stripe.com").is_err());
+    }
+
+    #[test]
+    fn test_url_host() {
+        assert_eq!(
+            url_host("https://api.stripe.com/v1?x=1"),
+            Some("api.stripe.com")
+        );
+        assert_eq!(
+            url_host("https://user:pw@www.example.com:8443"),
+            Some("example.com")
+        );
+        assert_eq!(url_host("stripe.com"), Some("stripe.com"));
+        assert_eq!(url_host("https:///path"), None);
+    }
+
+    #[test]
+    fn test_cli_parse_help_json() {
+        let cli = Cli::try_parse_from(["opz", "help", "--json", "run"]).unwrap();
+        assert!(cli.json);
+        match cli.cmd {
+            Some(Cmd::Help { command }) => {
+                assert_eq!(command, vec!["run".to_string()]);
+            }
+            _ => panic!("expected help command"),
+        }
+    }
+
+    #[test]
+    fn test_span_dump_requested_from_raw_args() {
+        let args = |list: &[&str]| list.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            span_dump_requested(&args(&["opz", "gen", "--trace-debug", "db"])),
+            Some(telemetry::SpanDump::Stderr)
+        );
+        assert_eq!(
+            span_dump_requested(&args(&["opz", "gen", "--trace-debug=spans.jsonl", "db"])),
+            Some(telemetry::SpanDump::File(PathBuf::from("spans.jsonl")))
+        );
+        assert_eq!(
+            span_dump_requested(&args(&["opz", "db", "--", "tool", "--trace-debug"])),
+            None
+        );
+        let cli = Cli::try_parse_from(["opz", "gen", "--trace-debug=spans.jsonl", "db"]).unwrap();
+        assert_eq!(cli.trace_debug, Some(Some(PathBuf::from("spans.jsonl"))));
+    }
+
+    #[test]
+    fn test_requested_fd_from_raw_args() {
+        let args = |list: &[&str]| list.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            requested_fd(&args(&["opz", "gen", "--fd", "3", "db"])),
+            Some(3)
+        );
+        assert_eq!(
+            requested_fd(&args(&["opz", "gen", "--fd=4", "db"])),
+            Some(4)
+        );
+        assert_eq!(requested_fd(&args(&["opz", "gen", "db"])), None);
+        assert_eq!(
+            requested_fd(&args(&["opz", "db", "--", "tool", "--fd", "3"])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_no_telemetry_requested_from_raw_args() {
+        let args = |list: &[&str]| list.iter().map(OsString::from).collect::<Vec<_>>();
+        assert!(no_telemetry_requested(&args(&[
+            "opz",
+            "gen",
+            "--no-telemetry",
+            "db"
+        ])));
+        assert!(!no_telemetry_requested(&args(&[
+            "opz",
+            "db",
+            "--",
+            "tool",
+            "--no-telemetry"
+        ])));
+        assert!(
+            Cli::try_parse_from(["opz", "gen", "--no-telemetry", "db"])
+                .unwrap()
+                .no_telemetry
+        );
+    }
+
+    #[test]
+    fn test_json_requested_stops_at_command_separator() {
+        let args = |list: &[&str]| list.iter().map(OsString::from).collect::<Vec<_>>();
+        assert!(json_requested(&args(&["opz", "find", "--json", "db"])));
+        assert!(!json_requested(&args(&[
+            "opz", "foo", "--", "tool", "--json"
+        ])));
+    }
+
+    #[test]
+    fn test_find_and_error_json_schema() {
+        let item = ItemListEntry {
+            id: "abc".to_string(),
+            title: "db prod".to_string(),
+            vault: Some(ItemVault {
+                id: "v1".to_string(),
+                name: "Team".to_string(),
+            }),
+            updated_at: None,
+            urls: Vec::new(),
+        };
+        let found = find_json(&[(&item, Some("my.1password.com"))]);
+        assert_eq!(
+            found,
+            serde_json::json!({ "items": [{
+                "id": "abc",
+                "title": "db prod",
+                "vault": { "id": "v1", "name": "Team" },
+                "account": "my.1password.com",
+            }] })
+        );
+
+        let err = anyhow!("op error").context("No item matched title: x");
+        assert_eq!(
+            error_json(&err),
+            serde_json::json!({ "error": {
+                "message": "No item matched title: x",
+                "causes": ["op error"],
+                "exit_code": 1,
+            } })
+        );
+        let candidate = error::Candidate {
+            id: "abc".to_string(),
+            title: "db prod".to_string(),
+            vault: Some("Team".to_string()),
+        };
+        let err = anyhow::Error::new(
+            error::Error::new(error::Kind::AmbiguousItem, "Please be more specific")
+                .with_candidates(vec![candidate])
+                .with_op_args("item list --format json"),
+        );
+        assert_eq!(
+            error_json(&err),
+            serde_json::json!({ "error": {
+                "kind": "ambiguous-item",
+                "message": "Please be more specific",
+                "causes": [],
+                "exit_code": 64,
+                "candidates": [{ "id": "abc", "title": "db prod", "vault": "Team" }],
+                "op_args": "item list --format json",
+            } })
+        );
+        let clap_err = Cli::try_parse_from(["opz", "find"]).unwrap_err();
+        let json = error_json(&clap_err.into());
+        assert_eq!(
+            json["error"]["message"],
+            "the following required arguments were not provided: <QUERY>"
+        );
+        assert_eq!(json["error"]["exit_code"], 2);
+    }
+
+    #[test]
+    fn test_command_tree_json_describes_subcommands_and_flags() {
+        let mut root = Cli::command();
+        root.build();
+        let tree = command_tree_json(&root);
+
+        assert_eq!(tree["name"], "opz");
+        let subcommands = tree["subcommands"].as_array().unwrap();
+        let run = subcommands
+            .iter()
+            .find(|sub| sub["name"] == "run")
+            .expect("run subcommand");
+        let env_file = run["args"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|arg| arg["id"] == "env_file")
+            .expect("env_file arg");
+        assert_eq!(env_file["long"], "env-file");
+        assert_eq!(env_file["takes_value"], true);
+        assert!(env_file["help"].as_str().unwrap().contains("env file"));
+
+        let vault = run["args"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|arg| arg["id"] == "vault")
+            .expect("propagated global vault arg");
+        assert_eq!(vault["global"], true);
+    }
+
+    #[test]
+    fn test_auth_source_detect() {
+        assert_eq!(
+            AuthSource::detect(true, true, true),
+            AuthSource::ServiceAccount
+        );
+        assert_eq!(
+            AuthSource::detect(true, false, true),
+            AuthSource::SessionToken
+        );
+        assert_eq!(
+            AuthSource::detect(true, false, false),
+            AuthSource::DesktopApp
+        );
+        assert_eq!(AuthSource::detect(false, false, false), AuthSource::None);
+    }
+
+    #[test]
+    fn test_whoami_report_signed_in_via_desktop_app() {
+        let me = WhoAmI {
+            url: "my.1password.com".to_string(),
+            email: "dev@example.com".to_string(),
+            user_type: "HUMAN".to_string(),
+            account_uuid: "ACC1".to_string(),
+        };
+        let accounts = vec![AccountListEntry {
+            url: "my.1password.com".to_string(),
+            email: "dev@example.com".to_string(),
+            account_uuid: "ACC1".to_string(),
+        }];
+
+        let report = whoami_report(Some(&me), &accounts, false, &AuthSource::DesktopApp);
+        assert!(report.contains("Signed in:             yes"));
+        assert!(report.contains("my.1password.com (ACC1)"));
+        assert!(report.contains("Service account token: not set"));
+        assert!(report.contains("Desktop app:           integration in use"));
+        assert!(report.contains("Configured accounts:   1"));
+    }
+
+    #[test]
+    fn test_whoami_report_not_signed_in() {
+        let report = whoami_report(None, &[], true, &AuthSource::ServiceAccount);
+        assert!(report.contains("Signed in:             no"));
+        assert!(report.contains("Service account token: set"));
+        assert!(report.contains("Desktop app:           not in use"));
+    }
+
+    #[test]
+    fn test_op_retry_delay_doubles_up_to_cap() {
+        let delays: Vec<u64> = (0..6)
+            .map(|attempt| op_retry_delay(attempt).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![500, 1000, 2000, 4000, 8000, 8000]);
+        assert_eq!(op_retry_delay(u32::MAX), OP_RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn test_env_diff_reports_missing_and_differing_keys() {
+        let pairs = |entries: &[(&str, &str)]| -> Vec<(String, String)> {
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let references = pairs(&[
+            ("API_KEY", "op://v/i/API_KEY"),
+            ("DB_PASSWORD", "op://v/i/DB_PASSWORD"),
+            ("TOKEN", "op://v/i/TOKEN"),
+            ("REF", "op://v/i/REF"),
+        ]);
+        let values: HashMap<String, String> = pairs(&[("API_KEY", "abc"), ("DB_PASSWORD", "new")])
+            .into_iter()
+            .collect();
+        let file = pairs(&[
+            ("API_KEY", "abc"),
+            ("DB_PASSWORD", "old"),
+            ("REF", "op://v/other/REF"),
+            ("DEBUG", "1"),
+        ]);
+        let diff = env_diff(&references, &values, &file);
+        assert_eq!(
+            diff,
+            EnvDiff {
+                only_in_item: vec!["TOKEN".to_string()],
+                only_in_file: vec!["DEBUG".to_string()],
+                different: vec![
+                    (
+                        "DB_PASSWORD".to_string(),
+                        "new".to_string(),
+                        "old".to_string()
+                    ),
+                    (
+                        "REF".to_string(),
+                        "op://v/i/REF".to_string(),
+                        "op://v/other/REF".to_string()
+                    ),
+                ],
+            }
+        );
+
+        let out = env_diff_output_string(&diff, "app", ".env", false);
+        assert!(out.contains("- TOKEN  (only in app)"));
+        assert!(out.contains("+ DEBUG  (only in .env)"));
+        assert!(out.contains("~ DB_PASSWORD  (values differ)"));
+        assert!(!out.contains("new"));
+        assert!(env_diff_output_string(&diff, "app", ".env", true).contains("    .env: old"));
+        assert_eq!(
+            env_diff_output_string(&EnvDiff::default(), "app", ".env", false),
+            "app and .env are in sync\n"
+        );
+    }
+
+    #[test]
+    fn test_direct_item_accepts_ids_and_op_references() {
+        assert_eq!(
+            direct_item("abcdefghijklmnopqrstuvwx12"),
+            Some(DirectItem {
+                vault: None,
+                item: "abcdefghijklmnopqrstuvwx12"
+            })
+        );
+        assert_eq!(
+            direct_item("op://Team Vault/Service Prod/password"),
+            Some(DirectItem {
+                vault: Some("Team Vault"),
+                item: "Service Prod"
+            })
+        );
+        assert_eq!(direct_item("op://Team Vault"), None);
+        assert_eq!(direct_item("Service Prod"), None);
+        assert_eq!(direct_item("ABCDEFGHIJKLMNOPQRSTUVWX12"), None);
+        assert_eq!(direct_item("abcdefghijklmnopqrstuvwx1"), None);
+    }
+
+    #[test]
+    fn test_preflight_report_checks_vault_permissions() {
+        let team = ItemVault {
+            id: "v1".to_string(),
+            name: "Team".to_string(),
+        };
+        let listed = Ok(vec![ItemListEntry {
+            id: "abc123".to_string(),
+            title: "foo".to_string(),
+            vault: Some(team.clone()),
+            updated_at: None,
+            urls: Vec::new(),
+        }]);
+        let granted = vec![
+            (PREFLIGHT_READ, Ok(vec![team])),
+            (PREFLIGHT_WRITE, Ok(Vec::new())),
+        ];
+
+        let report = preflight_report("foo", &listed, &granted);
+        assert!(!report.ok());
+        assert_eq!(
+            preflight_output_string(&[report]),
+            "foo\n  ok    item         'foo' (abc123) in vault Team\n  ok    read_items   vault Team grants read_items\n  FAIL  write_items  vault Team does not grant write_items to these credentials\n"
+        );
+
+        let missing = preflight_report("bar", &listed, &granted);
+        assert_eq!(missing.checks.len(), 1);
+        assert_eq!(missing.checks[0].detail, "No item matched title: bar");
+
+        let unlisted = preflight_report("foo", &Err(anyhow!("op error: not signed in\n")), &[]);
+        assert_eq!(unlisted.checks[0].detail, "op error: not signed in");
+    }
+
+    #[test]
+    fn test_field_listings_show_env_names_not_values() {
+        let mut host = make_field(Some("host"), true);
+        host.field_type = Some("STRING".to_string());
+        host.section = Some(ItemFieldSection {
+            label: Some("prod".to_string()),
+        });
+        let mut otp = make_field(Some("one-time password"), true);
+        otp.field_type = Some("OTP".to_string());
+        let item = make_item(vec![
+            concealed_field("API_KEY", "s3cret"),
+            host,
+            otp,
+            make_field(None, true),
+        ]);
+
+        let fields = field_listings(&item, &config::Config::default());
+        assert_eq!(fields[0].env.as_deref(), Some("API_KEY"));
+        assert!(fields[0].policies.is_empty());
+        assert_eq!(fields[1].field_type.as_deref(), Some("text"));
+        assert_eq!(
+            field_listing_output_string(&fields),
+            "LABEL              TYPE       SECTION  ENV\n\
+             API_KEY            concealed  -        API_KEY\n\
+             host               text       prod     host\n\
+             one-time password  otp        -        (skipped: \"one-time password\" is not a valid env var name)\n\
+             (no label)         -          -        (skipped: no label)\n"
+        );
+        assert!(!serde_json::to_string(&fields).unwrap().contains("s3cret"));
+    }
+
+    #[test]
+    fn test_field_listings_apply_policies() {
+        let item = make_item(vec![
+            concealed_field("MASTER_KEY", "a"),
+            concealed_field("DB_PASSWORD", "b"),
+            concealed_field("API_KEY", "c"),
+        ]);
+        let config: config::Config = toml::from_str(
+            "[policies]\n\"MASTER_*\" = [\"never-export\"]\n\"DB_*\" = [\"file-only\"]\n",
+        )
+        .unwrap();
+
+        let fields = field_listings(&item, &config);
+        assert_eq!(fields[0].env, None);
+        assert_eq!(fields[0].policies, vec!["never-export"]);
+        assert_eq!(fields[1].env.as_deref(), Some("DB_PASSWORD"));
+        assert_eq!(fields[1].policies, vec!["file-only"]);
+        assert_eq!(
+            field_listing_output_string(&fields),
+            "LABEL        TYPE       SECTION  ENV\n\
+             MASTER_KEY   concealed  -        (skipped: [policies] \"MASTER_*\" = never-export)\n\
+             DB_PASSWORD  concealed  -        DB_PASSWORD (file-only: --env-file reference only)\n\
+             API_KEY      concealed  -        API_KEY\n"
+        );
+    }
+
+    #[test]
+    fn test_item_summary_shows_lengths_not_values() {
+        let mut secret = make_field(Some("API_KEY"), true);
+        secret.field_type = Some("CONCEALED".to_string());
+        secret.section = Some(ItemFieldSection {
+            label: Some("prod".to_string()),
+        });
+        let item = ItemGet {
+            fields: vec![secret, make_field(Some("EMPTY"), false)],
+            tags: vec!["api".to_string()],
+            vault: None,
+            version: Some(3),
+            category: Some("API_CREDENTIAL".to_string()),
+            updated_at: Some("2026-01-02T03:04:05Z".to_string()),
+            urls: Vec::new(),
+        };
+        let entry = ItemListEntry {
+            id: "abc123".to_string(),
+            title: "foo".to_string(),
+            vault: Some(ItemVault {
+                id: "v1".to_string(),
+                name: "Team".to_string(),
+            }),
+            updated_at: None,
+            urls: Vec::new(),
+        };
+
+        let rendered = summary_output_string(&[item_summary(&entry, &item, true)]);
+        assert_eq!(
+            rendered,
+            "foo (abc123)\n  vault:     Team\n  category:  API_CREDENTIAL\n  tags:      api\n  sections:  prod\n  updated:   2026-01-02T03:04:05Z\n  version:   3\n  fields:\n    API_KEY  CONCEALED  4 chars  [prod]\n    EMPTY    -          empty\n"
+        );
+        assert!(!rendered.contains("test"));
+
+        let cached = item_summary(&entry, &redacted_item(&item), false);
+        assert_eq!(cached.fields[0].value_length, None);
+        assert_eq!(cached.fields[1].value_length, Some(0));
+    }
+
+    #[test]
+    fn test_follow_reference_chain() {
+        let values = HashMap::from([
+            ("op://v/shared/db", "op://v/root/db"),
+            ("op://v/root/db", "s3cret"),
+            ("op://v/a/x", "op://v/b/x"),
+            ("op://v/b/x", "op://v/a/x"),
+        ]);
+        let read = |reference: &str| {
+            values
+                .get(reference)
+                .map(|value| is_op_reference(value).then(|| value.to_string()))
+                .ok_or_else(|| anyhow!("not found: {reference}"))
+        };
+        assert_eq!(
+            follow_reference_chain("op://v/app/DB", "op://v/shared/db", read).unwrap(),
+            "op://v/root/db"
+        );
+        let err = follow_reference_chain("op://v/app/X", "op://v/a/x", read).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "reference cycle: op://v/app/X -> op://v/a/x -> op://v/b/x -> op://v/a/x"
+        );
+
+        let endless = |reference: &str| Ok(Some(format!("{reference}/next")));
+        let err = follow_reference_chain("op://v/app/Y", "op://v/n", endless).unwrap_err();
+        assert!(err.to_string().starts_with("more than 8 nested references"));
+    }
+
+    #[test]
+    fn test_nested_references_survive_redaction() {
+        let item = make_item(vec![
+            concealed_field("DB_PASSWORD", "op://Shared/postgres/password"),
+            concealed_field("API_KEY", "plain-secret"),
+        ]);
+        let redacted = redacted_item(&item);
+        assert!(!serde_json::to_string(&redacted)
+            .unwrap()
+            .contains("plain-secret"));
+        let found = vec![(
+            "abc123".to_string(),
+            "vault-id".to_string(),
+            "app".to_string(),
+            redacted,
+        )];
+        assert_eq!(
+            nested_references(&found),
+            HashMap::from([(
+                "op://vault-id/abc123/DB_PASSWORD".to_string(),
+                "op://Shared/postgres/password".to_string()
+            )])
+        );
+    }
+
+    #[test]
+    fn test_redacted_item_keeps_shape_without_values() {
+        let item = ItemGet {
+            fields: vec![
+                make_field(Some("API_KEY"), true),
+                make_field(Some("EMPTY"), false),
+            ],
+            tags: vec!["prod".to_string()],
+            vault: None,
+            version: Some(7),
+            category: Some("LOGIN".to_string()),
+            updated_at: None,
+            urls: Vec::new(),
+        };
+
+        let redacted = redacted_item(&item);
+        let json = serde_json::to_string(&redacted).unwrap();
+        assert!(!json.contains("\"test\""));
+        assert_eq!(
+            item_to_valid_labels(&redacted, &config::Config::default()).unwrap(),
+            item_to_valid_labels(&item, &config::Config::default()).unwrap()
+        );
+        assert_eq!(redacted.tags, vec!["prod"]);
+        assert_eq!(redacted.version, Some(7));
+    }
+
+    #[test]
+    fn test_item_cache_file_path_is_scoped_by_account() {
+        let a = item_cache_file_path(None, "abc").unwrap();
+        let b = item_cache_file_path(Some("team"), "abc").unwrap();
+        assert_ne!(a, b);
+        assert!(a
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("item_get_v2_"));
+    }
+
+    #[test]
+    fn test_item_list_entry_updated_at_is_optional() {
+        let entries: Vec<ItemListEntry> = serde_json::from_str(
+            r#"[{"id":"a","title":"A","updated_at":"2026-01-02T03:04:05Z"},{"id":"b","title":"B"}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            entries[0].updated_at.as_deref(),
+            Some("2026-01-02T03:04:05Z")
+        );
+        assert_eq!(entries[1].updated_at, None);
+    }
+
+    #[test]
+    fn test_cli_parse_config_export_import() {
+        let cli =
+            Cli::try_parse_from(["opz", "config", "export", "--bundle", "team.opzpkg"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Config {
+                action: ConfigCmd::Export { bundle },
+            }) => assert_eq!(bundle, PathBuf::from("team.opzpkg")),
+            _ => panic!("Expected config export"),
+        }
+
+        let cli = Cli::try_parse_from([
+            "opz",
+            "config",
+            "import",
+            "--bundle",
+            "team.opzpkg",
+            "--force",
+        ])
+        .unwrap();
+        match cli.cmd {
+            Some(Cmd::Config {
+                action: ConfigCmd::Import { force, .. },
+            }) => assert!(force),
+            _ => panic!("Expected config import"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parse_keep() {
+        let cli = Cli::try_parse_from([
+            "opz",
+            "run",
+            "--env-file",
+            ".env",
+            "--keep",
+            "none",
+            "foo",
+            "--",
+            "true",
+        ])
+        .unwrap();
+        match cli.cmd {
+            Some(Cmd::Run { run_opts, .. }) => assert_eq!(run_opts.keep, KeepEnvFile::None),
+            _ => panic!("Expected Run command"),
+        }
+
+        let cli = Cli::try_parse_from([
+            "opz",
+            "--env-file",
+            ".env",
+            "--keep=refs",
+            "foo",
+            "--",
+            "true",
+        ])
+        .unwrap();
+        assert_eq!(cli.run_opts.keep, KeepEnvFile::File);
+
+        let cli = Cli::try_parse_from(["opz", "run", "foo", "--", "true"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Run { run_opts, .. }) => assert_eq!(run_opts.keep, KeepEnvFile::File),
+            _ => panic!("Expected Run command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parse_eager_clear() {
+        let cli = Cli::try_parse_from(["opz", "--eager-clear", "foo", "--", "true"]).unwrap();
+        assert_eq!(cli.run_opts.eager_clear, Some(0));
+        assert_eq!(cli.run_opts.keep(), KeepEnvFile::None);
+        assert_eq!(cli.items, vec!["foo".to_string()]);
+
+        let cli =
+            Cli::try_parse_from(["opz", "run", "--eager-clear=250", "foo", "--", "true"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Run { run_opts, .. }) => assert_eq!(run_opts.eager_clear, Some(250)),
+            _ => panic!("Expected Run command"),
+        }
+
+        assert!(Cli::try_parse_from([
+            "opz",
+            "run",
+            "--eager-clear",
+            "--keep",
+            "file",
+            "foo",
+            "--",
+            "true"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_cli_parse_temp_env() {
+        let cli = Cli::try_parse_from(["opz", "run", "--temp-env", "foo", "--", "true"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Run { run_opts, .. }) => assert!(run_opts.temp_env),
+            _ => panic!("Expected Run command"),
+        }
+
+        for conflict in [["--env-file", ".env"], ["--keep", "refs"]] {
+            let mut args = vec!["opz", "run", "--temp-env"];
+            args.extend(conflict);
+            args.extend(["foo", "--", "true"]);
+            assert!(Cli::try_parse_from(args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_cli_parse_prompt() {
+        let cli = Cli::try_parse_from([
+            "opz", "run", "--prompt", "OTP", "--prompt", "DB_USER", "foo", "--", "true",
+        ])
+        .unwrap();
+        match cli.cmd {
+            Some(Cmd::Run { run_opts, .. }) => assert_eq!(run_opts.prompt, vec!["OTP", "DB_USER"]),
+            _ => panic!("Expected Run command"),
+        }
+    }
+
+    #[test]
+    fn test_prompt_values_rejects_invalid_names() {
+        let err = prompt_values(&["1BAD".to_string()]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--prompt 1BAD: not a valid environment variable name"
+        );
+        assert!(prompt_values(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_cli_parse_verbosity() {
+        let cli = Cli::try_parse_from(["opz", "gen", "-vv", "foo"]).unwrap();
+        assert_eq!(cli.verbose, 2);
+        assert!(!cli.quiet);
+
+        let cli = Cli::try_parse_from(["opz", "find", "--quiet", "foo"]).unwrap();
+        assert!(cli.quiet);
+
+        assert!(Cli::try_parse_from(["opz", "find", "-v", "-q", "foo"]).is_err());
+    }
+
+    #[test]
+    fn test_write_env_file_guarded_restore_removes_new_file() {
+        let tmp = TempDir::new().unwrap();
+        let existing = tmp.path().join("existing.env");
+        fs::write(&existing, "KEEP=1\n").unwrap();
+        let created = tmp.path().join("created.env");
+        let lines = vec!["API_KEY=op://v/i/API_KEY".to_string()];
+
+        write_env_file_guarded(&existing, &lines, EnvMerge::Replace)
+            .unwrap()
+            .run_now();
+        write_env_file_guarded(&created, &lines, EnvMerge::Replace)
+            .unwrap()
+            .run_now();
+
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "KEEP=1\n");
+        assert!(!created.exists());
+    }
+
+    #[test]
+    fn test_gc_report_string() {
+        let entry = |path: &str| generated::Entry {
+            path: PathBuf::from(path),
+            sha256: String::new(),
+            pid: 1,
+            created: true,
+        };
+        let results = vec![
+            (entry("/a/.env"), generated::Outcome::Removed),
+            (entry("/b/.env"), generated::Outcome::Missing),
+            (entry("/c/.env"), generated::Outcome::Modified),
+        ];
+        assert_eq!(
+            gc_report_string(&results, false),
+            "Removed: /a/.env\nKept (modified since opz wrote it): /c/.env\n"
+        );
+        assert!(gc_report_string(&results, true).starts_with("Would remove: /a/.env\n"));
+    }
+
+    #[test]
+    fn test_nix_attrset_string() {
+        let lines = vec![
+            "API_KEY=op://vault/item/API_KEY".to_string(),
+            "1BAD=op://vault/item/1BAD".to_string(),
+            "WEIRD=a\"b${c}\\".to_string(),
+        ];
+        assert_eq!(
+            nix_attrset_string(&lines),
+            "{\n  API_KEY = \"op://vault/item/API_KEY\";\n  \"1BAD\" = \"op://vault/item/1BAD\";\n  WEIRD = \"a\\\"b\\${c}\\\\\";\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_json_object_string() {
+        let lines = vec![
+            "API_KEY=op://vault/item/API_KEY".to_string(),
+            "# --- item: db ---".to_string(),
+            "DB_URL=a\"b".to_string(),
+        ];
+        assert_eq!(
+            json_object_string(&lines),
+            "{\n  \"API_KEY\": \"op://vault/item/API_KEY\",\n  \"DB_URL\": \"a\\\"b\"\n}\n"
+        );
+        assert_eq!(json_object_string(&[]), "{}\n");
+    }
+
+    #[test]
+    fn test_cli_parse_repeated_env_files_with_formats() {
+        let cli = Cli::try_parse_from([
+            "opz",
+            "--env-file",
+            ".env",
+            "--env-file",
+            "secrets.json:json",
+            "--env-file",
+            "C:odd",
+            "foo",
+            "--",
+            "env",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.env_file,
+            [
+                EnvFileArg::dotenv(PathBuf::from(".env")),
+                EnvFileArg {
+                    path: PathBuf::from("secrets.json"),
+                    format: Some(Format::Json),
+                },
+                EnvFileArg::dotenv(PathBuf::from("C:odd")),
+            ]
+        );
+        assert!(Cli::try_parse_from(["opz", "gen", "--env-file", ":nix", "foo"]).is_err());
+    }
+
+    #[test]
+    fn test_command_with_env_isolate() {
+        let secrets = HashMap::from([("API_KEY".to_string(), "secret".to_string())]);
+        let env_of = |inherit| {
+            let out = command_with_env(&["env".to_string()], &secrets, inherit)
+                .output()
+                .unwrap();
+            String::from_utf8(out.stdout).unwrap()
+        };
+        // Set by cargo for every test binary.
+        assert!(env_of(None).contains("CARGO_MANIFEST_DIR="));
+
+        let isolated = env_of(Some(ISOLATED_ENV.to_vec()));
+        assert!(isolated.contains("API_KEY=secret\n"));
+        assert!(isolated.contains("PATH="));
+        assert!(!isolated.contains("CARGO_MANIFEST_DIR="));
+
+        let passed = env_of(Some(vec!["PATH", "CARGO_MANIFEST_DIR"]));
+        assert!(passed.contains("CARGO_MANIFEST_DIR="));
+    }
+
+    #[test]
+    fn test_cli_parse_extra_env() {
+        let cli = Cli::try_parse_from([
+            "opz",
+            "run",
+            "--env",
+            "RUST_LOG=debug",
+            "--env",
+            "EMPTY=",
+            "--env",
+            "URL=a=b",
+            "foo",
+            "--",
+            "x",
+        ])
+        .unwrap();
+        match cli.cmd {
+            Some(Cmd::Run { run_opts, .. }) => assert_eq!(
+                run_opts.extra_env,
+                [
+                    ("RUST_LOG".to_string(), "debug".to_string()),
+                    ("EMPTY".to_string(), String::new()),
+                    ("URL".to_string(), "a=b".to_string()),
+                ]
+            ),
+            _ => panic!("expected run command"),
+        }
+        for bad in ["NOVALUE", "1X=y", "=y", "A-B=c"] {
+            assert!(
+                Cli::try_parse_from(["opz", "--env", bad, "foo", "--", "x"]).is_err(),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_cli_parse_isolate_and_pass() {
+        let cli = Cli::try_parse_from([
+            "opz",
+            "--isolate",
+            "--pass",
+            "LANG",
+            "--pass",
+            "SSH_AUTH_SOCK",
+            "foo",
+            "--",
+            "x",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.run_opts.inherited_env(),
+            Some(vec!["PATH", "HOME", "TERM", "LANG", "SSH_AUTH_SOCK"])
+        );
+        assert_eq!(
+            Cli::try_parse_from(["opz", "foo", "--", "x"])
+                .unwrap()
+                .run_opts
+                .inherited_env(),
+            None
+        );
+        assert!(Cli::try_parse_from(["opz", "--pass", "LANG", "foo", "--", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_parse_gen_stdout() {
+        let cli = Cli::try_parse_from(["opz", "gen", "--stdout", "foo"]).unwrap();
+        assert!(matches!(cli.cmd, Some(Cmd::Gen { stdout: true, .. })));
+        for conflict in [
+            &["--env-file", ".env"][..],
+            &["--fd", "3"],
+            &["--format", "nix"],
+        ] {
+            let mut args = vec!["opz", "gen", "--stdout"];
+            args.extend_from_slice(conflict);
+            args.push("foo");
+            assert!(Cli::try_parse_from(args).is_err(), "{conflict:?}");
+        }
+    }
+
+    #[test]
+    fn test_cli_parse_gen_format() {
+        let cli = Cli::try_parse_from(["opz", "gen", "--format", "nix", "foo"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Gen { format, .. }) => assert_eq!(format, Format::Nix),
+            _ => panic!("Expected Gen command"),
+        }
+
+        let cli = Cli::try_parse_from(["opz", "gen", "foo"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Gen { format, .. }) => assert_eq!(format, Format::Dotenv),
+            _ => panic!("Expected Gen command"),
+        }
+    }
+
+    #[test]
+    fn test_cache_use_serves_stale_unless_fresh_requested() {
+        let lenient = CachePolicy {
+            serve_stale: true,
+            offline: false,
+            refresh: false,
+        };
+        let strict = CachePolicy {
+            serve_stale: false,
+            ..lenient
+        };
+        let offline = CachePolicy {
+            offline: true,
+            ..lenient
+        };
+        let refresh = CachePolicy {
+            refresh: true,
+            ..lenient
+        };
+        let expired = item_list_cache_ttl() + Duration::from_secs(1);
+
+        assert_eq!(cache_use(Duration::from_secs(1), lenient), CacheUse::Fresh);
+        assert_eq!(cache_use(Duration::from_secs(1), strict), CacheUse::Fresh);
+        assert_eq!(cache_use(expired, lenient), CacheUse::Stale);
+        assert_eq!(cache_use(expired, strict), CacheUse::Refetch);
+        assert_eq!(
+            cache_use(ITEM_LIST_CACHE_MAX_STALE, lenient),
+            CacheUse::Refetch
+        );
+        assert_eq!(
+            cache_use(ITEM_LIST_CACHE_MAX_STALE, offline),
+            CacheUse::Fresh
+        );
+        assert_eq!(
+            cache_use(Duration::from_secs(1), refresh),
+            CacheUse::Refetch
+        );
+    }
+
+    #[test]
+    fn test_cli_parse_fresh_is_global() {
+        let cli = Cli::try_parse_from(["opz", "find", "db", "--fresh"]).unwrap();
+        assert!(cli.fresh);
+        assert!(!CachePolicy::from_cli(&cli).serve_stale);
+
+        let cli = Cli::try_parse_from(["opz", "find", "db"]).unwrap();
+        assert!(CachePolicy::from_cli(&cli).serve_stale);
+    }
+
+    #[test]
+    fn test_cli_parse_offline_conflicts_with_fresh() {
+        let cli = Cli::try_parse_from(["opz", "gen", "foo", "--offline"]).unwrap();
+        assert!(CachePolicy::from_cli(&cli).offline);
+        assert!(Cli::try_parse_from(["opz", "gen", "foo", "--offline", "--fresh"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_parse_refresh_and_no_cache_alias() {
+        let cli = Cli::try_parse_from(["opz", "gen", "foo", "--refresh"]).unwrap();
+        assert!(CachePolicy::from_cli(&cli).refresh);
+        let cli = Cli::try_parse_from(["opz", "run", "foo", "--no-cache", "--", "true"]).unwrap();
+        assert!(CachePolicy::from_cli(&cli).refresh);
+        assert!(Cli::try_parse_from(["opz", "gen", "foo", "--refresh", "--offline"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_parse_daemon_http() {
+        let cli = Cli::try_parse_from(["opz", "daemon", "--http", "127.0.0.1:7878"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Daemon { http }) => {
+                assert_eq!(http, Some("127.0.0.1:7878".parse().unwrap()))
+            }
+            _ => panic!("Expected daemon"),
+        }
+        assert!(Cli::try_parse_from(["opz", "daemon", "--http", "localhost"]).is_err());
+    }
+
+    #[test]
+    fn test_schema_check_reports_missing_empty_and_extra_keys() {
+        let keys =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|n| n.to_string()).collect() };
+        let references: Vec<(String, String)> = ["API_KEY", "DB_URL", "DEBUG_TOKEN"]
+            .iter()
+            .map(|key| (key.to_string(), format!("op://v/i/{key}")))
+            .collect();
+        let values = HashMap::from([
+            ("API_KEY".to_string(), "abc".to_string()),
+            ("DB_URL".to_string(), String::new()),
+        ]);
+        let check = schema_check(
+            &keys(&["API_KEY", "DB_URL", "REDIS_URL"]),
+            &references,
+            &values,
+        );
+        assert_eq!(
+            check,
+            SchemaCheck {
+                missing: keys(&["REDIS_URL"]),
+                empty: keys(&["DB_URL"]),
+                extra: keys(&["DEBUG_TOKEN"]),
+            }
+        );
+        assert!(!check.passed(false));
+        let out = schema_check_output_string(&check, "app", ".env.example");
+        assert!(out.contains("- REDIS_URL  (missing from app)"));
+        assert!(out.contains("! DB_URL  (empty in app)"));
+        assert!(out.contains("+ DEBUG_TOKEN  (not in .env.example)"));
+
+        let check = schema_check(&keys(&["API_KEY"]), &references, &values);
+        assert!(check.passed(false));
+        assert!(!check.passed(true));
+    }
+
+    #[test]
+    fn test_verify_env_compares_digests() {
+        let keys: Vec<String> = ["API_KEY", "DB_URL", "TOKEN", "CERT"]
+            .iter()
+            .map(|key| key.to_string())
+            .collect();
+        // CERT has no value: a `file-only` field, not expected in the environment.
+        let values = HashMap::from([
+            ("API_KEY".to_string(), "abc".to_string()),
+            ("DB_URL".to_string(), "postgres://new".to_string()),
+            ("TOKEN".to_string(), "t".to_string()),
+        ]);
+        let env = HashMap::from([
+            ("API_KEY", OsString::from("abc")),
+            ("DB_URL", OsString::from("postgres://old")),
+        ]);
+        let verification = verify_env(&keys, &values, |key| env.get(key).cloned());
+        assert_eq!(
+            verification,
+            EnvVerification {
+                missing: vec!["TOKEN".to_string()],
+                different: vec!["DB_URL".to_string()],
+                matching: 1,
+            }
+        );
+        let out = env_verification_output_string(&verification, "app");
+        assert_eq!(out, "- TOKEN  (not set)\n~ DB_URL  (differs from app)\n");
+        assert!(!out.contains("postgres"));
+    }
+
+    #[test]
+    fn test_cli_parse_diff() {
+        let cli = Cli::try_parse_from(["opz", "diff", "app"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Diff {
+                show_values,
+                item,
+                env_file,
+            }) => {
+                assert!(!show_values);
+                assert_eq!(item, "app");
+                assert_eq!(env_file, PathBuf::from(".env"));
+            }
+            _ => panic!("Expected diff"),
+        }
+        let cli = Cli::try_parse_from(["opz", "diff", "--show-values", "app", "dev.env"]).unwrap();
+        assert!(matches!(
+            cli.cmd,
+            Some(Cmd::Diff { show_values: true, env_file, .. }) if env_file == Path::new("dev.env")
+        ));
+    }
+
+    #[test]
+    fn test_cli_parse_last_and_recent() {
+        let cli = Cli::try_parse_from(["opz", "--last", "--", "npm", "start"]).unwrap();
+        assert!(cli.last);
+        assert!(cli.items.is_empty());
+        assert_eq!(cli.command, vec!["npm", "start"]);
+        assert!(Cli::try_parse_from(["opz", "--last", "foo", "--", "npm"]).is_err());
+        let cli = Cli::try_parse_from(["opz", "recent"]).unwrap();
+        assert!(matches!(cli.cmd, Some(Cmd::Recent)));
+    }
+
+    #[test]
+    fn test_format_recent_row() {
+        let entry = recent::Entry {
+            title: "app prod".to_string(),
+            id: "abc".to_string(),
+            used_at_unix: 1_000,
+        };
+        assert_eq!(
+            format_recent_row(&entry, 1_000 + 120),
+            "abc\tapp prod\t2m ago"
+        );
+    }
+
+    #[test]
+    fn test_cli_parse_resolve() {
+        let cli =
+            Cli::try_parse_from(["opz", "resolve", "--in", ".env.refs", "--out", ".env"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Resolve { input, out, backup }) => {
+                assert_eq!(input, PathBuf::from(".env.refs"));
+                assert_eq!(out, Some(PathBuf::from(".env")));
+                assert!(!backup);
+            }
+            _ => panic!("Expected resolve"),
+        }
+        assert!(Cli::try_parse_from(["opz", "resolve"]).is_err());
+        // There is nothing to back up when printing to stdout.
+        assert!(Cli::try_parse_from(["opz", "resolve", "--in", "x", "--backup"]).is_err());
+    }
+
+    #[test]
+    fn test_dotenv_value_quotes_only_when_needed() {
+        assert_eq!(dotenv_value("s3cr3t-Value_1"), "s3cr3t-Value_1");
+        assert_eq!(
+            dotenv_value("postgres://u:p@db:5432/app?x=1"),
+            "'postgres://u:p@db:5432/app?x=1'"
+        );
+        assert_eq!(dotenv_value("a b #c $HOME"), "'a b #c $HOME'");
+        assert_eq!(
+            dotenv_value("it's \"x\"\nline\\2"),
+            "\"it's \\\"x\\\"\\nline\\\\2\""
+        );
+        assert_eq!(dotenv_value(""), "");
+        // Double quotes expand `$` in shells and most dotenv loaders.
+        assert_eq!(dotenv_value("it's $HOME"), "\"it's \\$HOME\"");
+    }
+
+    #[test]
+    fn test_resolved_env_text_keeps_other_lines_verbatim() {
+        let content = "# shared settings\n\
+                       export API_TOKEN=\"op://v/api/token\" # rotated monthly\n\
+                       \n\
+                       LOG_LEVEL = debug  # local\n\
+                       DB_PASSWORD='op://v/db/password'\n\
+                       DB_PASSWORD=op://v/db/other\n";
+        let values: HashMap<String, String> = [
+            ("op://v/api/token", "s3cret"),
+            ("op://v/db/password", "p w"),
+            ("op://v/db/other", "x"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(
+            resolved_env_text(content, &values),
+            "# shared settings\n\
+             export API_TOKEN=s3cret\n\
+             \n\
+             LOG_LEVEL = debug  # local\n\
+             DB_PASSWORD='p w'\n\
+             DB_PASSWORD=x\n"
+        );
+        assert_eq!(
+            env_reference_line("export API_TOKEN=op://v/api/token"),
+            Some(("export API_TOKEN", "op://v/api/token"))
+        );
+        assert_eq!(env_reference_line("# A=op://v/i/f"), None);
+    }
+
+    #[test]
+    fn test_cli_parse_backup_and_restore() {
+        let cli = Cli::try_parse_from(["opz", "--env-file", ".env", "--backup", "foo", "--", "x"])
+            .unwrap();
+        assert!(cli.run_opts.backup);
+        assert!(Cli::try_parse_from(["opz", "--temp-env", "--backup", "foo", "--", "x"]).is_err());
+        let cli = Cli::try_parse_from(["opz", "restore"]).unwrap();
+        assert!(
+            matches!(cli.cmd, Some(Cmd::Restore { env_file }) if env_file == Path::new(".env"))
+        );
+    }
+
+    #[test]
+    fn test_cli_parse_lock_and_frozen() {
+        let cli = Cli::try_parse_from(["opz", "lock", "foo", "bar"]).unwrap();
+        assert!(matches!(cli.cmd, Some(Cmd::Lock { items }) if items == ["foo", "bar"]));
+        let cli = Cli::try_parse_from(["opz", "--frozen", "foo", "--", "x"]).unwrap();
+        assert!(cli.frozen);
+        assert!(!CachePolicy::from_cli(&cli).serve_stale);
+    }
+
+    #[test]
+    fn test_cli_parse_agent() {
+        let cli = Cli::try_parse_from(["opz", "agent", "--socket", "/tmp/opz.sock"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Agent { socket }) => {
+                assert_eq!(socket, Some(PathBuf::from("/tmp/opz.sock")))
+            }
+            _ => panic!("Expected agent"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parse_watch() {
+        let cli = Cli::try_parse_from([
+            "opz",
+            "watch",
+            "--interval",
+            "5",
+            "foo",
+            "--",
+            "server",
+            "--port",
+            "80",
+        ])
+        .unwrap();
+        match cli.cmd {
+            Some(Cmd::Watch {
+                interval,
+                items,
+                command,
+                ..
+            }) => {
+                assert_eq!(interval, 5);
+                assert_eq!(items, vec!["foo"]);
+                assert_eq!(command, vec!["server", "--port", "80"]);
+            }
+            _ => panic!("Expected watch"),
+        }
+        let args: Vec<OsString> = ["opz", "watch", "foo"].iter().map(OsString::from).collect();
+        assert_eq!(detect_command_hint(&args), "watch");
+        assert!(Cli::try_parse_from(["opz", "watch", "--interval", "0", "foo"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_parse_workspace_run() {
+        let cli = Cli::try_parse_from(["opz", "workspace", "run", "--", "npm", "test"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Workspace {
+                action: WorkspaceCmd::Run { command, .. },
+            }) => assert_eq!(command, vec!["npm", "test"]),
+            _ => panic!("Expected workspace run"),
+        }
+
+        assert!(Cli::try_parse_from(["opz", "workspace", "run"]).is_err());
+    }
+
+    #[test]
+    fn test_workspace_summary_string() {
+        let results = vec![
+            ("services/api".to_string(), true),
+            ("services/web".to_string(), false),
+        ];
+        assert_eq!(
+            workspace_summary_string(&results),
+            "MEMBER\tSTATUS\nservices/api\tok\nservices/web\tfailed\n"
+        );
+    }
+
+    #[test]
+    fn test_bench_cache_report_uses_sorted_samples() {
+        let mut cold = vec![
+            Duration::from_millis(30),
+            Duration::from_millis(10),
+            Duration::from_millis(20),
+        ];
+        let mut warm = vec![Duration::from_micros(500)];
+
+        assert_eq!(
+            bench_cache_report(&mut cold, &mut warm),
+            "PATH\tMIN\tMEDIAN\tMAX\n\
+             cold\t10.00ms\t20.00ms\t30.00ms\n\
+             warm\t0.50ms\t0.50ms\t0.50ms\n"
+        );
+    }
+
+    #[test]
+    fn test_cli_parse_bench_cache_is_hidden() {
+        let cli = Cli::try_parse_from(["opz", "--bench-cache"]).unwrap();
+        assert!(cli.bench_cache);
+        assert!(!Cli::command()
+            .render_long_help()
+            .to_string()
+            .contains("bench-cache"));
+    }
+
+    #[test]
+    fn test_panic_report_is_sanitized_and_includes_trace_id() {
+        let report = panic_report(
+            "called `unwrap` on token=abc123",
+            Some("src/main.rs:10"),
+            Some("0af7651916cd43dd8448eb211c80319c"),
+        );
+        assert!(report.contains("token=***"));
+        assert!(!report.contains("abc123"));
+        assert!(report.contains("(src/main.rs:10)"));
+        assert!(report.contains("trace_id=0af7651916cd43dd8448eb211c80319c"));
+
+        let report = panic_report("boom", None, None);
+        assert!(!report.contains("trace_id="));
+    }
+
+    #[test]
+    fn test_broken_pipe_detection() {
+        let err = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+            .context("write output");
+        assert!(is_broken_pipe(&err));
+        assert!(!is_broken_pipe(&anyhow!("op error")));
+
+        assert!(is_broken_pipe_panic(
+            "failed printing to stdout: Broken pipe (os error 32)"
+        ));
+        assert!(!is_broken_pipe_panic("called `unwrap` on an `Err` value"));
+    }
+
+    #[test]
+    fn test_cli_parse_legacy_env_positional_treated_as_item() {
+        let cli = Cli::try_parse_from(["opz", "run", "foo", ".env", "--", "env"]).unwrap();
+        match cli.cmd {
+            Some(Cmd::Run {
+                items, env_file, ..
+            }) => {
+                assert_eq!(items, vec!["foo".to_string(), ".env".to_string()]);
+                assert!(env_file.is_empty());
+            }
+            _ => panic!("expected run command"),
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! The library API: [`Client`] looks items up the way `opz find` and `opz gen` do (same
+//! caches, backends and title matching), and [`EnvRenderer`] turns them into env file text.
+//! [`EnvBuilder`] does both in one call. It collects and renders through the same
+//! `collect_env` and `render_env` as `opz gen`, so both export and skip the same fields.
+
+use crate::{
+    cli::{collect_env, find_items, item_list_cached, render_env, CachePolicy, ItemGet},
+    config::{self, Config},
+};
+use anyhow::Result;
+use std::{fmt, path::Path};
+
+/// Looks up 1Password items through the configured backends (the `op` CLI by default),
+/// sharing opz's on-disk caches with the `opz` command.
+///
+/// ```no_run
+/// let client = opz::Client::new().vault("Team");
+/// let items = client.items(&["common", "api"])?;
+/// let env = opz::EnvRenderer::default().render(&items)?;
+/// print!("{env}");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    account: Option<String>,
+    vault: Option<String>,
+    offline: bool,
+    refresh: bool,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Account to pass to `op` (`--account`); the CLI's default account otherwise.
+    pub fn account(mut self, account: impl Into<String>) -> Self {
+        self.account = Some(account.into());
+        self
+    }
+
+    /// Only search this vault (name or id); all readable vaults otherwise.
+    pub fn vault(mut self, vault: impl Into<String>) -> Self {
+        self.vault = Some(vault.into());
+        self
+    }
+
+    /// Answer only from caches, whatever their age, and fail instead of calling `op`.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Skip reading caches (what is fetched is still cached).
+    pub fn refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    fn policy(&self) -> CachePolicy {
+        CachePolicy {
+            serve_stale: true,
+            offline: self.offline,
+            refresh: self.refresh,
+        }
+    }
+
+    /// Items whose title contains `query`, ignoring case, like `opz find`.
+    pub fn find(&self, query: &str) -> Result<Vec<ItemRef>> {
+        let query = query.to_lowercase();
+        let items = item_list_cached(
+            self.account.as_deref(),
+            self.vault.as_deref(),
+            self.policy(),
+        )?;
+        Ok(items
+            .into_iter()
+            .filter(|item| item.title.to_lowercase().contains(&query))
+            .map(|item| ItemRef {
+                id: item.id,
+                title: item.title,
+                vault: item.vault.map(|vault| vault.name),
+            })
+            .collect())
+    }
+
+    /// The item titled `title` (exactly, or else the single title containing it).
+    pub fn item(&self, title: &str) -> Result<Item> {
+        let mut items = self.items(&[title])?;
+        Ok(items.remove(0))
+    }
+
+    /// [`Client::item`] for each title, looked up in parallel; results follow `titles`.
+    pub fn items<S: AsRef<str>>(&self, titles: &[S]) -> Result<Vec<Item>> {
+        let titles: Vec<String> = titles.iter().map(|t| t.as_ref().to_string()).collect();
+        let found = find_items(
+            self.account.as_deref(),
+            self.vault.as_deref(),
+            self.policy(),
+            &titles,
+        )?;
+        Ok(found
+            .into_iter()
+            .map(|(id, vault_id, title, item)| Item {
+                id,
+                vault_id,
+                title,
+                item,
+            })
+            .collect())
+    }
+}
+
+/// An item as listed by [`Client::find`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemRef {
+    pub id: String,
+    pub title: String,
+    /// Vault name.
+    pub vault: Option<String>,
+}
+
+/// An item's details, as `op item get` reports them.
+#[derive(Debug, Clone)]
+pub struct Item {
+    id: String,
+    vault_id: String,
+    title: String,
+    item: ItemGet,
+}
+
+impl Item {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn vault_id(&self) -> &str {
+        &self.vault_id
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Field labels, in item order.
+    pub fn labels(&self) -> Vec<&str> {
+        self.item
+            .fields
+            .iter()
+            .filter_map(|field| field.label.as_deref())
+            .collect()
+    }
+
+    /// The `op://` reference of the field labelled `label`, if the item has one.
+    pub fn reference(&self, label: &str) -> Option<String> {
+        self.labels()
+            .contains(&label)
+            .then(|| format!("op://{}/{}/{label}", self.vault_id, self.id))
+    }
+}
+
+/// Renders items as env file lines (`KEY=op://...` references, never values), applying an
+/// `.opz.toml` config's labels, field filters and `[output]` layout like `opz gen`.
+///
+/// Unlike the CLI it never prompts, so `prod-confirm` policies are left to the caller.
+#[derive(Debug, Clone, Default)]
+pub struct EnvRenderer {
+    config: Config,
+}
+
+impl EnvRenderer {
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Use the config `opz` would use in `dir`: the nearest `.opz.toml` over the global one.
+    pub fn for_dir(dir: &Path) -> Result<Self> {
+        Ok(Self::new(config::load(dir)?))
+    }
+
+    /// Env file text for `items`, as `opz gen` prints it; later items win on duplicate keys.
+    pub fn render(&self, items: &[Item]) -> Result<String> {
+        Ok(self.render_as(items, Format::Dotenv)?.text)
+    }
+
+    /// `items` rendered in `format`, with the fields that were left out.
+    pub fn render_as(&self, items: &[Item], format: Format) -> Result<Rendered> {
+        let found = items
+            .iter()
+            .map(|item| {
+                (
+                    item.id.clone(),
+                    item.vault_id.clone(),
+                    item.title.clone(),
+                    item.item.clone(),
+                )
+            })
+            .collect();
+        let collected = collect_env(&self.config, found)?;
+        Ok(render_env(&collected, &self.config.output, format))
+    }
+}
+
+/// Output format of [`EnvRenderer::render_as`] and `opz gen --format`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `KEY=op://...` lines
+    Dotenv,
+    /// Nix attrset of references, for flake/devenv shells
+    Nix,
+    /// JSON object of references, for tools that read their settings from JSON
+    Json,
+}
+
+/// Rendered env output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendered {
+    /// `KEY=op://...` lines in output order, one per key; what an env file is merged with.
+    pub lines: Vec<String>,
+    /// Labelled fields that produced no line.
+    pub skipped: Vec<SkippedField>,
+    /// The output in the requested [`Format`].
+    pub text: String,
+}
+
+impl fmt::Display for Rendered {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// A field left out of the env output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedField {
+    /// Title of the item holding the field.
+    pub item: String,
+    pub label: String,
+    /// The config rule that filtered it, or why it cannot be exported.
+    pub reason: String,
+}
+
+/// Looks items up and renders them in one go, with `[fields]` and `[labels]` settings
+/// layered over a base config.
+///
+/// ```no_run
+/// use opz::{EnvBuilder, Format};
+///
+/// let rendered = EnvBuilder::new()
+///     .item("api")
+///     .vault("Team")
+///     .prefix("APP_")
+///     .include("DB_*")
+///     .render(Format::Dotenv)?;
+/// print!("{rendered}");
+/// for skipped in &rendered.skipped {
+///     eprintln!("{}: {} ({})", skipped.item, skipped.label, skipped.reason);
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EnvBuilder {
+    client: Client,
+    config: Config,
+    items: Vec<String>,
+}
+
+impl EnvBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look items up with `client` (its account, vault and cache settings).
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Start from `config` instead of the defaults.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Start from the config `opz` would use in `dir`; see [`EnvRenderer::for_dir`].
+    pub fn for_dir(self, dir: &Path) -> Result<Self> {
+        Ok(self.config(config::load(dir)?))
+    }
+
+    /// Add an item by title; later items win on duplicate keys.
+    pub fn item(mut self, title: impl Into<String>) -> Self {
+        self.items.push(title.into());
+        self
+    }
+
+    /// See [`Client::account`].
+    pub fn account(mut self, account: impl Into<String>) -> Self {
+        self.client = self.client.account(account);
+        self
+    }
+
+    /// See [`Client::vault`].
+    pub fn vault(mut self, vault: impl Into<String>) -> Self {
+        self.client = self.client.vault(vault);
+        self
+    }
+
+    /// Prepend `prefix` to every key, like `[labels] prefix`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.config.labels.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Only export labels matching `pattern` (`*` wildcards), like `[fields] include`.
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.config.fields.include.push(pattern.into());
+        self
+    }
+
+    /// Leave out labels matching `pattern`, like `[fields] exclude`.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.config.fields.exclude.push(pattern.into());
+        self
+    }
+
+    /// Look the items up and render them in `format`.
+    pub fn render(self, format: Format) -> Result<Rendered> {
+        let items = self.client.items(&self.items)?;
+        EnvRenderer::new(self.config).render_as(&items, format)
+    }
+}
@@ -0,0 +1,1093 @@
+use anyhow::{anyhow, Context, Result};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const PROJECT_CONFIG_FILE: &str = ".opz.toml";
+
+/// Project files that may travel in a bundle. Nothing here may hold secret values.
+const BUNDLE_FILES: &[&str] = &[PROJECT_CONFIG_FILE];
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+const DEFAULT_PRODUCTION_TAGS: &[&str] = &["production", "prod"];
+
+/// Merged view of the global (`~/.config/opz/config.toml`) and project (`.opz.toml`) config.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Config {
+    /// Item used when a command is given no item titles (`opz -- npm start`).
+    pub item: Option<String>,
+
+    /// Vault to look `item` up in; takes precedence over `defaults.vault`.
+    pub vault: Option<String>,
+
+    /// Reviewed version of `item`; newer versions are reported as drift.
+    pub item_version: Option<u64>,
+
+    #[serde(default)]
+    pub guard: GuardConfig,
+
+    #[serde(default)]
+    pub workspace: WorkspaceConfig,
+
+    #[serde(default)]
+    pub defaults: Defaults,
+
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+
+    #[serde(default)]
+    pub fields: FieldFilter,
+
+    #[serde(default)]
+    pub labels: LabelTransform,
+
+    #[serde(default)]
+    pub output: OutputConfig,
+
+    #[serde(default)]
+    pub backend: BackendConfig,
+
+    /// Handling rules keyed by field label pattern (`*` wildcard). Unlike `[fields]`, profiles
+    /// cannot replace these, and global and project rules add up.
+    #[serde(default)]
+    pub policies: BTreeMap<String, Vec<FieldPolicy>>,
+
+    /// Named environments selected with `--profile` / `OPZ_PROFILE`.
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
+
+    /// Items of the active profile, which replace `item`.
+    #[serde(skip)]
+    pub profile_items: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Profile {
+    #[serde(default)]
+    pub items: Vec<String>,
+
+    pub vault: Option<String>,
+
+    /// Replaces the top-level `[fields]` filter while the profile is active.
+    pub fields: Option<FieldFilter>,
+}
+
+/// Fallbacks for flags that were not given on the command line.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Defaults {
+    pub vault: Option<String>,
+
+    /// Env file for `run`, `gen`, and the shorthand form (relative to the working directory).
+    pub env_file: Option<PathBuf>,
+
+    /// How long an item list cache counts as fresh.
+    pub cache_ttl_secs: Option<u64>,
+
+    /// How long cleanup may take after SIGTERM before opz exits regardless.
+    pub term_grace_secs: Option<u64>,
+}
+
+/// Used when the corresponding `OTEL_*` environment variable is unset.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct TelemetryConfig {
+    /// OTLP gRPC endpoint (`OTEL_EXPORTER_OTLP_ENDPOINT`).
+    pub endpoint: Option<String>,
+
+    /// Service name reported with every span (`OTEL_SERVICE_NAME`).
+    pub service_name: Option<String>,
+
+    /// `grpc`, `http/protobuf` or `http/json` (`OTEL_EXPORTER_OTLP_PROTOCOL`).
+    pub protocol: Option<String>,
+
+    /// Extra regexes masked in exported text, on top of `op://` references and the built-in
+    /// secret keys (`OPZ_TRACE_REDACT_PATTERNS`).
+    #[serde(default)]
+    pub redact_patterns: Vec<String>,
+
+    /// Longest exported text in bytes before it is truncated; 0 for no limit
+    /// (`OPZ_TRACE_TEXT_LIMIT`).
+    pub text_limit: Option<usize>,
+
+    /// Share of traces kept for a command, keyed by its span name (`find`, `complete_items`,
+    /// ...), over the `OTEL_TRACES_SAMPLER` default (`OPZ_TRACE_SAMPLE_<COMMAND>`).
+    #[serde(default)]
+    pub sample: BTreeMap<String, f64>,
+}
+
+/// Which field labels become env vars. Patterns match the whole label; `*` matches any run
+/// of characters.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct FieldFilter {
+    /// If non-empty, only labels matching one of these patterns are exported.
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    /// Labels matching any of these patterns are never exported.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// How field labels are turned into env var names.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct LabelTransform {
+    /// Exact label to env var name. Renamed labels skip `uppercase` and `prefix`.
+    #[serde(default)]
+    pub rename: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub uppercase: bool,
+
+    #[serde(default)]
+    pub prefix: Option<String>,
+
+    /// Give the built-in fields of Database and Login items conventional env names
+    /// (`DB_HOST`, `USERNAME`, ...) instead of their labels; off unless set to true, since it
+    /// renames keys existing setups export.
+    #[serde(default)]
+    pub smart: Option<bool>,
+}
+
+/// Conventional env names for the built-in fields of 1Password item categories, by category
+/// and field label (matched case-insensitively).
+const CATEGORY_KEYS: &[(&str, &[(&str, &str)])] = &[
+    (
+        "DATABASE",
+        &[
+            ("server", "DB_HOST"),
+            ("port", "DB_PORT"),
+            ("username", "DB_USER"),
+            ("password", "DB_PASSWORD"),
+            ("database", "DB_NAME"),
+        ],
+    ),
+    (
+        "LOGIN",
+        &[("username", "USERNAME"), ("password", "PASSWORD")],
+    ),
+];
+
+/// Conventional env name of the built-in field `label` of a `category` item, if it has one.
+pub fn category_key(category: &str, label: &str) -> Option<&'static str> {
+    CATEGORY_KEYS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(category))?
+        .1
+        .iter()
+        .find(|(field, _)| field.eq_ignore_ascii_case(label))
+        .map(|(_, key)| *key)
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct GuardConfig {
+    /// Commands (matched by program name) that must never be wrapped with production items.
+    #[serde(default)]
+    pub deny_commands: Vec<String>,
+
+    /// Item tags that mark an item as production (default: `production`, `prod`).
+    #[serde(default)]
+    pub production_tags: Option<Vec<String>>,
+}
+
+/// Sub-projects of a monorepo, each run with its own items by `opz workspace run`.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct WorkspaceConfig {
+    #[serde(default)]
+    pub members: Vec<WorkspaceMember>,
+
+    /// Directory of the config file that declared the members.
+    #[serde(skip)]
+    pub root: PathBuf,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WorkspaceMember {
+    /// Directory relative to the declaring config file.
+    pub path: PathBuf,
+
+    /// Item titles injected when running in this member.
+    pub items: Vec<String>,
+}
+
+/// Layout of generated env files, to keep large ones reviewable.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct OutputConfig {
+    /// Env vars listed here come first, in this order; the rest follow `order`.
+    #[serde(default)]
+    pub keys: Vec<String>,
+
+    /// Ordering of the remaining env vars (default: item order).
+    pub order: Option<OutputOrder>,
+
+    /// What goes between groups (default: nothing).
+    pub separators: Option<Separators>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputOrder {
+    /// Grouped by item, in the order items were given.
+    #[default]
+    Item,
+    /// Grouped by the 1Password section of each field.
+    Section,
+    /// Sorted by name, grouped by the part before the first `_`.
+    Alphabetical,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Separators {
+    #[default]
+    None,
+    Blank,
+    /// A blank line and a `# --- <group> ---` header.
+    Comment,
+}
+
+/// Where item lists and item details are looked up, in order of preference.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct BackendConfig {
+    /// Backends to try in order (default: `["cli"]`).
+    pub chain: Option<Vec<Backend>>,
+
+    /// Error classes that move on to the next backend (default: `["unavailable"]`).
+    pub fallback_on: Option<Vec<ErrorClass>>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// A 1Password Connect server (`OP_CONNECT_HOST` / `OP_CONNECT_TOKEN`).
+    Connect,
+    /// The `op` CLI.
+    Cli,
+    /// opz's own caches, whatever their age.
+    Cache,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorClass {
+    /// The backend could not be reached, timed out, or is not set up.
+    Unavailable,
+    /// The backend rejected the credentials or session.
+    Auth,
+    /// The backend does not know the item or vault.
+    NotFound,
+    Other,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum FieldPolicy {
+    /// Never exported, whatever `[fields]` or a profile says.
+    NeverExport,
+    /// Only written to `--env-file` (as a reference); never put in a command's environment.
+    FileOnly,
+    /// Never substituted into command arguments, even with `--allow-argv-secrets`.
+    ArgvForbidden,
+    /// Using the field from a production-tagged item needs an interactive confirmation.
+    ProdConfirm,
+}
+
+impl FieldPolicy {
+    /// Name as written in `[policies]`.
+    pub fn name(self) -> &'static str {
+        match self {
+            FieldPolicy::NeverExport => "never-export",
+            FieldPolicy::FileOnly => "file-only",
+            FieldPolicy::ArgvForbidden => "argv-forbidden",
+            FieldPolicy::ProdConfirm => "prod-confirm",
+        }
+    }
+}
+
+impl WorkspaceConfig {
+    pub fn member_dir(&self, member: &WorkspaceMember) -> PathBuf {
+        self.root.join(&member.path)
+    }
+}
+
+impl Config {
+    /// Project settings override global ones; deny-lists are combined.
+    fn merge(self, project: Config) -> Config {
+        let mut deny_commands = self.guard.deny_commands;
+        for command in project.guard.deny_commands {
+            if !deny_commands.contains(&command) {
+                deny_commands.push(command);
+            }
+        }
+
+        Config {
+            item: project.item.or(self.item),
+            vault: project.vault.or(self.vault),
+            item_version: project.item_version.or(self.item_version),
+            guard: GuardConfig {
+                deny_commands,
+                production_tags: project.guard.production_tags.or(self.guard.production_tags),
+            },
+            workspace: if project.workspace.members.is_empty() {
+                self.workspace
+            } else {
+                project.workspace
+            },
+            defaults: Defaults {
+                vault: project.defaults.vault.or(self.defaults.vault),
+                env_file: project.defaults.env_file.or(self.defaults.env_file),
+                cache_ttl_secs: project
+                    .defaults
+                    .cache_ttl_secs
+                    .or(self.defaults.cache_ttl_secs),
+                term_grace_secs: project
+                    .defaults
+                    .term_grace_secs
+                    .or(self.defaults.term_grace_secs),
+            },
+            telemetry: TelemetryConfig {
+                endpoint: {
+                    // A checked-in `.opz.toml` must not send item titles and `op` arguments to
+                    // a host of its choosing; only the user's own config picks the collector.
+                    if let Some(endpoint) = &project.telemetry.endpoint {
+                        tracing::warn!(
+                            "ignoring [telemetry] endpoint = \"{endpoint}\" in {PROJECT_CONFIG_FILE}; set it in the global config or OTEL_EXPORTER_OTLP_ENDPOINT"
+                        );
+                    }
+                    self.telemetry.endpoint
+                },
+                service_name: project
+                    .telemetry
+                    .service_name
+                    .or(self.telemetry.service_name),
+                protocol: project.telemetry.protocol.or(self.telemetry.protocol),
+                redact_patterns: [
+                    self.telemetry.redact_patterns,
+                    project.telemetry.redact_patterns,
+                ]
+                .concat(),
+                text_limit: project.telemetry.text_limit.or(self.telemetry.text_limit),
+                sample: self
+                    .telemetry
+                    .sample
+                    .into_iter()
+                    .chain(project.telemetry.sample)
+                    .collect(),
+            },
+            fields: FieldFilter {
+                include: if project.fields.include.is_empty() {
+                    self.fields.include
+                } else {
+                    project.fields.include
+                },
+                exclude: [self.fields.exclude, project.fields.exclude].concat(),
+            },
+            labels: LabelTransform {
+                rename: self
+                    .labels
+                    .rename
+                    .into_iter()
+                    .chain(project.labels.rename)
+                    .collect(),
+                uppercase: project.labels.uppercase || self.labels.uppercase,
+                prefix: project.labels.prefix.or(self.labels.prefix),
+                smart: project.labels.smart.or(self.labels.smart),
+            },
+            output: OutputConfig {
+                keys: if project.output.keys.is_empty() {
+                    self.output.keys
+                } else {
+                    project.output.keys
+                },
+                order: project.output.order.or(self.output.order),
+                separators: project.output.separators.or(self.output.separators),
+            },
+            backend: BackendConfig {
+                chain: project.backend.chain.or(self.backend.chain),
+                fallback_on: project.backend.fallback_on.or(self.backend.fallback_on),
+            },
+            policies: {
+                let mut policies = self.policies;
+                for (pattern, rules) in project.policies {
+                    let merged = policies.entry(pattern).or_default();
+                    for rule in rules {
+                        if !merged.contains(&rule) {
+                            merged.push(rule);
+                        }
+                    }
+                }
+                policies
+            },
+            profile: self.profile.into_iter().chain(project.profile).collect(),
+            profile_items: Vec::new(),
+        }
+    }
+
+    /// Overlay the named profile onto the config.
+    pub fn activate_profile(&mut self, name: &str) -> Result<()> {
+        let Some(profile) = self.profile.get(name).cloned() else {
+            let defined: Vec<&str> = self.profile.keys().map(String::as_str).collect();
+            return Err(anyhow!(
+                "Unknown profile '{name}'. Defined profiles: {}",
+                if defined.is_empty() {
+                    "(none)".to_string()
+                } else {
+                    defined.join(", ")
+                }
+            ));
+        };
+        self.profile_items = profile.items;
+        if profile.vault.is_some() {
+            self.vault = profile.vault;
+        }
+        if let Some(fields) = profile.fields {
+            self.fields = fields;
+        }
+        Ok(())
+    }
+
+    /// Items used when a command is given no item titles.
+    pub fn default_items(&self) -> Vec<String> {
+        if self.profile_items.is_empty() {
+            self.item.iter().cloned().collect()
+        } else {
+            self.profile_items.clone()
+        }
+    }
+
+    /// Env var name for a field label, or `None` when `[fields]` filters the label out or a
+    /// policy forbids exporting it. The result still has to be checked for being a valid env
+    /// var name.
+    pub fn env_key(&self, label: &str) -> Option<String> {
+        let mapping = self.map_label(label);
+        mapping.dropped_by.is_none().then_some(mapping.key)
+    }
+
+    /// The rules `env_key` applies to `label`, spelled out.
+    pub fn map_label(&self, label: &str) -> LabelMapping {
+        self.map_field(None, label)
+    }
+
+    /// Whether built-in fields get their category's conventional env names (`[labels] smart`).
+    pub fn smart_labels(&self) -> bool {
+        self.labels.smart.unwrap_or(false)
+    }
+
+    /// [`Config::map_label`] for a built-in field of a `category` item: with `[labels] smart =
+    /// true`, a Database or Login field starts from its conventional env name instead of the
+    /// label. Filters, policies and renames still match the label.
+    pub fn map_field(&self, category: Option<&str>, label: &str) -> LabelMapping {
+        let never_export = self.policies.iter().find(|(pattern, rules)| {
+            rules.contains(&FieldPolicy::NeverExport) && wildcard_match(pattern, label)
+        });
+        let dropped_by = if let Some((pattern, _)) = never_export {
+            Some(format!("[policies] \"{pattern}\" = never-export"))
+        } else if !self.fields.include.is_empty()
+            && !self.fields.include.iter().any(|p| wildcard_match(p, label))
+        {
+            Some("[fields] include (no pattern matches)".to_string())
+        } else {
+            self.fields
+                .exclude
+                .iter()
+                .find(|p| wildcard_match(p, label))
+                .map(|pattern| format!("[fields] exclude \"{pattern}\""))
+        };
+
+        let mut transforms = Vec::new();
+        let key = if let Some(renamed) = self.labels.rename.get(label) {
+            transforms.push(format!("[labels.rename] \"{label}\" = \"{renamed}\""));
+            renamed.clone()
+        } else {
+            let conventional = category
+                .filter(|_| self.smart_labels())
+                .and_then(|category| Some((category, category_key(category, label)?)));
+            let mut key = match conventional {
+                Some((category, conventional)) => {
+                    transforms.push(format!(
+                        "{category} item field \"{label}\" = \"{conventional}\""
+                    ));
+                    conventional.to_string()
+                }
+                None => label.to_string(),
+            };
+            if self.labels.uppercase {
+                key = key.to_ascii_uppercase();
+                transforms.push("[labels] uppercase".to_string());
+            }
+            if let Some(prefix) = self.labels.prefix.as_deref().filter(|p| !p.is_empty()) {
+                key = format!("{prefix}{key}");
+                transforms.push(format!("[labels] prefix = \"{prefix}\""));
+            }
+            key
+        };
+        LabelMapping {
+            key,
+            transforms,
+            dropped_by,
+        }
+    }
+
+    /// Every policy whose pattern matches `label`.
+    pub fn field_policies(&self, label: &str) -> BTreeSet<FieldPolicy> {
+        self.policies
+            .iter()
+            .filter(|(pattern, _)| wildcard_match(pattern, label))
+            .flat_map(|(_, rules)| rules.iter().copied())
+            .collect()
+    }
+}
+
+/// How a field label turns into an env var, for `opz explain-env`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelMapping {
+    /// Env var name the label maps to, whether or not it is exported.
+    pub key: String,
+    /// `[labels]` rules applied on the way, in order.
+    pub transforms: Vec<String>,
+    /// Rule that keeps the field out of the environment.
+    pub dropped_by: Option<String>,
+}
+
+/// Whole-string match where `*` matches any (possibly empty) run of characters.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+impl GuardConfig {
+    pub fn is_production_tag(&self, tag: &str) -> bool {
+        match &self.production_tags {
+            Some(tags) => tags.iter().any(|t| t.eq_ignore_ascii_case(tag)),
+            None => DEFAULT_PRODUCTION_TAGS
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(tag)),
+        }
+    }
+
+    pub fn denies(&self, program: &str) -> bool {
+        self.deny_commands.iter().any(|denied| denied == program)
+    }
+}
+
+/// Load the global config and the nearest project config above `cwd`.
+pub fn load(cwd: &Path) -> Result<Config> {
+    let global = match global_config_path() {
+        Some(path) if path.is_file() => read_config(&path)?,
+        _ => Config::default(),
+    };
+    let project = match find_project_config(cwd) {
+        Some(path) => read_config(&path)?,
+        None => Config::default(),
+    };
+
+    Ok(global.merge(project))
+}
+
+pub fn global_config_path() -> Option<PathBuf> {
+    ProjectDirs::from("dev", "opz", "opz").map(|proj| proj.config_dir().join("config.toml"))
+}
+
+/// Walk up from `start` like git does and return the first `.opz.toml`.
+pub fn find_project_config(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+fn read_config(path: &Path) -> Result<Config> {
+    let content = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    let mut config = parse_config(&content).with_context(|| format!("parse {}", path.display()))?;
+    config.workspace.root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    Ok(config)
+}
+
+fn parse_config(content: &str) -> Result<Config> {
+    Ok(toml::from_str(content)?)
+}
+
+/// Portable snapshot of a project's opz setup (`opz config export/import`).
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Bundle {
+    pub format: u32,
+    /// File name (relative to the project root) to content.
+    pub files: BTreeMap<String, String>,
+}
+
+/// Collect the bundle files from the project containing `cwd`.
+pub fn export_bundle(cwd: &Path) -> Result<Bundle> {
+    let config_path = find_project_config(cwd).ok_or_else(|| {
+        anyhow!(
+            "No {PROJECT_CONFIG_FILE} found in {} or its parents",
+            cwd.display()
+        )
+    })?;
+    let root = config_path.parent().unwrap_or(cwd);
+
+    let mut files = BTreeMap::new();
+    for name in BUNDLE_FILES {
+        let path = root.join(name);
+        if !path.is_file() {
+            continue;
+        }
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+        files.insert(name.to_string(), content);
+    }
+
+    let bundle = Bundle {
+        format: BUNDLE_FORMAT_VERSION,
+        files,
+    };
+    validate_bundle(&bundle)?;
+    Ok(bundle)
+}
+
+/// Write the bundle files into `dir`, returning the paths written. Existing files are only
+/// replaced with `force`.
+pub fn import_bundle(bundle: &Bundle, dir: &Path, force: bool) -> Result<Vec<PathBuf>> {
+    validate_bundle(bundle)?;
+
+    let targets: Vec<(PathBuf, &String)> = bundle
+        .files
+        .iter()
+        .map(|(name, content)| (dir.join(name), content))
+        .collect();
+    if !force {
+        if let Some((path, _)) = targets.iter().find(|(path, _)| path.exists()) {
+            return Err(anyhow!(
+                "{} already exists. Use --force to overwrite.",
+                path.display()
+            ));
+        }
+    }
+
+    for (path, content) in &targets {
+        fs::write(path, content).with_context(|| format!("write {}", path.display()))?;
+    }
+    Ok(targets.into_iter().map(|(path, _)| path).collect())
+}
+
+fn validate_bundle(bundle: &Bundle) -> Result<()> {
+    if bundle.format != BUNDLE_FORMAT_VERSION {
+        return Err(anyhow!(
+            "Unsupported bundle format {} (expected {BUNDLE_FORMAT_VERSION})",
+            bundle.format
+        ));
+    }
+    for (name, content) in &bundle.files {
+        if !BUNDLE_FILES.contains(&name.as_str()) {
+            return Err(anyhow!("Unexpected file in bundle: {name}"));
+        }
+        if name == PROJECT_CONFIG_FILE {
+            parse_config(content).with_context(|| format!("parse {name} from bundle"))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_config_guard_section() {
+        let config = parse_config(
+            r#"
+[guard]
+deny_commands = ["env", "printenv"]
+production_tags = ["live"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.guard.deny_commands, vec!["env", "printenv"]);
+        assert!(config.guard.is_production_tag("LIVE"));
+        assert!(!config.guard.is_production_tag("prod"));
+    }
+
+    #[test]
+    fn test_default_production_tags() {
+        let guard = GuardConfig::default();
+        assert!(guard.is_production_tag("production"));
+        assert!(guard.is_production_tag("Prod"));
+        assert!(!guard.is_production_tag("staging"));
+    }
+
+    #[test]
+    fn test_merge_unions_deny_lists_and_project_tags_win() {
+        let global =
+            parse_config("[guard]\ndeny_commands = [\"env\"]\nproduction_tags = [\"prod\"]\n")
+                .unwrap();
+        let project = parse_config(
+            "[guard]\ndeny_commands = [\"curl\", \"env\"]\nproduction_tags = [\"live\"]\n",
+        )
+        .unwrap();
+
+        let merged = global.merge(project);
+        assert_eq!(merged.guard.deny_commands, vec!["env", "curl"]);
+        assert_eq!(merged.guard.production_tags, Some(vec!["live".to_string()]));
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("DB_*", "DB_HOST"));
+        assert!(wildcard_match("*_DEBUG", "APP_DEBUG"));
+        assert!(wildcard_match("*TOKEN*", "TOKEN"));
+        assert!(wildcard_match("A*B*C", "AxxBxxC"));
+        assert!(wildcard_match("HOST", "HOST"));
+        assert!(!wildcard_match("HOST", "HOSTNAME"));
+        assert!(!wildcard_match("DB_*", "XDB_HOST"));
+        assert!(!wildcard_match("AB*BA", "ABA"));
+    }
+
+    #[test]
+    fn test_env_key_applies_filters_then_transforms() {
+        let config = parse_config(
+            r#"
+[fields]
+include = ["db_*", "password"]
+exclude = ["*_debug"]
+
+[labels]
+uppercase = true
+prefix = "APP_"
+rename = { password = "DB_PASSWORD" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.env_key("db_host").as_deref(), Some("APP_DB_HOST"));
+        assert_eq!(config.env_key("password").as_deref(), Some("DB_PASSWORD"));
+        assert_eq!(config.env_key("db_debug"), None);
+        assert_eq!(config.env_key("username"), None);
+        assert_eq!(Config::default().env_key("host").as_deref(), Some("host"));
+    }
+
+    #[test]
+    fn test_merge_defaults_project_wins_per_key() {
+        let global = parse_config(
+            "[defaults]\nvault = \"Personal\"\ncache_ttl_secs = 300\n[fields]\nexclude = [\"A\"]\n",
+        )
+        .unwrap();
+        let project = parse_config(
+            "[defaults]\nvault = \"Team\"\nterm_grace_secs = 25\n[fields]\nexclude = [\"B\"]\n",
+        )
+        .unwrap();
+
+        let merged = global.merge(project);
+        assert_eq!(merged.defaults.vault.as_deref(), Some("Team"));
+        assert_eq!(merged.defaults.cache_ttl_secs, Some(300));
+        assert_eq!(merged.defaults.term_grace_secs, Some(25));
+        assert_eq!(merged.fields.exclude, vec!["A", "B"]);
+    }
+
+    #[test]
+    fn test_merge_ignores_project_telemetry_endpoint() {
+        let global = parse_config("[telemetry]\nendpoint = \"http://localhost:4317\"\n").unwrap();
+        let project = parse_config(
+            "[telemetry]\nendpoint = \"http://collector.example\"\nservice_name = \"app\"\n",
+        )
+        .unwrap();
+        let merged = global.merge(project);
+        assert_eq!(
+            merged.telemetry.endpoint.as_deref(),
+            Some("http://localhost:4317")
+        );
+        assert_eq!(merged.telemetry.service_name.as_deref(), Some("app"));
+
+        let project =
+            parse_config("[telemetry]\nendpoint = \"http://collector.example\"\n").unwrap();
+        assert_eq!(Config::default().merge(project).telemetry.endpoint, None);
+    }
+
+    #[test]
+    fn test_project_item_mapping_found_from_subdirectory() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(
+            tmp.path().join(PROJECT_CONFIG_FILE),
+            "item = \"my-service prod\"\nvault = \"Team\"\n",
+        )
+        .unwrap();
+        let nested = tmp.path().join("src").join("bin");
+        fs::create_dir_all(&nested).unwrap();
+
+        let config = read_config(&find_project_config(&nested).unwrap()).unwrap();
+        let merged = Config::default().merge(config);
+        assert_eq!(merged.item.as_deref(), Some("my-service prod"));
+        assert_eq!(merged.vault.as_deref(), Some("Team"));
+    }
+
+    #[test]
+    fn test_activate_profile_overlays_items_vault_and_fields() {
+        let global = parse_config(
+            r#"
+[profile.prod]
+items = ["svc prod"]
+vault = "Prod"
+"#,
+        )
+        .unwrap();
+        let project = parse_config(
+            r#"
+item = "svc dev"
+vault = "Dev"
+
+[fields]
+exclude = ["DEBUG"]
+
+[profile.staging]
+items = ["svc staging", "shared"]
+fields = { include = ["DB_*"] }
+"#,
+        )
+        .unwrap();
+        let config = global.merge(project);
+        assert_eq!(config.default_items(), vec!["svc dev"]);
+
+        let mut staging = config.clone();
+        staging.activate_profile("staging").unwrap();
+        assert_eq!(staging.default_items(), vec!["svc staging", "shared"]);
+        assert_eq!(staging.vault.as_deref(), Some("Dev"));
+        assert_eq!(staging.fields.include, vec!["DB_*"]);
+        assert!(staging.fields.exclude.is_empty());
+
+        let mut prod = config.clone();
+        prod.activate_profile("prod").unwrap();
+        assert_eq!(prod.vault.as_deref(), Some("Prod"));
+
+        let err = config.clone().activate_profile("qa").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown profile 'qa'. Defined profiles: prod, staging"
+        );
+    }
+
+    #[test]
+    fn test_parse_output_section() {
+        let config = parse_config(
+            "[output]\nkeys = [\"DB_HOST\"]\norder = \"section\"\nseparators = \"comment\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.output.keys, vec!["DB_HOST"]);
+        assert_eq!(config.output.order, Some(OutputOrder::Section));
+        assert_eq!(config.output.separators, Some(Separators::Comment));
+        assert!(parse_config("[output]\norder = \"random\"\n").is_err());
+    }
+
+    #[test]
+    fn test_policies_add_up_and_survive_profiles() {
+        let global = parse_config(
+            "[policies]
+\"ROOT_*\" = [\"never-export\"]\n",
+        )
+        .unwrap();
+        let project = parse_config(
+            r#"
+[policies]
+"ROOT_*" = ["prod-confirm"]
+TLS_KEY = ["file-only", "argv-forbidden"]
+
+[profile.dev]
+fields = { include = ["*"] }
+"#,
+        )
+        .unwrap();
+        let mut config = global.merge(project);
+        config.activate_profile("dev").unwrap();
+
+        assert_eq!(
+            config.field_policies("ROOT_PASSWORD"),
+            BTreeSet::from([FieldPolicy::NeverExport, FieldPolicy::ProdConfirm])
+        );
+        assert_eq!(config.env_key("ROOT_PASSWORD"), None);
+        assert_eq!(config.env_key("TLS_KEY").as_deref(), Some("TLS_KEY"));
+        assert!(config.field_policies("HOST").is_empty());
+        assert!(parse_config("[policies]\nX = [\"shred\"]\n").is_err());
+    }
+
+    #[test]
+    fn test_map_label_names_the_rules() {
+        let config = parse_config(
+            r#"
+[fields]
+exclude = ["internal_*"]
+
+[labels]
+uppercase = true
+prefix = "APP_"
+rename = { "db url" = "DATABASE_URL" }
+"#,
+        )
+        .unwrap();
+
+        let mapping = config.map_label("token");
+        assert_eq!(mapping.key, "APP_TOKEN");
+        assert_eq!(
+            mapping.transforms,
+            vec!["[labels] uppercase", "[labels] prefix = \"APP_\""]
+        );
+        assert_eq!(mapping.dropped_by, None);
+
+        let mapping = config.map_label("db url");
+        assert_eq!(mapping.key, "DATABASE_URL");
+        assert_eq!(
+            mapping.transforms,
+            vec!["[labels.rename] \"db url\" = \"DATABASE_URL\""]
+        );
+
+        let mapping = config.map_label("internal_id");
+        assert_eq!(mapping.key, "APP_INTERNAL_ID");
+        assert_eq!(
+            mapping.dropped_by.as_deref(),
+            Some("[fields] exclude \"internal_*\"")
+        );
+        assert_eq!(config.env_key("internal_id"), None);
+    }
+
+    #[test]
+    fn test_map_field_uses_category_names() {
+        let config = parse_config(
+            "[labels]\nsmart = true\nprefix = \"APP_\"\nrename = { port = \"PGPORT\" }\n",
+        )
+        .unwrap();
+        let mapping = config.map_field(Some("DATABASE"), "server");
+        assert_eq!(mapping.key, "APP_DB_HOST");
+        assert_eq!(
+            mapping.transforms,
+            vec![
+                "DATABASE item field \"server\" = \"DB_HOST\"",
+                "[labels] prefix = \"APP_\""
+            ]
+        );
+        assert_eq!(config.map_field(Some("DATABASE"), "port").key, "PGPORT");
+        assert_eq!(
+            config.map_field(Some("LOGIN"), "Username").key,
+            "APP_USERNAME"
+        );
+        assert_eq!(config.map_field(Some("LOGIN"), "notes").key, "APP_notes");
+        assert_eq!(config.map_field(None, "server").key, "APP_server");
+
+        // Off by default, so existing keys keep their names.
+        let config = parse_config("[labels]\nprefix = \"APP_\"\n").unwrap();
+        assert_eq!(
+            config.map_field(Some("DATABASE"), "server").key,
+            "APP_server"
+        );
+    }
+
+    #[test]
+    fn test_parse_backend_section() {
+        let config = parse_config(
+            "[backend]\nchain = [\"connect\", \"cli\", \"cache\"]\nfallback_on = [\"unavailable\", \"auth\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.backend.chain,
+            Some(vec![Backend::Connect, Backend::Cli, Backend::Cache])
+        );
+        assert_eq!(
+            config.backend.fallback_on,
+            Some(vec![ErrorClass::Unavailable, ErrorClass::Auth])
+        );
+        assert!(parse_config("[backend]\nchain = [\"sdk\"]\n").is_err());
+    }
+
+    #[test]
+    fn test_workspace_members_resolve_against_config_dir() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join(PROJECT_CONFIG_FILE);
+        fs::write(
+            &path,
+            r#"
+[[workspace.members]]
+path = "services/api"
+items = ["api dev"]
+
+[[workspace.members]]
+path = "services/web"
+items = ["web dev", "shared"]
+"#,
+        )
+        .unwrap();
+
+        let config = read_config(&path).unwrap();
+        let members = &config.workspace.members;
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[1].items, vec!["web dev", "shared"]);
+        assert_eq!(
+            config.workspace.member_dir(&members[0]),
+            tmp.path().join("services/api")
+        );
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let source = TempDir::new().unwrap();
+        let nested = source.path().join("src");
+        fs::create_dir_all(&nested).unwrap();
+        let content = "[guard]\ndeny_commands = [\"env\"]\n";
+        fs::write(source.path().join(PROJECT_CONFIG_FILE), content).unwrap();
+        fs::write(source.path().join(".env"), "TOKEN=secret\n").unwrap();
+
+        let bundle = export_bundle(&nested).unwrap();
+        assert_eq!(
+            bundle.files.keys().collect::<Vec<_>>(),
+            vec![PROJECT_CONFIG_FILE]
+        );
+
+        let target = TempDir::new().unwrap();
+        let written = import_bundle(&bundle, target.path(), false).unwrap();
+        assert_eq!(written, vec![target.path().join(PROJECT_CONFIG_FILE)]);
+        assert_eq!(
+            fs::read_to_string(target.path().join(PROJECT_CONFIG_FILE)).unwrap(),
+            content
+        );
+
+        assert!(import_bundle(&bundle, target.path(), false).is_err());
+        assert!(import_bundle(&bundle, target.path(), true).is_ok());
+    }
+
+    #[test]
+    fn test_import_bundle_rejects_unknown_files_and_formats() {
+        let target = TempDir::new().unwrap();
+        let mut bundle = Bundle {
+            format: BUNDLE_FORMAT_VERSION,
+            files: BTreeMap::from([("../.env".to_string(), "X=1".to_string())]),
+        };
+        assert!(import_bundle(&bundle, target.path(), true).is_err());
+
+        bundle.files = BTreeMap::new();
+        bundle.format = BUNDLE_FORMAT_VERSION + 1;
+        assert!(import_bundle(&bundle, target.path(), true).is_err());
+    }
+
+    #[test]
+    fn test_find_project_config_walks_up() {
+        let tmp = TempDir::new().unwrap();
+        let nested = tmp.path().join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(tmp.path().join(PROJECT_CONFIG_FILE), "").unwrap();
+
+        assert_eq!(
+            find_project_config(&nested),
+            Some(tmp.path().join(PROJECT_CONFIG_FILE))
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! `opz daemon`: keeps the item list cache warm in the foreground and optionally serves
+//! read-only status (health, cache freshness, version) over loopback HTTP.
+//!
+//! The refresh loop and the status server run as supervised tasks: Ctrl-C or SIGTERM stops
+//! both cleanly, and a server that keeps failing takes the daemon down with it.
+
+use crate::{
+    cli::{cache_status_entries, cache_status_json, refresh_item_list_cache},
+    runtime,
+    supervisor::{Restart, Supervisor},
+    telemetry_span,
+};
+use anyhow::{anyhow, Context, Result};
+use serde_json::json;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+/// Refresh well inside the item list TTL so interactive runs always hit a fresh cache.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+const RESTART: Restart = Restart::OnFailure {
+    max: 5,
+    backoff: Duration::from_secs(1),
+};
+
+#[derive(Default)]
+struct State {
+    last_refresh: Option<SystemTime>,
+    last_error: Option<String>,
+}
+
+pub fn run(account: Option<&str>, vault: Option<&str>, http: Option<SocketAddr>) -> Result<()> {
+    let started = Instant::now();
+    let state = Arc::new(Mutex::new(State::default()));
+
+    let listener = match http {
+        Some(addr) => {
+            if !addr.ip().is_loopback() {
+                return Err(anyhow!(
+                    "--http must bind a loopback address (e.g. 127.0.0.1:7878), got {addr}"
+                ));
+            }
+            let listener =
+                std::net::TcpListener::bind(addr).with_context(|| format!("bind {addr}"))?;
+            listener.set_nonblocking(true)?;
+            eprintln!("Serving status on http://{}", listener.local_addr()?);
+            Some(listener)
+        }
+        None => None,
+    };
+
+    let account = account.map(str::to_string);
+    let vault = vault.map(str::to_string);
+    runtime::block_on(async move {
+        let mut tasks = Supervisor::new();
+        tasks.cancel_on_signal();
+
+        let refresh_state = Arc::clone(&state);
+        tasks.spawn("cache refresh", RESTART, move |token| {
+            refresh_loop(
+                account.clone(),
+                vault.clone(),
+                Arc::clone(&refresh_state),
+                token,
+            )
+        });
+
+        if let Some(listener) = listener {
+            let listener = Arc::new(TcpListener::from_std(listener)?);
+            tasks.spawn("status server", RESTART, move |token| {
+                serve(Arc::clone(&listener), started, Arc::clone(&state), token)
+            });
+        }
+
+        tasks.wait().await
+    })?
+}
+
+async fn refresh_loop(
+    account: Option<String>,
+    vault: Option<String>,
+    state: Arc<Mutex<State>>,
+    token: CancellationToken,
+) -> Result<()> {
+    loop {
+        let (account_arg, vault_arg) = (account.clone(), vault.clone());
+        let result = tokio::task::spawn_blocking(move || {
+            refresh_item_list_cache(account_arg.as_deref(), vault_arg.as_deref())
+        })
+        .await?;
+        record_refresh(&state, result.map(drop));
+
+        tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(REFRESH_INTERVAL) => {}
+        }
+    }
+}
+
+fn record_refresh(state: &Mutex<State>, result: Result<()>) {
+    let mut state = state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    match result {
+        Ok(()) => {
+            state.last_refresh = Some(SystemTime::now());
+            state.last_error = None;
+        }
+        Err(err) => {
+            tracing::warn!("failed to refresh item list cache: {err:#}");
+            state.last_error = Some(telemetry_span::sanitize_for_trace(&format!("{err:#}")));
+        }
+    }
+}
+
+async fn serve(
+    listener: Arc<TcpListener>,
+    started: Instant,
+    state: Arc<Mutex<State>>,
+    token: CancellationToken,
+) -> Result<()> {
+    loop {
+        let stream = tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            accepted = listener.accept() => accepted.context("accept status connection")?.0,
+        };
+        let stream = stream.into_std()?;
+        stream.set_nonblocking(false)?;
+        let state = Arc::clone(&state);
+        tokio::task::spawn_blocking(move || {
+            if let Err(err) = handle(stream, started, &state) {
+                tracing::warn!("status request failed: {err}");
+            }
+        });
+    }
+}
+
+fn handle(mut stream: TcpStream, started: Instant, state: &Mutex<State>) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let (status, body) = match route(&request_line) {
+        Route::Health => ("200 OK", json!({ "status": "ok" })),
+        Route::Status => {
+            let state = state
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let body = status_json(
+                &cache_status_entries().unwrap_or_default(),
+                SystemTime::now(),
+                started.elapsed(),
+                &state,
+            );
+            ("200 OK", body)
+        }
+        Route::NotFound => ("404 Not Found", json!({ "error": "not found" })),
+        Route::MethodNotAllowed => (
+            "405 Method Not Allowed",
+            json!({ "error": "method not allowed" }),
+        ),
+    };
+
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Route {
+    Health,
+    Status,
+    NotFound,
+    MethodNotAllowed,
+}
+
+fn route(request_line: &str) -> Route {
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Route::NotFound;
+    };
+    if method != "GET" {
+        return Route::MethodNotAllowed;
+    }
+    match target.split('?').next().unwrap_or_default() {
+        "/health" => Route::Health,
+        "/" | "/status" => Route::Status,
+        _ => Route::NotFound,
+    }
+}
+
+fn status_json(
+    entries: &[crate::cli::CacheStatusEntry],
+    now: SystemTime,
+    uptime: Duration,
+    state: &State,
+) -> serde_json::Value {
+    let caches = cache_status_json(entries, now);
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "pid": std::process::id(),
+        "uptime_secs": uptime.as_secs(),
+        "last_refresh_age_secs": state
+            .last_refresh
+            .map(|at| now.duration_since(at).unwrap_or_default().as_secs()),
+        "last_error": state.last_error,
+        "caches": caches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::CacheStatusEntry;
+
+    #[test]
+    fn test_route() {
+        assert_eq!(route("GET /health HTTP/1.1\r\n"), Route::Health);
+        assert_eq!(route("GET /status?x=1 HTTP/1.1\r\n"), Route::Status);
+        assert_eq!(route("GET / HTTP/1.1\r\n"), Route::Status);
+        assert_eq!(route("GET /secrets HTTP/1.1\r\n"), Route::NotFound);
+        assert_eq!(route("POST /status HTTP/1.1\r\n"), Route::MethodNotAllowed);
+        assert_eq!(route(""), Route::NotFound);
+    }
+
+    #[test]
+    fn test_status_json_reports_cache_freshness() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(10_000);
+        let entries = vec![CacheStatusEntry {
+            account: None,
+            vault: Some("Team".to_string()),
+            item_count: Some(3),
+            size: 512,
+            modified: now - Duration::from_secs(5),
+        }];
+        let state = State {
+            last_refresh: Some(now - Duration::from_secs(5)),
+            last_error: None,
+        };
+
+        let status = status_json(&entries, now, Duration::from_secs(42), &state);
+        assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(status["uptime_secs"], 42);
+        assert_eq!(status["last_refresh_age_secs"], 5);
+        assert_eq!(status["caches"][0]["vault"], "Team");
+        assert_eq!(status["caches"][0]["items"], 3);
+        assert_eq!(status["caches"][0]["state"], "fresh");
+    }
+}
@@ -0,0 +1,202 @@
+//! Failure classes with stable exit statuses, so scripts can tell "sign in first" from "no such
+//! item" without matching messages. Errors stay `anyhow` errors; a typed [`Error`] in the chain
+//! (as the error itself or as context) names the class, and I/O and parse errors are recognized
+//! from their source types. Anything else exits with 1.
+//!
+//! A typed error can also carry what a tool needs to recover without parsing text: the
+//! candidates of an ambiguous title and the (sanitized) `op` arguments that failed. They are
+//! reported when errors are printed as JSON (`--json` or `OPZ_ERROR_FORMAT=json`).
+
+use serde::Serialize;
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+
+/// Report the failure as one JSON object on stderr instead of text.
+pub fn set_json_format(json: bool) {
+    JSON_FORMAT.store(json, Ordering::Relaxed);
+}
+
+pub fn json_format() -> bool {
+    JSON_FORMAT.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// `op` has no usable session and none could be started.
+    NotSignedIn,
+    /// No item matched the requested title, or `op` reports it does not exist.
+    ItemNotFound,
+    /// Several items matched the requested title.
+    AmbiguousItem,
+    /// `op` could not be run, timed out, or failed for another reason.
+    OpFailed,
+    /// Reading or writing a file, pipe or socket failed.
+    Io,
+    /// A config file, cache or `op` output could not be parsed.
+    Parse,
+}
+
+impl Kind {
+    /// Exit status for the class, taken from sysexits(3) like the status for panics.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Kind::AmbiguousItem => 64,
+            Kind::Parse => 65,
+            Kind::ItemNotFound => 66,
+            Kind::OpFailed => 69,
+            Kind::Io => 74,
+            Kind::NotSignedIn => 77,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::NotSignedIn => "not-signed-in",
+            Kind::ItemNotFound => "item-not-found",
+            Kind::AmbiguousItem => "ambiguous-item",
+            Kind::OpFailed => "op-failed",
+            Kind::Io => "io",
+            Kind::Parse => "parse",
+        }
+    }
+}
+
+/// An item an ambiguous title could have meant.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Candidate {
+    pub id: String,
+    pub title: String,
+    pub vault: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    kind: Kind,
+    message: String,
+    candidates: Vec<Candidate>,
+    op_args: Option<String>,
+}
+
+impl Error {
+    pub fn new(kind: Kind, message: impl Into<String>) -> Self {
+        Error {
+            kind,
+            message: message.into(),
+            candidates: Vec::new(),
+            op_args: None,
+        }
+    }
+
+    pub fn with_candidates(mut self, candidates: Vec<Candidate>) -> Self {
+        self.candidates = candidates;
+        self
+    }
+
+    /// `args` must already be sanitized (see `logging::sanitize_op_args`).
+    pub fn with_op_args(mut self, args: impl Into<String>) -> Self {
+        self.op_args = Some(args.into());
+        self
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// The items an ambiguous title could have meant.
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    /// The sanitized arguments of the `op` call that failed.
+    pub fn op_args(&self) -> Option<&str> {
+        self.op_args.as_deref()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The class of `err`: the outermost typed [`Error`], else one inferred from the I/O or parse
+/// error it wraps.
+pub fn kind(err: &anyhow::Error) -> Option<Kind> {
+    if let Some(err) = err.downcast_ref::<Error>() {
+        return Some(err.kind);
+    }
+    err.chain().find_map(|cause| {
+        if cause.is::<serde_json::Error>() || cause.is::<toml::de::Error>() {
+            Some(Kind::Parse)
+        } else if cause.is::<std::io::Error>() {
+            Some(Kind::Io)
+        } else {
+            None
+        }
+    })
+}
+
+/// Exit status for `err`.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    kind(err).map_or(1, Kind::exit_code)
+}
+
+/// The `"error"` fields describing the typed error in `err`, if there is one.
+pub fn json_fields(err: &anyhow::Error) -> serde_json::Map<String, serde_json::Value> {
+    let mut fields = serde_json::Map::new();
+    if let Some(kind) = kind(err) {
+        fields.insert("kind".into(), kind.name().into());
+    }
+    if let Some(typed) = err.downcast_ref::<Error>() {
+        if !typed.candidates.is_empty() {
+            fields.insert(
+                "candidates".into(),
+                serde_json::to_value(&typed.candidates).unwrap_or_default(),
+            );
+        }
+        if let Some(args) = &typed.op_args {
+            fields.insert("op_args".into(), args.clone().into());
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_kind_prefers_typed_context_over_sources() {
+        let io = std::io::Error::from(std::io::ErrorKind::TimedOut);
+        let err = Err::<(), _>(io)
+            .context(Error::new(Kind::OpFailed, "failed to run op item list"))
+            .context("load item list")
+            .unwrap_err();
+        assert_eq!(kind(&err), Some(Kind::OpFailed));
+        assert_eq!(exit_code(&err), 69);
+        assert_eq!(
+            format!("{err:#}"),
+            "load item list: failed to run op item list: timed out"
+        );
+    }
+
+    #[test]
+    fn test_kind_from_sources() {
+        let parse = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        let err = anyhow::Error::new(parse).context("failed to parse op JSON output");
+        assert_eq!(kind(&err), Some(Kind::Parse));
+
+        let err = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+            .context("write .env");
+        assert_eq!(kind(&err), Some(Kind::Io));
+
+        assert_eq!(exit_code(&anyhow::anyhow!("something else")), 1);
+    }
+}
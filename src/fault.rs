@@ -0,0 +1,74 @@
+//! Fault injection for resilience tests. `OPZ_FAULT_INJECT` holds a comma-separated list of
+//! faults to simulate, so the retry, fallback and cleanup paths can be exercised without a
+//! broken 1Password setup. Not a user-facing feature.
+
+use std::sync::OnceLock;
+
+pub const FAULT_ENV: &str = "OPZ_FAULT_INJECT";
+
+/// Endpoint nothing listens on, standing in for an unreachable OTLP collector.
+pub const UNREACHABLE_COLLECTOR: &str = "http://127.0.0.1:9";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Every `op` invocation fails as if it timed out.
+    OpTimeout,
+    /// `op` JSON output arrives truncated.
+    MalformedJson,
+    /// `op` reports that no account is signed in.
+    SignedOut,
+    /// Cache files are written torn (only their first half lands on disk).
+    CorruptCache,
+    /// Traces go to a collector that refuses connections.
+    CollectorUnreachable,
+}
+
+impl Fault {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "op-timeout" => Some(Fault::OpTimeout),
+            "malformed-json" => Some(Fault::MalformedJson),
+            "signed-out" => Some(Fault::SignedOut),
+            "corrupt-cache" => Some(Fault::CorruptCache),
+            "collector-unreachable" => Some(Fault::CollectorUnreachable),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `fault` is switched on for this process.
+pub fn active(fault: Fault) -> bool {
+    static FAULTS: OnceLock<Vec<Fault>> = OnceLock::new();
+    FAULTS
+        .get_or_init(|| parse_list(&std::env::var(FAULT_ENV).unwrap_or_default()))
+        .contains(&fault)
+}
+
+fn parse_list(spec: &str) -> Vec<Fault> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let fault = Fault::parse(name);
+            if fault.is_none() {
+                // Telemetry startup asks first, before the logger exists.
+                eprintln!("Warning: ignoring unknown {FAULT_ENV} fault '{name}'");
+            }
+            fault
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(
+            parse_list("op-timeout, corrupt-cache,,bogus"),
+            vec![Fault::OpTimeout, Fault::CorruptCache]
+        );
+        assert!(parse_list("").is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+//! `--pipe-env-file`: hand content to another process through a named pipe instead of a
+//! regular file, so it only ever passes through kernel memory and never reaches a disk.
+//!
+//! The pipe is fed from a background thread, since opening a FIFO for writing blocks until
+//! someone opens it for reading. Where named pipes are unavailable, [`feed`] fails and callers
+//! write a file instead.
+
+use anyhow::Result;
+use std::{path::Path, thread::JoinHandle};
+
+/// A named pipe being fed; call [`Feed::finish`] once the reader is done with it.
+pub struct Feed {
+    writer: JoinHandle<std::io::Result<()>>,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    path: std::path::PathBuf,
+}
+
+/// Create an owner-only FIFO at `path` and write `content` to the first process that opens it.
+#[cfg(unix)]
+pub fn feed(path: &Path, content: String) -> Result<Feed> {
+    use anyhow::Context;
+    use std::{ffi::CString, io::Write, os::unix::ffi::OsStrExt};
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `c_path` is a valid NUL-terminated string that outlives the call.
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("create named pipe {}", path.display()));
+    }
+    let writer_path = path.to_path_buf();
+    let writer = std::thread::spawn(move || {
+        let mut pipe = std::fs::OpenOptions::new().write(true).open(&writer_path)?;
+        pipe.write_all(content.as_bytes())
+    });
+    Ok(Feed {
+        writer,
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(not(unix))]
+pub fn feed(_path: &Path, _content: String) -> Result<Feed> {
+    Err(anyhow::anyhow!("named pipes need a Unix platform"))
+}
+
+impl Feed {
+    /// Wait for the writer thread. If the reader never opened the pipe, or stopped reading
+    /// early, open it here and drain it so the thread can finish. Returns whether the content
+    /// was written in full (to whoever read it).
+    pub fn finish(self) -> bool {
+        #[cfg(unix)]
+        if !self.writer.is_finished() {
+            self.drain();
+        }
+        matches!(self.writer.join(), Ok(Ok(())))
+    }
+
+    #[cfg(unix)]
+    fn drain(&self) {
+        use std::{io::Read, os::unix::fs::OpenOptionsExt, time::Duration};
+
+        // Non-blocking, so opening the read end never waits for a writer that is already gone.
+        let Ok(mut pipe) = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+        else {
+            return;
+        };
+        let mut buf = [0u8; 8192];
+        while !self.writer.is_finished() {
+            if !matches!(pipe.read(&mut buf), Ok(n) if n > 0) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::FileTypeExt;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_feed_hands_content_to_the_reader() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("op-run.env");
+        let content = "KEY=op://vault/item/field\n".repeat(10_000);
+        let feed = feed(&path, content.clone()).unwrap();
+        assert!(std::fs::metadata(&path).unwrap().file_type().is_fifo());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+        assert!(feed.finish());
+    }
+
+    #[test]
+    fn test_finish_does_not_wait_for_a_reader_that_never_came() {
+        let tmp_dir = TempDir::new().unwrap();
+        let feed = feed(&tmp_dir.path().join("op-run.env"), "A=1\n".repeat(50_000)).unwrap();
+        feed.finish();
+    }
+}
@@ -0,0 +1,258 @@
+//! Registry of env files opz wrote for the duration of a single run (`--keep=none`), so files
+//! left behind by crashed or killed runs can be found and cleaned up later with `opz gc`.
+
+use crate::cache_file;
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub path: PathBuf,
+    /// Hash of the content opz wrote; a mismatch means someone edited the file since.
+    pub sha256: String,
+    /// The opz process that owns the file; the entry is stale once it is gone.
+    pub pid: u32,
+    /// Whether opz created the file (as opposed to merging into an existing one).
+    pub created: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct State {
+    #[serde(default)]
+    files: Vec<Entry>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Removed,
+    /// Already gone; only the record is dropped.
+    Missing,
+    /// Edited after opz wrote it; left in place.
+    Modified,
+    /// opz merged into a file that existed before; left in place for manual review.
+    Preexisting,
+}
+
+pub fn state_path() -> Option<PathBuf> {
+    ProjectDirs::from("dev", "opz", "opz").map(|proj| proj.data_dir().join("generated.json"))
+}
+
+/// Remember that this process wrote `content` to `path`.
+pub fn record(path: &Path, content: &[u8], created: bool) -> Result<()> {
+    let Some(state_path) = state_path() else {
+        return Ok(());
+    };
+    let path = std::path::absolute(path).with_context(|| format!("resolve {}", path.display()))?;
+    let entry = Entry {
+        sha256: sha256_hex(content),
+        pid: std::process::id(),
+        created,
+        path,
+    };
+    update(&state_path, |state| {
+        state.files.retain(|existing| existing.path != entry.path);
+        state.files.push(entry);
+    })
+}
+
+/// Drop the record for `path` once the run cleaned it up itself.
+pub fn forget(path: &Path) -> Result<()> {
+    let Some(state_path) = state_path() else {
+        return Ok(());
+    };
+    let path = std::path::absolute(path).with_context(|| format!("resolve {}", path.display()))?;
+    update(&state_path, |state| {
+        state.files.retain(|existing| existing.path != path)
+    })
+}
+
+/// Entries whose owning process has exited without cleaning up.
+pub fn stale() -> Result<Vec<Entry>> {
+    let Some(state_path) = state_path() else {
+        return Ok(Vec::new());
+    };
+    Ok(read_state(&state_path)?
+        .files
+        .into_iter()
+        .filter(|entry| !process_alive(entry.pid))
+        .collect())
+}
+
+/// Clean up stale entries. With `dry_run` nothing is removed and the records are kept.
+pub fn collect(dry_run: bool) -> Result<Vec<(Entry, Outcome)>> {
+    let Some(state_path) = state_path() else {
+        return Ok(Vec::new());
+    };
+    collect_in(&state_path, dry_run, process_alive)
+}
+
+fn collect_in(
+    state_path: &Path,
+    dry_run: bool,
+    alive: impl Fn(u32) -> bool,
+) -> Result<Vec<(Entry, Outcome)>> {
+    let mut results = Vec::new();
+    let mut outcome_error = None;
+    let mut apply = |state: &mut State| {
+        let (stale, live): (Vec<_>, Vec<_>) = std::mem::take(&mut state.files)
+            .into_iter()
+            .partition(|entry| !alive(entry.pid));
+        state.files = live;
+
+        for entry in stale {
+            let outcome = match classify(&entry) {
+                Outcome::Removed if !dry_run => match crate::output::remove_file(&entry.path) {
+                    Ok(()) => Outcome::Removed,
+                    Err(err) => {
+                        outcome_error.get_or_insert(
+                            anyhow::Error::from(err)
+                                .context(format!("remove {}", entry.path.display())),
+                        );
+                        state.files.push(entry);
+                        continue;
+                    }
+                },
+                outcome => outcome,
+            };
+            results.push((entry, outcome));
+        }
+    };
+
+    if dry_run {
+        apply(&mut read_state(state_path)?);
+    } else {
+        update(state_path, apply)?;
+    }
+    match outcome_error {
+        Some(err) => Err(err),
+        None => Ok(results),
+    }
+}
+
+fn classify(entry: &Entry) -> Outcome {
+    let Ok(content) = fs::read(&entry.path) else {
+        return Outcome::Missing;
+    };
+    if sha256_hex(&content) != entry.sha256 {
+        Outcome::Modified
+    } else if !entry.created {
+        Outcome::Preexisting
+    } else {
+        Outcome::Removed
+    }
+}
+
+fn update(state_path: &Path, change: impl FnOnce(&mut State)) -> Result<()> {
+    let _lock = cache_file::Lock::acquire(state_path)?;
+    let mut state = read_state(state_path)?;
+    change(&mut state);
+    cache_file::write_atomic(state_path, &serde_json::to_vec_pretty(&state)?)
+}
+
+fn read_state(state_path: &Path) -> Result<State> {
+    match fs::read(state_path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .with_context(|| format!("parse {}", state_path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+        Err(err) => Err(err).with_context(|| format!("read {}", state_path.display())),
+    }
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+#[cfg(unix)]
+pub fn process_alive(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks for existence and permission; nothing is delivered.
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+        return true;
+    }
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+pub fn process_alive(_pid: u32) -> bool {
+    // Without a liveness check, never treat another run's files as abandoned.
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(path: PathBuf, content: &[u8], pid: u32, created: bool) -> Entry {
+        Entry {
+            path,
+            sha256: sha256_hex(content),
+            pid,
+            created,
+        }
+    }
+
+    #[test]
+    fn test_collect_removes_only_unchanged_files_from_dead_runs() {
+        let tmp = TempDir::new().unwrap();
+        let state_path = tmp.path().join("state").join("generated.json");
+        let created = tmp.path().join("created.env");
+        let edited = tmp.path().join("edited.env");
+        let merged = tmp.path().join("merged.env");
+        let live = tmp.path().join("live.env");
+        fs::write(&created, "A=op://v/i/A\n").unwrap();
+        fs::write(&edited, "A=changed\n").unwrap();
+        fs::write(&merged, "A=op://v/i/A\n").unwrap();
+        fs::write(&live, "A=op://v/i/A\n").unwrap();
+
+        let content = b"A=op://v/i/A\n";
+        update(&state_path, |state| {
+            state.files = vec![
+                entry(created.clone(), content, 1, true),
+                entry(edited.clone(), content, 1, true),
+                entry(merged.clone(), content, 1, false),
+                entry(tmp.path().join("gone.env"), content, 1, true),
+                entry(live.clone(), content, 2, true),
+            ];
+        })
+        .unwrap();
+
+        let dry = collect_in(&state_path, true, |pid| pid == 2).unwrap();
+        assert_eq!(dry.len(), 4);
+        assert_eq!(dry[0].1, Outcome::Removed);
+        assert!(created.exists());
+        assert_eq!(read_state(&state_path).unwrap().files.len(), 5);
+
+        let outcomes: Vec<Outcome> = collect_in(&state_path, false, |pid| pid == 2)
+            .unwrap()
+            .into_iter()
+            .map(|(_, outcome)| outcome)
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Removed,
+                Outcome::Modified,
+                Outcome::Preexisting,
+                Outcome::Missing
+            ]
+        );
+        assert!(!created.exists());
+        assert!(edited.exists() && merged.exists() && live.exists());
+
+        let remaining = read_state(&state_path).unwrap().files;
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].path, live);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_process_alive_for_current_process() {
+        assert!(process_alive(std::process::id()));
+    }
+}
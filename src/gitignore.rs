@@ -0,0 +1,139 @@
+//! Keeps generated env files out of commits: asks git whether a path is ignored and, on
+//! request, adds an anchored pattern for it to the repository's top-level `.gitignore`.
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Status {
+    Ignored,
+    NotIgnored,
+    /// Outside a git work tree, or git is not installed.
+    NotInRepo,
+}
+
+/// Whether git would ignore `path` (which need not exist yet).
+pub fn status(path: &Path) -> Status {
+    let (dir, name) = split(path);
+    let Some(name) = name else {
+        return Status::NotInRepo;
+    };
+    let code = Command::new("git")
+        .args(["check-ignore", "-q", "--"])
+        .arg(name)
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .ok()
+        .and_then(|status| status.code());
+    match code {
+        Some(0) => Status::Ignored,
+        Some(1) => Status::NotIgnored,
+        _ => Status::NotInRepo,
+    }
+}
+
+/// Append an anchored pattern for `path` (e.g. `/app/.env`) to the top-level `.gitignore`,
+/// returning the pattern and the file it went into.
+pub fn add(path: &Path) -> Result<(String, PathBuf)> {
+    let (dir, name) = split(path);
+    let name = name.ok_or_else(|| anyhow!("{} has no file name", path.display()))?;
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .current_dir(dir)
+        .stderr(Stdio::null())
+        .output()
+        .context("run git rev-parse")?;
+    if !output.status.success() {
+        return Err(anyhow!("{} is not inside a git work tree", path.display()));
+    }
+    let toplevel = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim_end());
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("resolve {}", dir.display()))?;
+    let relative = dir
+        .strip_prefix(toplevel.canonicalize()?)
+        .with_context(|| format!("{} is outside {}", dir.display(), toplevel.display()))?
+        .join(name);
+    let pattern = format!(
+        "/{}",
+        relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    );
+
+    let gitignore = toplevel.join(".gitignore");
+    let existing = match fs::read_to_string(&gitignore) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).with_context(|| format!("read {}", gitignore.display())),
+    };
+    let separator = if existing.is_empty() || existing.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&gitignore)
+        .and_then(|mut file| writeln!(file, "{separator}{pattern}"))
+        .with_context(|| format!("update {}", gitignore.display()))?;
+    Ok((pattern, gitignore))
+}
+
+fn split(path: &Path) -> (&Path, Option<&std::ffi::OsStr>) {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    (dir, path.file_name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn git_repo() -> TempDir {
+        let tmp = TempDir::new().unwrap();
+        let status = Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(tmp.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        tmp
+    }
+
+    #[test]
+    fn test_add_makes_path_ignored() {
+        let repo = git_repo();
+        fs::create_dir(repo.path().join("app")).unwrap();
+        fs::write(repo.path().join(".gitignore"), "target").unwrap();
+        let env_file = repo.path().join("app/.env");
+        assert_eq!(status(&env_file), Status::NotIgnored);
+
+        let (pattern, gitignore) = add(&env_file).unwrap();
+        assert_eq!(pattern, "/app/.env");
+        assert_eq!(
+            fs::read_to_string(gitignore).unwrap(),
+            "target\n/app/.env\n"
+        );
+        assert_eq!(status(&env_file), Status::Ignored);
+    }
+
+    #[test]
+    fn test_status_outside_repo() {
+        let tmp = TempDir::new().unwrap();
+        assert_eq!(status(&tmp.path().join(".env")), Status::NotInRepo);
+    }
+}
@@ -0,0 +1,44 @@
+//! opz looks up 1Password items through the `op` CLI (or 1Password Connect), caches what it
+//! finds, and renders the items' fields as env file lines of `op://` references. The `opz`
+//! binary is a thin command line over this crate; other tools can embed the same lookup and
+//! rendering through [`Client`] and [`EnvRenderer`].
+//!
+//! Lookups share the `opz` command's on-disk caches and honor the same environment
+//! (`OP_ACCOUNT`, `OPZ_OP_BIN`, ...). Errors are [`anyhow::Error`]s; [`error_kind`] classifies
+//! them the way the command's exit status does.
+
+pub mod cli;
+
+mod agent;
+mod backend;
+mod backup;
+mod cache_file;
+mod client;
+mod config;
+mod daemon;
+mod error;
+mod fault;
+mod fifo;
+mod generated;
+mod gitignore;
+mod lockfile;
+mod logging;
+mod mask;
+mod op_version;
+mod output;
+mod parallel;
+mod recent;
+mod run_dir;
+mod runtime;
+mod service_account;
+mod shutdown;
+mod signin;
+mod supervisor;
+mod telemetry;
+mod telemetry_resource;
+mod telemetry_span;
+mod watch;
+
+pub use client::{Client, EnvBuilder, EnvRenderer, Format, Item, ItemRef, Rendered, SkippedField};
+pub use config::Config;
+pub use error::{kind as error_kind, Candidate, Error, Kind as ErrorKind};
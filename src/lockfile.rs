@@ -0,0 +1,147 @@
+//! `.opz.lock`: the exact item (id and vault id) each title resolved to when `opz lock` ran,
+//! with the item's version and update time. Runs look locked titles up by id, so a rename or a
+//! new item with the same title cannot change what a release build reads, and `--frozen`
+//! fails instead of warning when a locked item changed since.
+//!
+//! The file is TOML, kept next to the project's `.opz.toml` (or in the current directory if
+//! there is none) and meant to be committed. It holds ids only, never fields or values.
+
+use crate::config;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const LOCK_FILE: &str = ".opz.lock";
+
+const HEADER: &str = "# Written by `opz lock`; commit it to pin the items runs resolve.\n\n";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Lock {
+    #[serde(default, rename = "item")]
+    pub items: Vec<LockedItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LockedItem {
+    /// The ITEM argument as runs pass it.
+    pub title: String,
+    pub id: String,
+    pub vault_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+}
+
+impl Lock {
+    pub fn get(&self, title: &str) -> Option<&LockedItem> {
+        self.items.iter().find(|item| item.title == title)
+    }
+
+    /// Add `item`, replacing an earlier pin of the same title; entries stay sorted by title.
+    pub fn pin(&mut self, item: LockedItem) {
+        self.items.retain(|locked| locked.title != item.title);
+        self.items.push(item);
+        self.items.sort_by(|a, b| a.title.cmp(&b.title));
+    }
+}
+
+impl LockedItem {
+    /// How the item differs from this pin, if it does. The version decides when both sides
+    /// have one; otherwise the update time does.
+    pub fn change(&self, version: Option<u64>, updated_at: Option<&str>) -> Option<String> {
+        if let (Some(pinned), Some(current)) = (self.version, version) {
+            return (pinned != current).then(|| format!("version {pinned} -> {current}"));
+        }
+        match (self.updated_at.as_deref(), updated_at) {
+            (Some(pinned), Some(current)) if pinned != current => {
+                Some(format!("updated {current}, locked at {pinned}"))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The lockfile for a project run from `cwd`: next to the nearest `.opz.toml`, else in `cwd`.
+pub fn path(cwd: &Path) -> PathBuf {
+    config::find_project_config(cwd)
+        .and_then(|config| config.parent().map(|dir| dir.join(LOCK_FILE)))
+        .unwrap_or_else(|| cwd.join(LOCK_FILE))
+}
+
+/// The lock at `path`, or `None` if there is no lockfile.
+pub fn load(path: &Path) -> Result<Option<Lock>> {
+    match fs::read_to_string(path) {
+        Ok(content) => toml::from_str(&content)
+            .map(Some)
+            .with_context(|| format!("parse {}", path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("read {}", path.display())),
+    }
+}
+
+pub fn write(path: &Path, lock: &Lock) -> Result<()> {
+    let content = format!("{HEADER}{}", toml::to_string(lock)?);
+    fs::write(path, content).with_context(|| format!("write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn locked(title: &str, version: Option<u64>, updated_at: Option<&str>) -> LockedItem {
+        LockedItem {
+            title: title.to_string(),
+            id: format!("{title}-id"),
+            vault_id: "vault-id".to_string(),
+            version,
+            updated_at: updated_at.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_write_and_load_roundtrip() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join(LOCK_FILE);
+        assert_eq!(load(&path).unwrap(), None);
+
+        let mut lock = Lock::default();
+        lock.pin(locked("web", Some(3), Some("2026-10-01T00:00:00Z")));
+        lock.pin(locked("api", None, None));
+        lock.pin(locked("web", Some(4), Some("2026-10-02T00:00:00Z")));
+        write(&path, &lock).unwrap();
+
+        let loaded = load(&path).unwrap().unwrap();
+        assert_eq!(loaded, lock);
+        let titles: Vec<&str> = loaded.items.iter().map(|i| i.title.as_str()).collect();
+        assert_eq!(titles, vec!["api", "web"]);
+        assert_eq!(loaded.get("web").unwrap().version, Some(4));
+    }
+
+    #[test]
+    fn test_path_follows_the_project_config() {
+        let tmp_dir = TempDir::new().unwrap();
+        let nested = tmp_dir.path().join("app/src");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(path(&nested), nested.join(LOCK_FILE));
+
+        fs::write(tmp_dir.path().join(config::PROJECT_CONFIG_FILE), "").unwrap();
+        assert_eq!(path(&nested), tmp_dir.path().join(LOCK_FILE));
+    }
+
+    #[test]
+    fn test_change() {
+        let pin = locked("web", Some(3), Some("2026-10-01T00:00:00Z"));
+        assert_eq!(pin.change(Some(3), Some("2026-10-05T00:00:00Z")), None);
+        assert_eq!(pin.change(Some(4), None).as_deref(), Some("version 3 -> 4"));
+        assert_eq!(
+            pin.change(None, Some("2026-10-05T00:00:00Z")).as_deref(),
+            Some("updated 2026-10-05T00:00:00Z, locked at 2026-10-01T00:00:00Z")
+        );
+        assert_eq!(pin.change(None, None), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! Diagnostic logging on stderr via `tracing`, sized by `-v`/`--quiet` or `$OPZ_LOG`.
+//!
+//! Warnings keep their familiar `Warning: ...` form; debug output is prefixed with its level
+//! so it stands apart from command output. When OTLP export is on, opz's own warnings and
+//! errors are also exported as log records, whatever the stderr verbosity.
+
+use crate::telemetry;
+use anyhow::{Context, Result};
+use std::fmt::{self, Write as _};
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::{format::Writer, FmtContext, FormatEvent, FormatFields},
+    layer::{self, SubscriberExt},
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+/// Environment variable holding an `EnvFilter` directive (e.g. `debug`, `opz=trace`).
+pub const LOG_ENV: &str = "OPZ_LOG";
+
+/// Install the stderr logger. `$OPZ_LOG`, when set, overrides the flags.
+pub fn init(verbose: u8, quiet: bool) -> Result<()> {
+    let filter = match std::env::var(LOG_ENV) {
+        Ok(directives) if !directives.trim().is_empty() => EnvFilter::try_new(&directives)
+            .with_context(|| format!("invalid {LOG_ENV} filter '{directives}'"))?,
+        _ => EnvFilter::new(default_directives(verbose, quiet)),
+    };
+    let stderr = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .event_format(Format)
+        .with_filter(filter);
+    let export = telemetry::logs_enabled().then(|| ExportLayer.with_filter(LevelFilter::WARN));
+    // A second call (tests, nested runs) keeps the first logger.
+    let _ = tracing_subscriber::registry()
+        .with(stderr)
+        .with(export)
+        .try_init();
+    Ok(())
+}
+
+/// Hands opz's events to the OTLP log exporter. Other targets are left out, which also keeps
+/// the exporter's own failure reports from being exported in a loop.
+struct ExportLayer;
+
+impl<S: Subscriber> Layer<S> for ExportLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: layer::Context<'_, S>) {
+        let target = event.metadata().target();
+        if target != "opz" && !target.starts_with("opz::") {
+            return;
+        }
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        telemetry::emit_log(*event.metadata().level(), target, &message.0);
+    }
+}
+
+/// The event's message followed by its other fields as `name=value`.
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let fields = std::mem::take(&mut self.0);
+            let _ = write!(self.0, "{value:?}{fields}");
+        } else {
+            let _ = write!(self.0, " {}={value:?}", field.name());
+        }
+    }
+}
+
+/// Dependencies stay at `warn` so `-vv` shows opz's own decisions, not HTTP internals. The
+/// OpenTelemetry SDK reports failed exports as errors, which is noise whenever the collector is
+/// down, so it stays quiet unless `$OPZ_LOG` asks for it.
+fn default_directives(verbose: u8, quiet: bool) -> String {
+    if quiet {
+        return "error,opentelemetry_sdk=off".to_string();
+    }
+    let level = match verbose {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    };
+    format!("warn,opentelemetry_sdk=off,opz={level}")
+}
+
+/// Render `op` arguments for logs: values after `=` (field assignments, `token=...`) and
+/// `op://` references are masked.
+pub fn sanitize_op_args<S: AsRef<std::ffi::OsStr>>(args: impl IntoIterator<Item = S>) -> String {
+    let joined = args
+        .into_iter()
+        .map(|arg| {
+            let arg = arg.as_ref().to_string_lossy();
+            match arg.split_once('=') {
+                Some((key, _)) if !key.starts_with('-') => format!("{key}=***"),
+                _ => arg.into_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    crate::telemetry_span::sanitize_for_trace(&joined)
+}
+
+struct Format;
+
+impl<S, N> FormatEvent<S, N> for Format
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        match *event.metadata().level() {
+            Level::ERROR => write!(writer, "Error: ")?,
+            Level::WARN => write!(writer, "Warning: ")?,
+            level => write!(writer, "[{}] ", level.as_str().to_ascii_lowercase())?,
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_directives() {
+        assert_eq!(
+            default_directives(0, false),
+            "warn,opentelemetry_sdk=off,opz=warn"
+        );
+        assert_eq!(
+            default_directives(2, false),
+            "warn,opentelemetry_sdk=off,opz=debug"
+        );
+        assert_eq!(
+            default_directives(5, false),
+            "warn,opentelemetry_sdk=off,opz=trace"
+        );
+        assert_eq!(default_directives(3, true), "error,opentelemetry_sdk=off");
+    }
+
+    #[test]
+    fn test_sanitize_op_args_masks_values() {
+        assert_eq!(
+            sanitize_op_args([
+                "item",
+                "create",
+                "--vault=Team",
+                "API_TOKEN[password]=s3cret",
+                "op://Team/demo/API_TOKEN",
+            ]),
+            "item create --vault=Team API_TOKEN[password]=*** op://***"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! `--mask-output`: copy a command's stdout/stderr while replacing secret values with `***`.
+//! Matching works on the byte stream, so values split across reads and multi-line values are
+//! caught the same way as short tokens.
+
+use std::io::{self, Read, Write};
+
+const MASK: &[u8] = b"***";
+
+/// Lines of a multi-line value shorter than this are not masked on their own: short lines
+/// (`}`, `"type": 1,`, a trailing base64 `==`) would mask unrelated output.
+const MIN_LINE_LEN: usize = 8;
+
+pub struct Masker {
+    /// Longest first, so the longest value wins where several match.
+    secrets: Vec<Vec<u8>>,
+}
+
+impl Masker {
+    /// Mask each of `values`, and each line of a multi-line value on its own, since commands
+    /// often print such values (keys, certificates) a line at a time. Short lines and PEM
+    /// armor (`-----BEGIN ...-----`) are public in practice and only masked as part of the
+    /// whole value.
+    pub fn new<'a>(values: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut secrets: Vec<Vec<u8>> = Vec::new();
+        for value in values {
+            secrets.push(value.as_bytes().to_vec());
+            if value.contains('\n') {
+                secrets.extend(
+                    value
+                        .lines()
+                        .map(str::trim)
+                        .filter(|line| line.len() >= MIN_LINE_LEN && !is_pem_armor(line))
+                        .map(|line| line.as_bytes().to_vec()),
+                );
+            }
+        }
+        secrets.retain(|secret| !secret.is_empty());
+        secrets.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        secrets.dedup();
+        Masker { secrets }
+    }
+
+    /// Mask what can be decided from `pending` and return it. A tail that could still grow
+    /// into a secret stays in `pending` until more input (or `eof`) settles it.
+    fn drain(&self, pending: &mut Vec<u8>, eof: bool) -> Vec<u8> {
+        let mut out = Vec::with_capacity(pending.len());
+        let mut i = 0;
+        'scan: while i < pending.len() {
+            let rest = &pending[i..];
+            if !eof
+                && self
+                    .secrets
+                    .iter()
+                    .any(|secret| secret.len() > rest.len() && secret.starts_with(rest))
+            {
+                break;
+            }
+            for secret in &self.secrets {
+                if rest.starts_with(secret) {
+                    out.extend_from_slice(MASK);
+                    i += secret.len();
+                    continue 'scan;
+                }
+            }
+            out.push(pending[i]);
+            i += 1;
+        }
+        pending.drain(..i);
+        out
+    }
+}
+
+fn is_pem_armor(line: &str) -> bool {
+    line.starts_with("-----") && line.ends_with("-----")
+}
+
+/// Copy `reader` to `writer` until EOF, masking secrets and flushing after every read so
+/// interactive output is not held back.
+pub fn pump(mut reader: impl Read, mut writer: impl Write, masker: &Masker) -> io::Result<()> {
+    let mut pending = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        pending.extend_from_slice(&buf[..n]);
+        writer.write_all(&masker.drain(&mut pending, false))?;
+        writer.flush()?;
+    }
+    writer.write_all(&masker.drain(&mut pending, true))?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds the input in `chunk`-byte reads.
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.chunk.min(self.data.len()).min(buf.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn masked(masker: &Masker, input: &str, chunk: usize) -> String {
+        let mut out = Vec::new();
+        pump(
+            Chunked {
+                data: input.as_bytes(),
+                chunk,
+            },
+            &mut out,
+            masker,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_masks_values_split_across_reads() {
+        let masker = Masker::new(["s3cret", "s3cret-admin", ""]);
+        for chunk in [1, 2, 5, 8192] {
+            assert_eq!(
+                masked(&masker, "token=s3cret admin=s3cret-admin s3cre", chunk),
+                "token=*** admin=*** s3cre"
+            );
+        }
+    }
+
+    #[test]
+    fn test_masks_multiline_values_whole_and_by_line() {
+        let key = "-----BEGIN KEY-----\nAAAABBBBCCCC\nDD==\n-----END KEY-----";
+        let masker = Masker::new([key]);
+        assert_eq!(masked(&masker, &format!("key:\n{key}\n"), 3), "key:\n***\n");
+        assert_eq!(
+            masked(&masker, "line AAAABBBBCCCC only", 3),
+            "line *** only"
+        );
+        // Armor and short lines are not secrets on their own.
+        assert_eq!(
+            masked(&masker, "-----BEGIN KEY-----\nDD==\n", 3),
+            "-----BEGIN KEY-----\nDD==\n"
+        );
+    }
+}
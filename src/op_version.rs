@@ -0,0 +1,80 @@
+//! The installed `op` CLI's version, so opz can refuse a CLI it cannot drive with a clear
+//! upgrade message instead of failing later on output it cannot parse.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Oldest `op` opz works with: 1.x has a different command layout (`op list items`, ...).
+pub const MINIMUM: Version = Version::new(2, 0, 0);
+
+const UPGRADE_URL: &str = "https://developer.1password.com/docs/cli/get-started/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Version {
+    major: u32,
+    minor: u32,
+    patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse `op --version` output such as `2.30.0` or `2.31.0-beta.01`.
+    pub fn parse(output: &str) -> Option<Self> {
+        let core = output.trim().trim_start_matches('v');
+        let core = core.split(['-', '+']).next()?;
+        let mut parts = core.split('.').map(|part| part.parse::<u32>().ok());
+        Some(Version::new(
+            parts.next()??,
+            parts.next().flatten().unwrap_or(0),
+            parts.next().flatten().unwrap_or(0),
+        ))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Fail with an upgrade hint if `version` is older than [`MINIMUM`].
+pub fn check(version: Version) -> Result<()> {
+    if version < MINIMUM {
+        return Err(anyhow!(
+            "1Password CLI {version} is too old: opz needs `op` {MINIMUM} or newer. Upgrade it: {UPGRADE_URL}"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Version::parse("2.30.0\n"), Some(Version::new(2, 30, 0)));
+        assert_eq!(
+            Version::parse("2.31.0-beta.01"),
+            Some(Version::new(2, 31, 0))
+        );
+        assert_eq!(Version::parse("v1.12"), Some(Version::new(1, 12, 0)));
+        assert_eq!(Version::parse("unknown command"), None);
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check(Version::new(2, 0, 0)).is_ok());
+        assert!(check(Version::new(2, 30, 0)).is_ok());
+        let err = check(Version::new(1, 12, 4)).unwrap_err().to_string();
+        assert!(err.contains("1Password CLI 1.12.4 is too old"), "{err}");
+    }
+}
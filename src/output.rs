@@ -0,0 +1,395 @@
+//! Destinations for generated output. Formats render text; an [`OutputSink`] decides where the
+//! text goes, so every format works with every destination and file handling lives here once.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputSink {
+    /// Replace the file at this path.
+    File(PathBuf),
+    /// Print to stdout.
+    Stdout,
+    /// Write to a file descriptor inherited from the caller (`opz gen --fd 3 ... 3>file`),
+    /// closing it afterwards so a pipe reader sees EOF.
+    Fd(i32),
+}
+
+impl OutputSink {
+    /// `fd` wins over `path`, which may come from config defaults; neither means stdout.
+    pub fn new(path: Option<&Path>, fd: Option<i32>) -> Self {
+        match (fd, path) {
+            (Some(fd), _) => OutputSink::Fd(fd),
+            (None, Some(path)) => OutputSink::File(path.to_path_buf()),
+            (None, None) => OutputSink::Stdout,
+        }
+    }
+
+    /// Telemetry label for the kind of destination.
+    pub fn mode(&self) -> &'static str {
+        match self {
+            OutputSink::File(_) => "file",
+            OutputSink::Stdout => "stdout",
+            OutputSink::Fd(_) => "fd",
+        }
+    }
+
+    /// Where the output went, for telemetry and messages.
+    pub fn describe(&self) -> String {
+        match self {
+            OutputSink::File(path) => path.display().to_string(),
+            OutputSink::Stdout => "-".to_string(),
+            OutputSink::Fd(fd) => format!("fd {fd}"),
+        }
+    }
+
+    pub fn write(&self, text: &str) -> Result<()> {
+        match self {
+            OutputSink::File(path) => write_file(path, text),
+            OutputSink::Stdout => write_stdout(text),
+            OutputSink::Fd(fd) => write_fd(*fd, text),
+        }
+    }
+}
+
+/// Where per-run files go: `$XDG_RUNTIME_DIR` (per-user and usually in memory), falling back
+/// to the system temp directory.
+pub fn runtime_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_dir())
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+/// Replace the contents of `path` with `text`: write an owner-only (0600) temp file next to
+/// it, sync it, and rename it into place, so other users can never read the file and readers
+/// never see a partial write.
+pub fn write_file(path: &Path, text: &str) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut tmp = private_temp_file(dir)?;
+    tmp.write_all(text.as_bytes())
+        .and_then(|()| tmp.as_file().sync_all())
+        .with_context(|| format!("write {}", path.display()))?;
+    // Some filesystems (network mounts, FAT) ignore the requested mode.
+    if world_readable(tmp.path())? {
+        bail!(
+            "Refusing to write {}: files in {} are readable by every user on this filesystem",
+            path.display(),
+            dir.display()
+        );
+    }
+    tmp.persist(path)
+        .with_context(|| format!("write {}", path.display()))?;
+    Ok(())
+}
+
+/// Put `text` back at `path` with `permissions` (those of the file it replaced), through a
+/// temp file and a rename like [`write_file`], so an interrupted restore never leaves a
+/// half-written file.
+pub fn restore_file(path: &Path, text: &str, permissions: Option<fs::Permissions>) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut tmp = private_temp_file(dir)?;
+    tmp.write_all(text.as_bytes())
+        .and_then(|()| tmp.as_file().sync_all())
+        .with_context(|| format!("restore {}", path.display()))?;
+    if let Some(permissions) = permissions {
+        tmp.as_file()
+            .set_permissions(permissions)
+            .with_context(|| format!("restore {}", path.display()))?;
+    }
+    tmp.persist(path)
+        .with_context(|| format!("restore {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn private_temp_file(dir: &Path) -> Result<tempfile::NamedTempFile> {
+    use std::os::unix::fs::PermissionsExt;
+
+    tempfile::Builder::new()
+        .prefix(".opz-")
+        .permissions(fs::Permissions::from_mode(0o600))
+        .tempfile_in(dir)
+        .with_context(|| format!("create temp file in {}", dir.display()))
+}
+
+#[cfg(not(unix))]
+fn private_temp_file(dir: &Path) -> Result<tempfile::NamedTempFile> {
+    tempfile::Builder::new()
+        .prefix(".opz-")
+        .tempfile_in(dir)
+        .with_context(|| format!("create temp file in {}", dir.display()))
+}
+
+#[cfg(unix)]
+fn world_readable(path: &Path) -> Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)
+        .with_context(|| format!("inspect {}", path.display()))?
+        .permissions()
+        .mode();
+    Ok(mode & 0o004 != 0)
+}
+
+#[cfg(not(unix))]
+fn world_readable(_path: &Path) -> Result<bool> {
+    Ok(false)
+}
+
+/// Delete a generated file, overwriting its contents with zeros and syncing them first so
+/// they are not trivially recoverable from the freed blocks. Best effort: copy-on-write and
+/// flash storage may keep old copies regardless.
+pub fn remove_file(path: &Path) -> std::io::Result<()> {
+    let _ = wipe(path);
+    fs::remove_file(path)
+}
+
+/// Overwrite `path` in place with zeros and sync. Symlinks and files with other hard links are
+/// left alone, since their contents belong to someone else too.
+pub fn wipe(path: &Path) -> std::io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_file() || has_other_links(&metadata) {
+        return Ok(());
+    }
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    let zeros = [0u8; 8192];
+    let mut left = metadata.len();
+    while left > 0 {
+        let chunk = left.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..chunk])?;
+        left -= chunk as u64;
+    }
+    file.sync_all()
+}
+
+#[cfg(unix)]
+fn has_other_links(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn has_other_links(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Write command output to stdout, surfacing a closed pipe as an error (see
+/// `is_broken_pipe`) instead of the panic `print!` raises.
+pub fn write_stdout(text: &str) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    out.write_all(text.as_bytes())?;
+    out.flush()?;
+    Ok(())
+}
+
+/// `--fd` descriptors taken over by [`claim_fd`]: the number the caller passed and a private
+/// duplicate, or `None` if nothing was open under that number at startup.
+#[cfg(unix)]
+static CLAIMED_FDS: std::sync::Mutex<Vec<(i32, Option<std::os::fd::OwnedFd>)>> =
+    std::sync::Mutex::new(Vec::new());
+
+/// Take over `fd` before opz opens anything: if the caller never opened it, the first file opz
+/// opens would get that number and `--fd` would write into it. The open descriptor moves to a
+/// close-on-exec duplicate, so `op` and the command never inherit it. 0-2 are opz's own stdio
+/// and are left where they are.
+#[cfg(unix)]
+pub fn claim_fd(fd: i32) {
+    use std::os::fd::FromRawFd;
+
+    if fd <= 2 {
+        return;
+    }
+    // SAFETY: F_DUPFD_CLOEXEC only adds a descriptor; it fails if `fd` is not open.
+    let duplicate = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 3) };
+    let claimed = (duplicate != -1).then(|| {
+        // SAFETY: `duplicate` was just created and is owned here alone; the caller handed
+        // `fd` to opz for this output, so nothing else in the process uses it.
+        unsafe {
+            libc::close(fd);
+            std::os::fd::OwnedFd::from_raw_fd(duplicate)
+        }
+    });
+    CLAIMED_FDS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push((fd, claimed));
+}
+
+#[cfg(not(unix))]
+pub fn claim_fd(_fd: i32) {}
+
+#[cfg(unix)]
+fn write_fd(fd: i32, text: &str) -> Result<()> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    let claimed = {
+        let mut claimed = CLAIMED_FDS.lock().unwrap_or_else(|err| err.into_inner());
+        claimed
+            .iter()
+            .position(|(number, _)| *number == fd)
+            .map(|index| claimed.remove(index).1)
+    };
+    let owned: OwnedFd = match claimed {
+        Some(Some(owned)) => owned,
+        Some(None) => return Err(anyhow!("file descriptor {fd} is not open")),
+        // Not claimed at startup (library use): check it now.
+        None => {
+            // SAFETY: fcntl(2) with F_GETFD only inspects the descriptor table.
+            if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+                return Err(anyhow!("file descriptor {fd} is not open"));
+            }
+            // SAFETY: the descriptor is open, and the caller handed it to opz for this output
+            // only; nothing else in the process uses it.
+            unsafe { OwnedFd::from_raw_fd(fd) }
+        }
+    };
+    let mut file = fs::File::from(owned);
+    file.write_all(text.as_bytes())
+        .with_context(|| format!("write to file descriptor {fd}"))
+}
+
+#[cfg(not(unix))]
+fn write_fd(fd: i32, _text: &str) -> Result<()> {
+    Err(anyhow!(
+        "writing to file descriptor {fd} is only supported on Unix"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sink_selection() {
+        let path = Path::new(".env");
+        assert_eq!(
+            OutputSink::new(Some(path), None),
+            OutputSink::File(path.to_path_buf())
+        );
+        assert_eq!(OutputSink::new(Some(path), Some(3)), OutputSink::Fd(3));
+        assert_eq!(OutputSink::new(None, None), OutputSink::Stdout);
+        assert_eq!(OutputSink::Fd(3).describe(), "fd 3");
+    }
+
+    #[test]
+    fn test_file_sink_replaces_content() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("out.nix");
+        fs::write(&path, "old\n").unwrap();
+        let sink = OutputSink::File(path.clone());
+        sink.write("{\n}\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\n}\n");
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join(".env");
+        fs::write(&path, "OLD=1\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_file(&path, "NEW=op://v/i/f\n").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "NEW=op://v/i/f\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore_file_keeps_the_original_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join(".env");
+        fs::write(&path, "LOCAL=1\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let permissions = fs::metadata(&path).unwrap().permissions();
+
+        write_file(&path, "NEW=op://v/i/f\n").unwrap();
+        restore_file(&path, "LOCAL=1\n", Some(permissions)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "LOCAL=1\n");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_remove_file_scrubs_before_unlinking() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join(".env");
+        fs::write(&path, "API_TOKEN=s3cret\n").unwrap();
+        wipe(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![0u8; 17]);
+
+        remove_file(&path).unwrap();
+        assert!(!path.exists());
+        assert!(remove_file(&path).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wipe_leaves_hard_links_alone() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join(".env");
+        let other = tmp.path().join("backup.env");
+        fs::write(&path, "KEEP=1\n").unwrap();
+        fs::hard_link(&path, &other).unwrap();
+
+        remove_file(&path).unwrap();
+        assert_eq!(fs::read_to_string(&other).unwrap(), "KEEP=1\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fd_sink_rejects_closed_descriptor() {
+        let err = OutputSink::Fd(987).write("x").unwrap_err();
+        assert_eq!(err.to_string(), "file descriptor 987 is not open");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_claimed_fd_is_not_confused_with_a_later_file() {
+        use std::os::fd::AsRawFd;
+
+        // Claimed while closed: a file opened under the number later is never written to.
+        claim_fd(986);
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("later");
+        let later = fs::File::create(&path).unwrap();
+        // SAFETY: dup2 onto an unused number; `later` keeps its own descriptor.
+        assert_eq!(unsafe { libc::dup2(later.as_raw_fd(), 986) }, 986);
+        let err = OutputSink::Fd(986).write("x").unwrap_err();
+        assert_eq!(err.to_string(), "file descriptor 986 is not open");
+        // SAFETY: 986 was duplicated above and is not used elsewhere.
+        unsafe { libc::close(986) };
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+        // Claimed while open: the output goes to the original file even if the number moves on.
+        let out = tmp.path().join("out");
+        let file = fs::File::create(&out).unwrap();
+        // SAFETY: as above.
+        assert_eq!(unsafe { libc::dup2(file.as_raw_fd(), 985) }, 985);
+        claim_fd(985);
+        // SAFETY: fcntl(2) with F_GETFD only inspects the descriptor table.
+        assert_eq!(unsafe { libc::fcntl(985, libc::F_GETFD) }, -1);
+        OutputSink::Fd(985).write("A=op://v/i/f\n").unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), "A=op://v/i/f\n");
+    }
+}
@@ -0,0 +1,74 @@
+//! Bounded fan-out for independent `op` lookups (several items, vaults or accounts), so their
+//! latencies overlap instead of adding up while `op` and the 1Password app are not flooded.
+
+use opentelemetry::Context;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+
+/// Most lookups in flight at once.
+pub const MAX_WORKERS: usize = 4;
+
+/// `inputs.iter().map(f)`, run on up to [`MAX_WORKERS`] threads. Results keep the order of
+/// `inputs`, and spans started by `f` stay children of the caller's span.
+pub fn map<'a, T, R, F>(inputs: &'a [T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&'a T) -> R + Sync,
+{
+    let workers = MAX_WORKERS.min(inputs.len());
+    if workers <= 1 {
+        return inputs.iter().map(f).collect();
+    }
+    let cx = Context::current();
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(inputs.iter().map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                let _guard = cx.clone().attach();
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(input) = inputs.get(index) else {
+                        break;
+                    };
+                    let result = f(input);
+                    results
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())[index] = Some(result);
+                }
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .into_iter()
+        .map(|result| result.expect("every input is mapped"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_map_keeps_order_and_bounds_workers() {
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let inputs: Vec<u64> = (0..10).collect();
+        let doubled = map(&inputs, |n| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(10 - n));
+            running.fetch_sub(1, Ordering::SeqCst);
+            n * 2
+        });
+        assert_eq!(doubled, (0..10).map(|n| n * 2).collect::<Vec<_>>());
+        assert!(peak.load(Ordering::SeqCst) <= MAX_WORKERS);
+        assert!(peak.load(Ordering::SeqCst) > 1);
+    }
+}
@@ -0,0 +1,139 @@
+//! History of the items `opz run` used, by title and id only (never fields or values), so
+//! `opz recent` can list them and `opz --last -- <cmd>` can reuse the previous run's items
+//! without retyping long titles.
+
+use crate::cache_file;
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How many distinct items are remembered.
+const MAX_ENTRIES: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub title: String,
+    pub id: String,
+    pub used_at_unix: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct State {
+    /// Most recently used first, one entry per item id.
+    #[serde(default)]
+    items: Vec<Entry>,
+    /// Titles of the items the last run used, in the order it was given them.
+    #[serde(default)]
+    last: Vec<String>,
+}
+
+pub fn state_path() -> Option<PathBuf> {
+    ProjectDirs::from("dev", "opz", "opz").map(|proj| proj.data_dir().join("recent.json"))
+}
+
+/// Remember that a run just used `items` (title and id pairs).
+pub fn record(items: &[(String, String)]) -> Result<()> {
+    let Some(state_path) = state_path() else {
+        return Ok(());
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    update(&state_path, |state| apply(state, items, now))
+}
+
+/// Recently used items, most recent first.
+pub fn entries() -> Result<Vec<Entry>> {
+    let Some(state_path) = state_path() else {
+        return Ok(Vec::new());
+    };
+    Ok(read_state(&state_path)?.items)
+}
+
+/// Titles of the items the previous run used.
+pub fn last() -> Result<Vec<String>> {
+    let Some(state_path) = state_path() else {
+        return Ok(Vec::new());
+    };
+    Ok(read_state(&state_path)?.last)
+}
+
+fn apply(state: &mut State, items: &[(String, String)], now: u64) {
+    for (title, id) in items.iter().rev() {
+        state.items.retain(|entry| entry.id != *id);
+        state.items.insert(
+            0,
+            Entry {
+                title: title.clone(),
+                id: id.clone(),
+                used_at_unix: now,
+            },
+        );
+    }
+    state.items.truncate(MAX_ENTRIES);
+    state.last = items.iter().map(|(title, _)| title.clone()).collect();
+}
+
+fn update(state_path: &Path, change: impl FnOnce(&mut State)) -> Result<()> {
+    let _lock = cache_file::Lock::acquire(state_path)?;
+    let mut state = read_state(state_path)?;
+    change(&mut state);
+    cache_file::write_atomic(state_path, &serde_json::to_vec_pretty(&state)?)
+}
+
+fn read_state(state_path: &Path) -> Result<State> {
+    match fs::read(state_path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .with_context(|| format!("parse {}", state_path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+        Err(err) => Err(err).with_context(|| format!("read {}", state_path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn used(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items
+            .iter()
+            .map(|(title, id)| (title.to_string(), id.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_apply_moves_used_items_to_the_front() {
+        let mut state = State::default();
+        apply(&mut state, &used(&[("db", "id1"), ("api", "id2")]), 10);
+        apply(&mut state, &used(&[("db renamed", "id1")]), 20);
+        let titles: Vec<&str> = state.items.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, vec!["db renamed", "api"]);
+        assert_eq!(state.items[0].used_at_unix, 20);
+        assert_eq!(state.last, vec!["db renamed"]);
+
+        apply(&mut state, &used(&[("db", "id1"), ("api", "id2")]), 30);
+        let titles: Vec<&str> = state.items.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, vec!["db", "api"]);
+        assert_eq!(state.last, vec!["db", "api"]);
+    }
+
+    #[test]
+    fn test_apply_keeps_a_bounded_history() {
+        let mut state = State::default();
+        for n in 0..MAX_ENTRIES + 5 {
+            apply(
+                &mut state,
+                &used(&[(&format!("item-{n}"), &format!("id{n}"))]),
+                0,
+            );
+        }
+        assert_eq!(state.items.len(), MAX_ENTRIES);
+        assert_eq!(state.items[0].id, format!("id{}", MAX_ENTRIES + 4));
+    }
+}
@@ -0,0 +1,221 @@
+//! A private working directory per `opz run` invocation, named after its run id. Everything a
+//! run writes outside the user's `--env-file` (the `op run` batch file, the `--temp-env` file)
+//! lives there next to a manifest, so parallel runs never share a path and cleanup is
+//! removing one directory. Directories of runs that died are found by `opz gc`.
+
+use crate::output;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const DIR_PREFIX: &str = "opz-run-";
+const MANIFEST_FILE: &str = "manifest.json";
+
+static NEXT_SEQ: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub run_id: String,
+    /// The opz process that owns the directory; it is stale once that process is gone.
+    pub pid: u32,
+    pub started_at_unix: u64,
+    pub items: Vec<String>,
+    /// Files created in the directory, by name.
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct RunDir {
+    path: PathBuf,
+    manifest: Manifest,
+}
+
+impl RunDir {
+    /// Create an owner-only (0700) run directory under `$XDG_RUNTIME_DIR` (or the system temp
+    /// directory) and write its manifest.
+    pub fn create(items: &[String]) -> Result<Self> {
+        Self::create_in(&output::runtime_dir(), items)
+    }
+
+    fn create_in(base: &Path, items: &[String]) -> Result<Self> {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let pid = std::process::id();
+        // Workspace runs create several directories in one process.
+        let seq = NEXT_SEQ.fetch_add(1, Ordering::Relaxed);
+        let run_id = format!("{:x}-{pid}-{seq}", started.as_millis());
+        let path = base.join(format!("{DIR_PREFIX}{run_id}"));
+        create_private_dir(&path)?;
+        let run_dir = RunDir {
+            path,
+            manifest: Manifest {
+                run_id,
+                pid,
+                started_at_unix: started.as_secs(),
+                items: items.to_vec(),
+                files: Vec::new(),
+            },
+        };
+        run_dir.write_manifest()?;
+        Ok(run_dir)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.manifest.run_id
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path for a file named `name` in the directory, recorded in the manifest.
+    pub fn file(&mut self, name: &str) -> Result<PathBuf> {
+        if !self.manifest.files.iter().any(|file| file == name) {
+            self.manifest.files.push(name.to_string());
+            self.write_manifest()?;
+        }
+        Ok(self.path.join(name))
+    }
+
+    fn write_manifest(&self) -> Result<()> {
+        output::write_file(
+            &self.path.join(MANIFEST_FILE),
+            &serde_json::to_string_pretty(&self.manifest)?,
+        )
+    }
+}
+
+/// Remove a run directory, scrubbing its files first (see [`output::remove_file`]).
+pub fn remove(path: &Path) -> Result<()> {
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let _ = output::remove_file(&entry.path());
+        }
+    }
+    match fs::remove_dir_all(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Remove the directories of runs whose process is gone, returning them. With `dry_run`
+/// nothing is removed.
+pub fn collect(dry_run: bool) -> Result<Vec<PathBuf>> {
+    collect_in(
+        &output::runtime_dir(),
+        dry_run,
+        crate::generated::process_alive,
+    )
+}
+
+fn collect_in(base: &Path, dry_run: bool, alive: impl Fn(u32) -> bool) -> Result<Vec<PathBuf>> {
+    let Ok(entries) = fs::read_dir(base) else {
+        return Ok(Vec::new());
+    };
+    let mut stale = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_run_dir = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(DIR_PREFIX));
+        if !is_run_dir {
+            continue;
+        }
+        // Directories without a readable manifest may belong to a run that is still starting.
+        let Some(manifest) = fs::read(path.join(MANIFEST_FILE))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Manifest>(&bytes).ok())
+        else {
+            continue;
+        };
+        if alive(manifest.pid) {
+            continue;
+        }
+        if !dry_run {
+            remove(&path)?;
+        }
+        stale.push(path);
+    }
+    stale.sort();
+    Ok(stale)
+}
+
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(path)
+        .with_context(|| format!("create run directory {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(path: &Path) -> Result<()> {
+    fs::create_dir(path).with_context(|| format!("create run directory {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_dir_is_private_and_listed_in_manifest() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let base = TempDir::new().unwrap();
+        let mut run_dir = RunDir::create_in(base.path(), &["demo".to_string()]).unwrap();
+        let mode = fs::metadata(run_dir.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        let env_file = run_dir.file(".env").unwrap();
+        assert_eq!(env_file, run_dir.path().join(".env"));
+        let manifest: Manifest =
+            serde_json::from_slice(&fs::read(run_dir.path().join(MANIFEST_FILE)).unwrap()).unwrap();
+        assert_eq!(manifest.run_id, run_dir.id());
+        assert_eq!(manifest.items, vec!["demo"]);
+        assert_eq!(manifest.files, vec![".env"]);
+
+        fs::write(&env_file, "A=op://v/i/a\n").unwrap();
+        remove(run_dir.path()).unwrap();
+        assert!(!run_dir.path().exists());
+    }
+
+    #[test]
+    fn test_collect_removes_only_dead_runs() {
+        let base = TempDir::new().unwrap();
+        let live = RunDir::create_in(base.path(), &[]).unwrap();
+        let dead = RunDir {
+            path: base.path().join(format!("{DIR_PREFIX}dead")),
+            manifest: Manifest {
+                pid: 0,
+                ..live.manifest.clone()
+            },
+        };
+        create_private_dir(&dead.path).unwrap();
+        dead.write_manifest().unwrap();
+        fs::create_dir(base.path().join("unrelated")).unwrap();
+
+        let alive = |pid: u32| pid != 0;
+        assert_eq!(
+            collect_in(base.path(), true, alive).unwrap(),
+            vec![dead.path.clone()]
+        );
+        assert!(dead.path.exists());
+        collect_in(base.path(), false, alive).unwrap();
+        assert!(!dead.path.exists());
+        assert!(live.path().exists());
+    }
+}
@@ -0,0 +1,54 @@
+//! The Tokio runtime shared by opz's leaf I/O: `op` subprocesses, Connect API requests, the
+//! daemon's tasks and the OTLP exporter. The CLI itself stays synchronous and drives async code
+//! through [`block_on`]; the runtime only starts once something needs it.
+//!
+//! The cache layer (`item_list_cached`, `item_get_cached`) is still synchronous, fan-out across
+//! items, vaults and accounts runs on the threads of [`crate::parallel::map`], and background
+//! refresh is a detached `opz cache warm` process, since it has to outlive the command.
+
+use anyhow::{Context, Result};
+use std::{future::Future, sync::OnceLock};
+use tokio::runtime::{Handle, Runtime};
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// The shared runtime, started on first use.
+pub fn get() -> Result<&'static Runtime> {
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("opz-io")
+        .enable_all()
+        .build()
+        .context("failed to start Tokio runtime")?;
+    // A runtime built concurrently by another thread wins; this one is dropped unused.
+    let _ = RUNTIME.set(runtime);
+    Ok(RUNTIME.get().expect("runtime was just set"))
+}
+
+/// Drive `future` to completion from synchronous code, including code that already runs on a
+/// runtime thread (`spawn_blocking` closures, the OTLP-enabled `main`).
+pub fn block_on<F: Future>(future: F) -> Result<F::Output> {
+    match Handle::try_current() {
+        Ok(handle) => Ok(tokio::task::block_in_place(|| handle.block_on(future))),
+        Err(_) => Ok(get()?.block_on(future)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_on_nests_inside_blocking_tasks() {
+        let value = block_on(async {
+            tokio::task::spawn_blocking(|| block_on(async { 42 }).unwrap())
+                .await
+                .unwrap()
+        })
+        .unwrap();
+        assert_eq!(value, 42);
+    }
+}
@@ -0,0 +1,90 @@
+//! `OP_SERVICE_ACCOUNT_TOKEN` support. With a service account token `op` signs in without any
+//! user interaction and sees only the vaults granted to that account, so opz keeps its caches
+//! apart per token, refuses to stop for terminal prompts, and explains `op` access errors in
+//! terms of the token's vault grants.
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+
+pub const TOKEN_ENV: &str = "OP_SERVICE_ACCOUNT_TOKEN";
+
+/// Whether `op` will authenticate with a service account token.
+pub fn active() -> bool {
+    std::env::var_os(TOKEN_ENV).is_some_and(|token| !token.is_empty())
+}
+
+/// Cache subdirectory for the current token, if one is set. Named after a digest so the token
+/// itself never reaches the disk, and so caches written with other credentials (another token
+/// or a signed-in user) are never read.
+pub fn cache_scope() -> Option<String> {
+    std::env::var(TOKEN_ENV)
+        .ok()
+        .filter(|token| !token.is_empty())
+        .map(|token| scope_for(&token))
+}
+
+fn scope_for(token: &str) -> String {
+    let digest = hex::encode(Sha256::digest(token.as_bytes()));
+    format!("sa-{}", &digest[..16])
+}
+
+/// Fail instead of waiting for an answer nobody will give; `what` names the prompt.
+pub fn ensure_interactive(what: &str) -> Result<()> {
+    if active() {
+        return Err(anyhow!(
+            "{what} needs an interactive answer, which is not asked for while {TOKEN_ENV} is set"
+        ));
+    }
+    Ok(())
+}
+
+/// Extra context for an `op` failure under a service account token, when its stderr reads
+/// like the token lacks access to a vault or item.
+pub fn access_hint(stderr: &str) -> Option<String> {
+    if !active() || !is_access_error(stderr) {
+        return None;
+    }
+    Some(format!(
+        "The service account token in {TOKEN_ENV} can only read vaults it has been granted. \
+         Grant it access to the vault in 1Password, or pass a --vault it can read."
+    ))
+}
+
+fn is_access_error(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    [
+        "isn't a vault",
+        "not a vault",
+        "isn't an item",
+        "permission",
+        "forbidden",
+        "not authorized",
+        "unauthorized",
+        "access denied",
+    ]
+    .iter()
+    .any(|needle| stderr.contains(needle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_is_stable_and_hides_token() {
+        let scope = scope_for("ops_secret-token");
+        assert_eq!(scope, scope_for("ops_secret-token"));
+        assert_ne!(scope, scope_for("ops_other-token"));
+        assert!(scope.starts_with("sa-") && !scope.contains("secret"));
+        assert_eq!(scope.len(), 19);
+    }
+
+    #[test]
+    fn test_is_access_error() {
+        assert!(is_access_error(
+            "[ERROR] 2026/01/01 \"Prod\" isn't a vault in this account"
+        ));
+        assert!(is_access_error("403 Forbidden"));
+        assert!(!is_access_error("[ERROR] network is unreachable"));
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    cell::Cell,
+    sync::{Mutex, MutexGuard, Once, OnceLock},
+    time::{Duration, Instant},
+};
+
+/// How long cleanup may take after SIGTERM or SIGHUP before opz exits regardless. CI runners
+/// and Kubernetes follow SIGTERM with SIGKILL once their own grace period is over (10s for
+/// `docker stop`, 30s for pods by default), so cleanup has to fit inside theirs.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+static GRACE_PERIOD: OnceLock<Duration> = OnceLock::new();
+
+/// When the signal that started cleanup arrived.
+static SIGNALLED_AT: OnceLock<Instant> = OnceLock::new();
+
+/// Set the grace period (see [`DEFAULT_GRACE_PERIOD`]); only the first call counts.
+pub fn set_grace_period(grace: Duration) {
+    let _ = GRACE_PERIOD.set(grace);
+}
+
+pub fn grace_period() -> Duration {
+    *GRACE_PERIOD.get_or_init(|| DEFAULT_GRACE_PERIOD)
+}
+
+/// Share of the grace period a child command gets to exit after SIGTERM; the rest is kept for
+/// restoring env files and flushing telemetry.
+fn child_budget(grace: Duration) -> Duration {
+    grace - (grace / 4).min(Duration::from_secs(2))
+}
+
+/// Cleanup stages, executed in declaration order on every exit route
+/// (normal return, signal handler, panic hook).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// Terminate a still-running child command.
+    StopChild,
+    /// Put an env file back the way it was before opz touched it.
+    RestoreEnvFile,
+    /// Export buffered spans; last so earlier stages are still traced.
+    FlushTelemetry,
+}
+
+type Action = Box<dyn FnOnce() + Send>;
+
+struct Pending {
+    id: u64,
+    stage: Stage,
+    action: Action,
+}
+
+#[derive(Default)]
+struct Queue {
+    next_id: u64,
+    pending: Vec<Pending>,
+}
+
+impl Queue {
+    fn register(&mut self, stage: Stage, action: Action) -> u64 {
+        self.next_id += 1;
+        self.pending.push(Pending {
+            id: self.next_id,
+            stage,
+            action,
+        });
+        self.next_id
+    }
+
+    fn cancel(&mut self, id: u64) {
+        self.pending.retain(|pending| pending.id != id);
+    }
+
+    fn has_pending(&self, stage: Stage) -> bool {
+        self.pending.iter().any(|pending| pending.stage == stage)
+    }
+
+    fn take(&mut self, id: u64) -> Option<Pending> {
+        let index = self.pending.iter().position(|pending| pending.id == id)?;
+        Some(self.pending.remove(index))
+    }
+
+    /// Drain pending actions up to and including `last` in stage order (registration order
+    /// within a stage); later stages stay queued.
+    fn drain_through(&mut self, last: Stage) -> Vec<Pending> {
+        let (mut due, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|pending| pending.stage <= last);
+        self.pending = rest;
+        due.sort_by_key(|pending| pending.stage);
+        due
+    }
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    next_id: 0,
+    pending: Vec::new(),
+});
+
+fn queue() -> MutexGuard<'static, Queue> {
+    // A panic elsewhere must not prevent cleanup.
+    QUEUE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Held while cleanup actions run, so an exit route never finishes while another thread (the
+/// signal handler) is still halfway through restoring a file.
+static RUNNING: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    static IN_CLEANUP: Cell<bool> = const { Cell::new(false) };
+}
+
+fn running() -> MutexGuard<'static, ()> {
+    RUNNING
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Handle for a registered cleanup action. Dropping it keeps the action armed.
+#[must_use = "call `disarm` once the resource has been cleaned up normally"]
+pub struct Registration {
+    id: u64,
+}
+
+impl Registration {
+    /// The guarded work finished normally; the action is no longer needed.
+    pub fn disarm(self) {
+        queue().cancel(self.id);
+    }
+
+    /// Run the action now instead of at exit.
+    pub fn run_now(self) {
+        let pending = queue().take(self.id);
+        if let Some(pending) = pending {
+            (pending.action)();
+        }
+    }
+}
+
+pub fn register(stage: Stage, action: impl FnOnce() + Send + 'static) -> Registration {
+    let id = queue().register(stage, Box::new(action));
+    Registration { id }
+}
+
+/// Run every pending cleanup action once, in stage order. Safe to call from any exit route;
+/// actions registered after a run are picked up by the next one.
+pub fn run() {
+    run_through(Stage::FlushTelemetry);
+}
+
+/// Run pending actions up to and including `last`, leaving later stages for [`run`].
+pub fn run_through(last: Stage) {
+    // A cleanup action that panics lands here again through the panic hook.
+    if IN_CLEANUP.get() {
+        return;
+    }
+    let _running = running();
+    run_locked(last);
+}
+
+fn run_locked(last: Stage) {
+    IN_CLEANUP.set(true);
+    let pending = queue().drain_through(last);
+    for pending in pending {
+        (pending.action)();
+    }
+    IN_CLEANUP.set(false);
+}
+
+/// Stop the child command `pid` with SIGTERM. During signal-driven cleanup, wait for it to exit
+/// for most of the grace period and SIGKILL it if it is still running then; on other exit
+/// routes the signal is only sent.
+#[cfg(unix)]
+pub fn stop_child(pid: u32) {
+    let pid = pid as libc::pid_t;
+    // SAFETY: kill(2) has no memory-safety preconditions; a stale pid only yields ESRCH.
+    let signal = |signo| unsafe { libc::kill(pid, signo) == 0 };
+    signal(libc::SIGTERM);
+    let Some(until) = SIGNALLED_AT
+        .get()
+        .map(|signalled| *signalled + child_budget(grace_period()))
+    else {
+        return;
+    };
+    // The waiting thread reaps the child as soon as it exits, after which the pid is gone.
+    while signal(0) {
+        if Instant::now() >= until {
+            eprintln!(
+                "opz: command did not exit within the {}s grace period; killing it",
+                grace_period().as_secs_f32()
+            );
+            signal(libc::SIGKILL);
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[cfg(not(unix))]
+pub fn stop_child(_pid: u32) {}
+
+/// Run the cleanup sequence and exit with `128 + signo` on SIGINT, SIGTERM, or SIGHUP, so an
+/// interrupted opz does not leave env files full of secrets behind. Cleanup is bounded by the
+/// grace period (see [`set_grace_period`]): the child command gets most of it, env files are
+/// always restored, and a telemetry flush still running at the deadline is abandoned so opz
+/// exits before the sender's SIGKILL.
+///
+/// While a child command runs, SIGINT is left to the child (the terminal delivers it to the
+/// whole process group), as a shell does for foreground jobs: interactive programs keep their
+/// own Ctrl-C handling, and opz cleans up normally once the child exits.
+#[cfg(unix)]
+pub fn install_signal_handlers() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        if let Err(err) = spawn_signal_thread() {
+            tracing::warn!("failed to install signal handlers: {err:#}");
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn install_signal_handlers() {}
+
+#[cfg(unix)]
+fn spawn_signal_thread() -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+    // Register before returning so a signal right after this call is already handled.
+    let (mut interrupt, mut terminate, mut hangup) = {
+        let _runtime = runtime.enter();
+        (
+            signal(SignalKind::interrupt())?,
+            signal(SignalKind::terminate())?,
+            signal(SignalKind::hangup())?,
+        )
+    };
+    std::thread::Builder::new()
+        .name("opz-signals".to_string())
+        .spawn(move || {
+            let signo = runtime.block_on(async {
+                loop {
+                    tokio::select! {
+                        _ = interrupt.recv() => {
+                            if !queue().has_pending(Stage::StopChild) {
+                                return libc::SIGINT;
+                            }
+                        }
+                        _ = terminate.recv() => return libc::SIGTERM,
+                        _ = hangup.recv() => return libc::SIGHUP,
+                    }
+                }
+            });
+            let deadline = *SIGNALLED_AT.get_or_init(Instant::now) + grace_period();
+            // Exit while still holding the lock: the main thread may be returning too, and this
+            // keeps it from finishing first with a different status.
+            let _running = running();
+            run_locked(Stage::RestoreEnvFile);
+            let (done, flushed) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                run_locked(Stage::FlushTelemetry);
+                let _ = done.send(());
+            });
+            if flushed
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .is_err()
+            {
+                eprintln!("opz: telemetry flush did not finish within the grace period");
+            }
+            std::process::exit(128 + signo);
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn recorder(log: &Arc<Mutex<Vec<&'static str>>>, name: &'static str) -> Action {
+        let log = Arc::clone(log);
+        Box::new(move || log.lock().unwrap().push(name))
+    }
+
+    #[test]
+    fn test_queue_drains_in_stage_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut queue = Queue::default();
+        queue.register(Stage::FlushTelemetry, recorder(&log, "telemetry"));
+        queue.register(Stage::RestoreEnvFile, recorder(&log, "env-1"));
+        queue.register(Stage::StopChild, recorder(&log, "child"));
+        queue.register(Stage::RestoreEnvFile, recorder(&log, "env-2"));
+
+        for pending in queue.drain_through(Stage::FlushTelemetry) {
+            (pending.action)();
+        }
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["child", "env-1", "env-2", "telemetry"]
+        );
+        assert!(queue.drain_through(Stage::FlushTelemetry).is_empty());
+    }
+
+    #[test]
+    fn test_queue_cancel_removes_action() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut queue = Queue::default();
+        let id = queue.register(Stage::StopChild, recorder(&log, "child"));
+        queue.register(Stage::FlushTelemetry, recorder(&log, "telemetry"));
+        queue.cancel(id);
+
+        for pending in queue.drain_through(Stage::FlushTelemetry) {
+            (pending.action)();
+        }
+        assert_eq!(*log.lock().unwrap(), vec!["telemetry"]);
+    }
+
+    #[test]
+    fn test_queue_take_removes_single_action() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut queue = Queue::default();
+        let id = queue.register(Stage::RestoreEnvFile, recorder(&log, "env"));
+        queue.register(Stage::FlushTelemetry, recorder(&log, "telemetry"));
+
+        (queue.take(id).unwrap().action)();
+        assert!(queue.take(id).is_none());
+        assert_eq!(*log.lock().unwrap(), vec!["env"]);
+        assert_eq!(queue.drain_through(Stage::FlushTelemetry).len(), 1);
+    }
+
+    #[test]
+    fn test_queue_has_pending_tracks_stage() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut queue = Queue::default();
+        assert!(!queue.has_pending(Stage::StopChild));
+        let id = queue.register(Stage::StopChild, recorder(&log, "child"));
+        assert!(queue.has_pending(Stage::StopChild));
+        assert!(!queue.has_pending(Stage::RestoreEnvFile));
+        queue.cancel(id);
+        assert!(!queue.has_pending(Stage::StopChild));
+    }
+
+    #[test]
+    fn test_child_budget_leaves_time_for_cleanup() {
+        assert_eq!(
+            child_budget(Duration::from_secs(10)),
+            Duration::from_secs(8)
+        );
+        assert_eq!(
+            child_budget(Duration::from_secs(30)),
+            Duration::from_secs(28)
+        );
+        assert_eq!(
+            child_budget(Duration::from_secs(2)),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(child_budget(Duration::ZERO), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_queue_drain_through_keeps_later_stages() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut queue = Queue::default();
+        queue.register(Stage::FlushTelemetry, recorder(&log, "telemetry"));
+        queue.register(Stage::RestoreEnvFile, recorder(&log, "env"));
+
+        for pending in queue.drain_through(Stage::RestoreEnvFile) {
+            (pending.action)();
+        }
+        assert_eq!(*log.lock().unwrap(), vec!["env"]);
+
+        for pending in queue.drain_through(Stage::FlushTelemetry) {
+            (pending.action)();
+        }
+        assert_eq!(*log.lock().unwrap(), vec!["env", "telemetry"]);
+    }
+}
@@ -0,0 +1,155 @@
+//! Recovery when `op` reports that no account is signed in or the session expired: on a
+//! terminal, run `op signin` (which may prompt or unlock the desktop app) so the operation can
+//! be retried once; elsewhere, replace `op`'s stderr with instructions for signing in.
+
+use anyhow::{Context, Result};
+use std::{io::IsTerminal, process::Stdio, sync::Mutex};
+
+use crate::{
+    error::{Error, Kind},
+    service_account,
+};
+
+/// `OP_SESSION_*` variables printed by an interactive `op signin`, passed to every later `op`
+/// call of this process (never to the user's command).
+static SESSION_ENV: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Serializes sign-in across threads looking up items in parallel; `true` once one succeeded,
+/// after which the others just retry.
+static SIGNED_IN: Mutex<bool> = Mutex::new(false);
+
+const SIGNED_OUT_PHRASES: &[&str] = &[
+    "not currently signed in",
+    "you are not signed in",
+    "account is not signed in",
+    "session expired",
+    "session has expired",
+    "invalid session",
+];
+
+/// Whether an `op` failure means the CLI has no usable session.
+pub fn is_signed_out(message: &str) -> bool {
+    let message = message.to_lowercase();
+    SIGNED_OUT_PHRASES
+        .iter()
+        .any(|phrase| message.contains(phrase))
+}
+
+/// Session variables from an earlier [`recover`], for `op` commands.
+pub fn session_env() -> Vec<(String, String)> {
+    SESSION_ENV
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+/// Handle `err`, a signed-out failure from `op`: sign in on the terminal and return `Ok` so
+/// the caller retries, or return an error that says how to sign in.
+pub fn recover(account: Option<&str>, err: anyhow::Error) -> Result<()> {
+    if !can_prompt() {
+        return Err(instructions(account, &err));
+    }
+    let mut signed_in = SIGNED_IN
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if *signed_in {
+        return Ok(());
+    }
+    eprintln!("1Password CLI is not signed in; running `op signin`...");
+    let mut cmd = crate::cli::op_command(account)?;
+    cmd.arg("signin")
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::piped());
+    let out = crate::cli::run_op_interactive(cmd).context("failed to run op signin")?;
+    if !out.status.success() {
+        return Err(instructions(account, &err));
+    }
+    let exports = parse_exports(&String::from_utf8_lossy(&out.stdout));
+    SESSION_ENV
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .extend(exports);
+    *signed_in = true;
+    Ok(())
+}
+
+fn can_prompt() -> bool {
+    !service_account::active() && std::io::stdin().is_terminal() && std::io::stderr().is_terminal()
+}
+
+fn instructions(account: Option<&str>, err: &anyhow::Error) -> anyhow::Error {
+    Error::new(Kind::NotSignedIn, instructions_message(account, err)).into()
+}
+
+fn instructions_message(account: Option<&str>, err: &anyhow::Error) -> String {
+    let detail = format!("{err:#}");
+    let detail = detail
+        .lines()
+        .find(|line| is_signed_out(line))
+        .unwrap_or(detail.trim())
+        .trim();
+    if service_account::active() {
+        return format!(
+            "1Password CLI is not signed in: {detail}\n\
+             The token in {} was not accepted; check that it is valid and has not expired.",
+            service_account::TOKEN_ENV
+        );
+    }
+    let account_arg = account.map_or(String::new(), |account| format!(" --account {account}"));
+    format!(
+        "1Password CLI is not signed in: {detail}\n\
+         Sign in with `eval $(op signin{account_arg})` (or unlock the 1Password app if its CLI \
+         integration is on) and run the command again. In CI, set {} instead.",
+        service_account::TOKEN_ENV
+    )
+}
+
+/// `OP_SESSION_*` assignments from `op signin` output, whatever shell syntax it chose
+/// (`export NAME="value"`, `set -x NAME "value"`, `$env:NAME="value"`).
+fn parse_exports(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let start = line.find("OP_SESSION_")?;
+            let rest = &line[start..];
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let name = &rest[..end];
+            let value = rest[end..].rsplit('"').nth(1)?;
+            (!value.is_empty()).then(|| (name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_signed_out() {
+        assert!(is_signed_out(
+            "[ERROR] 2026/01/01 You are not currently signed in. Please run `op signin --help` for instructions"
+        ));
+        assert!(is_signed_out(
+            "[ERROR] session expired, sign in to create a new session"
+        ));
+        assert!(!is_signed_out("[ERROR] \"foo\" isn't an item"));
+    }
+
+    #[test]
+    fn test_parse_exports() {
+        assert_eq!(
+            parse_exports(
+                "export OP_SESSION_my=\"abc123\"\n# This command is meant to be used with your shell's eval function.\n"
+            ),
+            vec![("OP_SESSION_my".to_string(), "abc123".to_string())]
+        );
+        assert_eq!(
+            parse_exports("set -x OP_SESSION_team \"xyz\";"),
+            vec![("OP_SESSION_team".to_string(), "xyz".to_string())]
+        );
+        assert!(parse_exports("").is_empty());
+    }
+}
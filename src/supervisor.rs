@@ -0,0 +1,229 @@
+//! Supervised background tasks: long-running loops (cache refreshes, servers, child processes)
+//! run as named tokio tasks under one cancellation token, so restarts and shutdown are handled
+//! in one place instead of by ad-hoc threads.
+
+use anyhow::{anyhow, Context, Result};
+use std::{future::Future, time::Duration};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// Upper bound for the exponential backoff between restarts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Restart {
+    /// Run once; an error stops the whole group.
+    Never,
+    /// Restart after an error or panic, at most `max` times, waiting `backoff` (doubled per
+    /// attempt) in between. Once the limit is exceeded the error stops the whole group.
+    OnFailure { max: u32, backoff: Duration },
+}
+
+/// A group of tasks that share one cancellation token.
+///
+/// Tasks are closures that build a fresh future per attempt from the group's token, and are
+/// expected to return `Ok(())` promptly once the token is cancelled.
+pub struct Supervisor {
+    token: CancellationToken,
+    tasks: JoinSet<(String, Result<()>)>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self {
+            token: CancellationToken::new(),
+            tasks: JoinSet::new(),
+        }
+    }
+
+    pub fn spawn<F, Fut>(&mut self, name: &str, restart: Restart, task: F)
+    where
+        F: FnMut(CancellationToken) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let name = name.to_string();
+        let token = self.token.clone();
+        self.tasks.spawn(async move {
+            let result = supervise(&name, restart, token, task).await;
+            (name, result)
+        });
+    }
+
+    /// Cancel the group on Ctrl-C or SIGTERM.
+    pub fn cancel_on_signal(&mut self) {
+        self.spawn("signals", Restart::Never, |token| async move {
+            tokio::select! {
+                _ = token.cancelled() => {}
+                result = shutdown_signal() => {
+                    result?;
+                    eprintln!("Shutting down...");
+                    token.cancel();
+                }
+            }
+            Ok(())
+        });
+    }
+
+    /// Wait for every task to finish. The first task that fails for good cancels the rest,
+    /// and its error is returned once they have stopped.
+    pub async fn wait(mut self) -> Result<()> {
+        let mut first_error = None;
+        while let Some(joined) = self.tasks.join_next().await {
+            let (name, result) = joined.context("supervisor task aborted")?;
+            if let Err(err) = result {
+                self.token.cancel();
+                first_error.get_or_insert(err.context(format!("task '{name}' failed")));
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+async fn supervise<F, Fut>(
+    name: &str,
+    restart: Restart,
+    token: CancellationToken,
+    mut task: F,
+) -> Result<()>
+where
+    F: FnMut(CancellationToken) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let mut failures = 0u32;
+    loop {
+        if token.is_cancelled() {
+            return Ok(());
+        }
+        // Each attempt runs as its own task so a panic counts as a failure of this task
+        // instead of tearing down the supervisor.
+        let result = match tokio::spawn(task(token.clone())).await {
+            Ok(result) => result,
+            Err(err) if err.is_panic() => Err(anyhow!("panicked")),
+            Err(err) => Err(anyhow!(err)),
+        };
+        let Err(err) = result else {
+            return Ok(());
+        };
+        let backoff = match restart {
+            Restart::OnFailure { max, backoff } if failures < max => backoff,
+            _ => return Err(err),
+        };
+        let delay = restart_delay(backoff, failures);
+        failures += 1;
+        tracing::warn!(
+            "{name} failed ({err:#}); restarting in {}s",
+            delay.as_secs_f32()
+        );
+        tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+fn restart_delay(backoff: Duration, failures: u32) -> Duration {
+    backoff
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(MAX_BACKOFF)
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).context("listen for SIGTERM")?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result.context("listen for Ctrl-C"),
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> Result<()> {
+    tokio::signal::ctrl_c().await.context("listen for Ctrl-C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::block_on;
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn test_cancel_stops_every_task() {
+        let result = block_on(async {
+            let mut supervisor = Supervisor::new();
+            for name in ["a", "b"] {
+                supervisor.spawn(name, Restart::Never, |token| async move {
+                    token.cancelled().await;
+                    Ok(())
+                });
+            }
+            supervisor.spawn("stop", Restart::Never, |token| async move {
+                token.cancel();
+                Ok(())
+            });
+            supervisor.wait().await
+        })
+        .unwrap();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_failing_task_is_restarted_until_it_succeeds() {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counted = Arc::clone(&attempts);
+        let result = block_on(async move {
+            let mut supervisor = Supervisor::new();
+            let restart = Restart::OnFailure {
+                max: 3,
+                backoff: Duration::from_millis(1),
+            };
+            supervisor.spawn("flaky", restart, move |_| {
+                let attempt = counted.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match attempt {
+                        0 => Err(anyhow!("first attempt fails")),
+                        1 => panic!("second attempt panics"),
+                        _ => Ok(()),
+                    }
+                }
+            });
+            supervisor.wait().await
+        })
+        .unwrap();
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_exhausted_restarts_cancel_the_group() {
+        let result = block_on(async {
+            let mut supervisor = Supervisor::new();
+            supervisor.spawn("idle", Restart::Never, |token| async move {
+                token.cancelled().await;
+                Ok(())
+            });
+            let restart = Restart::OnFailure {
+                max: 1,
+                backoff: Duration::from_millis(1),
+            };
+            supervisor.spawn("broken", restart, |_| async { Err(anyhow!("boom")) });
+            supervisor.wait().await
+        })
+        .unwrap();
+        let err = result.unwrap_err();
+        assert_eq!(format!("{err:#}"), "task 'broken' failed: boom");
+    }
+
+    #[test]
+    fn test_restart_delay_doubles_up_to_cap() {
+        let backoff = Duration::from_secs(1);
+        assert_eq!(restart_delay(backoff, 0), Duration::from_secs(1));
+        assert_eq!(restart_delay(backoff, 3), Duration::from_secs(8));
+        assert_eq!(restart_delay(backoff, 30), MAX_BACKOFF);
+    }
+}
@@ -1,52 +1,287 @@
-use opentelemetry::{global, KeyValue};
-use opentelemetry_otlp::WithExportConfig;
+use opentelemetry::{
+    global,
+    logs::{AnyValue, LogRecord, Logger, LoggerProvider, Severity},
+    metrics::{Counter, Histogram},
+    KeyValue,
+};
+use opentelemetry_otlp::{ExporterBuildError, Protocol, WithExportConfig};
 use opentelemetry_sdk::{
-    trace::{Sampler, SdkTracerProvider},
+    error::{OTelSdkError, OTelSdkResult},
+    logs::SdkLoggerProvider,
+    metrics::SdkMeterProvider,
+    trace::{Sampler, SdkTracerProvider, SpanData, SpanExporter},
     Resource,
 };
-use std::process::Command;
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    process::Command,
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    config::TelemetryConfig,
+    fault::{self, Fault},
+    telemetry_resource,
+    telemetry_span::sanitize_for_trace,
+};
 
 pub struct TelemetryHandle {
     provider: Option<SdkTracerProvider>,
+    meter_provider: Option<SdkMeterProvider>,
+    logger_provider: Option<SdkLoggerProvider>,
 }
 
 impl TelemetryHandle {
     pub fn disabled() -> Self {
-        Self { provider: None }
+        Self {
+            provider: None,
+            meter_provider: None,
+            logger_provider: None,
+        }
+    }
+
+    /// Whether spans are recorded anywhere (a collector or `--trace-debug`).
+    pub fn traces_enabled(&self) -> bool {
+        self.provider.is_some()
     }
 
     pub fn shutdown_best_effort(self) {
         if let Some(provider) = self.provider {
+            // Spans are exported in batches; send what is still queued before shutting down.
+            // Like metrics, this fails whenever the collector is down.
+            if let Err(err) = provider.force_flush() {
+                tracing::debug!("span export on shutdown failed: {err}");
+            }
             if let Err(err) = provider.shutdown() {
                 eprintln!("Warning: telemetry shutdown failed: {err}");
             }
         }
+        // Shutting down exports what was recorded since the last periodic export. That fails
+        // whenever the collector is down, which is not worth a warning on every command.
+        if let Some(meter_provider) = self.meter_provider {
+            if let Err(err) = meter_provider.shutdown() {
+                tracing::debug!("metrics export on shutdown failed: {err}");
+            }
+        }
+        if let Some(logger_provider) = self.logger_provider {
+            if let Err(err) = logger_provider.shutdown() {
+                eprintln!("Warning: log export shutdown failed: {err}");
+            }
+        }
     }
 }
 
-pub fn init(command_hint: &str, service_version: &str) -> TelemetryHandle {
-    let Some(endpoint) = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok() else {
-        return TelemetryHandle::disabled();
-    };
+/// `OTEL_SDK_DISABLED=true` or `OPZ_NO_TELEMETRY=1`.
+pub fn disabled_by_env() -> bool {
+    std::env::var("OTEL_SDK_DISABLED").is_ok_and(|value| value.eq_ignore_ascii_case("true"))
+        || std::env::var("OPZ_NO_TELEMETRY").is_ok_and(|value| value == "1")
+}
 
-    let service_name =
-        std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| env!("CARGO_PKG_NAME").to_string());
-    let git_commit = resolve_git_commit();
-    let sampler = sampler_from_env();
+/// OTLP endpoint from the environment, falling back to the `[telemetry]` config section.
+fn endpoint(config: &TelemetryConfig) -> Option<String> {
+    if fault::active(Fault::CollectorUnreachable) {
+        return Some(fault::UNREACHABLE_COLLECTOR.to_string());
+    }
+    std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+        .ok()
+        .or_else(|| config.endpoint.clone())
+}
 
-    let exporter = match opentelemetry_otlp::SpanExporter::builder()
-        .with_tonic()
-        .with_endpoint(endpoint)
-        .with_timeout(std::time::Duration::from_millis(1000))
-        .build()
-    {
-        Ok(exporter) => exporter,
-        Err(err) => {
+/// `OTEL_EXPORTER_OTLP_PROTOCOL`, falling back to `[telemetry] protocol`, as given.
+fn configured_protocol(config: &TelemetryConfig) -> Option<String> {
+    std::env::var("OTEL_EXPORTER_OTLP_PROTOCOL")
+        .ok()
+        .or_else(|| config.protocol.clone())
+}
+
+fn parse_protocol(protocol: &str) -> Option<Protocol> {
+    match protocol {
+        "grpc" => Some(Protocol::Grpc),
+        "http/protobuf" => Some(Protocol::HttpBinary),
+        "http/json" => Some(Protocol::HttpJson),
+        _ => None,
+    }
+}
+
+/// The configured OTLP protocol; gRPC unless another valid one is set.
+fn protocol(config: &TelemetryConfig) -> Protocol {
+    configured_protocol(config)
+        .and_then(|protocol| parse_protocol(&protocol))
+        .unwrap_or(Protocol::Grpc)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Signal {
+    Traces,
+    Metrics,
+    Logs,
+}
+
+impl Signal {
+    const ALL: [Signal; 3] = [Signal::Traces, Signal::Metrics, Signal::Logs];
+
+    fn name(self) -> &'static str {
+        match self {
+            Signal::Traces => "traces",
+            Signal::Metrics => "metrics",
+            Signal::Logs => "logs",
+        }
+    }
+}
+
+/// Where `signal` is exported: `OTEL_EXPORTER_OTLP_<SIGNAL>_ENDPOINT` as given, else the
+/// shared endpoint.
+fn signal_endpoint(signal: Signal, base: Option<&str>, protocol: Protocol) -> Option<String> {
+    let var = format!(
+        "OTEL_EXPORTER_OTLP_{}_ENDPOINT",
+        signal.name().to_uppercase()
+    );
+    match std::env::var(var) {
+        Ok(endpoint) if !endpoint.is_empty() => Some(endpoint),
+        _ => base.map(|base| join_signal_path(base, signal, protocol)),
+    }
+}
+
+/// The shared endpoint is a base URL over HTTP, which gets the signal's path (`/v1/traces`);
+/// gRPC routes by service instead.
+fn join_signal_path(base: &str, signal: Signal, protocol: Protocol) -> String {
+    match protocol {
+        Protocol::Grpc => base.to_string(),
+        _ => format!("{}/v1/{}", base.trim_end_matches('/'), signal.name()),
+    }
+}
+
+/// Whether anything is exported over gRPC, whose exporter needs the Tokio runtime from the
+/// start.
+pub fn exports_over_grpc(config: &TelemetryConfig) -> bool {
+    let base = endpoint(config);
+    protocol(config) == Protocol::Grpc
+        && Signal::ALL
+            .iter()
+            .any(|signal| signal_endpoint(*signal, base.as_deref(), Protocol::Grpc).is_some())
+}
+
+fn span_exporter(
+    protocol: Protocol,
+    endpoint: String,
+) -> Result<opentelemetry_otlp::SpanExporter, ExporterBuildError> {
+    let builder = opentelemetry_otlp::SpanExporter::builder();
+    match protocol {
+        Protocol::Grpc => builder
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .with_timeout(EXPORT_TIMEOUT)
+            .build(),
+        _ => builder
+            .with_http()
+            .with_protocol(protocol)
+            .with_endpoint(endpoint)
+            .with_timeout(EXPORT_TIMEOUT)
+            .build(),
+    }
+}
+
+fn log_exporter(
+    protocol: Protocol,
+    endpoint: String,
+) -> Result<opentelemetry_otlp::LogExporter, ExporterBuildError> {
+    let builder = opentelemetry_otlp::LogExporter::builder();
+    match protocol {
+        Protocol::Grpc => builder
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .with_timeout(EXPORT_TIMEOUT)
+            .build(),
+        _ => builder
+            .with_http()
+            .with_protocol(protocol)
+            .with_endpoint(endpoint)
+            .with_timeout(EXPORT_TIMEOUT)
+            .build(),
+    }
+}
+
+fn metric_exporter(
+    protocol: Protocol,
+    endpoint: String,
+) -> Result<opentelemetry_otlp::MetricExporter, ExporterBuildError> {
+    let builder = opentelemetry_otlp::MetricExporter::builder();
+    match protocol {
+        Protocol::Grpc => builder
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .with_timeout(EXPORT_TIMEOUT)
+            .build(),
+        _ => builder
+            .with_http()
+            .with_protocol(protocol)
+            .with_endpoint(endpoint)
+            .with_timeout(EXPORT_TIMEOUT)
+            .build(),
+    }
+}
+
+/// Where spans go instead of the collector, for debugging telemetry without one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpanDump {
+    Stderr,
+    /// Appended to, one JSON object per line.
+    File(PathBuf),
+}
+
+/// `OTEL_TRACES_EXPORTER=console` prints spans on stderr.
+fn console_exporter_requested() -> bool {
+    std::env::var("OTEL_TRACES_EXPORTER").is_ok_and(|exporter| exporter == "console")
+}
+
+/// Traces go to `span_dump` (or stderr with `OTEL_TRACES_EXPORTER=console`) if set, else to
+/// their OTLP endpoint; logs and metrics only ever go to theirs. Auth headers come from
+/// `OTEL_EXPORTER_OTLP_HEADERS` and its per-signal variants, read by the exporters.
+pub fn init(
+    command_hint: &str,
+    service_version: &str,
+    config: &TelemetryConfig,
+    span_dump: Option<SpanDump>,
+) -> TelemetryHandle {
+    let span_dump = span_dump.or_else(|| console_exporter_requested().then_some(SpanDump::Stderr));
+    if let Some(configured) = configured_protocol(config) {
+        if parse_protocol(&configured).is_none() {
             eprintln!(
-                "Warning: failed to initialize OTLP exporter for {command_hint}: {err}. Telemetry disabled."
+                "Warning: unknown OTLP protocol '{configured}' (expected grpc, http/protobuf or http/json); using grpc."
             );
-            return TelemetryHandle::disabled();
         }
+    }
+    let protocol = protocol(config);
+    let base = endpoint(config);
+    let [traces_endpoint, metrics_endpoint, logs_endpoint] =
+        Signal::ALL.map(|signal| signal_endpoint(signal, base.as_deref(), protocol));
+    if span_dump.is_none()
+        && traces_endpoint.is_none()
+        && metrics_endpoint.is_none()
+        && logs_endpoint.is_none()
+    {
+        return TelemetryHandle::disabled();
+    }
+
+    let service_name = std::env::var("OTEL_SERVICE_NAME")
+        .ok()
+        .or_else(|| config.service_name.clone())
+        .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string());
+    let git_commit = resolve_git_commit();
+    let sampler = match command_sample_ratio(command_hint, config, |name| std::env::var(name).ok())
+    {
+        Some(ratio) => {
+            let ratio = Sampler::TraceIdRatioBased(ratio);
+            if parent_based_sampler() {
+                Sampler::ParentBased(Box::new(ratio))
+            } else {
+                ratio
+            }
+        }
+        None => sampler_from_env(),
     };
 
     let resource = Resource::builder()
@@ -56,21 +291,307 @@ pub fn init(command_hint: &str, service_version: &str) -> TelemetryHandle {
             service_version.to_string(),
         ))
         .with_attribute(KeyValue::new("git.commit", git_commit))
+        .with_attributes(telemetry_resource::detect())
         .build();
 
-    let provider = SdkTracerProvider::builder()
-        .with_resource(resource)
-        .with_sampler(sampler)
-        .with_simple_exporter(exporter)
-        .build();
+    let builder = SdkTracerProvider::builder()
+        .with_resource(resource.clone())
+        .with_sampler(sampler);
+    let provider = match (span_dump, traces_endpoint) {
+        (Some(dump), _) => match JsonSpanExporter::open(&dump) {
+            Ok(exporter) => Some(builder.with_simple_exporter(exporter).build()),
+            Err(err) => {
+                eprintln!(
+                    "Warning: failed to open span output for {command_hint}: {err}. Telemetry disabled."
+                );
+                return TelemetryHandle::disabled();
+            }
+        },
+        (None, Some(endpoint)) => match span_exporter(protocol, endpoint) {
+            // Batched so that ending a span does not wait for the collector; the queue and
+            // schedule follow the OTEL_BSP_* variables.
+            Ok(exporter) => Some(builder.with_batch_exporter(exporter).build()),
+            Err(err) => {
+                eprintln!(
+                    "Warning: failed to initialize OTLP exporter for {command_hint}: {err}. Telemetry disabled."
+                );
+                return TelemetryHandle::disabled();
+            }
+        },
+        (None, None) => None,
+    };
+
+    if let Some(provider) = &provider {
+        global::set_tracer_provider(provider.clone());
+    }
+
+    let logger_provider = match logs_endpoint.map(|endpoint| log_exporter(protocol, endpoint)) {
+        None => None,
+        // Batched like spans: records are often logged on runtime threads, where the blocking
+        // HTTP client must not wait for the collector.
+        Some(Ok(exporter)) => {
+            let logger_provider = SdkLoggerProvider::builder()
+                .with_resource(resource.clone())
+                .with_batch_exporter(exporter)
+                .build();
+            let _ = LOGGER_PROVIDER.set(logger_provider.clone());
+            Some(logger_provider)
+        }
+        Some(Err(err)) => {
+            eprintln!(
+                "Warning: failed to initialize OTLP log exporter for {command_hint}: {err}. Log export disabled."
+            );
+            None
+        }
+    };
 
-    global::set_tracer_provider(provider.clone());
+    let meter_provider = match metrics_endpoint.map(|endpoint| metric_exporter(protocol, endpoint))
+    {
+        None => None,
+        Some(Ok(exporter)) => {
+            let meter_provider = SdkMeterProvider::builder()
+                .with_resource(resource)
+                .with_periodic_exporter(exporter)
+                .build();
+            global::set_meter_provider(meter_provider.clone());
+            Some(meter_provider)
+        }
+        Some(Err(err)) => {
+            eprintln!(
+                "Warning: failed to initialize OTLP metric exporter for {command_hint}: {err}. Metrics disabled."
+            );
+            None
+        }
+    };
 
     TelemetryHandle {
-        provider: Some(provider),
+        provider,
+        meter_provider,
+        logger_provider,
+    }
+}
+
+const EXPORT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Writes each finished span as one JSON object per line.
+struct JsonSpanExporter {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl std::fmt::Debug for JsonSpanExporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonSpanExporter").finish_non_exhaustive()
     }
 }
 
+impl JsonSpanExporter {
+    fn open(dump: &SpanDump) -> std::io::Result<Self> {
+        let out: Box<dyn Write + Send> = match dump {
+            SpanDump::Stderr => Box::new(std::io::stderr()),
+            SpanDump::File(path) => {
+                Box::new(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+        };
+        Ok(Self::new(out))
+    }
+
+    fn new(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            out: Mutex::new(out),
+        }
+    }
+}
+
+impl SpanExporter for JsonSpanExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        let mut out = self
+            .out
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for span in &batch {
+            writeln!(out, "{}", span_json(span))
+                .map_err(|err| OTelSdkError::InternalFailure(err.to_string()))?;
+        }
+        out.flush()
+            .map_err(|err| OTelSdkError::InternalFailure(err.to_string()))
+    }
+}
+
+fn span_json(span: &SpanData) -> serde_json::Value {
+    let (status, status_message) = match &span.status {
+        opentelemetry::trace::Status::Unset => ("unset", None),
+        opentelemetry::trace::Status::Ok => ("ok", None),
+        opentelemetry::trace::Status::Error { description } => {
+            ("error", Some(description.to_string()))
+        }
+    };
+    let parent_span_id = (span.parent_span_id != opentelemetry::trace::SpanId::INVALID)
+        .then(|| span.parent_span_id.to_string());
+    let events: Vec<serde_json::Value> = span
+        .events
+        .iter()
+        .map(|event| {
+            serde_json::json!({
+                "name": event.name,
+                "time_unix_nano": unix_nanos(event.timestamp),
+                "attributes": attributes_json(&event.attributes),
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "name": span.name,
+        "trace_id": span.span_context.trace_id().to_string(),
+        "span_id": span.span_context.span_id().to_string(),
+        "parent_span_id": parent_span_id,
+        "start_time_unix_nano": unix_nanos(span.start_time),
+        "duration_ms": span
+            .end_time
+            .duration_since(span.start_time)
+            .unwrap_or_default()
+            .as_secs_f64()
+            * 1000.0,
+        "status": status,
+        "status_message": status_message,
+        "attributes": attributes_json(&span.attributes),
+        "events": events,
+    })
+}
+
+fn attributes_json(attributes: &[KeyValue]) -> serde_json::Map<String, serde_json::Value> {
+    attributes
+        .iter()
+        .map(|kv| {
+            let value = match &kv.value {
+                opentelemetry::Value::Bool(value) => (*value).into(),
+                opentelemetry::Value::I64(value) => (*value).into(),
+                opentelemetry::Value::F64(value) => (*value).into(),
+                other => other.to_string().into(),
+            };
+            (kv.key.to_string(), value)
+        })
+        .collect()
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+/// Set by [`init`]; there is no global logger provider to register it with.
+static LOGGER_PROVIDER: OnceLock<SdkLoggerProvider> = OnceLock::new();
+
+/// Whether [`emit_log`] exports anything.
+pub fn logs_enabled() -> bool {
+    LOGGER_PROVIDER.get().is_some()
+}
+
+/// Export `message`, sanitized like span attributes, as a log record. It carries the active
+/// span's trace and span ids, so collectors can show it next to the trace.
+pub fn emit_log(level: tracing::Level, target: &str, message: &str) {
+    let Some(provider) = LOGGER_PROVIDER.get() else {
+        return;
+    };
+    let (severity, severity_text) = match level {
+        tracing::Level::ERROR => (Severity::Error, "ERROR"),
+        tracing::Level::WARN => (Severity::Warn, "WARN"),
+        tracing::Level::INFO => (Severity::Info, "INFO"),
+        tracing::Level::DEBUG => (Severity::Debug, "DEBUG"),
+        tracing::Level::TRACE => (Severity::Trace, "TRACE"),
+    };
+    let logger = provider.logger("opz");
+    let mut record = logger.create_log_record();
+    record.set_timestamp(std::time::SystemTime::now());
+    record.set_severity_number(severity);
+    record.set_severity_text(severity_text);
+    record.set_target(target.to_string());
+    record.set_body(AnyValue::from(sanitize_for_trace(message)));
+    logger.emit(record);
+}
+
+/// The instruments behind the `record_*` functions. They come from the global meter provider,
+/// so they record nothing unless [`init`] set one up.
+struct Metrics {
+    runs: Counter<u64>,
+    op_duration: Histogram<f64>,
+    cache_lookups: Counter<u64>,
+    fields_exported: Histogram<u64>,
+}
+
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let meter = global::meter("opz");
+        Metrics {
+            runs: meter
+                .u64_counter("opz.runs")
+                .with_description("opz invocations by subcommand and outcome")
+                .build(),
+            op_duration: meter
+                .f64_histogram("opz.op.duration")
+                .with_description("Duration of `op` CLI calls")
+                .with_unit("s")
+                .build(),
+            cache_lookups: meter
+                .u64_counter("opz.cache.lookups")
+                .with_description("Cache lookups by cache and result (hit or miss)")
+                .build(),
+            fields_exported: meter
+                .u64_histogram("opz.fields.exported")
+                .with_description("Env vars exported per `gen` or `run`")
+                .with_boundaries(vec![0.0, 1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0])
+                .build(),
+        }
+    })
+}
+
+fn outcome(ok: bool) -> &'static str {
+    if ok {
+        "ok"
+    } else {
+        "error"
+    }
+}
+
+pub fn record_run(command: &str, ok: bool) {
+    metrics().runs.add(
+        1,
+        &[
+            KeyValue::new("cli.command", command.to_string()),
+            KeyValue::new("outcome", outcome(ok)),
+        ],
+    );
+}
+
+/// `subcommand` is the `op` subcommand without arguments (`item get`), to keep the number of
+/// series small.
+pub fn record_op_call(subcommand: &str, elapsed: Duration, ok: bool) {
+    metrics().op_duration.record(
+        elapsed.as_secs_f64(),
+        &[
+            KeyValue::new("op.command", subcommand.to_string()),
+            KeyValue::new("outcome", outcome(ok)),
+        ],
+    );
+}
+
+/// `cache` is `item_list`, `item` or `metadata`.
+pub fn record_cache_lookup(cache: &'static str, hit: bool) {
+    metrics().cache_lookups.add(
+        1,
+        &[
+            KeyValue::new("cache", cache),
+            KeyValue::new("result", if hit { "hit" } else { "miss" }),
+        ],
+    );
+}
+
+pub fn record_fields_exported(command: &'static str, count: usize) {
+    metrics()
+        .fields_exported
+        .record(count as u64, &[KeyValue::new("cli.command", command)]);
+}
+
 fn resolve_git_commit() -> String {
     if let Ok(v) = std::env::var("OPZ_GIT_COMMIT") {
         let trimmed = v.trim();
@@ -120,6 +641,32 @@ fn sampler_from_env() -> Sampler {
     }
 }
 
+fn parent_based_sampler() -> bool {
+    std::env::var("OTEL_TRACES_SAMPLER")
+        .is_ok_and(|name| name.to_ascii_lowercase().starts_with("parentbased_"))
+}
+
+/// The sampling ratio set for `command` by `OPZ_TRACE_SAMPLE_<COMMAND>` or
+/// `[telemetry.sample]`, so frequent, low-value commands (`find`, completion) can be sampled
+/// down while the rest follow `OTEL_TRACES_SAMPLER`.
+fn command_sample_ratio(
+    command: &str,
+    config: &TelemetryConfig,
+    var: impl Fn(&str) -> Option<String>,
+) -> Option<f64> {
+    let name = format!("OPZ_TRACE_SAMPLE_{}", command.to_ascii_uppercase());
+    let from_env = var(&name).and_then(|raw| match raw.trim().parse::<f64>() {
+        Ok(ratio) if ratio.is_finite() => Some(ratio),
+        _ => {
+            eprintln!("Warning: ignoring {name}={raw}; expected a ratio between 0 and 1.");
+            None
+        }
+    });
+    from_env
+        .or_else(|| config.sample.get(command).copied())
+        .map(|ratio| ratio.clamp(0.0, 1.0))
+}
+
 fn sample_ratio_arg() -> f64 {
     std::env::var("OTEL_TRACES_SAMPLER_ARG")
         .ok()
@@ -127,3 +674,102 @@ fn sample_ratio_arg() -> f64 {
         .map(|value| value.clamp(0.0, 1.0))
         .unwrap_or(1.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{Span, Tracer, TracerProvider};
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_signal_paths_follow_protocol() {
+        assert_eq!(parse_protocol("http/protobuf"), Some(Protocol::HttpBinary));
+        assert_eq!(parse_protocol("http"), None);
+        assert_eq!(
+            join_signal_path(
+                "http://collector:4318/",
+                Signal::Traces,
+                Protocol::HttpBinary
+            ),
+            "http://collector:4318/v1/traces"
+        );
+        assert_eq!(
+            join_signal_path("http://collector:4318", Signal::Logs, Protocol::HttpJson),
+            "http://collector:4318/v1/logs"
+        );
+        assert_eq!(
+            join_signal_path("http://collector:4317", Signal::Metrics, Protocol::Grpc),
+            "http://collector:4317"
+        );
+    }
+
+    #[test]
+    fn test_command_sample_ratio_prefers_env_over_config() {
+        let config = TelemetryConfig {
+            sample: [
+                ("find".to_string(), 0.1),
+                ("complete_items".to_string(), 2.0),
+            ]
+            .into(),
+            ..TelemetryConfig::default()
+        };
+        let env = |value: &'static str| {
+            move |name: &str| (name == "OPZ_TRACE_SAMPLE_FIND").then(|| value.to_string())
+        };
+        let no_env = |_: &str| None;
+        assert_eq!(command_sample_ratio("find", &config, no_env), Some(0.1));
+        assert_eq!(command_sample_ratio("find", &config, env("0.5")), Some(0.5));
+        assert_eq!(
+            command_sample_ratio("find", &config, env("often")),
+            Some(0.1)
+        );
+        assert_eq!(
+            command_sample_ratio("complete_items", &config, no_env),
+            Some(1.0)
+        );
+        assert_eq!(command_sample_ratio("run", &config, no_env), None);
+    }
+
+    #[test]
+    fn test_json_span_exporter_writes_one_line_per_span() {
+        let buf = SharedBuf::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(JsonSpanExporter::new(Box::new(buf.clone())))
+            .build();
+        let tracer = provider.tracer("test");
+        tracer.in_span("cli.gen", |_| {
+            let mut span = tracer.start("op item get");
+            span.set_attribute(KeyValue::new("op.exit_status", 0));
+            span.add_event("cache", vec![KeyValue::new("hit", false)]);
+            span.end();
+        });
+
+        let text = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let spans: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(spans.len(), 2);
+        let (child, root) = (&spans[0], &spans[1]);
+        assert_eq!(child["name"], "op item get");
+        assert_eq!(child["trace_id"], root["trace_id"]);
+        assert_eq!(child["parent_span_id"], root["span_id"]);
+        assert_eq!(root["parent_span_id"], serde_json::Value::Null);
+        assert_eq!(child["attributes"]["op.exit_status"], 0);
+        assert_eq!(child["events"][0]["attributes"]["hit"], false);
+        assert_eq!(child["status"], "unset");
+    }
+}
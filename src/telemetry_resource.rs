@@ -0,0 +1,162 @@
+//! Resource attributes describing where opz runs, so traces from a laptop, a container and a
+//! CI job can be told apart: `host.name`, `os.type`, `container.id` and `ci.provider`.
+
+use opentelemetry::KeyValue;
+
+/// The attributes that could be detected; missing ones are left out.
+pub fn detect() -> Vec<KeyValue> {
+    let mut attrs = vec![KeyValue::new("os.type", os_type())];
+    if let Some(host) = host_name() {
+        attrs.push(KeyValue::new("host.name", host));
+    }
+    if let Some(id) = container_id() {
+        attrs.push(KeyValue::new("container.id", id));
+    }
+    if let Some(provider) = ci_provider(|name| std::env::var(name).ok()) {
+        attrs.push(KeyValue::new("ci.provider", provider));
+    }
+    attrs
+}
+
+/// The OpenTelemetry `os.type` value, which calls macOS `darwin`.
+fn os_type() -> &'static str {
+    match std::env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    }
+}
+
+#[cfg(unix)]
+fn host_name() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length, and one byte is kept for the NUL.
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len() - 1) };
+    if rc != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    let name = String::from_utf8_lossy(&buf[..len]).into_owned();
+    (!name.is_empty()).then_some(name)
+}
+
+#[cfg(not(unix))]
+fn host_name() -> Option<String> {
+    std::env::var("COMPUTERNAME")
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+fn container_id() -> Option<String> {
+    let read = |path| std::fs::read_to_string(path).ok();
+    read("/proc/self/cgroup")
+        .as_deref()
+        .and_then(container_id_from_cgroup)
+        .or_else(|| {
+            read("/proc/self/mountinfo")
+                .as_deref()
+                .and_then(container_id_from_mountinfo)
+        })
+}
+
+fn is_container_id(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// The id in a cgroup v1 path (`/docker/<id>`) or a systemd scope (`docker-<id>.scope`,
+/// `cri-containerd-<id>.scope`, ...).
+fn container_id_from_cgroup(cgroup: &str) -> Option<String> {
+    cgroup.lines().find_map(|line| {
+        let path = line.splitn(3, ':').nth(2)?;
+        let last = path.rsplit('/').next()?;
+        let last = last.strip_suffix(".scope").unwrap_or(last);
+        let id = last.rsplit(['-', ':']).next()?;
+        is_container_id(id).then(|| id.to_string())
+    })
+}
+
+/// With cgroup v2 namespaces the cgroup path is just `/`, but Docker and containerd still
+/// mount files from the container's own directory (`.../containers/<id>/hostname`).
+fn container_id_from_mountinfo(mountinfo: &str) -> Option<String> {
+    mountinfo.lines().find_map(|line| {
+        line.split_whitespace().find_map(|field| {
+            let segments: Vec<&str> = field.split('/').collect();
+            segments.windows(2).find_map(|pair| {
+                (matches!(pair[0], "containers" | "sandboxes") && is_container_id(pair[1]))
+                    .then(|| pair[1].to_string())
+            })
+        })
+    })
+}
+
+/// The CI service running opz, from the variables each one sets; `other` for an unknown one
+/// that sets `CI`.
+fn ci_provider(var: impl Fn(&str) -> Option<String>) -> Option<&'static str> {
+    const PROVIDERS: [(&str, &str); 9] = [
+        ("GITHUB_ACTIONS", "github_actions"),
+        ("GITLAB_CI", "gitlab"),
+        ("CIRCLECI", "circleci"),
+        ("BUILDKITE", "buildkite"),
+        ("JENKINS_URL", "jenkins"),
+        ("TF_BUILD", "azure_pipelines"),
+        ("BITBUCKET_BUILD_NUMBER", "bitbucket"),
+        ("TRAVIS", "travis"),
+        ("CODEBUILD_BUILD_ID", "aws_codebuild"),
+    ];
+    let set = |name: &str| var(name).is_some_and(|value| !value.is_empty() && value != "false");
+    PROVIDERS
+        .iter()
+        .find(|(name, _)| set(name))
+        .map(|(_, provider)| *provider)
+        .or_else(|| set("CI").then_some("other"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "8b5c4ae1f8e2c7a94d8b8e4f0f6a0a6f3c3a8e9d1b2c3d4e5f60718293a4b5c6";
+
+    #[test]
+    fn test_container_id_from_cgroup_and_mountinfo() {
+        assert_eq!(
+            container_id_from_cgroup(&format!("12:memory:/docker/{ID}\n0::/\n")).as_deref(),
+            Some(ID)
+        );
+        assert_eq!(
+            container_id_from_cgroup(&format!(
+                "0::/kubepods.slice/kubepods-pod1.slice/cri-containerd-{ID}.scope\n"
+            ))
+            .as_deref(),
+            Some(ID)
+        );
+        assert_eq!(
+            container_id_from_cgroup("0::/\n4:memory:/user.slice\n"),
+            None
+        );
+        assert_eq!(
+            container_id_from_mountinfo(&format!(
+                "612 598 254:1 /var/lib/docker/containers/{ID}/hostname /etc/hostname rw - ext4 /dev/vda1 rw\n"
+            ))
+            .as_deref(),
+            Some(ID)
+        );
+    }
+
+    #[test]
+    fn test_ci_provider_from_env() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(
+            ci_provider(env(&[("CI", "true"), ("GITHUB_ACTIONS", "true")])),
+            Some("github_actions")
+        );
+        assert_eq!(ci_provider(env(&[("CI", "1")])), Some("other"));
+        assert_eq!(ci_provider(env(&[("CI", "false")])), None);
+        assert_eq!(ci_provider(env(&[])), None);
+    }
+}
@@ -1,16 +1,105 @@
 use anyhow::Result;
 use opentelemetry::{
     global,
+    propagation::TextMapPropagator,
     trace::{Span, TraceContextExt, Tracer},
     Context, KeyValue,
 };
+use opentelemetry_sdk::propagation::TraceContextPropagator;
 use regex::Regex;
+use std::collections::HashMap;
 use std::ffi::OsString;
 use std::process::Command;
-use std::sync::OnceLock;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::config::TelemetryConfig;
 
 const TRACE_TEXT_LIMIT: usize = 512;
 
+/// How [`sanitize_for_trace`] masks and truncates text; the built-in rules until
+/// [`configure_redaction`] runs.
+struct Redaction {
+    patterns: Vec<Regex>,
+    limit: usize,
+}
+
+static REDACTION: OnceLock<Redaction> = OnceLock::new();
+
+impl Redaction {
+    fn apply(&self, input: &str) -> String {
+        let masked_op = op_reference_regex().replace_all(input, "op://***");
+        let mut out = secret_key_value_regex()
+            .replace_all(&masked_op, "$1***")
+            .into_owned();
+        for pattern in &self.patterns {
+            out = mask_matches(pattern, &out);
+        }
+
+        if self.limit > 0 && out.len() > self.limit {
+            let mut end = self.limit;
+            while !out.is_char_boundary(end) {
+                end -= 1;
+            }
+            out.truncate(end);
+            out.push_str("...[truncated]");
+        }
+
+        out
+    }
+}
+
+/// Replace each match of `pattern` with `***`, or only its first capture group if it has
+/// one (`API_TOKEN=(\S+)` keeps `API_TOKEN=`).
+fn mask_matches(pattern: &Regex, input: &str) -> String {
+    pattern
+        .replace_all(input, |caps: &regex::Captures<'_>| {
+            let whole = caps.get(0).expect("group 0 is the match");
+            match caps.get(1) {
+                Some(secret) => format!(
+                    "{}***{}",
+                    &input[whole.start()..secret.start()],
+                    &input[secret.end()..whole.end()]
+                ),
+                None => "***".to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Apply the `[telemetry]` redaction settings, or `OPZ_TRACE_REDACT_PATTERNS` (regexes
+/// separated by `;`) and `OPZ_TRACE_TEXT_LIMIT` when set. Invalid patterns are reported and
+/// skipped. Only the first call has an effect.
+pub fn configure_redaction(config: &TelemetryConfig) {
+    let patterns: Vec<String> = match std::env::var("OPZ_TRACE_REDACT_PATTERNS") {
+        Ok(patterns) => patterns
+            .split(';')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Err(_) => config.redact_patterns.clone(),
+    };
+    let limit = match std::env::var("OPZ_TRACE_TEXT_LIMIT") {
+        Ok(limit) => limit.trim().parse().unwrap_or_else(|_| {
+            eprintln!("Warning: ignoring invalid OPZ_TRACE_TEXT_LIMIT '{limit}'");
+            config.text_limit.unwrap_or(TRACE_TEXT_LIMIT)
+        }),
+        Err(_) => config.text_limit.unwrap_or(TRACE_TEXT_LIMIT),
+    };
+    let patterns = patterns
+        .iter()
+        .filter_map(|pattern| match Regex::new(pattern) {
+            Ok(regex) => Some(regex),
+            Err(err) => {
+                eprintln!("Warning: ignoring invalid trace redaction pattern '{pattern}': {err}");
+                None
+            }
+        })
+        .collect();
+    let _ = REDACTION.set(Redaction { patterns, limit });
+}
+
 pub fn with_span<T>(name: &str, attrs: Vec<KeyValue>, f: impl FnOnce() -> T) -> T {
     let tracer = global::tracer("opz");
     let mut span = tracer.start_with_context(name.to_string(), &Context::current());
@@ -48,6 +137,191 @@ pub fn record_error_message(message: &str) {
     );
 }
 
+/// Set an attribute on the active span (e.g. an outcome only known after a child span ends).
+pub fn set_attribute(attr: KeyValue) {
+    Context::current().span().set_attribute(attr);
+}
+
+/// Why the lookup in progress is not answered from a cache (`missing`, `expired`, ...).
+#[derive(Debug, Clone, Copy)]
+struct CacheBypass(&'static str);
+
+/// Run `f`, which fetches what a cache could not provide, with `reason` recorded on the active
+/// span and on the spans of the `op` calls `f` makes.
+pub fn with_cache_bypass<T>(reason: &'static str, f: impl FnOnce() -> T) -> T {
+    set_attribute(KeyValue::new("cache.bypass_reason", reason));
+    let _guard = Context::current_with_value(CacheBypass(reason)).attach();
+    f()
+}
+
+/// The reason given to the innermost [`with_cache_bypass`], if any.
+pub fn cache_bypass_reason() -> Option<&'static str> {
+    Context::current()
+        .get::<CacheBypass>()
+        .map(|bypass| bypass.0)
+}
+
+/// How the run's item list lookups and title matches went, summarized on the
+/// `cli.<command>` span by [`record_resolution`].
+#[derive(Debug, Default)]
+struct Resolution {
+    cache_hits: u32,
+    cache_misses: u32,
+    oldest_cache: Option<Duration>,
+    stale: bool,
+    matches: u32,
+    fuzzy: bool,
+    candidates: usize,
+}
+
+impl Resolution {
+    fn attributes(&self) -> Vec<KeyValue> {
+        let mut attrs = Vec::new();
+        if self.cache_hits + self.cache_misses > 0 {
+            attrs.push(KeyValue::new("cache.hit", self.cache_misses == 0));
+        }
+        if let Some(age) = self.oldest_cache {
+            attrs.push(KeyValue::new("cache.age_seconds", age.as_secs() as i64));
+            attrs.push(KeyValue::new("cache.stale", self.stale));
+        }
+        if self.matches > 0 {
+            let kind = if self.fuzzy { "fuzzy" } else { "exact" };
+            attrs.push(KeyValue::new("match.kind", kind));
+            attrs.push(KeyValue::new("match.candidates", self.candidates as i64));
+        }
+        attrs
+    }
+}
+
+static RESOLUTION: Mutex<Resolution> = Mutex::new(Resolution {
+    cache_hits: 0,
+    cache_misses: 0,
+    oldest_cache: None,
+    stale: false,
+    matches: 0,
+    fuzzy: false,
+    candidates: 0,
+});
+
+fn resolution() -> std::sync::MutexGuard<'static, Resolution> {
+    RESOLUTION
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// An item list answered from a cache `age` old (`None`: fetched from `op`).
+pub fn note_item_list_lookup(cached: Option<(Duration, bool)>) {
+    let mut resolution = resolution();
+    match cached {
+        Some((age, stale)) => {
+            resolution.cache_hits += 1;
+            resolution.oldest_cache = resolution.oldest_cache.max(Some(age));
+            resolution.stale |= stale;
+        }
+        None => resolution.cache_misses += 1,
+    }
+}
+
+/// A title resolved against an item list: by exact title or, failing that, by substring,
+/// with `candidates` items matching.
+pub fn note_title_match(exact: bool, candidates: usize) {
+    let mut resolution = resolution();
+    resolution.matches += 1;
+    resolution.fuzzy |= !exact;
+    resolution.candidates = resolution.candidates.max(candidates);
+}
+
+static RUN_ATTRIBUTES: Mutex<Vec<KeyValue>> = Mutex::new(Vec::new());
+
+/// Set `attr` on the `cli.<command>` span when it ends, however deeply nested the caller is.
+pub fn set_run_attribute(attr: KeyValue) {
+    RUN_ATTRIBUTES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push(attr);
+}
+
+/// Record `cache.hit`, `cache.age_seconds`, `cache.stale`, `match.kind` (fuzzy if any title
+/// was), `match.candidates` (the most for one title) and the [`set_run_attribute`] attributes
+/// on the active span.
+pub fn record_run_summary() {
+    let resolution = std::mem::take(&mut *resolution());
+    let attributes = std::mem::take(
+        &mut *RUN_ATTRIBUTES
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    );
+    let cx = Context::current();
+    let span = cx.span();
+    for attr in resolution.attributes().into_iter().chain(attributes) {
+        span.set_attribute(attr);
+    }
+}
+
+/// Whether sanitized arguments may be recorded (`OPZ_TRACE_CAPTURE_ARGS=1`).
+pub fn capture_args() -> bool {
+    std::env::var("OPZ_TRACE_CAPTURE_ARGS").ok().as_deref() == Some("1")
+}
+
+pub fn record_panic(message: &str, location: Option<&str>) {
+    let sanitized = sanitize_for_trace(message);
+    let cx = Context::current();
+    let span = cx.span();
+    span.set_status(opentelemetry::trace::Status::error(sanitized.clone()));
+    let mut attrs = vec![
+        KeyValue::new("exception.type", "panic"),
+        KeyValue::new("exception.message", sanitized),
+    ];
+    if let Some(location) = location {
+        attrs.push(KeyValue::new("code.location", location.to_string()));
+    }
+    span.add_event("panic".to_string(), attrs);
+}
+
+/// The active span's W3C trace context as env vars (`TRACEPARENT`, and `TRACESTATE` when it is
+/// not empty), so a command opz launches can continue the trace. Empty when nothing is traced.
+pub fn trace_context_env() -> Vec<(String, String)> {
+    let cx = Context::current();
+    if !cx.span().span_context().is_valid() {
+        return Vec::new();
+    }
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&cx, &mut carrier);
+    let mut env: Vec<(String, String)> = carrier
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(key, value)| (key.to_ascii_uppercase(), value))
+        .collect();
+    env.sort();
+    env
+}
+
+/// The context the root span starts in: the trace opz itself was launched under, from
+/// `TRACEPARENT` and `TRACESTATE` (a Makefile run by a traced tool, or `opz` nested in
+/// `opz run`), so nested invocations add to that trace instead of starting their own. The
+/// current context if they are unset or malformed.
+pub fn parent_context_from_env() -> Context {
+    parent_context_from(|name| std::env::var(name).ok())
+}
+
+fn parent_context_from(var: impl Fn(&str) -> Option<String>) -> Context {
+    let carrier: HashMap<String, String> = ["traceparent", "tracestate"]
+        .into_iter()
+        .filter_map(|key| Some((key.to_string(), var(&key.to_ascii_uppercase())?)))
+        .collect();
+    TraceContextPropagator::new().extract(&carrier)
+}
+
+/// Hex trace id of the active span, if telemetry is recording it (sampled out traces never
+/// reach the backend).
+pub fn current_trace_id() -> Option<String> {
+    let cx = Context::current();
+    let span = cx.span();
+    let span_context = span.span_context();
+    (span_context.is_valid() && span_context.is_sampled())
+        .then(|| span_context.trace_id().to_string())
+}
+
 pub fn build_cli_trace_attrs(command_name: &str, args: &[OsString]) -> Vec<KeyValue> {
     let mut attrs = vec![
         KeyValue::new("cli.command", command_name.to_string()),
@@ -59,7 +333,7 @@ pub fn build_cli_trace_attrs(command_name: &str, args: &[OsString]) -> Vec<KeyVa
         attrs.push(KeyValue::new("cwd", cwd.display().to_string()));
     }
 
-    if std::env::var("OPZ_TRACE_CAPTURE_ARGS").ok().as_deref() == Some("1") {
+    if capture_args() {
         let raw_args = args
             .iter()
             .skip(1)
@@ -97,16 +371,11 @@ fn resolve_git_commit_attr() -> String {
 }
 
 pub fn sanitize_for_trace(input: &str) -> String {
-    let masked_op = op_reference_regex().replace_all(input, "op://***");
-    let masked_keys = secret_key_value_regex().replace_all(&masked_op, "$1***");
-
-    let mut out = masked_keys.into_owned();
-    if out.len() > TRACE_TEXT_LIMIT {
-        out.truncate(TRACE_TEXT_LIMIT);
-        out.push_str("...[truncated]");
-    }
-
-    out
+    static BUILT_IN: Redaction = Redaction {
+        patterns: Vec::new(),
+        limit: TRACE_TEXT_LIMIT,
+    };
+    REDACTION.get().unwrap_or(&BUILT_IN).apply(input)
 }
 
 fn op_reference_regex() -> &'static Regex {
@@ -126,7 +395,67 @@ fn secret_key_value_regex() -> &'static Regex {
 
 #[cfg(test)]
 mod tests {
-    use super::sanitize_for_trace;
+    use super::{
+        cache_bypass_reason, parent_context_from, sanitize_for_trace, trace_context_env,
+        with_cache_bypass, Redaction, Resolution,
+    };
+    use opentelemetry::{
+        trace::{TraceContextExt, Tracer, TracerProvider},
+        Context,
+    };
+
+    #[test]
+    fn test_trace_context_env_follows_active_span() {
+        assert!(trace_context_env().is_empty());
+
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder().build();
+        let span = provider.tracer("test").start("command_exec");
+        let cx = Context::current_with_span(span);
+        let (trace_id, span_id) = {
+            let span = cx.span();
+            let span_context = span.span_context();
+            (span_context.trace_id(), span_context.span_id())
+        };
+        let _guard = cx.attach();
+        assert_eq!(
+            trace_context_env(),
+            vec![(
+                "TRACEPARENT".to_string(),
+                format!("00-{trace_id}-{span_id}-01")
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parent_context_from_traceparent() {
+        let env = |traceparent: &'static str| {
+            move |name: &str| (name == "TRACEPARENT").then(|| traceparent.to_string())
+        };
+        let cx = parent_context_from(env(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ));
+        let span = cx.span();
+        let parent = span.span_context();
+        assert!(parent.is_remote());
+        assert_eq!(
+            parent.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(parent.span_id().to_string(), "00f067aa0ba902b7");
+
+        let cx = parent_context_from(env("not-a-traceparent"));
+        assert!(!cx.span().span_context().is_valid());
+    }
+
+    #[test]
+    fn test_cache_bypass_reason_is_scoped() {
+        assert_eq!(cache_bypass_reason(), None);
+        let inner = with_cache_bypass("expired", || {
+            with_cache_bypass("changed", cache_bypass_reason)
+        });
+        assert_eq!(inner, Some("changed"));
+        assert_eq!(cache_bypass_reason(), None);
+    }
 
     #[test]
     fn test_sanitize_for_trace_masks_op_reference() {
@@ -153,4 +482,57 @@ mod tests {
         assert!(sanitized.ends_with("...[truncated]"));
         assert!(sanitized.len() > 512);
     }
+
+    #[test]
+    fn test_resolution_attributes_summarize_the_run() {
+        assert!(Resolution::default().attributes().is_empty());
+
+        let resolution = Resolution {
+            cache_hits: 2,
+            cache_misses: 1,
+            oldest_cache: Some(std::time::Duration::from_secs(90)),
+            stale: true,
+            matches: 2,
+            fuzzy: true,
+            candidates: 3,
+        };
+        let attrs: Vec<String> = resolution
+            .attributes()
+            .iter()
+            .map(|kv| format!("{}={}", kv.key, kv.value))
+            .collect();
+        assert_eq!(
+            attrs,
+            [
+                "cache.hit=false",
+                "cache.age_seconds=90",
+                "cache.stale=true",
+                "match.kind=fuzzy",
+                "match.candidates=3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redaction_applies_custom_patterns_and_limit() {
+        let redaction = Redaction {
+            patterns: vec![
+                regex::Regex::new(r"ACME_[A-Z]+_KEY=(\S+)").unwrap(),
+                regex::Regex::new(r"sk-[a-z0-9]+").unwrap(),
+            ],
+            limit: 40,
+        };
+        assert_eq!(
+            redaction.apply("ACME_DB_KEY=hunter2 sk-abc123 token=x"),
+            "ACME_DB_KEY=*** *** token=***"
+        );
+        let truncated = redaction.apply(&"é".repeat(30));
+        assert_eq!(truncated, format!("{}...[truncated]", "é".repeat(20)));
+
+        let unlimited = Redaction {
+            patterns: Vec::new(),
+            limit: 0,
+        };
+        assert_eq!(unlimited.apply(&"a".repeat(600)).len(), 600);
+    }
 }
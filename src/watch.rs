@@ -0,0 +1,215 @@
+//! `opz watch`: runs a command with secrets like `opz run`, polls the items' `updated_at`, and
+//! restarts the command with freshly resolved secrets when one of them changes (a rotated
+//! password, a new API key).
+//!
+//! The secrets are resolved before the old command is stopped, so a failed `op` call leaves
+//! it running on the old secrets until the next poll tries again. The old command then gets
+//! SIGTERM and the grace period to exit before it is killed, so servers can finish in-flight
+//! requests. A command that exits on its own ends the watch with its status, and a failed poll
+//! is only reported.
+//!
+//! This is a plain loop rather than a [`crate::supervisor::Supervisor`] task: the supervisor
+//! restarts async tasks after failures with backoff, while here restarts follow item changes,
+//! the command's own exit ends the watch with its status, and the child is stopped through the
+//! same [`shutdown`] stages as with `opz run`.
+
+use crate::shutdown;
+use anyhow::{Context, Result};
+use std::{
+    process::{Child, ExitStatus},
+    time::{Duration, Instant},
+};
+
+/// How often [`run`] checks whether the command exited while it waits for the next poll.
+const EXIT_CHECK: Duration = Duration::from_millis(100);
+
+/// Run the command `spawn` starts from what `prepare` resolved until it exits, restarting it
+/// whenever `versions` (item title and `updated_at` pairs) reports something different from
+/// what the running command was started with. `versions` is called every `interval`.
+pub fn run<P>(
+    interval: Duration,
+    grace: Duration,
+    mut prepare: impl FnMut() -> Result<P>,
+    mut spawn: impl FnMut(P) -> Result<Child>,
+    mut versions: impl FnMut() -> Result<Vec<(String, Option<String>)>>,
+) -> Result<ExitStatus> {
+    let mut seen = versions()?;
+    let mut prepared = prepare()?;
+    loop {
+        let mut child = spawn(prepared)?;
+        let pid = child.id();
+        let stop_child = shutdown::register(shutdown::Stage::StopChild, move || {
+            shutdown::stop_child(pid)
+        });
+        prepared = loop {
+            if let Some(status) = wait_timeout(&mut child, interval)? {
+                stop_child.disarm();
+                return Ok(status);
+            }
+            let current = match versions() {
+                Ok(current) => current,
+                Err(err) => {
+                    tracing::warn!("failed to check items for changes: {err:#}");
+                    continue;
+                }
+            };
+            let changed = changed_titles(&seen, &current);
+            if changed.is_empty() {
+                continue;
+            }
+            match prepare() {
+                Ok(prepared) => {
+                    seen = current;
+                    eprintln!(
+                        "opz: {} changed; restarting the command",
+                        changed.join(", ")
+                    );
+                    break prepared;
+                }
+                // `seen` stays as it was, so the next poll tries again.
+                Err(err) => tracing::warn!(
+                    "{} changed, but resolving the new secrets failed; the command keeps running: {err:#}",
+                    changed.join(", ")
+                ),
+            }
+        };
+        terminate(&mut child, grace)?;
+        stop_child.disarm();
+    }
+}
+
+/// Titles whose `updated_at` differs between the two polls, or that appeared or went away.
+fn changed_titles(
+    before: &[(String, Option<String>)],
+    after: &[(String, Option<String>)],
+) -> Vec<String> {
+    let mut changed: Vec<String> = after
+        .iter()
+        .filter(|entry| !before.contains(entry))
+        .chain(
+            before
+                .iter()
+                .filter(|(title, _)| !after.iter().any(|(other, _)| other == title)),
+        )
+        .map(|(title, _)| title.clone())
+        .collect();
+    changed.dedup();
+    changed
+}
+
+/// The child's exit status if it exits within `timeout`.
+fn wait_timeout(child: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>> {
+    let until = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait().context("failed to wait for command")? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= until {
+            return Ok(None);
+        }
+        std::thread::sleep(EXIT_CHECK.min(until - now));
+    }
+}
+
+/// Ask the child to exit with SIGTERM and kill it if it is still running after `grace`.
+fn terminate(child: &mut Child, grace: Duration) -> Result<()> {
+    #[cfg(unix)]
+    {
+        // SAFETY: kill(2) has no memory-safety preconditions, and the child has not been
+        // reaped yet, so its pid cannot have been reused.
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+        if wait_timeout(child, grace)?.is_some() {
+            return Ok(());
+        }
+        eprintln!(
+            "opz: command did not exit within the {}s grace period; killing it",
+            grace.as_secs_f32()
+        );
+    }
+    #[cfg(not(unix))]
+    let _ = grace;
+    child.kill().context("failed to kill command")?;
+    child.wait().context("failed to wait for command")?;
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_changed_titles() {
+        let poll = |entries: &[(&str, &str)]| -> Vec<(String, Option<String>)> {
+            entries
+                .iter()
+                .map(|(title, updated)| (title.to_string(), Some(updated.to_string())))
+                .collect()
+        };
+        let before = poll(&[("db", "1"), ("api", "1")]);
+        assert!(changed_titles(&before, &before).is_empty());
+        assert_eq!(
+            changed_titles(&before, &poll(&[("db", "2"), ("api", "1")])),
+            vec!["db"]
+        );
+        assert_eq!(changed_titles(&before, &poll(&[("db", "1")])), vec!["api"]);
+    }
+
+    #[test]
+    fn test_run_restarts_the_command_when_an_item_changes() {
+        let mut starts = 0;
+        let mut polls = 0;
+        let status = run(
+            Duration::from_millis(50),
+            Duration::from_secs(5),
+            || {
+                starts += 1;
+                // The first command only stops when it is terminated; the second one exits.
+                Ok(if starts == 1 { "sleep 30" } else { "exit 3" })
+            },
+            |script| Ok(Command::new("sh").args(["-c", script]).spawn()?),
+            || {
+                polls += 1;
+                let updated = if polls < 3 { "1" } else { "2" };
+                Ok(vec![("db".to_string(), Some(updated.to_string()))])
+            },
+        )
+        .unwrap();
+        assert_eq!(starts, 2);
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    fn test_failed_resolve_keeps_the_old_command_running() {
+        let mut prepares = 0;
+        let mut spawns = 0;
+        let mut polls = 0;
+        let status = run(
+            Duration::from_millis(50),
+            Duration::from_secs(5),
+            || {
+                prepares += 1;
+                match prepares {
+                    1 => Ok("sleep 30"),
+                    2 => Err(anyhow::anyhow!("op is down")),
+                    _ => Ok("exit 5"),
+                }
+            },
+            |script| {
+                spawns += 1;
+                Ok(Command::new("sh").args(["-c", script]).spawn()?)
+            },
+            || {
+                polls += 1;
+                let updated = if polls < 2 { "1" } else { "2" };
+                Ok(vec![("db".to_string(), Some(updated.to_string()))])
+            },
+        )
+        .unwrap();
+        // The failed resolve left the first command alone; the next poll retried the change.
+        assert_eq!(prepares, 3);
+        assert_eq!(spawns, 2);
+        assert_eq!(status.code(), Some(5));
+    }
+}
@@ -0,0 +1,407 @@
+//! Resilience checks driven by `OPZ_FAULT_INJECT`, against the fake `op` in
+//! `tests/fixtures/fake-op`. Each test gets its own cache, data and runtime directories.
+#![cfg(unix)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+struct Sandbox {
+    dir: tempfile::TempDir,
+}
+
+impl Sandbox {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().expect("create tempdir");
+        for sub in ["home", "cache", "data", "config", "runtime", "work"] {
+            fs::create_dir(dir.path().join(sub)).expect("create sandbox dir");
+        }
+        Sandbox { dir }
+    }
+
+    fn path(&self, sub: &str) -> PathBuf {
+        self.dir.path().join(sub)
+    }
+
+    fn work(&self) -> PathBuf {
+        self.path("work")
+    }
+
+    fn opz(&self, faults: &str, args: &[&str]) -> Output {
+        self.opz_with(&[], faults, args)
+    }
+
+    fn opz_with(&self, envs: &[(&str, &str)], faults: &str, args: &[&str]) -> Output {
+        self.command(envs, faults, args).output().expect("run opz")
+    }
+
+    fn command(&self, envs: &[(&str, &str)], faults: &str, args: &[&str]) -> Command {
+        let fake_op = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fake-op/op");
+        let mut command = Command::new(env!("CARGO_BIN_EXE_opz"));
+        command
+            .args(args)
+            .current_dir(self.work())
+            .env("OPZ_OP_BIN", fake_op)
+            .env("HOME", self.path("home"))
+            .env("XDG_CACHE_HOME", self.path("cache"))
+            .env("XDG_DATA_HOME", self.path("data"))
+            .env("XDG_CONFIG_HOME", self.path("config"))
+            .env("XDG_RUNTIME_DIR", self.path("runtime"))
+            .env("FAKE_OP_ITEMS", "3")
+            .env("FAKE_OP_FIELDS", "2")
+            .env("OPZ_FAULT_INJECT", faults)
+            .env("OPZ_LOG", "opz=debug")
+            .env_remove("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .env_remove("OP_ACCOUNT")
+            .env_remove("OP_VAULT")
+            .envs(envs.iter().copied());
+        command
+    }
+}
+
+fn stderr(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stderr).into_owned()
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+fn assert_success(out: &Output) {
+    assert!(
+        out.status.success(),
+        "status: {:?}\nstderr:\n{}",
+        out.status,
+        stderr(out)
+    );
+}
+
+/// Failed with the exit status of the expected error class, through the normal error path
+/// rather than a panic.
+fn assert_error(out: &Output, code: i32, needle: &str) {
+    assert_eq!(out.status.code(), Some(code), "stderr:\n{}", stderr(out));
+    assert!(
+        stderr(out).contains(needle),
+        "expected '{needle}' in stderr:\n{}",
+        stderr(out)
+    );
+}
+
+#[test]
+fn op_timeout_is_reported() {
+    let sandbox = Sandbox::new();
+    let out = sandbox.opz("op-timeout", &["gen", "item-1"]);
+    assert_error(&out, 69, "op timed out (injected fault)");
+}
+
+#[test]
+fn op_timeout_is_retried_with_retries() {
+    let sandbox = Sandbox::new();
+    let out = sandbox.opz("op-timeout", &["gen", "--retries", "2", "item-1"]);
+    assert_error(&out, 69, "op timed out (injected fault)");
+    assert!(
+        stderr(&out).contains("retrying in 0.5s (1/2)"),
+        "{}",
+        stderr(&out)
+    );
+    assert!(
+        stderr(&out).contains("retrying in 1.0s (2/2)"),
+        "{}",
+        stderr(&out)
+    );
+}
+
+#[test]
+fn hung_op_is_killed_after_op_timeout() {
+    let sandbox = Sandbox::new();
+    let started = std::time::Instant::now();
+    let out = sandbox.opz_with(
+        &[("FAKE_OP_LATENCY", "30")],
+        "",
+        &["gen", "--op-timeout", "1", "item-1"],
+    );
+    assert_error(
+        &out,
+        69,
+        "op item list --format json timed out after 1s (--op-timeout) and was killed",
+    );
+    assert!(started.elapsed() < std::time::Duration::from_secs(20));
+}
+
+#[test]
+fn op_timeout_falls_back_to_cache_backend() {
+    let sandbox = Sandbox::new();
+    fs::write(
+        sandbox.work().join(".opz.toml"),
+        "[backend]\nchain = [\"cli\", \"cache\"]\n",
+    )
+    .unwrap();
+    assert_success(&sandbox.opz("", &["gen", "item-1"]));
+
+    let out = sandbox.opz("op-timeout", &["gen", "--refresh", "item-1"]);
+    assert_success(&out);
+    assert!(stdout(&out).contains("FIELD_1=op://vault1/id1/FIELD_1"));
+    assert!(
+        stderr(&out).contains("cli backend failed"),
+        "{}",
+        stderr(&out)
+    );
+}
+
+#[test]
+fn missing_op_bin_is_reported() {
+    let sandbox = Sandbox::new();
+    let out = sandbox.opz("", &["gen", "--op-bin", "/nonexistent/op", "item-1"]);
+    assert_error(&out, 69, "1Password CLI not found at /nonexistent/op");
+}
+
+#[test]
+fn running_agent_answers_when_op_times_out() {
+    let sandbox = Sandbox::new();
+    let socket = sandbox.path("runtime").join("agent.sock");
+    let socket = socket.to_str().unwrap();
+    let mut agent = sandbox
+        .command(&[], "", &["agent", "--socket", socket])
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("start agent");
+    let started = Instant::now();
+    while !Path::new(socket).exists() && started.elapsed() < Duration::from_secs(10) {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    // A fresh cache and an `op` that always times out: only the agent can answer.
+    fs::create_dir(sandbox.path("cache2")).unwrap();
+    let cache = sandbox.path("cache2");
+    let out = sandbox.opz_with(
+        &[
+            ("OPZ_AGENT_SOCK", socket),
+            ("XDG_CACHE_HOME", cache.to_str().unwrap()),
+        ],
+        "op-timeout",
+        &["gen", "item-1"],
+    );
+    agent.kill().unwrap();
+    agent.wait().unwrap();
+    assert_success(&out);
+    assert!(stdout(&out).contains("FIELD_1=op://"));
+    assert!(
+        stderr(&out).contains("served by the agent"),
+        "{}",
+        stderr(&out)
+    );
+}
+
+#[test]
+fn lookup_failures_have_their_own_exit_status() {
+    let sandbox = Sandbox::new();
+    let out = sandbox.opz("", &["gen", "no-such-item"]);
+    assert_error(&out, 66, "No item matched title: no-such-item");
+    let out = sandbox.opz("", &["gen", "item-"]);
+    assert_error(&out, 64, "Please be more specific");
+    assert_error(&out, 64, "id2  [Bench]  item-2");
+}
+
+#[test]
+fn json_errors_go_to_stderr_as_one_object() {
+    let sandbox = Sandbox::new();
+    let out = sandbox.opz_with(
+        &[("OPZ_ERROR_FORMAT", "json"), ("OPZ_LOG", "off")],
+        "",
+        &["gen", "item-"],
+    );
+    assert_eq!(out.status.code(), Some(64));
+    assert!(stdout(&out).is_empty(), "{}", stdout(&out));
+    let last_line = stderr(&out).lines().last().unwrap_or_default().to_string();
+    let json: serde_json::Value = serde_json::from_str(&last_line).expect("JSON error");
+    assert_eq!(json["error"]["kind"], "ambiguous-item");
+    assert_eq!(
+        json["error"]["candidates"].as_array().map(Vec::len),
+        Some(3)
+    );
+    assert!(!stderr(&out).contains("Candidates:"), "{}", stderr(&out));
+
+    let out = sandbox.opz_with(
+        &[("OPZ_LOG", "off")],
+        "op-timeout",
+        &["gen", "--json", "item-1"],
+    );
+    let json: serde_json::Value = serde_json::from_str(stderr(&out).trim()).expect("JSON error");
+    assert_eq!(json["error"]["kind"], "op-failed");
+    assert_eq!(json["error"]["exit_code"], 69);
+    // The item list is cached by the first run, so the item lookup is what fails.
+    assert_eq!(json["error"]["op_args"], "item get id1 --format json");
+}
+
+#[test]
+fn traced_failures_print_their_trace_id() {
+    let sandbox = Sandbox::new();
+    let spans = sandbox.path("spans.json");
+    let trace_debug = format!("--trace-debug={}", spans.display());
+    let out = sandbox.opz_with(
+        &[("OPZ_LOG", "off")],
+        "",
+        &["gen", "--json", &trace_debug, "no-such-item"],
+    );
+    assert_eq!(out.status.code(), Some(66));
+    let json: serde_json::Value = serde_json::from_str(stderr(&out).trim()).expect("JSON error");
+    let trace_id = json["error"]["trace_id"].as_str().expect("trace_id");
+    let root = fs::read_to_string(&spans)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|span| span["name"] == "cli.gen")
+        .expect("root span");
+    assert_eq!(root["trace_id"], trace_id);
+
+    let out = sandbox.opz("", &["gen", &trace_debug, "no-such-item"]);
+    assert!(stderr(&out).contains("\ntrace_id="), "{}", stderr(&out));
+    let out = sandbox.opz("", &["gen", "no-such-item"]);
+    assert!(!stderr(&out).contains("trace_id="), "{}", stderr(&out));
+}
+
+#[test]
+fn malformed_json_is_an_error() {
+    let sandbox = Sandbox::new();
+    let out = sandbox.opz("malformed-json", &["find", "item"]);
+    assert_error(&out, 65, "failed to parse op JSON output");
+}
+
+#[test]
+fn signed_out_without_terminal_prints_signin_instructions() {
+    let sandbox = Sandbox::new();
+    let out = sandbox.opz("signed-out", &["gen", "item-1"]);
+    assert_error(&out, 77, "1Password CLI is not signed in");
+    assert_error(&out, 77, "eval $(op signin)");
+}
+
+#[test]
+fn corrupt_cache_is_treated_as_a_miss() {
+    let sandbox = Sandbox::new();
+    assert_success(&sandbox.opz("corrupt-cache", &["gen", "item-1"]));
+
+    let out = sandbox.opz("", &["gen", "item-1"]);
+    assert_success(&out);
+    assert!(stdout(&out).contains("FIELD_2=op://vault1/id1/FIELD_2"));
+    assert!(
+        stderr(&out).contains("item list cache miss"),
+        "{}",
+        stderr(&out)
+    );
+}
+
+#[test]
+fn failed_run_cleans_up_env_file_and_run_dir() {
+    let sandbox = Sandbox::new();
+    // Warm the caches so the item lookup succeeds and only secret resolution fails.
+    assert_success(&sandbox.opz("", &["gen", "item-1"]));
+
+    let out = sandbox.opz(
+        "op-timeout",
+        &[
+            "run",
+            "--keep",
+            "none",
+            "--env-file",
+            ".env",
+            "item-1",
+            "--",
+            "true",
+        ],
+    );
+    assert_error(&out, 69, "op timed out (injected fault)");
+    assert!(!sandbox.work().join(".env").exists());
+    assert_eq!(fs::read_dir(sandbox.path("runtime")).unwrap().count(), 0);
+
+    let gc = sandbox.opz("", &["gc", "--dry-run"]);
+    assert_success(&gc);
+    assert!(!stderr(&gc).contains("Would remove"), "{}", stderr(&gc));
+}
+
+#[test]
+fn keep_modes_decide_what_the_env_file_holds_after_the_run() {
+    let sandbox = Sandbox::new();
+    let env_file = sandbox.work().join(".env");
+    let run = |keep: &str| {
+        fs::write(&env_file, "LOCAL=1\n").unwrap();
+        let out = sandbox.opz(
+            "",
+            &[
+                "run",
+                "--keep",
+                keep,
+                "--env-file",
+                ".env",
+                "item-1",
+                "--",
+                "true",
+            ],
+        );
+        assert_success(&out);
+        fs::read_to_string(&env_file).unwrap()
+    };
+
+    let kept = run("file");
+    assert!(kept.starts_with("LOCAL=1\n"), "{kept}");
+    assert!(kept.contains("FIELD_1=op://vault1/id1/FIELD_1"), "{kept}");
+    assert!(!kept.contains("value-"), "{kept}");
+    // The file never holds values, so `refs` leaves exactly what `file` does.
+    assert_eq!(run("refs"), kept);
+    assert_eq!(run("none"), "LOCAL=1\n");
+}
+
+#[test]
+fn shared_secret_warning_does_not_depend_on_the_item_cache() {
+    let sandbox = Sandbox::new();
+    let envs = [("FAKE_OP_VALUE", "same")];
+    let warning = "FIELD_1, FIELD_2 have the same secret value";
+    for attempt in ["fetched", "cached"] {
+        let out = sandbox.opz_with(&envs, "", &["run", "item-1", "--", "true"]);
+        assert_success(&out);
+        assert!(
+            stderr(&out).contains(warning),
+            "{attempt}:\n{}",
+            stderr(&out)
+        );
+    }
+
+    // `gen` never reads the values, so it only sees them in details fresh from `op`.
+    let out = sandbox.opz_with(&envs, "", &["gen", "item-1"]);
+    assert_success(&out);
+    assert!(!stderr(&out).contains(warning), "{}", stderr(&out));
+    let out = sandbox.opz_with(&envs, "", &["gen", "--refresh", "item-1"]);
+    assert_success(&out);
+    assert!(stderr(&out).contains(warning), "{}", stderr(&out));
+}
+
+#[test]
+fn mask_output_masks_fields_of_every_type() {
+    let sandbox = Sandbox::new();
+    let out = sandbox.opz_with(
+        &[
+            ("FAKE_OP_TYPE", "STRING"),
+            ("FAKE_OP_VALUE", "plain-text-secret"),
+        ],
+        "",
+        &[
+            "run",
+            "--mask-output",
+            "item-1",
+            "--",
+            "sh",
+            "-c",
+            "echo \"token=$FIELD_1\"",
+        ],
+    );
+    assert_success(&out);
+    assert_eq!(stdout(&out), "token=***\n");
+}
+
+#[test]
+fn unreachable_collector_does_not_fail_the_command() {
+    let sandbox = Sandbox::new();
+    let out = sandbox.opz("collector-unreachable", &["gen", "item-1"]);
+    assert_success(&out);
+    assert!(stdout(&out).contains("FIELD_1=op://"));
+}
@@ -0,0 +1,60 @@
+//! The library API against the fake `op` in `tests/fixtures/fake-op`.
+#![cfg(unix)]
+
+use std::path::Path;
+
+#[test]
+fn client_finds_items_and_renderer_writes_references() {
+    let dir = tempfile::tempdir().expect("create tempdir");
+    let fake_op = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fake-op/op");
+    // Set before the first lookup; the `op` settings are read once per process.
+    std::env::set_var("OPZ_OP_BIN", fake_op);
+    std::env::set_var("FAKE_OP_ITEMS", "12");
+    std::env::set_var("FAKE_OP_FIELDS", "2");
+    for var in ["HOME", "XDG_CACHE_HOME", "XDG_DATA_HOME", "XDG_CONFIG_HOME"] {
+        std::env::set_var(var, dir.path());
+    }
+    std::env::remove_var("OP_ACCOUNT");
+    std::env::remove_var("OP_VAULT");
+
+    let client = opz::Client::new();
+    let found = client.find("ITEM-1").unwrap();
+    assert_eq!(found.len(), 4, "{found:?}");
+    assert_eq!(found[0].vault.as_deref(), Some("Bench"));
+
+    let err = client.item("item-").unwrap_err();
+    assert_eq!(opz::error_kind(&err), Some(opz::ErrorKind::AmbiguousItem));
+    let typed = err.downcast_ref::<opz::Error>().unwrap();
+    assert_eq!(typed.candidates().len(), 12);
+
+    let items = client.items(&["item-2", "item-3"]).unwrap();
+    assert_eq!(items[0].title(), "item-2");
+    assert_eq!(items[1].labels(), ["FIELD_1", "FIELD_2"]);
+    assert_eq!(
+        items[1].reference("FIELD_2").as_deref(),
+        Some("op://vault1/id3/FIELD_2")
+    );
+
+    let env = opz::EnvRenderer::default().render(&items).unwrap();
+    assert!(env.contains("FIELD_1=op://vault1/id3/FIELD_1"), "{env}");
+    assert!(!env.contains("value-"), "{env}");
+
+    let rendered = opz::EnvBuilder::new()
+        .item("item-4")
+        .prefix("APP_")
+        .include("*_2")
+        .render(opz::Format::Dotenv)
+        .unwrap();
+    assert_eq!(rendered.lines, ["APP_FIELD_2=op://vault1/id4/FIELD_2"]);
+    assert_eq!(rendered.skipped.len(), 1);
+    assert_eq!(rendered.skipped[0].label, "FIELD_1");
+    assert_eq!(
+        rendered.skipped[0].reason,
+        "[fields] include (no pattern matches)"
+    );
+    let nix = opz::EnvBuilder::new()
+        .item("item-4")
+        .render(opz::Format::Nix)
+        .unwrap();
+    assert!(nix.text.starts_with("{\n"), "{nix}");
+}